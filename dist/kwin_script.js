@@ -10,4 +10,20 @@ function send(window) {
     );
 }
 
-workspace.windowActivated.connect(send);
+let activeWindow = null;
+
+function sendActiveWindow() {
+    send(activeWindow);
+}
+
+// Title changes of the active window are sent too, for save triggers matching window titles
+workspace.windowActivated.connect((window) => {
+    if (activeWindow) {
+        activeWindow.captionChanged.disconnect(sendActiveWindow);
+    }
+    activeWindow = window;
+    if (window) {
+        window.captionChanged.connect(sendActiveWindow);
+        send(window);
+    }
+});
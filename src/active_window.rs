@@ -1,55 +1,276 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use log::info;
-use tokio::sync::{RwLock, mpsc};
+use log::{info, warn};
+use tokio::{
+    process::Command,
+    sync::{RwLock, mpsc, watch},
+};
 use zbus::interface;
 
-use crate::utils;
+use crate::{kwin::KWinScriptManager, utils};
+
+/// How often the wlroots and X11 backends ask for the focused window
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The focused window as reported by the compositor
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActiveWindow {
+    /// Desktop file name on KWin, app id on wlroots and WM_CLASS on X11
+    pub class: String,
+    pub title: String,
+    pub fullscreen: bool,
+}
+
+impl ActiveWindow {
+    /// Whether `app` from config names this window's class, ignoring case
+    pub fn is_app(&self, app: &str) -> bool {
+        !self.class.is_empty() && self.class.eq_ignore_ascii_case(app)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    /// Script loaded into KWin calls back over D-Bus
+    KWin,
+    /// Polls `lswt`, which uses wlr-foreign-toplevel-management
+    Wlr,
+    /// Polls `xprop`
+    X11,
+}
+
+impl Backend {
+    fn detect() -> Option<Self> {
+        let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        if desktop.split(':').any(|desktop| desktop == "KDE") {
+            Some(Backend::KWin)
+        } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(Backend::Wlr)
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Some(Backend::X11)
+        } else {
+            None
+        }
+    }
+}
 
 struct ActiveWindowManager {
-    tx: mpsc::Sender<(String, String, bool)>,
+    tx: mpsc::Sender<ActiveWindow>,
 }
 
 #[interface(name = "ovh.kabus.trayplay.ActiveWindowManager")]
 impl ActiveWindowManager {
     async fn set_active_window(&self, desktop_file: &str, title: &str, fullscreen: bool) {
         self.tx
-            .send((desktop_file.to_string(), title.to_string(), fullscreen))
+            .send(ActiveWindow {
+                class: desktop_file.to_string(),
+                title: title.to_string(),
+                fullscreen,
+            })
             .await
             .unwrap();
     }
 }
 
-pub async fn setup_active_window_manager(
-    app_name: Arc<RwLock<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let (app_name_tx, mut app_name_rx) = mpsc::channel(8);
+/// Reports the focused window using whichever backend fits the running session
+pub struct ActiveWindowWatcher {
+    conn: zbus::Connection,
+    kwin_script_manager: Option<KWinScriptManager<'static>>,
+    rx: watch::Receiver<ActiveWindow>,
+}
 
-    let active_window_manager = ActiveWindowManager { tx: app_name_tx };
+impl ActiveWindowWatcher {
+    pub async fn start() -> Result<Self, Box<dyn std::error::Error>> {
+        let (tx, rx) = watch::channel(ActiveWindow::default());
+        let backend = Backend::detect();
+        info!("Watching the active window with {:?} backend", backend);
 
-    let _conn = zbus::connection::Builder::session()?
-        .name("ovh.kabus.trayplay")?
-        .serve_at("/ovh/kabus/trayplay", active_window_manager)?
-        .build()
-        .await?;
+        // The bus name is also what keeps a second instance from starting, so it's claimed with
+        // every backend
+        let (window_tx, mut window_rx) = mpsc::channel(8);
+        let conn = zbus::connection::Builder::session()?
+            .name("ovh.kabus.trayplay")?
+            .serve_at("/ovh/kabus/trayplay", ActiveWindowManager { tx: window_tx })?
+            .build()
+            .await?;
 
-    tokio::spawn(async move {
-        // Move connection inside tokio task so it doesn't get dropped immediately
-        let _conn = _conn;
+        let mut kwin_script_manager = None;
+        match backend {
+            Some(Backend::KWin) => {
+                tokio::spawn(async move {
+                    while let Some(window) = window_rx.recv().await {
+                        tx.send_replace(window);
+                    }
+                });
+
+                let manager = KWinScriptManager::new().await?;
+                manager.load().await;
+                kwin_script_manager = Some(manager);
+            }
+            Some(Backend::Wlr) => {
+                tokio::spawn(poll(tx, wlr_active_window));
+            }
+            Some(Backend::X11) => {
+                tokio::spawn(poll(tx, x11_active_window));
+            }
+            None => warn!("Cannot detect the active window in this session"),
+        }
+
+        Ok(Self {
+            conn,
+            kwin_script_manager,
+            rx,
+        })
+    }
+
+    /// Connection owning TrayPlay's bus name, other interfaces are served on it too
+    pub fn connection(&self) -> &zbus::Connection {
+        &self.conn
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ActiveWindow> {
+        self.rx.clone()
+    }
+
+    pub async fn stop(&self) {
+        if let Some(kwin_script_manager) = &self.kwin_script_manager {
+            kwin_script_manager.unload().await;
+        }
+    }
+}
 
+/// Keeps `app_name` up to date with the running game or else the fullscreen app
+pub fn track_app_name(
+    mut active_window: watch::Receiver<ActiveWindow>,
+    mut game: watch::Receiver<Option<String>>,
+    app_name: Arc<RwLock<String>>,
+) {
+    tokio::spawn(async move {
         loop {
-            if let Some((desktop_file, title, fullscreen)) = app_name_rx.recv().await {
-                if fullscreen {
-                    let app_name_new = utils::get_app_name(&desktop_file).unwrap().unwrap_or(title);
-                    info!("Current app is now {}", app_name_new);
-                    *app_name.write().await = app_name_new;
-                } else if *app_name.read().await != "unknown" {
-                    info!("Current app is unknown");
-                    *app_name.write().await = "unknown".to_string();
+            let changed = tokio::select! {
+                changed = active_window.changed() => changed,
+                changed = game.changed() => changed,
+            };
+            if changed.is_err() {
+                break;
+            }
+            let window = active_window.borrow_and_update().clone();
+            let game = game.borrow_and_update().clone();
+
+            if let Some(game) = game {
+                if *app_name.read().await != game {
+                    info!("Current app is now {}", game);
+                    *app_name.write().await = game;
                 }
+            } else if window.fullscreen {
+                let app_name_new = utils::get_app_name(&window.class)
+                    .ok()
+                    .flatten()
+                    .unwrap_or(window.title);
+                info!("Current app is now {}", app_name_new);
+                *app_name.write().await = app_name_new;
+            } else if *app_name.read().await != "unknown" {
+                info!("Current app is unknown");
+                *app_name.write().await = "unknown".to_string();
             }
         }
     });
+}
+
+async fn poll<F, Fut>(tx: watch::Sender<ActiveWindow>, query: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Option<ActiveWindow>>,
+{
+    loop {
+        if let Some(window) = query().await {
+            tx.send_if_modified(|current| {
+                let changed = *current != window;
+                *current = window;
+                changed
+            });
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Value of `"key": "..."` or `"key": true` in a flat JSON object. `lswt` output is simple
+/// enough that this avoids pulling in a JSON parser.
+fn json_field<'a>(object: &'a str, key: &str) -> Option<&'a str> {
+    let start = object.find(&format!("\"{}\"", key))? + key.len() + 2;
+    let value = object[start..].trim_start().strip_prefix(':')?.trim_start();
+
+    match value.strip_prefix('"') {
+        Some(string) => {
+            let mut escaped = false;
+            let end = string.char_indices().find_map(|(index, char)| {
+                let end = char == '"' && !escaped;
+                escaped = char == '\\' && !escaped;
+                end.then_some(index)
+            })?;
+            Some(&string[..end])
+        }
+        None => value.split([',', '}']).next().map(str::trim),
+    }
+}
+
+async fn wlr_active_window() -> Option<ActiveWindow> {
+    let output = command_output("lswt", &["-j"]).await?;
+
+    // Every toplevel is an object of its own inside the "toplevels" list
+    output
+        .split('{')
+        .find(|object| json_field(object, "activated") == Some("true"))
+        .map(|object| ActiveWindow {
+            class: json_field(object, "app-id").unwrap_or_default().to_string(),
+            title: json_field(object, "title")
+                .unwrap_or_default()
+                .replace("\\\"", "\""),
+            fullscreen: json_field(object, "fullscreen") == Some("true"),
+        })
+}
+
+async fn x11_active_window() -> Option<ActiveWindow> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let root = command_output("xprop", &["-root", "_NET_ACTIVE_WINDOW"]).await?;
+    let id = root.split_whitespace().last()?.to_string();
+    if id == "0x0" {
+        return Some(ActiveWindow::default());
+    }
+
+    let properties = command_output(
+        "xprop",
+        &["-id", &id, "WM_CLASS", "_NET_WM_NAME", "_NET_WM_STATE"],
+    )
+    .await?;
+
+    let mut window = ActiveWindow::default();
+    for line in properties.lines() {
+        let Some((name, value)) = line.split_once(" = ") else {
+            continue;
+        };
+
+        if name.starts_with("WM_CLASS") {
+            // WM_CLASS(STRING) = "instance", "class"
+            window.class = value
+                .rsplit(", ")
+                .next()
+                .unwrap_or(value)
+                .trim_matches('"')
+                .to_string();
+        } else if name.starts_with("_NET_WM_NAME") {
+            window.title = value.trim_matches('"').to_string();
+        } else if name.starts_with("_NET_WM_STATE") {
+            window.fullscreen = value.contains("_NET_WM_STATE_FULLSCREEN");
+        }
+    }
 
-    Ok(())
+    Some(window)
 }
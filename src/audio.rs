@@ -0,0 +1,368 @@
+use std::{process::Command, sync::Arc, time::Duration};
+
+use log::error;
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{ActionEvent, config::Config};
+
+/// Processes whose streams are muted and turned up or down
+const RECORDER_BINARIES: [&str; 2] = ["gpu-screen-recorder", "wf-recorder"];
+/// Volume streams are recorded at unless configured otherwise, in percent
+pub const DEFAULT_VOLUME: i64 = 100;
+/// How often mute and volume are applied to the streams of restarted recorders
+const LEVEL_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How often the devices and applications offered in the tray menus are listed again
+const CHOICES_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Output device (sink) of PulseAudio or PipeWire's PulseAudio server
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioOutput {
+    pub description: String,
+    /// Source that carries what is played on the device, this is what gets recorded
+    pub monitor: String,
+}
+
+/// Input device (source) like a microphone
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInput {
+    pub description: String,
+    pub name: String,
+}
+
+/// What the audio menus of the tray offer to pick
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AudioChoices {
+    pub outputs: Vec<AudioOutput>,
+    pub inputs: Vec<AudioInput>,
+    pub playing_apps: Vec<String>,
+}
+
+impl AudioChoices {
+    fn list() -> Self {
+        Self {
+            outputs: output_devices(),
+            inputs: input_devices(),
+            playing_apps: playing_apps(),
+        }
+    }
+}
+
+/// Lists the audio devices and playing applications every few seconds, so building the tray menu never waits for `pactl`.
+/// Changes are reported with [`ActionEvent::AudioChoicesChanged`].
+pub fn setup_choices_watcher(action_tx: Sender<ActionEvent>) {
+    tokio::spawn(async move {
+        let mut last = AudioChoices::default();
+
+        loop {
+            if let Ok(choices) = tokio::task::spawn_blocking(AudioChoices::list).await
+                && choices != last
+            {
+                last = choices.clone();
+                if action_tx
+                    .send(ActionEvent::AudioChoicesChanged(choices))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(CHOICES_POLL_INTERVAL).await;
+        }
+    });
+}
+
+/// Entries of `pactl list <kind>`, empty if it isn't available
+fn pactl_list(kind: &str, header: &str) -> Vec<String> {
+    // Field names are translated otherwise
+    let Ok(output) = Command::new("pactl")
+        .args(["list", kind])
+        .env("LC_ALL", "C")
+        .output()
+    else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .split(header)
+        .skip(1)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Value of a `Name: value` line of a `pactl list` entry
+fn field(entry: &str, name: &str) -> Option<String> {
+    entry
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(name)?.strip_prefix(": "))
+        .map(str::to_string)
+}
+
+/// Value of a `name = "value"` line in the properties of a `pactl list` entry
+fn property(entry: &str, name: &str) -> Option<String> {
+    entry.lines().find_map(|line| {
+        let value = line.trim().strip_prefix(name)?.strip_prefix(" = ")?;
+        Some(value.trim_matches('"').to_string())
+    })
+}
+
+/// Output devices reported by `pactl`, empty if it isn't available
+pub fn output_devices() -> Vec<AudioOutput> {
+    // Sink #47
+    //     Name: alsa_output.pci-0000_00_1f.3.analog-stereo
+    //     Description: Built-in Audio Analog Stereo
+    //     Monitor Source: alsa_output.pci-0000_00_1f.3.analog-stereo.monitor
+    pactl_list("sinks", "Sink #")
+        .iter()
+        .filter_map(|sink| {
+            let monitor = field(sink, "Monitor Source")?;
+            Some(AudioOutput {
+                description: field(sink, "Description").unwrap_or(monitor.clone()),
+                monitor,
+            })
+        })
+        .collect()
+}
+
+/// Input devices reported by `pactl`, without the monitors of output devices
+pub fn input_devices() -> Vec<AudioInput> {
+    // Source #48
+    //     Name: alsa_input.usb-Blue_Microphones_Yeti-00.analog-stereo
+    //     Description: Yeti Stereo Microphone Analog Stereo
+    //     Monitor of Sink: n/a
+    pactl_list("sources", "Source #")
+        .iter()
+        .filter(|source| field(source, "Monitor of Sink").is_none_or(|sink| sink == "n/a"))
+        .filter_map(|source| {
+            let name = field(source, "Name")?;
+            Some(AudioInput {
+                description: field(source, "Description").unwrap_or(name.clone()),
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Names of the applications playing audio right now, as gpu-screen-recorder's `app:` tracks
+/// match them
+pub fn playing_apps() -> Vec<String> {
+    // Sink Input #112
+    //     Properties:
+    //         application.name = "Firefox"
+    let mut apps = pactl_list("sink-inputs", "Sink Input #")
+        .iter()
+        .filter_map(|input| property(input, "application.name"))
+        .collect::<Vec<_>>();
+    apps.sort();
+    apps.dedup();
+
+    apps
+}
+
+/// Case-insensitive match of `name` against `pattern`, where `*` matches any text
+fn matches_app_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    for (index, part) in parts.iter().enumerate() {
+        if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+/// Application names for `patterns`: patterns with `*` are expanded to the matching
+/// applications playing right now, other names are kept as they are
+pub fn app_names(patterns: &[String]) -> Vec<String> {
+    let playing = if patterns.iter().any(|pattern| pattern.contains('*')) {
+        playing_apps()
+    } else {
+        Vec::new()
+    };
+
+    let mut names = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains('*') {
+            names.push(pattern.clone());
+            continue;
+        }
+        names.extend(
+            playing
+                .iter()
+                .filter(|app| matches_app_pattern(pattern, app))
+                .cloned(),
+        );
+    }
+    names.sort();
+    names.dedup();
+
+    names
+}
+
+/// Audio the recorders capture, muted separately from the tray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CapturedAudio {
+    /// Whatever is played, recorded from the monitors of output devices
+    Desktop,
+    /// Input devices
+    Microphone,
+}
+
+impl CapturedAudio {
+    pub fn name(self) -> &'static str {
+        match self {
+            CapturedAudio::Desktop => "desktop audio",
+            CapturedAudio::Microphone => "the microphone",
+        }
+    }
+
+    /// Whether `mute_desktop_audio` or `mute_microphone` is on
+    pub fn is_muted(self, config: &Config) -> bool {
+        match self {
+            CapturedAudio::Desktop => config.mute_desktop_audio,
+            CapturedAudio::Microphone => config.mute_microphone,
+        }
+    }
+
+    /// `desktop_audio_volume` or `microphone_volume`, in percent
+    pub fn volume(self, config: &Config) -> i64 {
+        match self {
+            CapturedAudio::Desktop => config.desktop_audio_volume,
+            CapturedAudio::Microphone => config.microphone_volume,
+        }
+    }
+}
+
+/// `pactl list source-outputs` entries of the recorders' streams capturing `audio`
+fn recorder_streams(audio: CapturedAudio) -> Vec<String> {
+    // Source #48
+    //     Monitor of Sink: n/a
+    let sources = pactl_list("sources", "Source #")
+        .iter()
+        .filter(|source| {
+            let monitor = field(source, "Monitor of Sink").is_some_and(|sink| sink != "n/a");
+            monitor == (audio == CapturedAudio::Desktop)
+        })
+        .filter_map(|source| source.lines().next().map(|index| index.trim().to_string()))
+        .collect::<Vec<_>>();
+
+    // Source Output #130
+    //     Source: 48
+    //     Mute: no
+    //     Volume: front-left: 65536 / 100% / 0.00 dB,   front-right: 65536 / 100% / 0.00 dB
+    //     Properties:
+    //         application.process.binary = "gpu-screen-recorder"
+    pactl_list("source-outputs", "Source Output #")
+        .into_iter()
+        .filter(|output| {
+            property(output, "application.process.binary")
+                .is_some_and(|binary| RECORDER_BINARIES.contains(&binary.as_str()))
+        })
+        .filter(|output| field(output, "Source").is_some_and(|source| sources.contains(&source)))
+        .collect()
+}
+
+/// Runs `pactl <command> <stream index> <value>` for each of `streams`
+fn set_streams(streams: &[String], command: &str, value: &str) -> Result<usize, String> {
+    for stream in streams {
+        let index = stream.lines().next().unwrap_or_default().trim();
+        let status = Command::new("pactl")
+            .args([command, index, value])
+            .status()
+            .map_err(|err| format!("cannot run pactl: {}", err))?;
+        if !status.success() {
+            return Err(format!("pactl exited with {}", status));
+        }
+    }
+
+    Ok(streams.len())
+}
+
+/// Mutes or unmutes what the recorders capture of `audio`. Only their streams are muted, the
+/// devices keep working in other apps. Returns how many streams were changed.
+pub fn set_recorder_audio_muted(audio: CapturedAudio, muted: bool) -> Result<usize, String> {
+    let mute = if muted { "yes" } else { "no" };
+    let streams = recorder_streams(audio)
+        .into_iter()
+        .filter(|stream| field(stream, "Mute").as_deref() != Some(mute))
+        .collect::<Vec<_>>();
+
+    set_streams(
+        &streams,
+        "set-source-output-mute",
+        if muted { "1" } else { "0" },
+    )
+}
+
+/// Sets the volume the recorders capture `audio` at, in percent. The devices keep their volume
+/// in other apps. Returns how many streams were changed.
+pub fn set_recorder_audio_volume(audio: CapturedAudio, percent: i64) -> Result<usize, String> {
+    let volume = format!("{}%", percent.max(0));
+    let streams = recorder_streams(audio)
+        .into_iter()
+        .filter(|stream| {
+            // Every channel gets the same volume, checking the first one is enough
+            let current = field(stream, "Volume").and_then(|volume| {
+                volume
+                    .split('/')
+                    .nth(1)
+                    .map(|percent| percent.trim().to_string())
+            });
+            current.as_ref() != Some(&volume)
+        })
+        .collect::<Vec<_>>();
+
+    set_streams(&streams, "set-source-output-volume", &volume)
+}
+
+/// Applies `mute_desktop_audio`, `mute_microphone` and the volumes to the streams of recorders as
+/// they start, which can't be told to do it themselves
+pub fn setup_audio_levels(config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        // Errors pop up a dialog, so they're only reported once until it works again
+        let mut failing = false;
+
+        loop {
+            let levels = {
+                let config = config.read().await;
+                [CapturedAudio::Desktop, CapturedAudio::Microphone]
+                    .map(|audio| (audio, audio.is_muted(&config), audio.volume(&config)))
+            };
+            for (audio, muted, volume) in levels {
+                if !muted && volume == DEFAULT_VOLUME {
+                    continue;
+                }
+                let result = tokio::task::spawn_blocking(move || {
+                    if muted {
+                        set_recorder_audio_muted(audio, true)
+                    } else {
+                        set_recorder_audio_volume(audio, volume)
+                    }
+                })
+                .await;
+                match result {
+                    Ok(Err(err)) if !failing => {
+                        error!("Cannot change the level of {}: {}", audio.name(), err);
+                        failing = true;
+                    }
+                    Ok(Ok(_)) => failing = false,
+                    _ => {}
+                }
+            }
+
+            tokio::time::sleep(LEVEL_CHECK_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use ashpd::desktop::background::Background;
+use log::{info, warn};
+
+const DESKTOP_FILE_NAME: &str = "ovh.kabus.trayplay.desktop";
+
+fn autostart_file_path() -> PathBuf {
+    let mut path = dirs::config_dir().unwrap();
+    path.push("autostart");
+    path.push(DESKTOP_FILE_NAME);
+    path
+}
+
+/// Enables or disables starting TrayPlay on login. Returns whether autostart ended up enabled,
+/// which may differ from `enabled` if the user denied or revoked the permission.
+pub async fn set_autostart(enabled: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    if ashpd::is_sandboxed().await {
+        // Flatpak apps can't write autostart files on the host, the Background portal does it for us
+        let response = Background::request()
+            .auto_start(enabled)
+            .command(["trayplay"])
+            .reason("Start recording replays when you log in")
+            .send()
+            .await?
+            .response()?;
+
+        if enabled && !response.auto_start() {
+            warn!(
+                "Permission to start TrayPlay on login was denied. You can allow it in System Settings > Applications."
+            );
+        }
+
+        return Ok(response.auto_start());
+    }
+
+    let path = autostart_file_path();
+    if enabled {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let system_desktop_file = PathBuf::from("/usr/share/applications").join(DESKTOP_FILE_NAME);
+        if std::fs::exists(&system_desktop_file)? {
+            std::fs::copy(system_desktop_file, &path)?;
+        } else {
+            std::fs::write(
+                &path,
+                "[Desktop Entry]\nType=Application\nName=TrayPlay\nExec=trayplay\nIcon=media-skip-backward\n",
+            )?;
+        }
+        info!("Created autostart entry at {}", path.display());
+    } else if std::fs::exists(&path)? {
+        std::fs::remove_file(&path)?;
+        info!("Removed autostart entry at {}", path.display());
+    }
+
+    Ok(enabled)
+}
+
+/// Checks that autostart is still in place, e.g. the user didn't revoke it in system settings
+/// or delete the autostart file. Returns the actual state.
+pub async fn verify_autostart() -> Result<bool, Box<dyn std::error::Error>> {
+    if ashpd::is_sandboxed().await {
+        set_autostart(true).await
+    } else {
+        Ok(std::fs::exists(autostart_file_path())?)
+    }
+}
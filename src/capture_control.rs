@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures_util::StreamExt;
+use log::info;
+use tokio::{
+    sync::{Mutex, mpsc::Sender},
+    task::JoinHandle,
+};
+use zbus::{
+    Connection,
+    fdo::{DBusProxy, NameOwnerChangedStream},
+    interface,
+    message::Header,
+    names::BusName,
+};
+
+use crate::ActionEvent;
+
+/// Lets other apps (password prompts, screen-sharing tools...) pause the replay buffer. Each
+/// client's pause lasts until it calls ResumeCapture or disconnects from the bus.
+struct CaptureControl {
+    action_tx: Sender<ActionEvent>,
+    /// Tasks waiting for a pausing client to disconnect
+    watchers: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+}
+
+#[interface(name = "ovh.kabus.trayplay.Capture")]
+impl CaptureControl {
+    async fn pause_capture(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        reason: &str,
+    ) -> zbus::fdo::Result<()> {
+        let client = client_name(&header)?;
+        info!("{} asked to pause capture: {}", client, reason);
+
+        // Held until the pause is sent, so a disconnect is always handled after it
+        let mut watchers = self.watchers.lock().await;
+        if !watchers.contains_key(&client) {
+            let proxy = DBusProxy::new(connection).await?;
+            let changes = proxy
+                .receive_name_owner_changed_with_args(&[(0, client.as_str())])
+                .await?;
+            // Watched first, so the client can't disconnect unseen in between
+            let name = BusName::try_from(client.as_str())
+                .map_err(|err| zbus::fdo::Error::InvalidArgs(err.to_string()))?;
+            if !proxy.name_has_owner(name).await? {
+                return Err(zbus::fdo::Error::NameHasNoOwner(format!(
+                    "{} disconnected",
+                    client
+                )));
+            }
+
+            let watcher = tokio::spawn(resume_on_disconnect(
+                changes,
+                client.clone(),
+                self.action_tx.clone(),
+                self.watchers.clone(),
+            ));
+            watchers.insert(client.clone(), watcher);
+        }
+
+        self.action_tx
+            .send(ActionEvent::PauseCapture(client, reason.to_string()))
+            .await
+            .map_err(|_| quitting())
+    }
+
+    async fn resume_capture(&self, #[zbus(header)] header: Header<'_>) -> zbus::fdo::Result<()> {
+        let client = client_name(&header)?;
+        info!("{} asked to resume capture", client);
+
+        let mut watchers = self.watchers.lock().await;
+        if let Some(watcher) = watchers.remove(&client) {
+            watcher.abort();
+        }
+        self.action_tx
+            .send(ActionEvent::ResumeCapture(client))
+            .await
+            .map_err(|_| quitting())
+    }
+}
+
+fn client_name(header: &Header<'_>) -> zbus::fdo::Result<String> {
+    header
+        .sender()
+        .map(|sender| sender.to_string())
+        .ok_or_else(|| zbus::fdo::Error::InvalidArgs("Unknown sender".to_string()))
+}
+
+/// Error for calls that arrive while TrayPlay is quitting and no longer handles actions
+fn quitting() -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed("TrayPlay is quitting".to_string())
+}
+
+/// Lifts the pause of a client that quit or crashed without resuming
+async fn resume_on_disconnect(
+    mut changes: NameOwnerChangedStream,
+    client: String,
+    action_tx: Sender<ActionEvent>,
+    watchers: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+) {
+    while let Some(change) = changes.next().await {
+        if change.args().is_ok_and(|args| args.new_owner().is_none()) {
+            info!("{} disconnected without resuming capture", client);
+            let mut watchers = watchers.lock().await;
+            watchers.remove(&client);
+            let _ = action_tx.send(ActionEvent::ResumeCapture(client)).await;
+            return;
+        }
+    }
+}
+
+/// Serves the Capture interface next to the active window one, on the connection owning
+/// TrayPlay's bus name
+pub async fn serve(
+    connection: &Connection,
+    action_tx: Sender<ActionEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    connection
+        .object_server()
+        .at(
+            "/ovh/kabus/trayplay",
+            CaptureControl {
+                action_tx,
+                watchers: Arc::new(Mutex::new(HashMap::new())),
+            },
+        )
+        .await?;
+
+    Ok(())
+}
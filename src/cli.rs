@@ -0,0 +1,402 @@
+use std::{
+    error::Error,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    config::Config,
+    config_preview, gsr, monitors,
+    notes::{self, NotesExport},
+    watch_later,
+};
+
+pub const USAGE: &str = "Usage:
+  trayplay                     Run the tray app
+  trayplay record [OPTIONS]    Record the screen once and exit
+  trayplay list [OPTIONS]      List saved clips with their notes
+  trayplay events [--follow]   Print recent events of the running tray app as JSON lines,
+                               and with --follow every new one as it happens
+  trayplay delete [OPTIONS] <CLIP>
+                               Delete a clip with its note, exported copies and index entry
+  trayplay watch-later [--remove] [CLIP]
+                               Queue a clip to watch later, or take it off with --remove.
+                               Without a clip, list the queue
+  trayplay config set [--dry-run] <KEY> <VALUE>
+                               Change a setting (dotted for nested ones, e.g. webcam.enabled)
+                               and apply it to the running tray app
+  trayplay export-notes <FILE> Save the notes and favorites of all clips to a JSON file
+  trayplay import-notes <FILE> Restore notes and favorites saved with export-notes
+
+Record options:
+  --duration <SECONDS>   Stop after the given number of seconds (default: until Ctrl+C)
+  --output <FILE>        Output file, container is picked from its extension
+                         (default: new file in the replay directory)
+
+List options:
+  --search <TEXT>        Only clips whose path or note contains the text
+
+Delete options:
+  --yes                  Don't ask for confirmation
+
+Config set options:
+  --dry-run              Only show the recorder command line the change results in and
+                         whether it restarts the recorder";
+
+pub enum CliCommand {
+    Tray,
+    Help,
+    Record {
+        duration: Option<Duration>,
+        output: Option<PathBuf>,
+    },
+    List {
+        search: Option<String>,
+    },
+    Delete {
+        clip: PathBuf,
+        yes: bool,
+    },
+    Events {
+        follow: bool,
+    },
+    WatchLater {
+        clip: Option<PathBuf>,
+        remove: bool,
+    },
+    ConfigSet {
+        key: String,
+        value: String,
+        dry_run: bool,
+    },
+    ExportNotes(PathBuf),
+    ImportNotes(PathBuf),
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<CliCommand, String> {
+    match args.next().as_deref() {
+        None => Ok(CliCommand::Tray),
+        Some("-h" | "--help" | "help") => Ok(CliCommand::Help),
+        Some("record") => {
+            let mut duration = None;
+            let mut output = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--duration" | "-d" => {
+                        let value = args.next().ok_or("--duration requires a value")?;
+                        let secs = value
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid duration: {}", value))?;
+                        duration = Some(Duration::from_secs(secs));
+                    }
+                    "--output" | "-o" => {
+                        output = Some(PathBuf::from(
+                            args.next().ok_or("--output requires a value")?,
+                        ));
+                    }
+                    other => return Err(format!("Unknown option: {}", other)),
+                }
+            }
+
+            Ok(CliCommand::Record { duration, output })
+        }
+        Some("list") => {
+            let mut search = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--search" | "-s" => {
+                        search = Some(args.next().ok_or("--search requires a value")?);
+                    }
+                    other => return Err(format!("Unknown option: {}", other)),
+                }
+            }
+
+            Ok(CliCommand::List { search })
+        }
+        Some("events") => {
+            let mut follow = false;
+
+            for arg in args {
+                match arg.as_str() {
+                    "--follow" | "-f" => follow = true,
+                    other => return Err(format!("Unknown option: {}", other)),
+                }
+            }
+
+            Ok(CliCommand::Events { follow })
+        }
+        Some("delete") => {
+            let mut clip = None;
+            let mut yes = false;
+
+            for arg in args {
+                match arg.as_str() {
+                    "--yes" | "-y" => yes = true,
+                    other if other.starts_with('-') => {
+                        return Err(format!("Unknown option: {}", other));
+                    }
+                    other => clip = Some(PathBuf::from(other)),
+                }
+            }
+
+            Ok(CliCommand::Delete {
+                clip: clip.ok_or("delete requires the clip to delete")?,
+                yes,
+            })
+        }
+        Some("watch-later") => {
+            let mut clip = None;
+            let mut remove = false;
+
+            for arg in args {
+                match arg.as_str() {
+                    "--remove" | "-r" => remove = true,
+                    other if other.starts_with('-') => {
+                        return Err(format!("Unknown option: {}", other));
+                    }
+                    other => clip = Some(PathBuf::from(other)),
+                }
+            }
+            if remove && clip.is_none() {
+                return Err("--remove requires the clip to take off the queue".to_string());
+            }
+
+            Ok(CliCommand::WatchLater { clip, remove })
+        }
+        Some("config") => {
+            if args.next().as_deref() != Some("set") {
+                return Err("config requires a subcommand: set".to_string());
+            }
+            let mut dry_run = false;
+            let mut positional = Vec::new();
+
+            for arg in args {
+                match arg.as_str() {
+                    "--dry-run" | "-n" => dry_run = true,
+                    other if other.starts_with("--") => {
+                        return Err(format!("Unknown option: {}", other));
+                    }
+                    _ => positional.push(arg),
+                }
+            }
+
+            match <[String; 2]>::try_from(positional) {
+                Ok([key, value]) => Ok(CliCommand::ConfigSet {
+                    key,
+                    value,
+                    dry_run,
+                }),
+                Err(_) => Err("config set requires a key and a value".to_string()),
+            }
+        }
+        Some(command @ ("export-notes" | "import-notes")) => {
+            let file = PathBuf::from(
+                args.next()
+                    .ok_or(format!("{} requires the file to use", command))?,
+            );
+            if let Some(other) = args.next() {
+                return Err(format!("Unknown option: {}", other));
+            }
+
+            Ok(match command {
+                "export-notes" => CliCommand::ExportNotes(file),
+                _ => CliCommand::ImportNotes(file),
+            })
+        }
+        Some(other) => Err(format!("Unknown command: {}", other)),
+    }
+}
+
+pub async fn record(
+    duration: Option<Duration>,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    // Nothing listens for config events outside of the tray app
+    let (action_tx, _action_rx) = mpsc::channel(1);
+    let mut config = Config::load(action_tx).await;
+    monitors::resolve_screen(&mut config).map_err(|err| err.to_string())?;
+    monitors::update_display_framerate(&mut config);
+
+    let output = output.unwrap_or_else(|| {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        config.replay_dir().join(format!(
+            "Recording_{}.{}",
+            timestamp,
+            config.container.to_string()
+        ))
+    });
+
+    println!("Recording to {}", output.display());
+    gsr::record(&config, &output, duration).await?;
+    println!("Saved {}", output.display());
+
+    Ok(())
+}
+
+pub async fn list(search: Option<String>) -> Result<(), Box<dyn Error>> {
+    let (action_tx, _action_rx) = mpsc::channel(1);
+    let config = Config::load(action_tx).await;
+    let search = search.map(|search| search.to_lowercase());
+    let queued = watch_later::queued();
+
+    for clip in notes::list_clips(&config.replay_dir()) {
+        let note = notes::read_note(&clip);
+        if let Some(search) = &search {
+            let matches = |text: &str| text.to_lowercase().contains(search);
+            if !matches(&clip.to_string_lossy()) && !note.as_deref().is_some_and(matches) {
+                continue;
+            }
+        }
+
+        if queued.contains(&clip) {
+            println!("{} (watch later)", clip.display());
+        } else {
+            println!("{}", clip.display());
+        }
+        if let Some(note) = note {
+            println!("    {}", note);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn watch_later(clip: Option<PathBuf>, remove: bool) -> Result<(), Box<dyn Error>> {
+    let Some(clip) = clip else {
+        for clip in watch_later::queued() {
+            println!("{}", clip.display());
+        }
+        return Ok(());
+    };
+
+    // Queued by absolute path, like the tray app does
+    let clip = std::path::absolute(&clip)?;
+    if remove {
+        if !watch_later::remove(&clip) {
+            return Err(format!("{} is not queued", clip.display()).into());
+        }
+    } else {
+        if !clip.is_file() {
+            return Err(format!("{} does not exist", clip.display()).into());
+        }
+        if !watch_later::add(&clip) {
+            println!("{} is already queued", clip.display());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn delete(clip: PathBuf, yes: bool) -> Result<(), Box<dyn Error>> {
+    if !clip.is_file() {
+        return Err(format!("{} does not exist", clip.display()).into());
+    }
+    if !yes {
+        print!(
+            "Delete {} along with its note and exported copies? [y/N] ",
+            clip.display()
+        );
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(());
+        }
+    }
+
+    for path in notes::delete_clip(&clip)? {
+        println!("Deleted {}", path.display());
+    }
+
+    Ok(())
+}
+
+pub async fn config_set(key: String, value: String, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let (action_tx, _action_rx) = mpsc::channel(1);
+    let config = Config::load(action_tx).await;
+
+    let changed = config.with_value(&key, &value)?;
+    let preview = config_preview::preview(&config, &changed);
+    if dry_run {
+        println!("{}", preview);
+        return Ok(());
+    }
+    if let Some(problem) = preview.problem {
+        return Err(format!("not saved, the recorder cannot start with it: {}", problem).into());
+    }
+    println!("{}", preview);
+
+    changed.write_file();
+    let running = running_tray_apps();
+    for pid in &running {
+        signal::kill(*pid, Signal::SIGHUP)?;
+    }
+    if running.is_empty() {
+        println!("Saved, TrayPlay uses it when it starts");
+    } else {
+        println!("Saved and applied to the running TrayPlay");
+    }
+
+    Ok(())
+}
+
+/// Other TrayPlay processes, the tray app reloads its config on SIGHUP
+fn running_tray_apps() -> Vec<Pid> {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    processes
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_string_lossy().parse::<i32>().ok()?;
+            let comm = std::fs::read_to_string(entry.path().join("comm")).ok()?;
+            (comm.trim() == "trayplay" && pid as u32 != std::process::id())
+                .then_some(Pid::from_raw(pid))
+        })
+        .collect()
+}
+
+pub async fn export_notes(file: PathBuf) -> Result<(), Box<dyn Error>> {
+    let (action_tx, _action_rx) = mpsc::channel(1);
+    let config = Config::load(action_tx).await;
+
+    let export = notes::export_notes(&config.replay_dir());
+    std::fs::write(&file, serde_json::to_string_pretty(&export)?)?;
+    println!(
+        "Saved the notes and favorites of {} clips to {}",
+        export.clips.len(),
+        file.display()
+    );
+
+    Ok(())
+}
+
+pub async fn import_notes(file: PathBuf) -> Result<(), Box<dyn Error>> {
+    let (action_tx, _action_rx) = mpsc::channel(1);
+    let config = Config::load(action_tx).await;
+
+    let export: NotesExport = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+    let result = notes::import_notes(&config.replay_dir(), &export)?;
+    for missing in &result.missing {
+        println!("Not found: {}", missing.display());
+    }
+    println!(
+        "Restored the notes and favorites of {} clips",
+        result.imported
+    );
+
+    Ok(())
+}
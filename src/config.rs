@@ -1,81 +1,1511 @@
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::sync::mpsc::Sender;
+use toml::{Table, Value};
 
-use crate::ActionEvent;
+use crate::{
+    ActionEvent, audio, denoise, gsr_gtk,
+    utils::{VIDEOS_DIR_PLACEHOLDER, resolve_path},
+};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Config {
+    /// Capture target, also accepted as `capture_source`
+    #[serde(alias = "capture_source")]
     pub screen: String,
+    /// EDID serial of the monitor in `screen`, used to find it again on another connector
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_serial: Option<String>,
+    /// Recorded area as WxH+X+Y when `screen` is "region"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Record whichever window has focus. `screen` is kept for when this is turned off.
+    #[serde(default)]
+    pub follow_focus: bool,
+    /// Record HDR with gpu-screen-recorder's *_hdr codecs, where supported
+    #[serde(default)]
+    pub hdr: bool,
+    /// Overridden by a quality preset's `color_range`
+    #[serde(default)]
+    pub color_range: ColorRange,
+    #[serde(default)]
+    pub bit_depth: BitDepth,
+    #[serde(default)]
+    pub recorder_backend: RecorderBackend,
+    #[serde(default)]
+    pub encoder: Encoder,
+    /// Appended to every gpu-screen-recorder command line, for flags TrayPlay has no setting for
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    #[serde(default)]
+    pub excluded_monitors: Vec<String>,
     pub container: Container,
     pub audio_tracks: Vec<String>,
+    /// Monitor source of the output device recorded in place of "default_output" (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_output: Option<String>,
+    /// Microphone recorded in place of "default_input" (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub microphone: Option<String>,
+    /// Mix all audio tracks, the microphone included, into a single track instead of recording
+    /// one track each
+    #[serde(default)]
+    pub mix_audio_tracks: bool,
+    /// Which applications are recorded in place of the output device
+    #[serde(default)]
+    pub app_audio_mode: AppAudioMode,
+    /// Application names for `app_audio_mode`, `*` matches any text
+    #[serde(default)]
+    pub audio_apps: Vec<String>,
+    #[serde(default = "default_true")]
+    pub include_microphone: bool,
+    /// Filter noise out of the microphone with RNNoise, through PipeWire
+    #[serde(default)]
+    pub noise_suppression: bool,
+    /// Silence the microphone track without restarting the recorder
+    #[serde(default)]
+    pub mute_microphone: bool,
+    /// Silence desktop audio without restarting the recorder
+    #[serde(default)]
+    pub mute_desktop_audio: bool,
+    /// Volume desktop audio is recorded at, in percent
+    #[serde(default = "default_volume")]
+    pub desktop_audio_volume: i64,
+    /// Volume the microphone is recorded at, in percent
+    #[serde(default = "default_volume")]
+    pub microphone_volume: i64,
+    /// 0 matches the refresh rate of the recorded monitor
     pub framerate: i64,
+    /// Largest output resolution as WxH, bigger captures are scaled down keeping their aspect
+    /// ratio (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_resolution: Option<String>,
     pub clear_buffer_on_save: bool,
+    /// Keep gpu-screen-recorder running while the replay buffer is turned off, so turning it
+    /// back on is instant
+    #[serde(default)]
+    pub warm_standby: bool,
+    /// Keep the buffer across restarts of the recorder and of TrayPlay, only possible with the
+    /// segment based backends
+    #[serde(default)]
+    pub persist_buffer: bool,
+    /// Save gpu-screen-recorder's buffer before a settings change restarts it, which would
+    /// discard it
+    #[serde(default)]
+    pub save_before_restart: bool,
+    /// Capture monitors through the ScreenCast portal while gsr-kms-server lacks the capability
+    /// to capture them directly
+    #[serde(default = "default_true")]
+    pub portal_without_kms_permission: bool,
+    /// Offer to grant gsr-kms-server the capability on start
+    #[serde(default = "default_true")]
+    pub ask_for_kms_permission: bool,
     pub quality: Quality,
+    /// Explicit rate control used instead of `quality` and quality presets (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_control: Option<RateControl>,
+    /// Name of the quality preset used instead of `quality` (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality_preset: Option<String>,
+    #[serde(default)]
+    pub quality_presets: Vec<QualityPreset>,
     pub replay_directory: PathBuf,
+    /// Where files are written before they're complete (optional), next to the replays otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub work_directory: Option<PathBuf>,
     pub replay_duration_secs: i64,
+    #[serde(default)]
+    pub replay_storage: ReplayStorage,
+    #[serde(default)]
+    pub autostart: bool,
+    #[serde(default)]
+    pub pause_during_calls: bool,
+    #[serde(default)]
+    pub private_apps: Vec<String>,
+    #[serde(default)]
+    pub game_sessions: GameSessions,
+    #[serde(default)]
+    pub gamescope_hotkey: GamescopeHotkey,
+    #[serde(default = "default_true")]
+    pub lower_quality_on_overload: bool,
+    /// Seconds between keyframes, where saved replays can start and players can seek to
+    #[serde(default = "default_keyframe_interval")]
+    pub keyframe_interval_secs: f64,
+    /// GPU that captures and encodes, as card path (/dev/dri/card1) or vendor (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<String>,
+    #[serde(default = "default_true")]
+    pub record_cursor: bool,
+    #[serde(default)]
+    pub trim_dead_air: bool,
+    #[serde(default)]
+    pub normalize_loudness: bool,
+    /// Extra ffmpeg output options for saved replays, keyed by container extension
+    #[serde(default)]
+    pub muxer_options: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub vertical_export: VerticalExport,
+    #[serde(default)]
+    pub share_copy: ShareCopy,
+    /// Buttons of the notification shown when a replay is saved, an OSD is shown without any
+    #[serde(default)]
+    pub notification_actions: Vec<ReplayAction>,
+    /// Command the edit action opens replays with, e.g. "kdenlive"
+    #[serde(default)]
+    pub video_editor: Option<String>,
+    #[serde(default)]
+    pub webcam: Webcam,
+    #[serde(default)]
+    pub streaming: Streaming,
+    #[serde(default)]
+    pub boost: Boost,
+    #[serde(default)]
+    pub thermal_throttling: ThermalThrottling,
+    #[serde(default)]
+    pub systemd_scope: SystemdScope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub menu_layout: Option<Vec<MenuEntry>>,
+    #[serde(default)]
+    pub active_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    #[serde(default)]
+    pub power_profiles: PowerProfiles,
+    #[serde(default)]
+    pub recorders: Vec<RecorderConfig>,
+    #[serde(default)]
+    pub custom_actions: Vec<CustomAction>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    #[serde(default)]
+    pub save_cooldowns: SaveCooldowns,
 
     #[serde(skip, default = "Option::default")]
-    action_event_tx: Option<Sender<ActionEvent>>,
+    action_event_tx: Option<EventSender>,
+    #[serde(skip)]
+    system: SystemConfig,
+    /// Refresh rate of the recorded monitor, what a `framerate` of 0 records at
+    #[serde(skip)]
+    pub display_framerate: Option<i64>,
+    /// Settings as the user chose them while they are temporarily lowered or raised, what gets
+    /// written to the config file in their place
+    #[serde(skip)]
+    chosen_settings: Option<SettingsSnapshot>,
+}
+
+/// Channel to the main loop. Two configs holding it are equal when it leads to the same loop, so
+/// comparing configs only looks at the settings.
+#[derive(Clone)]
+struct EventSender(Sender<ActionEvent>);
+
+impl PartialEq for EventSender {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_channel(&other.0)
+    }
 }
 
 impl Config {
+    /// Loads the user config layered over the system-wide config and built-in defaults.
+    /// Keys locked in the system config always take the system value.
     pub async fn load(action_event_tx: Sender<ActionEvent>) -> Self {
-        let mut path = dirs::config_dir().unwrap();
-        path.push("trayplay.toml");
+        let user_exists = config_path().exists();
+        let mut config = Self::read(action_event_tx)
+            .unwrap_or_else(|err| panic!("Cannot parse config file: {}", err));
+
+        if !user_exists {
+            println!("Config missing. Replacing with defaults");
+            // Carry over settings from gpu-screen-recorder's own GUI if the user migrates from it
+            if gsr_gtk::import_settings(&mut config) {
+                config.enforce_locks();
+            }
+            config.write_file();
+        }
+
+        config
+    }
+
+    /// Reads the config like [`Config::load`], without writing one when the user has none
+    pub fn read(action_event_tx: Sender<ActionEvent>) -> Result<Self, String> {
+        let system = SystemConfig::load();
+        let user: Option<Table> = match std::fs::read_to_string(config_path()) {
+            Ok(config) => Some(toml::from_str(&config).map_err(|err| err.to_string())?),
+            Err(_) => None,
+        };
+
+        let mut table = Table::try_from(Config::default()).unwrap();
+        merge_tables(&mut table, system.defaults.clone());
+        if let Some(user) = user {
+            merge_tables(&mut table, user);
+        }
+        merge_tables(&mut table, system.locked_values());
+
+        let mut config: Self = table.try_into().map_err(|err| err.to_string())?;
+        if let Some(problem) = config
+            .rate_control
+            .and_then(|rate_control| rate_control.problem())
+        {
+            return Err(format!("rate_control: {}", problem));
+        }
+        if let Some(problem) = config.recorders.iter().find_map(RecorderConfig::problem) {
+            return Err(format!("recorders: {}", problem));
+        }
+        config.action_event_tx = Some(EventSender(action_event_tx));
+        config.system = system;
+
+        Ok(config)
+    }
+
+    /// Copy of the config with `key` (dotted for nested keys, e.g. `webcam.enabled`) set to
+    /// `value`. The value is read as TOML, or taken as a string if it isn't valid TOML.
+    pub fn with_value(&self, key: &str, value: &str) -> Result<Config, String> {
+        if self.is_locked(key) {
+            return Err(format!("{} is locked by the system-wide config", key));
+        }
+        let value = toml::from_str::<Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| Value::String(value.to_string()));
+
+        let mut table = Table::try_from(self).unwrap();
+        let mut parts: Vec<&str> = key.split('.').map(str::trim).collect();
+        let last = parts
+            .pop()
+            .filter(|last| !last.is_empty())
+            .ok_or("No key given")?;
+        let mut parent = &mut table;
+        for part in parts {
+            parent = match parent
+                .entry(part)
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(table) => table,
+                _ => return Err(format!("{} has no settings inside it", part)),
+            };
+        }
+        parent.insert(last.to_string(), value.clone());
+
+        let mut config: Config = table
+            .try_into()
+            .map_err(|err| format!("Invalid value for {}: {}", key, err))?;
+        // Unknown keys are dropped when reading, so they don't come back
+        let written = Value::Table(Table::try_from(&config).unwrap());
+        let kept = key
+            .split('.')
+            .map(str::trim)
+            .try_fold(&written, |value, part| value.get(part));
+        let same = match (kept, &value) {
+            (Some(Value::Float(kept)), Value::Integer(value)) => *kept == *value as f64,
+            (kept, value) => kept == Some(value),
+        };
+        if !same {
+            return Err(format!("{} is not a setting that takes {}", key, value));
+        }
+        if let Some(problem) = config
+            .rate_control
+            .and_then(|rate_control| rate_control.problem())
+        {
+            return Err(format!("Invalid value for {}: {}", key, problem));
+        }
+        if let Some(problem) = config.recorders.iter().find_map(RecorderConfig::problem) {
+            return Err(format!("Invalid value for {}: {}", key, problem));
+        }
+
+        config.forget_stale_screen_serial(self);
+        config.action_event_tx = self.action_event_tx.clone();
+        config.system = self.system.clone();
+        config.display_framerate = self.display_framerate;
+        Ok(config)
+    }
+
+    /// Whether the system-wide config prevents changing `key`. Nested keys like `webcam.enabled`
+    /// are locked together with their top-level key.
+    pub fn is_locked(&self, key: &str) -> bool {
+        let key = key.split('.').next().unwrap_or_default().trim();
+        self.system.locked.iter().any(|locked| locked == key)
+    }
+
+    /// Resets locked keys to their system-wide values
+    fn enforce_locks(&mut self) {
+        if self.system.locked.is_empty() {
+            return;
+        }
+
+        let mut table = Table::try_from(&*self).unwrap();
+        merge_tables(&mut table, self.system.locked_values());
+
+        let mut config: Self = table.try_into().unwrap();
+        config.action_event_tx = self.action_event_tx.take();
+        config.system = std::mem::take(&mut self.system);
+        config.display_framerate = self.display_framerate;
+        *self = config;
+    }
+
+    /// Copies settings overridden by the profile into the config. Passing `None` only clears
+    /// the active profile and leaves current settings as they are.
+    pub fn apply_profile(&mut self, name: Option<&str>) -> bool {
+        let Some(name) = name else {
+            self.active_profile = None;
+            return true;
+        };
+
+        let Some(profile) = self.profiles.iter().find(|p| p.name == name).cloned() else {
+            return false;
+        };
+
+        self.apply_settings(&profile);
+        self.active_profile = Some(profile.name);
+
+        true
+    }
+
+    fn apply_settings(&mut self, profile: &Profile) {
+        if let Some(framerate) = profile.framerate {
+            self.framerate = framerate;
+        }
+        if let Some(quality) = profile.quality {
+            self.quality = quality;
+            self.quality_preset = None;
+        }
+        if let Some(container) = profile.container {
+            self.container = container;
+        }
+        if let Some(replay_duration_secs) = profile.replay_duration_secs {
+            self.replay_duration_secs = replay_duration_secs;
+        }
+        self.enforce_locks();
+    }
+
+    pub fn capture_target(&self) -> CaptureTarget {
+        if self.follow_focus {
+            CaptureTarget::Focused
+        } else {
+            CaptureTarget::from(self.screen.as_str())
+        }
+    }
+
+    /// Points the recorder at `target`, forgetting the serial of the previous monitor. Focused
+    /// turns on `follow_focus` and leaves `screen` alone.
+    pub fn set_capture_target(&mut self, target: CaptureTarget) {
+        self.follow_focus = target == CaptureTarget::Focused;
+        if !self.follow_focus {
+            self.screen = target.to_string();
+            self.screen_serial = None;
+        }
+    }
 
-        match std::fs::read_to_string(path) {
-            Ok(config) => {
-                let mut config: Self = toml::from_str(&config).expect("Cannot parse config file");
-                config.action_event_tx = Some(action_event_tx);
+    /// Forgets `screen_serial` when `screen` was changed by hand since `previous`, the serial of
+    /// the previous monitor would send recording back to it
+    pub fn forget_stale_screen_serial(&mut self, previous: &Config) {
+        if self.screen != previous.screen && self.screen_serial == previous.screen_serial {
+            self.screen_serial = None;
+        }
+    }
+
+    /// Width and height of `scale_resolution`, if it is set and valid
+    pub fn scale_size(&self) -> Option<(u32, u32)> {
+        self.scale_resolution.as_deref().and_then(parse_resolution)
+    }
+
+    /// Captures every setting a profile can change, so it can be restored later
+    pub fn snapshot(&self) -> SettingsSnapshot {
+        SettingsSnapshot {
+            active_profile: self.active_profile.clone(),
+            quality_preset: self.quality_preset.clone(),
+            rate_control: self.rate_control,
+            settings: Profile {
+                name: String::new(),
+                framerate: Some(self.framerate),
+                quality: Some(self.quality),
+                container: Some(self.container),
+                replay_duration_secs: Some(self.replay_duration_secs),
+                hotkeys: Vec::new(),
+                apps: Vec::new(),
+            },
+        }
+    }
+
+    /// Framerate to record at, the monitor's refresh rate (or 60 if it's unknown) when
+    /// `framerate` is 0
+    pub fn effective_framerate(&self) -> i64 {
+        match self.framerate {
+            0 => self.display_framerate.unwrap_or(60),
+            framerate => framerate,
+        }
+    }
+
+    /// Raises framerate and quality to the boost settings
+    pub fn apply_boost(&mut self) {
+        self.framerate = self.boost.framerate;
+        if let Some(quality) = self.boost.quality {
+            self.quality = quality;
+            self.quality_preset = None;
+            self.rate_control = None;
+        }
+        self.enforce_locks();
+    }
 
-                config
+    /// Lowers framerate and switches to the quality preset of `thermal_throttling`. Returns what
+    /// was changed, `None` if nothing was.
+    pub fn apply_thermal_throttling(&mut self) -> Option<String> {
+        let mut changes = Vec::new();
+        let throttling = self.thermal_throttling.clone();
+        if self.effective_framerate() > throttling.framerate && !self.is_locked("framerate") {
+            self.framerate = throttling.framerate;
+            changes.push(format!("framerate to {} fps", throttling.framerate));
+        }
+        if let Some(preset) = throttling.quality_preset
+            && self.quality_preset.as_ref() != Some(&preset)
+            && self.quality_presets.iter().any(|p| p.name == preset)
+            && !self.is_locked("quality_preset")
+        {
+            changes.push(format!("quality to {}", preset));
+            self.quality_preset = Some(preset);
+        }
+
+        (!changes.is_empty()).then(|| changes.join(" and "))
+    }
+
+    /// Steps quality (or the rate control, if one is set) down one level, or framerate down to 30
+    /// once quality is at its lowest or a quality preset is in use. Returns what was changed,
+    /// `None` if there is nothing left to lower.
+    pub fn lower_quality(&mut self) -> Option<String> {
+        if let Some(rate_control) = self.rate_control {
+            if let Some(lowered) = rate_control.lowered()
+                && !self.is_locked("rate_control")
+            {
+                self.rate_control = Some(lowered);
+                return Some(format!("rate control to {}", lowered));
             }
-            Err(_) => Config::default(),
+            return self.lower_framerate();
+        }
+
+        // Presets have no order to step down in, the one in use is kept and framerate lowered
+        if self.active_quality_preset().is_some() {
+            return self.lower_framerate();
+        }
+
+        let lower = match self.quality {
+            Quality::Ultra => Some(Quality::VeryHigh),
+            Quality::VeryHigh => Some(Quality::High),
+            Quality::High => Some(Quality::Medium),
+            Quality::Medium => None,
+        };
+
+        if let Some(quality) = lower
+            && !self.is_locked("quality")
+        {
+            self.quality = quality;
+            return Some(format!("quality to {}", quality.to_string()));
         }
+
+        self.lower_framerate()
     }
 
-    pub async fn save(&self) {
-        let mut path = dirs::config_dir().unwrap();
-        path.push("trayplay.toml");
+    /// Lowers framerate to 30, what's left once quality is at its lowest
+    fn lower_framerate(&mut self) -> Option<String> {
+        if self.effective_framerate() > 30 && !self.is_locked("framerate") {
+            self.framerate = 30;
+            return Some("framerate to 30 fps".to_string());
+        }
+
+        None
+    }
+
+    pub fn restore(&mut self, snapshot: SettingsSnapshot) {
+        self.apply_settings(&snapshot.settings);
+        self.active_profile = snapshot.active_profile;
+        self.quality_preset = snapshot.quality_preset;
+        self.rate_control = snapshot.rate_control;
+    }
+
+    /// Keeps the current settings as the ones written to the config file, so the ones changed
+    /// next only last until [`Config::end_temporary_settings`]
+    pub fn begin_temporary_settings(&mut self) {
+        if self.chosen_settings.is_none() {
+            self.chosen_settings = Some(self.snapshot());
+        }
+    }
+
+    /// Writes the current settings to the config file again
+    pub fn end_temporary_settings(&mut self) {
+        self.chosen_settings = None;
+    }
+
+    /// Absolute path of the replay directory
+    pub fn replay_dir(&self) -> PathBuf {
+        resolve_path(&self.replay_directory)
+    }
 
-        std::fs::write(path, toml::to_string(&self).unwrap()).expect("Failed to write config file");
+    /// Absolute path of the work directory, if one is set
+    pub fn work_dir(&self) -> Option<PathBuf> {
+        self.work_directory.as_deref().map(resolve_path)
+    }
+
+    /// The quality preset in use, if it still exists
+    pub fn active_quality_preset(&self) -> Option<&QualityPreset> {
+        self.quality_preset
+            .as_ref()
+            .and_then(|name| self.quality_presets.iter().find(|p| &p.name == name))
+    }
 
-        self.action_event_tx
+    pub fn active_profile(&self) -> Option<&Profile> {
+        self.active_profile
+            .as_ref()
+            .and_then(|name| self.profiles.iter().find(|p| &p.name == name))
+    }
+
+    /// Audio tracks passed to the recorder. Input devices (microphones) are left out when
+    /// `include_microphone` is off, the default devices are swapped for `audio_output` and
+    /// `microphone` (or the noise suppressed microphone), and with `mix_audio_tracks` they are merged the way gpu-screen-recorder
+    /// expects, `output|input`. The output device is replaced by the applications picked with
+    /// `app_audio_mode`, and left out if none of them is playing.
+    pub fn recorded_audio_tracks(&self) -> Vec<String> {
+        let app_track = self.app_audio_track();
+        let tracks = self
+            .audio_tracks
+            .iter()
+            .filter(|track| self.include_microphone || !track.contains("input"))
+            .filter_map(
+                |track| match (track.as_str(), &self.audio_output, &self.microphone) {
+                    ("default_output", _, _) if self.app_audio_mode == AppAudioMode::Only => {
+                        app_track.clone()
+                    }
+                    ("default_output", _, _) if app_track.is_some() => app_track.clone(),
+                    ("default_output", Some(output), _) => Some(output.clone()),
+                    ("default_input", _, _) if denoise::is_running() => {
+                        Some(denoise::SOURCE_NAME.to_string())
+                    }
+                    ("default_input", _, Some(microphone)) => Some(microphone.clone()),
+                    _ => Some(track.clone()),
+                },
+            )
+            .collect::<Vec<_>>();
+
+        if self.mix_audio_tracks && !tracks.is_empty() {
+            vec![tracks.join("|")]
+        } else {
+            tracks
+        }
+    }
+
+    /// gpu-screen-recorder's `app:` or `app-inverse:` track for `audio_apps`, if any of them
+    /// applies. Patterns are matched against the applications playing right now, plain names
+    /// are passed as they are so the application is recorded once it starts.
+    fn app_audio_track(&self) -> Option<String> {
+        let prefix = match self.app_audio_mode {
+            AppAudioMode::All => return None,
+            AppAudioMode::Only => "app",
+            AppAudioMode::Except => "app-inverse",
+        };
+        let apps = audio::app_names(&self.audio_apps);
+        if apps.is_empty() {
+            return None;
+        }
+
+        Some(
+            apps.iter()
+                .map(|app| format!("{}:{}", prefix, app))
+                .collect::<Vec<_>>()
+                .join("|"),
+        )
+    }
+
+    /// Writes the config file without notifying the rest of the app. Values that come from the
+    /// system-wide config are left out, so the user keeps following them if the admin changes them.
+    pub fn write_file(&self) {
+        let mut chosen;
+        let config = match &self.chosen_settings {
+            Some(settings) => {
+                chosen = self.clone();
+                chosen.restore(settings.clone());
+                &chosen
+            }
+            None => self,
+        };
+        let mut table = Table::try_from(config).unwrap();
+        table.retain(|key, value| {
+            !self.is_locked(key) && self.system.defaults.get(key) != Some(value)
+        });
+        let contents = toml::to_string(&table).unwrap();
+
+        if std::fs::read_to_string(config_path()).is_ok_and(|current| current != contents)
+            && let Err(err) = backup_config_file()
+        {
+            warn!("Cannot back up config file: {}", err);
+        }
+
+        std::fs::write(config_path(), contents).expect("Failed to write config file");
+    }
+
+    pub async fn save(&self) {
+        self.write_file();
+        self.apply().await;
+    }
+
+    /// Makes the rest of the app pick up changed settings without writing them to the config file
+    pub async fn apply(&self) {
+        // The tray saves too, it must not go down if the main loop did
+        let sent = self
+            .action_event_tx
             .as_ref()
             .unwrap()
+            .0
             .send(ActionEvent::ConfigSaved)
-            .await
-            .unwrap();
+            .await;
+        if sent.is_err() {
+            warn!("Settings were saved but TrayPlay stopped working, they apply after a restart");
+        }
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        println!("Config missing or broken. Replacing with defaults");
+/// gpu-screen-recorder's own default
+fn default_keyframe_interval() -> f64 {
+    2.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_volume() -> i64 {
+    audio::DEFAULT_VOLUME
+}
+
+fn config_path() -> PathBuf {
+    let mut path = dirs::config_dir().unwrap();
+    path.push("trayplay.toml");
+    path
+}
+
+/// Number of previous config versions kept in `config_backup_dir()`
+const MAX_CONFIG_BACKUPS: usize = 10;
+
+fn config_backup_dir() -> PathBuf {
+    let mut path = dirs::state_dir().unwrap();
+    path.push("trayplay");
+    path.push("config_backups");
+    path
+}
+
+/// Copies the current config file into the backup directory, dropping the oldest backups
+fn backup_config_file() -> std::io::Result<()> {
+    std::fs::create_dir_all(config_backup_dir())?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    std::fs::copy(
+        config_path(),
+        config_backup_dir().join(format!("trayplay_{}.toml", timestamp)),
+    )?;
+
+    for (path, _) in config_backups().into_iter().skip(MAX_CONFIG_BACKUPS) {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Previous versions of the config file with the time they were replaced, newest first
+pub fn config_backups() -> Vec<(PathBuf, SystemTime)> {
+    let Ok(entries) = std::fs::read_dir(config_backup_dir()) else {
+        return Vec::new();
+    };
+
+    let mut backups: Vec<(PathBuf, SystemTime)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?.modified().ok()?)))
+        .collect();
+    backups.sort_by_key(|(_, replaced)| std::cmp::Reverse(*replaced));
+    backups
+}
 
-        let mut path = dirs::config_dir().unwrap();
-        path.push("trayplay.toml");
+/// Replaces the config file with a backup. The current config is backed up first, so restoring
+/// can be undone.
+pub fn restore_config_backup(backup: &Path) -> std::io::Result<()> {
+    backup_config_file()?;
+    std::fs::copy(backup, config_path())?;
 
-        let instance = Self {
+    Ok(())
+}
+
+const SYSTEM_CONFIG_PATH: &str = "/etc/trayplay/config.toml";
+
+/// Defaults set by the administrator in /etc/trayplay/config.toml. It uses the same keys as the
+/// user config, plus a `locked` list of top-level keys users can't change.
+#[derive(PartialEq, Clone, Default)]
+struct SystemConfig {
+    defaults: Table,
+    locked: Vec<String>,
+}
+
+impl SystemConfig {
+    fn load() -> Self {
+        let Ok(config) = std::fs::read_to_string(SYSTEM_CONFIG_PATH) else {
+            return Self::default();
+        };
+        let mut defaults: Table = toml::from_str(&config).expect("Cannot parse system config file");
+
+        let locked = match defaults.remove("locked") {
+            Some(Value::Array(keys)) => keys
+                .into_iter()
+                .filter_map(|key| match key {
+                    Value::String(key) if defaults.contains_key(&key) => Some(key),
+                    key => {
+                        warn!(
+                            "Ignoring locked key {} without a value in {}",
+                            key, SYSTEM_CONFIG_PATH
+                        );
+                        None
+                    }
+                })
+                .collect(),
+            Some(_) => {
+                warn!("locked in {} must be a list of keys", SYSTEM_CONFIG_PATH);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+
+        Self { defaults, locked }
+    }
+
+    fn locked_values(&self) -> Table {
+        self.defaults
+            .iter()
+            .filter(|(key, _)| self.locked.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Merges `overlay` into `base`. Nested tables are merged key by key, other values are replaced.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parses a resolution like "1920x1080"
+pub fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let (width, height) = resolution.trim().split_once('x')?;
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    (size.0 > 0 && size.1 > 0).then_some(size)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
             screen: "screen".to_string(),
+            screen_serial: None,
+            region: None,
+            follow_focus: false,
+            hdr: false,
+            color_range: ColorRange::Limited,
+            bit_depth: BitDepth::Eight,
+            recorder_backend: RecorderBackend::default(),
+            encoder: Encoder::default(),
+            extra_args: Vec::new(),
+            excluded_monitors: Vec::new(),
             audio_tracks: vec!["default_output".to_string(), "default_input".to_string()],
+            audio_output: None,
+            microphone: None,
+            mix_audio_tracks: false,
+            app_audio_mode: AppAudioMode::All,
+            audio_apps: Vec::new(),
+            include_microphone: true,
+            noise_suppression: false,
+            mute_microphone: false,
+            mute_desktop_audio: false,
+            desktop_audio_volume: audio::DEFAULT_VOLUME,
+            microphone_volume: audio::DEFAULT_VOLUME,
             framerate: 60,
+            scale_resolution: None,
             clear_buffer_on_save: true,
+            warm_standby: false,
+            persist_buffer: false,
+            save_before_restart: false,
+            portal_without_kms_permission: true,
+            ask_for_kms_permission: true,
             quality: Quality::Ultra,
-            replay_directory: dirs::video_dir().unwrap(),
+            rate_control: None,
+            quality_preset: None,
+            quality_presets: Vec::new(),
+            replay_directory: PathBuf::from(VIDEOS_DIR_PLACEHOLDER),
+            work_directory: None,
             container: Container::MKV,
             replay_duration_secs: 180,
+            replay_storage: ReplayStorage::Ram,
+            autostart: false,
+            pause_during_calls: false,
+            private_apps: Vec::new(),
+            game_sessions: GameSessions::default(),
+            gamescope_hotkey: GamescopeHotkey::default(),
+            lower_quality_on_overload: true,
+            keyframe_interval_secs: default_keyframe_interval(),
+            gpu: None,
+            record_cursor: true,
+            trim_dead_air: false,
+            normalize_loudness: false,
+            muxer_options: BTreeMap::new(),
+            vertical_export: VerticalExport::default(),
+            share_copy: ShareCopy::default(),
+            notification_actions: Vec::new(),
+            video_editor: None,
+            webcam: Webcam::default(),
+            streaming: Streaming::default(),
+            boost: Boost::default(),
+            thermal_throttling: ThermalThrottling::default(),
+            systemd_scope: SystemdScope::default(),
+            menu_layout: None,
+            active_profile: None,
+            profiles: Vec::new(),
+            power_profiles: PowerProfiles::default(),
+            recorders: Vec::new(),
+            custom_actions: Vec::new(),
+            triggers: Vec::new(),
+            save_cooldowns: SaveCooldowns::default(),
             action_event_tx: None,
+            system: SystemConfig::default(),
+            display_framerate: None,
+            chosen_settings: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub framerate: Option<i64>,
+    pub quality: Option<Quality>,
+    pub container: Option<Container>,
+    pub replay_duration_secs: Option<i64>,
+    #[serde(default)]
+    pub hotkeys: Vec<Hotkey>,
+    #[serde(default)]
+    pub apps: Vec<String>,
+}
+
+/// Entries of the tray menu, in the order they are shown
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MenuEntry {
+    SaveReplay,
+    CopyFrame,
+    Microphone,
+    MuteMicrophone,
+    MuteDesktopAudio,
+    FollowFocus,
+    Profile,
+    RestoreQuality,
+    Boost,
+    ExportVertical,
+    AddNote,
+    DeleteReplay,
+    WatchLater,
+    Record,
+    Stream,
+    Recorders,
+    CustomActions,
+    Settings,
+    Statistics,
+    Logs,
+    Troubleshooting,
+    About,
+    Separator,
+    Quit,
+}
+
+pub fn default_menu_layout() -> Vec<MenuEntry> {
+    vec![
+        MenuEntry::SaveReplay,
+        MenuEntry::CopyFrame,
+        MenuEntry::Microphone,
+        MenuEntry::MuteMicrophone,
+        MenuEntry::MuteDesktopAudio,
+        MenuEntry::FollowFocus,
+        MenuEntry::Profile,
+        MenuEntry::RestoreQuality,
+        MenuEntry::Boost,
+        MenuEntry::ExportVertical,
+        MenuEntry::AddNote,
+        MenuEntry::DeleteReplay,
+        MenuEntry::WatchLater,
+        MenuEntry::Record,
+        MenuEntry::Stream,
+        MenuEntry::Recorders,
+        MenuEntry::CustomActions,
+        MenuEntry::Separator,
+        MenuEntry::Settings,
+        MenuEntry::Statistics,
+        MenuEntry::Troubleshooting,
+        MenuEntry::About,
+        MenuEntry::Separator,
+        MenuEntry::Quit,
+    ]
+}
+
+#[derive(PartialEq, Clone)]
+pub struct SettingsSnapshot {
+    active_profile: Option<String>,
+    quality_preset: Option<String>,
+    rate_control: Option<RateControl>,
+    settings: Profile,
+}
+
+/// Named set of encoder options selectable next to the built-in qualities
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct QualityPreset {
+    pub name: String,
+    /// Passed as -k, gpu-screen-recorder picks one when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Passed as -bm: "qp", "vbr" or "cbr"
+    #[serde(default = "default_bitrate_mode")]
+    pub bitrate_mode: String,
+    /// Passed as -q: a quality name for qp and vbr, bitrate in kbps for cbr
+    pub quality: String,
+    /// Passed as -cr: "limited" or "full"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_range: Option<String>,
+}
+
+fn default_bitrate_mode() -> String {
+    "qp".to_string()
+}
+
+/// Tray menu item running a shell command, `{clip}` is replaced with the last saved replay
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct CustomAction {
+    pub label: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// Condition that saves a replay automatically when met
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum Trigger {
+    FileModified {
+        path: PathBuf,
+    },
+    DbusSignal {
+        #[serde(default)]
+        bus: Bus,
+        interface: String,
+        member: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    /// Case-insensitive match on the active window's title
+    WindowTitle {
+        contains: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Bus {
+    #[default]
+    Session,
+    System,
+}
+
+/// Extra gpu-screen-recorder instance running next to the main replay buffer. Framerate,
+/// quality, container and audio are shared with the main config.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct RecorderConfig {
+    pub name: String,
+    pub screen: String,
+    #[serde(default)]
+    pub mode: RecorderMode,
+    /// Where replays or recordings are saved, defaults to `replay_directory`. Resolved the same
+    /// way as `replay_directory`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+    /// Recordings continue in a new file after this many minutes (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_minutes: Option<u64>,
+    /// Recordings continue in a new file once the current one reaches this size (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_size_gb: Option<f64>,
+}
+
+impl RecorderConfig {
+    /// Why the split limits can't be used, if they can't. A limit of 0 would start a new part
+    /// on every check.
+    pub fn problem(&self) -> Option<String> {
+        if self.split_minutes == Some(0) {
+            return Some(format!("split_minutes of {} must be above 0", self.name));
+        }
+        if self
+            .split_size_gb
+            .is_some_and(|size_gb| size_gb.is_nan() || size_gb <= 0.0)
+        {
+            return Some(format!("split_size_gb of {} must be above 0", self.name));
+        }
+        None
+    }
+
+    /// Whether recordings are cut into parts
+    pub fn splits(&self) -> bool {
+        self.mode == RecorderMode::Recording
+            && (self.split_minutes.is_some() || self.split_size_gb.is_some())
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RecorderMode {
+    #[default]
+    Replay,
+    /// Records continuously into a new file every time the recorder starts
+    Recording,
+}
+
+/// Profiles applied automatically when the power source changes
+#[derive(Serialize, Deserialize, PartialEq, Default, Clone)]
+pub struct PowerProfiles {
+    pub ac: Option<String>,
+    pub battery: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Hotkey {
+    pub id: String,
+    pub description: String,
+    pub trigger: String,
+    pub action: HotkeyAction,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HotkeyAction {
+    SaveReplay,
+    SaveLast(i64),
+    Boost,
+    CopyFrame,
+}
+
+/// Small transcode written next to every saved replay, meant for sharing
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct ShareCopy {
+    pub enabled: bool,
+    pub height: u32,
+    pub video_bitrate_kbps: u32,
+}
+
+impl Default for ShareCopy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            height: 720,
+            video_bitrate_kbps: 4000,
+        }
+    }
+}
+
+/// What asked for a replay to be saved
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum SaveSource {
+    Tray,
+    Hotkey,
+    /// SIGUSR1, usually sent by scripts
+    Signal,
+    /// Save trigger from config, by its position in `triggers`
+    Trigger(usize),
+}
+
+impl SaveSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaveSource::Tray => "tray",
+            SaveSource::Hotkey => "hotkey",
+            SaveSource::Signal => "signal",
+            SaveSource::Trigger(_) => "trigger",
+        }
+    }
+}
+
+/// Minimum time between two saves from the same source, so automated saves can't fill the disk
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct SaveCooldowns {
+    pub hotkey_secs: u64,
+    pub signal_secs: u64,
+    pub trigger_secs: u64,
+}
+
+impl SaveCooldowns {
+    pub fn of(&self, source: SaveSource) -> Duration {
+        Duration::from_secs(match source {
+            SaveSource::Tray => 0,
+            SaveSource::Hotkey => self.hotkey_secs,
+            SaveSource::Signal => self.signal_secs,
+            SaveSource::Trigger(_) => self.trigger_secs,
+        })
+    }
+}
+
+impl Default for SaveCooldowns {
+    fn default() -> Self {
+        Self {
+            hotkey_secs: 0,
+            signal_secs: 0,
+            trigger_secs: 10,
+        }
+    }
+}
+
+/// Applications whose audio is recorded instead of everything played on the output device,
+/// only gpu-screen-recorder can tell them apart
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AppAudioMode {
+    #[default]
+    All,
+    /// Only the applications in `audio_apps`
+    Only,
+    /// Everything but the applications in `audio_apps`, like a voice chat
+    Except,
+}
+
+/// Games launched from Steam, Lutris or Heroic, detected from their processes
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct GameSessions {
+    /// Saves replays into a folder named after the game and counts clips per game session
+    pub enabled: bool,
+    /// Keep the replay buffer off while no game is running, only while `enabled`
+    pub control_buffer: bool,
+}
+
+/// Save hotkey read from the keyboards while gamescope runs, which keeps key presses from the
+/// desktop's global shortcuts
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct GamescopeHotkey {
+    pub enabled: bool,
+    /// In the notation of the global shortcuts portal, e.g. "ALT+F10"
+    pub trigger: String,
+}
+
+impl Default for GamescopeHotkey {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            trigger: "ALT+F10".to_string(),
+        }
+    }
+}
+
+/// Runs gpu-screen-recorder in its own systemd user scope with resource limits
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[serde(default)]
+pub struct SystemdScope {
+    pub enabled: bool,
+    /// CPUWeight= of the scope, 1 to 10000 (systemd's default is 100)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_weight: Option<u32>,
+    /// MemoryMax= of the scope, e.g. "2G"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<String>,
+}
+
+/// RTMP server the "Start streaming" tray action streams to
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct Streaming {
+    pub url: String,
+    /// Appended to `url`, kept apart so the URL can be shown and shared without it
+    pub key: String,
+    pub video_bitrate_kbps: u32,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            key: String::new(),
+            video_bitrate_kbps: 6000,
+        }
+    }
+}
+
+impl Streaming {
+    /// URL with the stream key gpu-screen-recorder sends to, `None` without a server
+    pub fn target(&self) -> Option<String> {
+        if self.url.is_empty() {
+            return None;
+        }
+
+        Some(if self.key.is_empty() {
+            self.url.clone()
+        } else {
+            format!("{}/{}", self.url.trim_end_matches('/'), self.key)
+        })
+    }
+}
+
+/// What `screen` captures. It's kept as a plain string in config, which is passed to
+/// gpu-screen-recorder as -w.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureTarget {
+    /// gpu-screen-recorder picks the monitor
+    Screen,
+    ScreenDirect,
+    /// Monitor with the focused window, followed as focus moves
+    Focused,
+    /// Monitor or window picked in the desktop portal's dialog
+    Portal,
+    Region,
+    /// X11 window id like 0x3a00007
+    Window(String),
+    /// Monitor connector like DP-1
+    Monitor(String),
+}
+
+impl From<&str> for CaptureTarget {
+    fn from(screen: &str) -> Self {
+        match screen {
+            "screen" => CaptureTarget::Screen,
+            "screen-direct" => CaptureTarget::ScreenDirect,
+            "focused" => CaptureTarget::Focused,
+            "portal" => CaptureTarget::Portal,
+            "region" => CaptureTarget::Region,
+            window if window.chars().all(|c| c.is_ascii_hexdigit() || c == 'x') => {
+                CaptureTarget::Window(window.to_string())
+            }
+            connector => CaptureTarget::Monitor(connector.to_string()),
+        }
+    }
+}
+
+impl Display for CaptureTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureTarget::Screen => write!(f, "screen"),
+            CaptureTarget::ScreenDirect => write!(f, "screen-direct"),
+            CaptureTarget::Focused => write!(f, "focused"),
+            CaptureTarget::Portal => write!(f, "portal"),
+            CaptureTarget::Region => write!(f, "region"),
+            CaptureTarget::Window(id) => write!(f, "{}", id),
+            CaptureTarget::Monitor(connector) => write!(f, "{}", connector),
+        }
+    }
+}
+
+/// What keeps the replay buffer
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecorderBackend {
+    #[default]
+    GpuScreenRecorder,
+    /// ScreenCast portal and GStreamer, for systems without gpu-screen-recorder
+    Screencast,
+    /// wf-recorder, for wlroots compositors without gpu-screen-recorder
+    WfRecorder,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RateControlMode {
+    /// Constant bitrate
+    Cbr,
+    /// Variable bitrate around a target
+    Vbr,
+    /// Constant quantizer
+    Cqp,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct RateControl {
+    pub mode: RateControlMode,
+    /// Bitrate in kbps for CBR and VBR, quantizer (0-51, lower is better) for CQP
+    pub value: u32,
+}
+
+/// Highest quantizer of H.264, HEVC and AV1 encoders in CQP mode
+pub const MAX_QP: u32 = 51;
+/// Lowering quality on overload doesn't go past these, below them clips are unwatchable
+const MIN_LOWERED_BITRATE_KBPS: u32 = 2000;
+const MAX_LOWERED_QP: u32 = 35;
+
+impl RateControl {
+    /// Why the value can't be used with the mode, if it can't
+    pub fn problem(&self) -> Option<String> {
+        match self.mode {
+            RateControlMode::Cqp if self.value > MAX_QP => Some(format!(
+                "the quantizer must be between 0 and {}, not {}",
+                MAX_QP, self.value
+            )),
+            RateControlMode::Cbr | RateControlMode::Vbr if self.value == 0 => {
+                Some("the bitrate must be above 0 kbps".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// One step lower quality: a quarter less bitrate, or a quantizer 4 higher. `None` if it's
+    /// already as low as it goes.
+    pub fn lowered(&self) -> Option<Self> {
+        let value = match self.mode {
+            RateControlMode::Cqp if self.value < MAX_LOWERED_QP => {
+                (self.value + 4).min(MAX_LOWERED_QP)
+            }
+            RateControlMode::Cbr | RateControlMode::Vbr
+                if self.value > MIN_LOWERED_BITRATE_KBPS =>
+            {
+                (self.value * 3 / 4).max(MIN_LOWERED_BITRATE_KBPS)
+            }
+            _ => return None,
         };
 
-        std::fs::write(path, toml::to_string(&instance).unwrap())
-            .expect("Failed to write config file");
+        Some(Self { value, ..*self })
+    }
+}
 
-        instance
+impl Display for RateControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mode {
+            RateControlMode::Cbr => write!(f, "CBR {} kbps", self.value),
+            RateControlMode::Vbr => write!(f, "VBR {} kbps", self.value),
+            RateControlMode::Cqp => write!(f, "CQP {}", self.value),
+        }
     }
 }
 
+/// Video encoder. gpu-screen-recorder only tells GPU from CPU encoding and uses whatever API the
+/// GPU has, wf-recorder can use any of them.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoder {
+    /// Left to the recorder
+    #[default]
+    Auto,
+    Vaapi,
+    Nvenc,
+    Qsv,
+    Software,
+}
+
+/// Settings of the temporary "Boost" tray action
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(default)]
+pub struct Boost {
+    pub framerate: i64,
+    pub quality: Option<Quality>,
+    pub duration_secs: u64,
+}
+
+impl Default for Boost {
+    fn default() -> Self {
+        Self {
+            framerate: 120,
+            quality: None,
+            duration_secs: 600,
+        }
+    }
+}
+
+/// Settings lowered while the CPU or GPU overheats, e.g. in a laptop, and restored once it cools
+/// down
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct ThermalThrottling {
+    pub enabled: bool,
+    /// Temperature of the hottest CPU package or GPU edge sensor settings are lowered at, in °C
+    pub hot_celsius: u32,
+    /// Temperature all sensors have to be back below before settings are restored
+    pub cool_celsius: u32,
+    pub framerate: i64,
+    /// Name of a quality preset to switch to, e.g. one with a more efficient codec (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality_preset: Option<String>,
+}
+
+impl Default for ThermalThrottling {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hot_celsius: 90,
+            cool_celsius: 75,
+            framerate: 30,
+            quality_preset: None,
+        }
+    }
+}
+
+/// Webcam composited into a corner of the recording
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct Webcam {
+    pub enabled: bool,
+    pub device: PathBuf,
+    pub corner: WebcamCorner,
+    /// Size of the webcam relative to the recorded screen
+    pub size_percent: u32,
+}
+
+impl Default for Webcam {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: PathBuf::from("/dev/video0"),
+            corner: WebcamCorner::BottomRight,
+            size_percent: 25,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WebcamCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+pub struct VerticalExport {
+    #[serde(default)]
+    pub fit: VerticalFit,
+    #[serde(default)]
+    pub anchor: CropAnchor,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum VerticalFit {
+    #[default]
+    Crop,
+    Letterbox,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CropAnchor {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum Quality {
@@ -97,6 +1527,94 @@ impl ToString for Quality {
     }
 }
 
+/// Where gpu-screen-recorder keeps the replay buffer
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayStorage {
+    #[default]
+    Ram,
+    /// Segment files in the replay directory, for long buffers that don't fit in memory
+    Disk,
+}
+
+impl Display for ReplayStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayStorage::Ram => write!(f, "ram"),
+            ReplayStorage::Disk => write!(f, "disk"),
+        }
+    }
+}
+
+/// Range of the recorded YUV values. Limited (16-235) is what players expect, full keeps the
+/// darkest and brightest shades of desktop content apart.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorRange {
+    #[default]
+    Limited,
+    Full,
+}
+
+impl Display for ColorRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorRange::Limited => write!(f, "limited"),
+            ColorRange::Full => write!(f, "full"),
+        }
+    }
+}
+
+/// Bits per color component of the recorded pixels. 10-bit reduces banding in gradients and
+/// needs an HEVC or AV1 encoder.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default, Debug)]
+pub enum BitDepth {
+    #[default]
+    #[serde(rename = "8bit")]
+    Eight,
+    #[serde(rename = "10bit")]
+    Ten,
+}
+
+/// Something to do with a saved replay, from the tray or its notification
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayAction {
+    Open,
+    Folder,
+    Editor,
+    Share,
+    Delete,
+    Favorite,
+    WatchLater,
+}
+
+impl ReplayAction {
+    pub const ALL: [ReplayAction; 7] = [
+        ReplayAction::Open,
+        ReplayAction::Folder,
+        ReplayAction::Editor,
+        ReplayAction::Share,
+        ReplayAction::Delete,
+        ReplayAction::Favorite,
+        ReplayAction::WatchLater,
+    ];
+}
+
+impl Display for ReplayAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayAction::Open => write!(f, "open"),
+            ReplayAction::Folder => write!(f, "folder"),
+            ReplayAction::Editor => write!(f, "editor"),
+            ReplayAction::Share => write!(f, "share"),
+            ReplayAction::Delete => write!(f, "delete"),
+            ReplayAction::Favorite => write!(f, "favorite"),
+            ReplayAction::WatchLater => write!(f, "watch_later"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum Container {
@@ -106,6 +1624,18 @@ pub enum Container {
     WEBM,
 }
 
+impl Container {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "mp4" => Some(Container::MP4),
+            "mkv" => Some(Container::MKV),
+            "flv" => Some(Container::FLV),
+            "webm" => Some(Container::WEBM),
+            _ => None,
+        }
+    }
+}
+
 impl ToString for Container {
     fn to_string(&self) -> String {
         match self {
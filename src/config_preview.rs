@@ -0,0 +1,124 @@
+use std::fmt::Display;
+
+use crate::{
+    config::{Config, RecorderBackend},
+    gsr, monitors,
+    recorder::Error,
+};
+
+/// What applying a config change does to the replay buffer
+pub struct Preview {
+    /// gpu-screen-recorder command line with the change, `None` for the other backends
+    command_line: Option<Vec<String>>,
+    /// Options that go away, each a flag with its values
+    removed: Vec<String>,
+    added: Vec<String>,
+    /// Whether the recorder is restarted, discarding the buffer
+    pub restart: bool,
+    /// Why the recorder can't start with the change, if it can't
+    pub problem: Option<String>,
+}
+
+impl Display for Preview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(problem) = &self.problem {
+            return write!(f, "The recorder cannot start with this change: {}", problem);
+        }
+        match &self.command_line {
+            Some(command_line) => {
+                writeln!(f, "Recorder command line:\n  {}", command_line.join(" "))?;
+                for option in &self.removed {
+                    writeln!(f, "- {}", option)?;
+                }
+                for option in &self.added {
+                    writeln!(f, "+ {}", option)?;
+                }
+            }
+            None => writeln!(f, "Only gpu-screen-recorder command lines can be previewed")?,
+        }
+
+        if self.restart {
+            write!(f, "The recorder restarts, the replay buffer is discarded")
+        } else {
+            write!(f, "The recorder keeps running with its replay buffer")
+        }
+    }
+}
+
+/// Compares the replay buffer's recorder before and after `changed` replaces `current`
+pub fn preview(current: &Config, changed: &Config) -> Preview {
+    let gsr_used = |config: &Config| config.recorder_backend == RecorderBackend::GpuScreenRecorder;
+    if !gsr_used(current) || !gsr_used(changed) {
+        return Preview {
+            command_line: None,
+            removed: Vec::new(),
+            added: Vec::new(),
+            restart: true,
+            problem: None,
+        };
+    }
+
+    let command_line = match replay_command_line(changed) {
+        Ok(command_line) => command_line,
+        Err(problem) => {
+            return Preview {
+                command_line: None,
+                removed: Vec::new(),
+                added: Vec::new(),
+                restart: false,
+                problem: Some(problem),
+            };
+        }
+    };
+    let after = options(&command_line);
+    // The current config may already be one the recorder can't start with
+    let before = replay_command_line(current)
+        .map(|command_line| options(&command_line))
+        .unwrap_or_default();
+
+    Preview {
+        problem: None,
+        restart: before != after,
+        removed: before
+            .iter()
+            .filter(|option| !after.contains(option))
+            .cloned()
+            .collect(),
+        added: after
+            .iter()
+            .filter(|option| !before.contains(option))
+            .cloned()
+            .collect(),
+        command_line: Some(command_line),
+    }
+}
+
+/// Command line the recorder would be started with, with the monitor resolved as on start.
+/// Fails with why gpu-screen-recorder can't record with `config`.
+fn replay_command_line(config: &Config) -> Result<Vec<String>, String> {
+    let mut config = config.clone();
+    // An unplugged monitor makes the recorder fail to start, not the preview
+    let _ = monitors::resolve_screen(&mut config);
+    monitors::update_display_framerate(&mut config);
+    match gsr::replay_command(&config) {
+        Ok(command) => Ok(gsr::command_line(&command)),
+        Err(Error::InvalidOptions(problem)) => Err(problem),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Groups a command line into its flags with their values, e.g. "-f 60"
+fn options(command_line: &[String]) -> Vec<String> {
+    let mut options: Vec<String> = Vec::new();
+    for arg in command_line {
+        match options.last_mut() {
+            Some(option) if option.starts_with('-') && !arg.starts_with('-') => {
+                option.push(' ');
+                option.push_str(arg);
+            }
+            _ => options.push(arg.clone()),
+        }
+    }
+
+    options
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use log::{info, warn};
+use tokio::process::Command;
+
+use crate::config::CustomAction;
+
+/// Placeholder in custom action commands replaced with the path of the last saved replay
+const CLIP_PLACEHOLDER: &str = "{clip}";
+
+/// Quotes `value` so the shell passes it as a single argument
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Runs a custom action's command with `sh -c` in the background
+pub fn run(action: &CustomAction, last_replay: Option<&Path>) {
+    let command = if action.command.contains(CLIP_PLACEHOLDER) {
+        let Some(last_replay) = last_replay else {
+            warn!(
+                "\"{}\" needs a saved replay, but no replay has been saved yet",
+                action.label
+            );
+            return;
+        };
+        action.command.replace(
+            CLIP_PLACEHOLDER,
+            &shell_quote(&last_replay.to_string_lossy()),
+        )
+    } else {
+        action.command.clone()
+    };
+
+    info!("Running custom action \"{}\": {}", action.label, command);
+    let label = action.label.clone();
+    tokio::spawn(async move {
+        match Command::new("sh").arg("-c").arg(&command).status().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("Custom action \"{}\" failed with {}", label, status),
+            Err(err) => warn!("Cannot run custom action \"{}\": {}", label, err),
+        }
+    });
+}
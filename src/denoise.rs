@@ -0,0 +1,224 @@
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use log::{error, info};
+use tokio::{
+    process::Command,
+    sync::{mpsc::Sender, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{ActionEvent, config::Config};
+
+/// Virtual microphone the filter creates, recorded in place of the real one
+pub const SOURCE_NAME: &str = "trayplay_denoised_microphone";
+/// How long the filter gets to create its source, the real microphone is recorded otherwise
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Whether the filter's source can be recorded, the real microphone is recorded otherwise
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Standalone PipeWire instance running RNNoise (noise-suppression-for-voice) on the
+/// microphone as a filter-chain
+fn filter_config(microphone: Option<&str>) -> String {
+    let target = microphone
+        .map(|microphone| format!("target.object = \"{}\"", microphone))
+        .unwrap_or_default();
+
+    format!(
+        r#"context.properties = {{ log.level = 0 }}
+context.spa-libs = {{
+    audio.convert.* = audioconvert/libspa-audioconvert
+    support.*       = support/libspa-support
+}}
+context.modules = [
+    {{ name = libpipewire-module-rt flags = [ ifexists nofail ] }}
+    {{ name = libpipewire-module-protocol-native }}
+    {{ name = libpipewire-module-client-node }}
+    {{ name = libpipewire-module-adapter }}
+    {{ name = libpipewire-module-filter-chain
+        args = {{
+            node.description = "TrayPlay noise suppression"
+            media.name       = "TrayPlay noise suppression"
+            filter.graph = {{
+                nodes = [
+                    {{
+                        type   = ladspa
+                        name   = rnnoise
+                        plugin = librnnoise_ladspa
+                        label  = noise_suppressor_mono
+                        control = {{ "VAD Threshold (%)" = 50.0 }}
+                    }}
+                ]
+            }}
+            capture.props = {{
+                node.name    = "capture.{source}"
+                node.passive = true
+                audio.rate   = 48000
+                {target}
+            }}
+            playback.props = {{
+                node.name   = "{source}"
+                media.class = Audio/Source
+                audio.rate  = 48000
+            }}
+        }}
+    }}
+]
+"#,
+        source = SOURCE_NAME,
+        target = target,
+    )
+}
+
+/// A running filter process, watched by a task that owns it
+struct Filter {
+    pid: u32,
+    /// Microphone it was started for
+    microphone: Option<String>,
+    stop_tx: oneshot::Sender<()>,
+    watcher: JoinHandle<()>,
+}
+
+/// Runs the noise suppression filter while `noise_suppression` is on in config
+pub struct NoiseSuppression {
+    filter: Option<Filter>,
+    action_tx: Sender<ActionEvent>,
+}
+
+impl NoiseSuppression {
+    pub fn new(action_tx: Sender<ActionEvent>) -> Self {
+        Self {
+            filter: None,
+            action_tx,
+        }
+    }
+
+    /// Starts, restarts or stops the filter to match config. Call before starting the recorder,
+    /// it waits for the filter's source to show up.
+    pub async fn apply(&mut self, config: &Config) {
+        let wanted = config.noise_suppression && config.include_microphone;
+        if let Some(filter) = &self.filter
+            && wanted
+            && filter.microphone == config.microphone
+        {
+            return;
+        }
+        self.stop().await;
+        if !wanted {
+            return;
+        }
+
+        match self.start(config.microphone.clone()).await {
+            Ok(()) => info!("Started noise suppression"),
+            Err(err) => error!(
+                "Cannot start noise suppression, it needs PipeWire and the RNNoise LADSPA plugin (noise-suppression-for-voice): {}",
+                err
+            ),
+        }
+    }
+
+    async fn start(&mut self, microphone: Option<String>) -> Result<(), String> {
+        let path = config_path();
+        std::fs::write(&path, filter_config(microphone.as_deref()))
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
+
+        let mut child = Command::new("pipewire")
+            .arg("-c")
+            .arg(&path)
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("cannot run pipewire: {}", err))?;
+
+        let started = tokio::time::Instant::now();
+        while !source_exists().await {
+            if let Ok(Some(status)) = child.try_wait() {
+                return Err(format!("the filter exited with {}", status));
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                let _ = child.kill().await;
+                return Err("the filter did not start in time".to_string());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let pid = child.id().unwrap_or_default();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let action_tx = self.action_tx.clone();
+        let watcher = tokio::spawn(async move {
+            tokio::select! {
+                status = child.wait() => {
+                    // Its source is gone, recording it would record nothing
+                    RUNNING.store(false, Ordering::Relaxed);
+                    let _ = action_tx
+                        .send(ActionEvent::NoiseSuppressionExited(
+                            pid,
+                            status.ok().and_then(|status| status.code()),
+                        ))
+                        .await;
+                }
+                _ = stop_rx => {
+                    let _ = child.kill().await;
+                }
+            }
+        });
+
+        self.filter = Some(Filter {
+            pid,
+            microphone,
+            stop_tx,
+            watcher,
+        });
+        RUNNING.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(filter) = self.filter.take() {
+            RUNNING.store(false, Ordering::Relaxed);
+            let _ = filter.stop_tx.send(());
+            let _ = filter.watcher.await;
+            info!("Stopped noise suppression");
+        }
+    }
+
+    /// Forgets the filter of process `pid`, which exited on its own. Returns whether it was the
+    /// running one, the recorder then has to be restarted to record the real microphone.
+    pub fn exited(&mut self, pid: u32) -> bool {
+        if self.filter.as_ref().is_none_or(|filter| filter.pid != pid) {
+            return false;
+        }
+
+        self.filter = None;
+        true
+    }
+}
+
+fn config_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("trayplay-noise-suppression.conf")
+}
+
+async fn source_exists() -> bool {
+    let Ok(output) = Command::new("pactl")
+        .args(["list", "short", "sources"])
+        .output()
+        .await
+    else {
+        return false;
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line.split('\t').nth(1) == Some(SOURCE_NAME))
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+use log::warn;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::broadcast,
+};
+
+use crate::{config::SaveSource, recorder::RecorderStatus, timeline};
+
+/// Events kept for clients that connect later, `trayplay events` prints them
+const HISTORY_LENGTH: usize = 100;
+/// Events a slow client can fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 256;
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<String> = broadcast::channel(CHANNEL_CAPACITY).0;
+}
+
+static HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Something scripts and overlays can react to, sent to them as one JSON object per line
+pub enum Event {
+    SaveStarted(SaveSource),
+    SaveFinished {
+        path: PathBuf,
+        duration: Option<Duration>,
+    },
+    SaveFailed(String),
+    /// The replay buffer started, stopped or was paused
+    BufferChanged {
+        status: RecorderStatus,
+        pause_reasons: Vec<String>,
+    },
+    /// "Start recording" in the tray menu started recording into `path`
+    RecordingStarted(PathBuf),
+    RecordingFinished(PathBuf),
+    /// Anything logged as an error
+    Error(String),
+}
+
+impl Event {
+    /// How the event reads in the Statistics window's timeline, if it's shown there
+    fn timeline_entry(&self) -> Option<String> {
+        match self {
+            Event::SaveStarted(source) => Some(format!("Save requested ({})", source.name())),
+            Event::SaveFinished { path, .. } => Some(format!(
+                "Saved {}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            // Logged as an error as well, which says why
+            Event::SaveFailed(_) => None,
+            Event::BufferChanged {
+                status,
+                pause_reasons,
+            } if pause_reasons.is_empty() => Some(format!("Replay buffer {}", status)),
+            Event::BufferChanged { pause_reasons, .. } => Some(format!(
+                "Replay buffer paused: {}",
+                pause_reasons.join(", ")
+            )),
+            Event::RecordingStarted(_) => Some("Recording started".to_string()),
+            Event::RecordingFinished(path) => Some(format!(
+                "Recorded {}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            Event::Error(message) => Some(format!("Error: {}", message)),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let (name, fields) = match self {
+            Event::SaveStarted(source) => ("save_started", json!({ "source": source.name() })),
+            Event::SaveFinished { path, duration } => (
+                "save_finished",
+                json!({
+                    "path": path.to_string_lossy(),
+                    "duration_secs": duration.map(|duration| duration.as_secs_f64()),
+                }),
+            ),
+            Event::SaveFailed(error) => ("save_failed", json!({ "error": error })),
+            Event::BufferChanged {
+                status,
+                pause_reasons,
+            } => (
+                "buffer_changed",
+                json!({
+                    "status": status.to_string(),
+                    "pause_reasons": pause_reasons,
+                }),
+            ),
+            Event::RecordingStarted(path) => (
+                "recording_started",
+                json!({ "path": path.to_string_lossy() }),
+            ),
+            Event::RecordingFinished(path) => (
+                "recording_finished",
+                json!({ "path": path.to_string_lossy() }),
+            ),
+            Event::Error(message) => ("error", json!({ "message": message })),
+        };
+
+        let mut json = json!({ "event": name, "time": time });
+        if let (Value::Object(json), Value::Object(fields)) = (&mut json, fields) {
+            json.extend(fields);
+        }
+        json.to_string()
+    }
+}
+
+/// Sends `event` to every connected client. Can be called from any thread.
+pub fn publish(event: Event) {
+    if let Some(entry) = event.timeline_entry() {
+        timeline::record(entry);
+    }
+    let json = event.to_json();
+    {
+        let mut history = HISTORY.lock().unwrap();
+        if history.len() == HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(json.clone());
+    }
+    // Nobody listening is fine
+    let _ = EVENTS.send(json);
+}
+
+/// The socket is only made in the runtime directory, which only the user can read
+pub fn socket_path() -> Option<PathBuf> {
+    dirs::runtime_dir().map(|dir| dir.join("trayplay-events.sock"))
+}
+
+/// Serves events on [`socket_path`]. A client writes `follow` or `history` on a line, gets the
+/// recent events and, when following, every new one as it happens. Returns the path of the
+/// socket if it was made, for removing it on quit.
+pub fn setup_event_socket() -> Option<PathBuf> {
+    let Some(path) = socket_path() else {
+        warn!("There is no runtime directory, event clients can't connect");
+        return None;
+    };
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        warn!(
+            "Another TrayPlay is serving events on {}, event clients will connect to it",
+            path.display()
+        );
+        return None;
+    }
+    // Left behind by an instance that didn't quit cleanly
+    let _ = std::fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!(
+                "Cannot listen for event clients on {}: {}",
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_client(stream));
+        }
+    });
+
+    Some(path)
+}
+
+async fn serve_client(stream: UnixStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut mode = String::new();
+    if BufReader::new(reader).read_line(&mut mode).await.is_err() {
+        return;
+    }
+
+    // Subscribed before the history is sent, so nothing falls in between
+    let mut events = EVENTS.subscribe();
+    let history = HISTORY
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|event| format!("{}\n", event))
+        .collect::<String>();
+    if writer.write_all(history.as_bytes()).await.is_err() || mode.trim() != "follow" {
+        return;
+    }
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if writer
+            .write_all(format!("{}\n", event).as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Connects to the running TrayPlay and copies its events to stdout
+pub async fn print_events(socket: &Path, follow: bool) -> std::io::Result<()> {
+    let stream = UnixStream::connect(socket).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(if follow { b"follow\n" } else { b"history\n" })
+        .await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        stdout.write_all(format!("{}\n", line).as_bytes()).await?;
+        // Read by other programs through a pipe, which would buffer otherwise
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::{
+    ffi::CStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+
+/// Conventions of the user's locale that matter for the few numbers, dates and times TrayPlay
+/// shows, as the C library has them. There is no translation.
+struct Locale {
+    decimal_separator: char,
+    clock_24h: bool,
+    date_order: DateOrder,
+    date_separator: char,
+}
+
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// Item of the C library's locale data, empty if it has none
+fn langinfo(locale: nix::libc::locale_t, item: nix::libc::nl_item) -> String {
+    if locale.is_null() {
+        return String::new();
+    }
+    let value = unsafe { nix::libc::nl_langinfo_l(item, locale) };
+    if value.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Order and separator of a strftime date format, "%d.%m.%Y" -> (DayMonthYear, '.')
+fn date_format(format: &str) -> (DateOrder, char) {
+    let format = format.replace("%D", "%m/%d/%y").replace("%F", "%Y-%m-%d");
+    let position = |conversions: &[&str]| {
+        conversions
+            .iter()
+            .filter_map(|conversion| format.find(conversion))
+            .min()
+            .unwrap_or(usize::MAX)
+    };
+    let (day, month, year) = (
+        position(&["%d", "%e"]),
+        position(&["%m", "%b"]),
+        position(&["%Y", "%y"]),
+    );
+    let order = if year < month {
+        DateOrder::YearMonthDay
+    } else if month < day {
+        DateOrder::MonthDayYear
+    } else {
+        DateOrder::DayMonthYear
+    };
+    // The first character between two conversions, locales that write words there get a dash
+    let separator = format
+        .split('%')
+        .skip(1)
+        .filter_map(|part| part.chars().nth(1))
+        .find(|char| !char.is_whitespace())
+        .filter(|char| char.is_ascii_punctuation())
+        .unwrap_or('-');
+
+    (order, separator)
+}
+
+impl Locale {
+    /// Reads the conventions from the C library's data for the locale set in the environment.
+    /// Without one, or if it isn't installed, the C locale's are used.
+    fn from_env() -> Self {
+        let new_locale = |name: &CStr| unsafe {
+            nix::libc::newlocale(
+                nix::libc::LC_NUMERIC_MASK | nix::libc::LC_TIME_MASK,
+                name.as_ptr(),
+                std::ptr::null_mut(),
+            )
+        };
+        let mut locale = new_locale(c"");
+        if locale.is_null() {
+            locale = new_locale(c"C");
+        }
+
+        let decimal_separator = langinfo(locale, nix::libc::RADIXCHAR)
+            .chars()
+            .next()
+            .unwrap_or('.');
+        let time_format = langinfo(locale, nix::libc::T_FMT);
+        let clock_24h = !["%I", "%l", "%r"]
+            .iter()
+            .any(|conversion| time_format.contains(conversion));
+        let (date_order, date_separator) = date_format(&langinfo(locale, nix::libc::D_FMT));
+        if !locale.is_null() {
+            unsafe { nix::libc::freelocale(locale) };
+        }
+
+        Self {
+            decimal_separator,
+            clock_24h,
+            date_order,
+            date_separator,
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOCALE: Locale = Locale::from_env();
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{} {}", format_decimal(size, 1), UNITS[unit])
+    }
+}
+
+/// "," or "." as numbers are written in the user's locale
+pub fn decimal_separator() -> char {
+    LOCALE.decimal_separator
+}
+
+/// `value` with `decimals` digits after the locale's decimal separator
+pub fn format_decimal(value: f64, decimals: usize) -> String {
+    format!("{:.*}", decimals, value).replace('.', &LOCALE.decimal_separator.to_string())
+}
+
+/// Local time of `time` with its date, or just the time if it's today
+pub fn format_date_time(time: SystemTime) -> String {
+    let Some(local) = local_time(time) else {
+        return String::new();
+    };
+
+    let clock = format_clock(&local);
+    let today = local_time(SystemTime::now());
+    if today.is_some_and(|today| today.tm_yday == local.tm_yday && today.tm_year == local.tm_year) {
+        return clock;
+    }
+
+    format!("{} {}", format_date(&local), clock)
+}
+
+/// Local time of `time` with its date, even if it's today
+pub fn format_full_date_time(time: SystemTime) -> String {
+    let Some(local) = local_time(time) else {
+        return String::new();
+    };
+
+    format!("{} {}", format_date(&local), format_clock(&local))
+}
+
+fn format_clock(local: &nix::libc::tm) -> String {
+    if LOCALE.clock_24h {
+        format!("{:02}:{:02}", local.tm_hour, local.tm_min)
+    } else {
+        let hour = match local.tm_hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        let suffix = if local.tm_hour < 12 { "AM" } else { "PM" };
+        format!("{}:{:02} {}", hour, local.tm_min, suffix)
+    }
+}
+
+fn format_date(local: &nix::libc::tm) -> String {
+    let (year, month, day) = (local.tm_year + 1900, local.tm_mon + 1, local.tm_mday);
+    let separator = LOCALE.date_separator;
+    match LOCALE.date_order {
+        DateOrder::DayMonthYear => {
+            format!("{:02}{}{:02}{}{}", day, separator, month, separator, year)
+        }
+        DateOrder::MonthDayYear => {
+            format!("{:02}{}{:02}{}{}", month, separator, day, separator, year)
+        }
+        DateOrder::YearMonthDay => {
+            format!("{}{}{:02}{}{:02}", year, separator, month, separator, day)
+        }
+    }
+}
+
+/// Local time of `time` for file names, sorting by name sorts by time: "2025-10-09_14-05-09"
+pub fn format_file_time(time: SystemTime) -> String {
+    let Some(local) = local_time(time) else {
+        return String::new();
+    };
+
+    format!(
+        "{}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        local.tm_year + 1900,
+        local.tm_mon + 1,
+        local.tm_mday,
+        local.tm_hour,
+        local.tm_min,
+        local.tm_sec
+    )
+}
+
+/// Broken-down local time, using the system's time zone
+fn local_time(time: SystemTime) -> Option<nix::libc::tm> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as nix::libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<nix::libc::tm>() };
+    let result = unsafe { nix::libc::localtime_r(&secs, &mut tm) };
+
+    (!result.is_null()).then_some(tm)
+}
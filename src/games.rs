@@ -0,0 +1,115 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use log::info;
+use tokio::sync::{RwLock, mpsc::Sender, watch};
+
+use crate::{ActionEvent, config::Config};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Name of a game launched by Steam, Lutris or Heroic, found in the environment the launcher
+/// gives the game's processes
+fn game_from_environ(environ: &[u8]) -> Option<String> {
+    let mut steam_app_id = None;
+    let mut lutris_game = None;
+    let mut heroic_app = None;
+    for variable in environ.split(|byte| *byte == 0) {
+        let variable = String::from_utf8_lossy(variable);
+        let Some((name, value)) = variable.split_once('=') else {
+            continue;
+        };
+        match name {
+            // Steam sets 0 for some of its own helpers
+            "SteamAppId" if value != "0" => steam_app_id = Some(value.to_string()),
+            "GAME_NAME" => lutris_game = Some(value.to_string()),
+            "HEROIC_APP_NAME" => heroic_app = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    steam_app_id
+        .map(|app_id| steam_game_name(&app_id).unwrap_or(format!("steam_app_{}", app_id)))
+        .or(lutris_game)
+        .or(heroic_app)
+        .filter(|game| !game.is_empty())
+        // Used as the name of the folder replays are saved to
+        .map(|game| game.replace('/', "-"))
+}
+
+/// Name from the app manifest Steam keeps for every installed game
+fn steam_game_name(app_id: &str) -> Option<String> {
+    let home = dirs::home_dir()?;
+    let libraries: [PathBuf; 3] = [
+        home.join(".local/share/Steam/steamapps"),
+        home.join(".steam/steam/steamapps"),
+        home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam/steamapps"),
+    ];
+    let manifest = libraries.iter().find_map(|library| {
+        std::fs::read_to_string(library.join(format!("appmanifest_{}.acf", app_id))).ok()
+    })?;
+
+    // "name"		"Dota 2"
+    manifest.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("\"name\"")?.trim();
+        Some(value.trim_matches('"').to_string())
+    })
+}
+
+/// The first game found among the user's processes
+fn running_game() -> Option<String> {
+    std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        // Processes of other users can't be read
+        .filter_map(|entry| std::fs::read(entry.path().join("environ")).ok())
+        .find_map(|environ| game_from_environ(&environ))
+}
+
+/// Watches for games starting and stopping if game sessions are enabled in config. `game` is
+/// kept up to date with the running game; every change, and every change of the game session
+/// settings, is reported with [`ActionEvent::GameSessionChanged`].
+pub fn setup_game_watcher(
+    action_tx: Sender<ActionEvent>,
+    config: Arc<RwLock<Config>>,
+) -> watch::Receiver<Option<String>> {
+    let (tx, rx) = watch::channel(None);
+    tokio::spawn(async move {
+        let mut last = None;
+
+        loop {
+            let settings = config.read().await.game_sessions;
+            let game = if settings.enabled {
+                tokio::task::spawn_blocking(running_game)
+                    .await
+                    .ok()
+                    .flatten()
+            } else {
+                None
+            };
+
+            let state = Some((settings, game.clone()));
+            if state != last {
+                if game != *tx.borrow() {
+                    match &game {
+                        Some(game) => info!("Game session of {} started", game),
+                        None => info!("Game session ended"),
+                    }
+                }
+                last = state;
+                tx.send_replace(game.clone());
+                if action_tx
+                    .send(ActionEvent::GameSessionChanged(game))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+
+    rx
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{ErrorKind, Read},
+    os::fd::AsRawFd,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+use nix::libc;
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{
+    ActionEvent,
+    config::{Config, SaveSource},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the listener checks whether it should stop while no key is pressed
+const STOP_CHECK_MS: i32 = 500;
+const EV_KEY: u16 = 1;
+const KEY_PRESSED: i32 = 1;
+/// Modifiers by their name in triggers, with the key codes of their left and right keys
+const MODIFIERS: [(&str, [u16; 2]); 4] = [
+    ("CTRL", [29, 97]),
+    ("SHIFT", [42, 54]),
+    ("ALT", [56, 100]),
+    ("SUPER", [125, 126]),
+];
+/// Letter keys by their place on a QWERTY keyboard, key codes are per place, not per letter
+const LETTER_ROWS: [(&str, u16); 3] = [("QWERTYUIOP", 16), ("ASDFGHJKL", 30), ("ZXCVBNM", 44)];
+
+/// A shortcut trigger like "ALT+F10", as key codes
+#[derive(Debug, PartialEq)]
+struct Trigger {
+    modifiers: Vec<[u16; 2]>,
+    key: u16,
+}
+
+impl Trigger {
+    /// Takes the notation of the global shortcuts portal, e.g. "ALT+SHIFT+F9"
+    fn parse(trigger: &str) -> Option<Trigger> {
+        let mut modifiers = Vec::new();
+        let mut key = None;
+        for part in trigger.split('+').map(|part| part.trim().to_uppercase()) {
+            let part = match part.as_str() {
+                "CONTROL" => "CTRL",
+                "LOGO" | "META" => "SUPER",
+                part => part,
+            };
+            match MODIFIERS.iter().find(|(name, _)| *name == part) {
+                Some((_, codes)) => modifiers.push(*codes),
+                None if key.is_none() => key = Some(key_code(part)?),
+                // Two keys that aren't modifiers
+                None => return None,
+            }
+        }
+
+        Some(Trigger {
+            modifiers,
+            key: key?,
+        })
+    }
+
+    /// Whether exactly the trigger's modifiers are held, so ALT+SHIFT+F10 isn't taken for ALT+F10
+    fn modifiers_match(&self, pressed: &HashSet<u16>) -> bool {
+        MODIFIERS.iter().all(|(_, codes)| {
+            let held = codes.iter().any(|code| pressed.contains(code));
+            held == self.modifiers.contains(codes)
+        })
+    }
+}
+
+/// Linux key code (see linux/input-event-codes.h) of a key that isn't a modifier
+fn key_code(name: &str) -> Option<u16> {
+    if let Some(number) = name.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        return match number {
+            1..=10 => Some(58 + number),
+            11 | 12 => Some(76 + number),
+            13..=24 => Some(170 + number),
+            _ => None,
+        };
+    }
+
+    let mut chars = name.chars();
+    if let (Some(char), None) = (chars.next(), chars.next()) {
+        if let Some(digit) = char.to_digit(10) {
+            // 1 to 9, then 0
+            return Some(if digit == 0 { 11 } else { digit as u16 + 1 });
+        }
+        return LETTER_ROWS
+            .iter()
+            .find_map(|(row, first)| row.find(char).map(|place| first + place as u16));
+    }
+
+    match name {
+        "PRINT" => Some(99),
+        "SCROLLLOCK" => Some(70),
+        "PAUSE" => Some(119),
+        "INSERT" => Some(110),
+        "HOME" => Some(102),
+        "END" => Some(107),
+        _ => None,
+    }
+}
+
+/// Whether gamescope runs, nested in the desktop or as its own session
+fn gamescope_running() -> bool {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+
+    processes
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        // gamescope, gamescope-wl
+        .any(|comm| comm.trim().starts_with("gamescope"))
+}
+
+/// Whether the bitmap in /sys/class/input/eventN/device/capabilities/key has `code`. It's
+/// written as hex words, the last one holding the lowest codes.
+fn has_key(capabilities: &str, code: u16) -> bool {
+    let bits = usize::BITS as usize;
+    capabilities
+        .split_whitespace()
+        .rev()
+        .nth(code as usize / bits)
+        .and_then(|word| usize::from_str_radix(word, 16).ok())
+        .is_some_and(|word| word & (1 << (code as usize % bits)) != 0)
+}
+
+/// Input devices that have the trigger's key, opened for reading. Needs read access to
+/// /dev/input, usually by being in the input group.
+fn open_keyboards(trigger: &Trigger) -> std::io::Result<Vec<File>> {
+    let mut keyboards = Vec::new();
+    let mut denied = None;
+    for entry in std::fs::read_dir("/sys/class/input")?.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("event") {
+            continue;
+        }
+        let capabilities = std::fs::read_to_string(entry.path().join("device/capabilities/key"))
+            .unwrap_or_default();
+        if !has_key(&capabilities, trigger.key) {
+            continue;
+        }
+
+        match File::open(format!("/dev/input/{}", name)) {
+            Ok(keyboard) => keyboards.push(keyboard),
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => denied = Some(err),
+            Err(err) => info!("Cannot open /dev/input/{}: {}", name, err),
+        }
+    }
+
+    match denied {
+        Some(err) if keyboards.is_empty() => Err(err),
+        _ => Ok(keyboards),
+    }
+}
+
+/// Reads key presses of `keyboards` until `stop` is set, saving a replay on every press of
+/// `trigger`. Blocks.
+fn listen(
+    mut keyboards: Vec<File>,
+    trigger: Trigger,
+    stop: Arc<AtomicBool>,
+    action_tx: Sender<ActionEvent>,
+) {
+    const EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+    let mut pressed = HashSet::new();
+    let mut buffer = [0u8; EVENT_SIZE * 64];
+
+    while !stop.load(Ordering::Relaxed) && !keyboards.is_empty() {
+        let mut fds: Vec<libc::pollfd> = keyboards
+            .iter()
+            .map(|keyboard| libc::pollfd {
+                fd: keyboard.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let ready =
+            unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, STOP_CHECK_MS) };
+        if ready < 0 {
+            if std::io::Error::last_os_error().kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+
+        let mut unplugged = Vec::new();
+        for (index, fd) in fds.iter().enumerate() {
+            if fd.revents & (libc::POLLERR | libc::POLLHUP) != 0 {
+                unplugged.push(index);
+                continue;
+            }
+            if fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+
+            let Ok(read) = keyboards[index].read(&mut buffer) else {
+                unplugged.push(index);
+                continue;
+            };
+            for chunk in buffer[..read].chunks_exact(EVENT_SIZE) {
+                let event: libc::input_event =
+                    unsafe { std::ptr::read_unaligned(chunk.as_ptr().cast()) };
+                if event.type_ != EV_KEY {
+                    continue;
+                }
+                // Held keys repeat with 2, those aren't new presses
+                match event.value {
+                    KEY_PRESSED => {
+                        if event.code == trigger.key && trigger.modifiers_match(&pressed) {
+                            info!("Save hotkey pressed inside gamescope");
+                            if action_tx
+                                .blocking_send(ActionEvent::SaveReplay(SaveSource::Hotkey))
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                        pressed.insert(event.code);
+                    }
+                    0 => {
+                        pressed.remove(&event.code);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for index in unplugged.into_iter().rev() {
+            keyboards.remove(index);
+        }
+    }
+}
+
+/// Listens for the save hotkey on the keyboards themselves while gamescope runs and
+/// `gamescope_hotkey` is enabled, as gamescope keeps key presses from the desktop's global
+/// shortcuts
+pub fn setup_gamescope_hotkey(action_tx: Sender<ActionEvent>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        // Trigger the running listener was started for, and how to stop it
+        let mut listener: Option<(String, Arc<AtomicBool>)> = None;
+        // Not warned about again until the trigger changes
+        let mut failed_trigger = None;
+
+        loop {
+            let settings = config.read().await.gamescope_hotkey.clone();
+            let active = settings.enabled
+                && tokio::task::spawn_blocking(gamescope_running)
+                    .await
+                    .unwrap_or(false);
+
+            if let Some((trigger, stop)) = &listener {
+                if stop.load(Ordering::Relaxed) {
+                    // Its keyboards were unplugged, it's started again with the current ones
+                    listener = None;
+                } else if !active || *trigger != settings.trigger {
+                    stop.store(true, Ordering::Relaxed);
+                    listener = None;
+                    info!("Stopped listening for the save hotkey inside gamescope");
+                }
+            }
+            if !active {
+                // Tried again with the next game, e.g. after joining the input group
+                failed_trigger = None;
+            }
+
+            if active && listener.is_none() && failed_trigger.as_ref() != Some(&settings.trigger) {
+                let keyboards = Trigger::parse(&settings.trigger)
+                    .ok_or_else(|| {
+                        format!("\"{}\" is not a trigger like \"ALT+F10\"", settings.trigger)
+                    })
+                    .and_then(|trigger| match open_keyboards(&trigger) {
+                        Ok(keyboards) if keyboards.is_empty() => {
+                            Err("no keyboard was found".to_string())
+                        }
+                        Ok(keyboards) => Ok((trigger, keyboards)),
+                        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                            Err("keyboards cannot be read, add yourself to the input group"
+                                .to_string())
+                        }
+                        Err(err) => Err(err.to_string()),
+                    });
+
+                match keyboards {
+                    Ok((trigger, keyboards)) => {
+                        info!(
+                            "Listening for {} on {} keyboard(s) while gamescope runs",
+                            settings.trigger,
+                            keyboards.len()
+                        );
+                        let stop = Arc::new(AtomicBool::new(false));
+                        let action_tx = action_tx.clone();
+                        let listener_stop = stop.clone();
+                        tokio::task::spawn_blocking(move || {
+                            listen(keyboards, trigger, listener_stop.clone(), action_tx);
+                            listener_stop.store(true, Ordering::Relaxed);
+                        });
+                        listener = Some((settings.trigger.clone(), stop));
+                        failed_trigger = None;
+                    }
+                    Err(reason) => {
+                        warn!(
+                            "Cannot listen for the save hotkey inside gamescope: {}",
+                            reason
+                        );
+                        failed_trigger = Some(settings.trigger.clone());
+                    }
+                }
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,125 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::info;
+
+use crate::config::Config;
+
+/// GPU vendors by their PCI vendor id
+const VENDORS: [(&str, &str); 3] = [("0x10de", "NVIDIA"), ("0x1002", "AMD"), ("0x8086", "Intel")];
+
+/// A GPU that can capture and encode, as found in /sys/class/drm
+#[derive(Debug, Clone)]
+pub struct Gpu {
+    /// e.g. /dev/dri/card1
+    pub card: PathBuf,
+    /// e.g. /dev/dri/renderD129, used for VA-API
+    pub render_node: Option<PathBuf>,
+    /// "NVIDIA", "AMD", "Intel" or the PCI vendor id of others
+    pub vendor: String,
+    /// Kernel driver, "nvidia" for the proprietary NVIDIA one
+    pub driver: String,
+    /// PCI address as Mesa's DRI_PRIME takes it, e.g. pci-0000_01_00_0
+    pub pci_tag: Option<String>,
+}
+
+impl Display for Gpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.vendor,
+            self.card.file_name().unwrap_or_default().to_string_lossy()
+        )
+    }
+}
+
+impl Gpu {
+    /// Whether `gpu` from config (a card path like /dev/dri/card1 or a vendor like "nvidia")
+    /// means this GPU
+    pub fn matches(&self, gpu: &str) -> bool {
+        Path::new(gpu) == self.card || self.vendor.eq_ignore_ascii_case(gpu)
+    }
+
+    /// Makes `command` and the graphics libraries it loads use this GPU
+    pub fn apply_env(&self, command: &mut Command) {
+        if self.driver == "nvidia" {
+            // What prime-run sets, DRI_PRIME is Mesa only
+            command
+                .env("__NV_PRIME_RENDER_OFFLOAD", "1")
+                .env("__GLX_VENDOR_LIBRARY_NAME", "nvidia")
+                .env("__VK_LAYER_NV_optimus", "NVIDIA_only");
+        } else if let Some(pci_tag) = &self.pci_tag {
+            command.env("DRI_PRIME", pci_tag);
+        }
+    }
+}
+
+/// GPUs of this machine, in the order of their cards
+pub fn gpus() -> Vec<Gpu> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+
+    let mut gpus: Vec<Gpu> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            // card1-eDP-1 and the like are its connectors
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("card") && !name.contains('-')
+        })
+        .filter_map(|entry| {
+            let device = entry.path().join("device");
+            let vendor_id = std::fs::read_to_string(device.join("vendor")).ok()?;
+            let vendor_id = vendor_id.trim();
+            let vendor = VENDORS
+                .iter()
+                .find(|(id, _)| *id == vendor_id)
+                .map_or(vendor_id, |(_, name)| name)
+                .to_string();
+            let driver = std::fs::read_link(device.join("driver"))
+                .ok()
+                .and_then(|driver| Some(driver.file_name()?.to_string_lossy().into_owned()))
+                .unwrap_or_default();
+            // 0000:01:00.0
+            let pci_tag = std::fs::read_link(&device).ok().and_then(|path| {
+                Some(format!(
+                    "pci-{}",
+                    path.file_name()?.to_string_lossy().replace([':', '.'], "_")
+                ))
+            });
+            let render_node = std::fs::read_dir(device.join("drm"))
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .find(|name| name.starts_with("renderD"))
+                .map(|name| Path::new("/dev/dri").join(name));
+
+            Some(Gpu {
+                card: Path::new("/dev/dri").join(entry.file_name()),
+                render_node,
+                vendor,
+                driver,
+                pci_tag,
+            })
+        })
+        .collect();
+    gpus.sort_by(|a, b| a.card.cmp(&b.card));
+    gpus
+}
+
+/// The GPU `gpu` in config names, `None` when it's not set or the GPU is missing
+pub fn selected(config: &Config) -> Option<Gpu> {
+    let wanted = config.gpu.as_deref()?;
+    let gpu = gpus().into_iter().find(|gpu| gpu.matches(wanted));
+    if gpu.is_none() {
+        // Not a warning, it would show a dialog on every start of the recorder
+        info!("GPU {} not found, letting the recorder pick one", wanted);
+    }
+    gpu
+}
@@ -1,118 +1,592 @@
 use std::{
-    fmt::Display,
+    collections::{BTreeSet, VecDeque},
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     str::FromStr,
     sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
-use log::debug;
+use log::{debug, error, info};
 use nix::{
     sys::signal::{self, Signal},
     unistd::Pid,
 };
-use tokio::{sync::RwLock, task::JoinHandle};
+use tokio::{
+    sync::{Notify, RwLock, mpsc::Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    ActionEvent,
+    config::{BitDepth, CaptureTarget, Config, Container, Encoder, RecorderBackend},
+    format::format_file_time,
+    gsr_command::{GsrCommandBuilder, container_problem},
+    gsr_info,
+    jobs::JobQueue,
+    kms, monitors,
+    recorder::{Error, Recorder, RecorderStatus, TURNED_OFF, replay_saved},
+    stats::Statistics,
+};
+
+/// Why HDR can't be recorded with the current config and system, if it can't
+pub fn hdr_support(config: &Config) -> Result<(), String> {
+    hdr_capture_support(config)?;
+    match container_problem(config.container, "av1_hdr") {
+        Some(problem) => Err(problem),
+        None => Ok(()),
+    }
+}
+
+/// Like [`hdr_support`], but leaves the container to [`GsrCommandBuilder::validate`], which
+/// refuses to record HDR into one that can't hold it
+fn hdr_capture_support(config: &Config) -> Result<(), String> {
+    if config.recorder_backend != RecorderBackend::GpuScreenRecorder {
+        return Err("only the gpu_screen_recorder backend can record HDR".to_string());
+    }
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        return Err("HDR is only captured in a Wayland session".to_string());
+    }
+    if config.encoder == Encoder::Software {
+        return Err("HDR needs GPU encoding".to_string());
+    }
+    if matches!(
+        config.capture_target(),
+        CaptureTarget::Portal | CaptureTarget::Window(_)
+    ) {
+        return Err(
+            "HDR can only be captured from a monitor, not a window or the portal".to_string(),
+        );
+    }
+    if kms::use_portal(config) {
+        return Err(
+            "monitors are captured through the portal, gsr-kms-server lacks the permission to capture them directly"
+                .to_string(),
+        );
+    }
+    if !gsr_info::video_codecs()
+        .iter()
+        .any(|codec| codec.ends_with("_hdr"))
+    {
+        return Err("the GPU cannot encode HDR video with gpu-screen-recorder".to_string());
+    }
+
+    Ok(())
+}
+
+/// HDR variant of the preset's codec, or of HEVC, when HDR is on and can be captured
+pub fn hdr_codec(config: &Config) -> Option<&'static str> {
+    if !config.hdr {
+        return None;
+    }
+    if let Err(reason) = hdr_capture_support(config) {
+        info!("Recording without HDR: {}", reason);
+        return None;
+    }
+
+    let preset_codec = config
+        .active_quality_preset()
+        .and_then(|preset| preset.codec.clone())
+        .unwrap_or_default();
+    let supported = |codec: &str| {
+        gsr_info::video_codecs()
+            .iter()
+            .any(|supported| supported == codec)
+    };
+    if preset_codec.starts_with("av1") && supported("av1_hdr") {
+        Some("av1_hdr")
+    } else if supported("hevc_hdr") {
+        Some("hevc_hdr")
+    } else {
+        Some("av1_hdr")
+    }
+}
+
+/// 10-bit variant of the preset's codec, or of HEVC, when 10-bit is picked and `container`
+/// holds it. HDR codecs are 10-bit already.
+pub fn ten_bit_codec(config: &Config, container: Container) -> Option<&'static str> {
+    if config.bit_depth != BitDepth::Ten {
+        return None;
+    }
+    if config.encoder == Encoder::Software {
+        info!("Recording 8-bit video: software encoding only supports h264");
+        return None;
+    }
+
+    let preset_codec = config
+        .active_quality_preset()
+        .and_then(|preset| preset.codec.clone())
+        .unwrap_or_default();
+    let candidates = if preset_codec.starts_with("av1") {
+        ["av1_10bit", "hevc_10bit"]
+    } else {
+        ["hevc_10bit", "av1_10bit"]
+    };
+    let codec = candidates.into_iter().find(|codec| {
+        gsr_info::video_codecs()
+            .iter()
+            .any(|supported| supported == codec)
+            && container_problem(container, codec).is_none()
+    });
+    if codec.is_none() {
+        info!(
+            "Recording 8-bit video: the GPU cannot encode 10-bit into {} with gpu-screen-recorder",
+            container.to_string()
+        );
+    }
+
+    codec
+}
+
+/// The replay buffer's gpu-screen-recorder invocation
+pub fn replay_command(config: &Config) -> Result<Command, Error> {
+    GsrCommandBuilder::new(config, config.container)
+        .replay(config.replay_duration_secs, config.replay_storage)
+        .restart_replay_on_save(config.clear_buffer_on_save)
+        .output(config.replay_dir())
+        .build()
+}
+
+/// Environment variables set for `command` as NAME=value, then its program and arguments
+pub fn command_line(command: &Command) -> Vec<String> {
+    command
+        .get_envs()
+        .filter_map(|(name, value)| {
+            Some(format!(
+                "{}={}",
+                name.to_string_lossy(),
+                value?.to_string_lossy()
+            ))
+        })
+        .chain(
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned()),
+        )
+        .collect()
+}
+
+/// Starts a command built by [`GsrCommandBuilder`], logging it whole so the effect of
+/// `extra_args` can be checked. `secret` (e.g. a stream key in the URL) is left out of the log.
+pub fn spawn_logged(command: &mut Command, secret: Option<&str>) -> std::io::Result<Child> {
+    let command_line = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| match secret {
+            Some(secret) if arg == secret => "<hidden>".to_string(),
+            _ => format!("{:?}", arg),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    info!("Running {}", command_line);
+    command.spawn()
+}
+
+/// Records straight to `output` until `duration` elapses or Ctrl+C is pressed.
+pub async fn record(
+    config: &Config,
+    output: &Path,
+    duration: Option<Duration>,
+) -> Result<(), Error> {
+    let container = output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(Container::from_extension)
+        .unwrap_or(config.container);
+
+    let mut process = spawn_logged(
+        &mut GsrCommandBuilder::new(config, container)
+            .output(output)
+            .build()?,
+        None,
+    )?;
+
+    match duration {
+        Some(duration) => {
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        None => {
+            tokio::signal::ctrl_c().await?;
+        }
+    }
 
-use crate::config::Config;
+    // gpu-screen-recorder finalizes the file on SIGINT. It may have already received one from
+    // the terminal together with us, so a missing process is not an error here.
+    match signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGINT) {
+        Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+        Err(err) => return Err(err.into()),
+    }
+    let status = process.wait()?;
+    if !status.success() {
+        return Err(Error::IoError(std::io::Error::other(format!(
+            "gpu-screen-recorder exited with {}",
+            status
+        ))));
+    }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub enum Error {
-    IoError(std::io::Error),
-    NixErrno(nix::errno::Errno),
-    RecorderNotRunning,
+    Ok(())
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "gpu-screen-recorder handler error: {:?}", self)
+/// Exit code of a process whose output has closed. One that keeps running without output is
+/// killed.
+async fn wait_for_exit(process: &mut Child) -> Option<i32> {
+    let deadline = Instant::now() + EXIT_TIMEOUT;
+    loop {
+        match process.try_wait() {
+            Ok(Some(status)) => return status.code(),
+            Ok(None) if Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(50)).await
+            }
+            Ok(None) => {
+                let _ = process.kill();
+                return process.wait().ok().and_then(|status| status.code());
+            }
+            Err(_) => return None,
+        }
     }
 }
 
-impl std::error::Error for Error {}
+/// Sends the screen to an RTMP server, separately from the replay buffer
+#[derive(Default)]
+pub struct LiveStream {
+    pid: Option<u32>,
+}
+
+impl LiveStream {
+    /// Starts streaming, [`ActionEvent::StreamExited`] is sent when gpu-screen-recorder exits
+    pub fn start(&mut self, config: &Config, action_tx: Sender<ActionEvent>) -> Result<(), Error> {
+        let Some(target) = config.streaming.target() else {
+            return Err(Error::InvalidOptions(
+                "no streaming server is set".to_string(),
+            ));
+        };
+        let mut process = spawn_logged(
+            &mut GsrCommandBuilder::new(config, Container::FLV)
+                .bitrate_kbps(config.streaming.video_bitrate_kbps)
+                .output(&target)
+                .build()?,
+            Some(&target),
+        )?;
+
+        info!("Started streaming");
+        let pid = process.id();
+        self.pid = Some(pid);
+        tokio::spawn(async move {
+            let status = tokio::task::spawn_blocking(move || process.wait()).await;
+            let code = status
+                .ok()
+                .and_then(|status| status.ok())
+                .and_then(|status| status.code());
+            let _ = action_tx.send(ActionEvent::StreamExited(pid, code)).await;
+        });
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), Error> {
+        let Some(pid) = self.pid.take() else {
+            return Err(Error::RecorderNotRunning);
+        };
+
+        match signal::kill(Pid::from_raw(pid as i32), Signal::SIGINT) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => return Err(err.into()),
+        }
+        info!("Stopped streaming");
+
+        Ok(())
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.pid.is_some()
+    }
+
+    /// Forgets the stream of process `pid`, which exited. Returns whether it was still live,
+    /// i.e. gpu-screen-recorder exited on its own, e.g. because the connection failed.
+    pub fn exited(&mut self, pid: u32) -> bool {
+        if self.pid != Some(pid) {
+            return false;
+        }
 
-impl From<std::io::Error> for Error {
-    fn from(value: std::io::Error) -> Self {
-        Self::IoError(value)
+        self.pid = None;
+        true
     }
 }
 
-impl From<nix::errno::Errno> for Error {
-    fn from(value: nix::errno::Errno) -> Self {
-        Self::NixErrno(value)
+/// Records everything into a file until stopped, next to the replay buffer and with its quality,
+/// container and capture settings
+#[derive(Default)]
+pub struct Recording {
+    process: Option<Child>,
+    /// File being recorded to
+    path: Option<PathBuf>,
+}
+
+impl Recording {
+    /// Starts recording into `app`'s folder in the replay directory, named like its replays.
+    /// Returns the file.
+    pub fn start(&mut self, config: &Config, app: &str) -> Result<PathBuf, Error> {
+        let mut config = config.clone();
+        monitors::resolve_screen(&mut config)?;
+
+        let dir = config.replay_dir().join(app);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "{}_recording_{}.{}",
+            app,
+            format_file_time(SystemTime::now()),
+            config.container.to_string()
+        ));
+        let process = spawn_logged(
+            &mut GsrCommandBuilder::new(&config, config.container)
+                .output(&path)
+                .build()?,
+            None,
+        )?;
+
+        info!("Started recording to {}", path.display());
+        self.process = Some(process);
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    /// Finalizes the file, returns it. Waits for gpu-screen-recorder to finish writing, which
+    /// takes a while for long recordings.
+    pub async fn stop(&mut self) -> Result<PathBuf, Error> {
+        let (Some(mut process), Some(path)) = (self.process.take(), self.path.take()) else {
+            return Err(Error::RecorderNotRunning);
+        };
+
+        match signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGINT) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => return Err(err.into()),
+        }
+        let status = tokio::task::spawn_blocking(move || process.wait())
+            .await
+            .map_err(std::io::Error::other)??;
+        if !status.success() {
+            return Err(Error::IoError(std::io::Error::other(format!(
+                "gpu-screen-recorder exited with {}",
+                status
+            ))));
+        }
+        info!("Stopped recording to {}", path.display());
+
+        Ok(path)
+    }
+
+    /// Whether it's recording. gpu-screen-recorder exits on its own if capturing fails.
+    pub fn is_recording(&mut self) -> bool {
+        match &mut self.process {
+            Some(process) => matches!(process.try_wait(), Ok(None)),
+            None => false,
+        }
     }
 }
 
+/// Parts of the warnings gpu-screen-recorder prints when the encoder can't keep up
+const OVERLOAD_WARNINGS: [&str; 3] = ["overload", "dropped frame", "can't keep up"];
+
+/// Number of overload warnings within `OVERLOAD_WINDOW` after which quality gets lowered
+const OVERLOAD_THRESHOLD: usize = 5;
+const OVERLOAD_WINDOW: Duration = Duration::from_secs(60);
+
+/// Delay before restarting a crashed recorder, doubled with every crash in a row
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+/// Crashes in a row after which it's not restarted anymore
+const MAX_RESTARTS: u32 = 5;
+/// A recorder that ran this long before crashing starts counting crashes from zero again
+const STABLE_RUN: Duration = Duration::from_secs(60);
+/// How long a restart waits for the buffer to be saved, see `save_before_restart`
+const SAVE_BEFORE_RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an exited recorder's process is waited for after its output closed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Replay lengths (in seconds) gpu-screen-recorder can save on SIGRTMIN+offset
+const SAVE_LAST_SIGNAL_OFFSETS: [(i64, i32); 6] =
+    [(10, 1), (30, 2), (60, 3), (300, 4), (600, 5), (1800, 6)];
+
 pub struct GpuScreenRecorder {
     process: Option<Child>,
     config: Arc<RwLock<Config>>,
     app_name: Arc<RwLock<String>>,
+    stats: Arc<RwLock<Statistics>>,
+    jobs: JobQueue,
+    action_tx: Sender<ActionEvent>,
+    last_replay: Arc<RwLock<Option<PathBuf>>>,
+    pause_reasons: BTreeSet<String>,
+    /// gpu-screen-recorder is kept running while turned off, see `warm_standby`
+    standby: bool,
+    /// When the buffer was turned back on from standby. What it recorded before that is never
+    /// saved.
+    resumed_from_standby: Option<Instant>,
     stdout_task_handle: Option<JoinHandle<()>>,
     stderr_task_handle: Option<JoinHandle<()>>,
+    /// What the running process was started with, see [`command_line`]
+    command_line: Vec<String>,
+    /// Process that was told to stop, its exit is not a crash
+    stopped_pid: Option<u32>,
+    started_at: Option<Instant>,
+    /// Crashes in a row, see `MAX_RESTARTS`
+    crashes: u32,
+    /// Gave up restarting after too many crashes
+    failed: bool,
+    /// Notified when gpu-screen-recorder has written a replay
+    replay_written: Arc<Notify>,
 }
 
 impl GpuScreenRecorder {
     pub async fn new(
         config: Arc<RwLock<Config>>,
         app_name: Arc<RwLock<String>>,
+        stats: Arc<RwLock<Statistics>>,
+        jobs: JobQueue,
+        action_tx: Sender<ActionEvent>,
     ) -> Result<Self, Error> {
         Ok(Self {
             process: None,
             config,
             app_name,
+            stats,
+            jobs,
+            action_tx,
+            last_replay: Arc::new(RwLock::new(None)),
+            pause_reasons: BTreeSet::new(),
+            standby: false,
+            resumed_from_standby: None,
+            command_line: Vec::new(),
+            stopped_pid: None,
+            started_at: None,
+            crashes: 0,
+            failed: false,
+            replay_written: Arc::new(Notify::new()),
             stderr_task_handle: None,
             stdout_task_handle: None,
         })
     }
 
-    pub async fn start(&mut self) -> Result<(), Error> {
+    fn is_paused(&self) -> bool {
+        !self.pause_reasons.is_empty()
+    }
+
+    /// Signals gpu-screen-recorder to save the whole buffer, or the length at SIGRTMIN+`offset`
+    async fn request_save(&self, offset: Option<i32>) -> Result<(), Error> {
+        let Some(process) = &self.process else {
+            return Err(Error::RecorderNotRunning);
+        };
+
+        match offset {
+            Some(offset) => {
+                // Realtime signals are not covered by nix's Signal enum
+                let result =
+                    unsafe { nix::libc::kill(process.id() as i32, nix::libc::SIGRTMIN() + offset) };
+                nix::errno::Errno::result(result)?;
+            }
+            None => signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGUSR1)?,
+        }
+        self.stats.write().await.save_requested();
+
+        Ok(())
+    }
+
+    /// Saves the buffer and waits until it's written, so stopping the recorder doesn't cut the
+    /// save short
+    async fn save_before_restart(&mut self) {
+        // Registered before the save is requested, so a quick one isn't missed
+        let replay_written = self.replay_written.clone();
+        let written = replay_written.notified();
+        if let Err(err) = self.save_replay().await {
+            info!("Cannot save the replay buffer before restarting: {}", err);
+            return;
+        }
+
+        info!("Saving the replay buffer before restarting");
+        if tokio::time::timeout(SAVE_BEFORE_RESTART_TIMEOUT, written)
+            .await
+            .is_err()
+        {
+            info!("The replay buffer wasn't saved in time, restarting anyway");
+        }
+    }
+
+    /// Seconds since leaving standby, while the buffer still holds frames from before
+    async fn secs_since_standby(&self) -> Option<i64> {
+        let secs = self.resumed_from_standby?.elapsed().as_secs() as i64;
+        (secs < self.config.read().await.replay_duration_secs).then_some(secs)
+    }
+}
+
+impl Recorder for GpuScreenRecorder {
+    async fn start(&mut self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        if self.process.is_some() {
+            self.stats.write().await.record_restart();
+        }
+        self.resumed_from_standby = None;
+
+        monitors::resolve_screen(&mut *self.config.write().await)?;
+
         let config = self.config.read().await;
 
-        let mut process = Command::new("gpu-screen-recorder")
-            .arg("-w")
-            .arg(&config.screen)
-            .arg("-c")
-            .arg(config.container.to_string())
-            .arg("-f")
-            .arg(config.framerate.to_string())
-            .arg("-r")
-            .arg(config.replay_duration_secs.to_string())
-            .arg("-restart-replay-on-save")
-            .arg(if config.clear_buffer_on_save {
-                "yes"
-            } else {
-                "no"
-            })
-            .arg("-bm")
-            .arg("qp")
-            .arg("-q")
-            .arg(config.quality.to_string())
-            .args(config.audio_tracks.iter().flat_map(|track| ["-a", track]))
-            .arg("-o")
-            .arg(&config.replay_directory)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
+        let mut command = replay_command(&config)?;
+        self.command_line = command_line(&command);
+        let mut process =
+            spawn_logged(command.stdout(Stdio::piped()).stderr(Stdio::piped()), None)?;
+        let pid = process.id();
 
         let stderr = process.stderr.take().unwrap();
+        let action_tx = self.action_tx.clone();
         self.stderr_task_handle = Some(tokio::spawn(async move {
             let reader = BufReader::new(stderr);
+            let mut overload_warnings = VecDeque::new();
+            let mut overload_reported = false;
             for line in reader.lines().filter_map(|line| line.ok()) {
                 debug!(target: "gpu-screen-recorder stderr", "{}", line);
+
+                let lowercase = line.to_lowercase();
+                if overload_reported
+                    || !OVERLOAD_WARNINGS
+                        .iter()
+                        .any(|warning| lowercase.contains(warning))
+                {
+                    continue;
+                }
+
+                let now = Instant::now();
+                overload_warnings.push_back(now);
+                overload_warnings.retain(|time| now - *time < OVERLOAD_WINDOW);
+                if overload_warnings.len() >= OVERLOAD_THRESHOLD {
+                    // Reported once per process, lowering quality restarts the recorder anyway
+                    overload_reported = true;
+                    let _ = action_tx.try_send(ActionEvent::EncoderOverloaded);
+                }
             }
         }));
 
         let stdout = process.stdout.take().unwrap();
         let app_name_clone = self.app_name.clone();
         let config_clone = self.config.clone();
+        let stats_clone = self.stats.clone();
+        let jobs_clone = self.jobs.clone();
+        let last_replay_clone = self.last_replay.clone();
+        let action_tx = self.action_tx.clone();
+        let replay_written = self.replay_written.clone();
         self.stdout_task_handle = Some(tokio::spawn(async move {
             let reader = BufReader::new(stdout);
             for line in reader.lines().filter_map(|line| line.ok()) {
                 let path = PathBuf::from_str(&line)
                     .expect("gpu-screen-recorder stdout must only contain file paths");
+                replay_written.notify_waiters();
 
-                let mut target_path = config_clone.read().await.replay_directory.clone();
+                let mut target_path = config_clone.read().await.replay_dir();
                 target_path.push(app_name_clone.read().await.clone());
                 if !std::fs::exists(&target_path).unwrap() {
                     std::fs::create_dir(&target_path).unwrap()
@@ -127,17 +601,81 @@ impl GpuScreenRecorder {
                         ),
                 );
 
-                std::fs::rename(path, target_path).expect("failed to move replay");
+                match std::fs::rename(path, &target_path) {
+                    Ok(_) => {
+                        replay_saved(
+                            target_path,
+                            &config_clone,
+                            &stats_clone,
+                            &jobs_clone,
+                            &last_replay_clone,
+                            &action_tx,
+                        )
+                        .await
+                    }
+                    Err(err) => {
+                        error!("Failed to move replay: {}", err);
+                        stats_clone
+                            .write()
+                            .await
+                            .record_failure(format!("failed to move replay: {}", err));
+                    }
+                }
             }
+            // Its output closes when it exits, whether it was stopped or crashed
+            let _ = action_tx.send(ActionEvent::ReplayRecorderExited(pid)).await;
         }));
 
         self.process = Some(process);
+        self.started_at = Some(Instant::now());
+        // Started again after giving up, e.g. with other settings
+        if self.failed {
+            self.failed = false;
+            self.crashes = 0;
+        }
 
         Ok(())
     }
 
-    pub async fn stop(&mut self) -> Result<(), Error> {
+    /// Keeps the process, and the buffer, when the config gives the same command line, e.g.
+    /// after changing what happens to saved replays
+    async fn restart(&mut self) -> Result<(), Error> {
+        let running = self
+            .process
+            .as_mut()
+            .is_some_and(|process| matches!(process.try_wait(), Ok(None)));
+        if running && !self.standby && !self.is_paused() {
+            monitors::resolve_screen(&mut *self.config.write().await)?;
+            // A config the recorder can't start with keeps the running one
+            let command = replay_command(&*self.config.read().await)?;
+            if command_line(&command) == self.command_line {
+                info!("Recorder settings unchanged, keeping the replay buffer");
+                return Ok(());
+            }
+            if self.config.read().await.save_before_restart {
+                self.save_before_restart().await;
+            }
+        }
+
+        match self.stop().await {
+            Ok(_) | Err(Error::RecorderNotRunning) => {}
+            Err(err) => return Err(err),
+        }
+        self.start().await
+    }
+
+    async fn last_replay(&self) -> Option<PathBuf> {
+        self.last_replay.read().await.clone()
+    }
+
+    async fn set_last_replay(&self, path: PathBuf) {
+        *self.last_replay.write().await = Some(path);
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        self.standby = false;
         if let Some(process) = &self.process {
+            self.stopped_pid = Some(process.id());
             signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGTERM)?;
 
             Ok(())
@@ -146,13 +684,150 @@ impl GpuScreenRecorder {
         }
     }
 
-    pub async fn save_replay(&mut self) -> Result<(), Error> {
-        // info!("Saving replay from {}", self.app_name.read().await);
-        if let Some(process) = &self.process {
-            signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGUSR1)?;
-            Ok(())
+    /// The replay buffer can't be paused in gpu-screen-recorder, so it's discarded. Only with
+    /// `warm_standby` and when just turned off, it keeps recording but can't be saved.
+    async fn pause(&mut self, reason: &str) -> Result<(), Error> {
+        let was_paused = self.is_paused();
+        self.pause_reasons.insert(reason.to_string());
+        if self.process.is_none() || (was_paused && !self.standby) {
+            return Ok(());
+        }
+
+        // Privacy reasons always stop capture
+        let only_turned_off = self.pause_reasons.len() == 1 && reason == TURNED_OFF;
+        if only_turned_off && self.config.read().await.warm_standby {
+            info!("Replay recording turned off, keeping gpu-screen-recorder on standby");
+            self.standby = true;
         } else {
-            Err(Error::RecorderNotRunning)
+            info!("Pausing replay recording: {}", reason);
+            self.stop().await?;
+            self.process = None;
         }
+
+        Ok(())
+    }
+
+    async fn resume(&mut self, reason: &str) -> Result<(), Error> {
+        if self.pause_reasons.remove(reason) && !self.is_paused() {
+            if self.standby {
+                info!("Resuming replay recording from standby");
+                self.standby = false;
+                self.resumed_from_standby = Some(Instant::now());
+            } else {
+                info!("Resuming replay recording");
+                self.start().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_paused_for(&self, reason: &str) -> bool {
+        self.pause_reasons.contains(reason)
+    }
+
+    fn pause_reasons(&self) -> Vec<String> {
+        self.pause_reasons.iter().cloned().collect()
+    }
+
+    fn status(&self) -> RecorderStatus {
+        if self.is_paused() {
+            RecorderStatus::Paused
+        } else if self.failed {
+            RecorderStatus::Failed
+        } else if self.process.is_some() {
+            RecorderStatus::Running
+        } else {
+            RecorderStatus::Stopped
+        }
+    }
+
+    /// Restarts after `RESTART_BACKOFF`, doubled for every crash in a row, until
+    /// `MAX_RESTARTS` is reached
+    async fn exited(&mut self, pid: u32) -> Option<Duration> {
+        if self.stopped_pid == Some(pid) {
+            return None;
+        }
+        // Exits of processes that have already been replaced
+        let process = self
+            .process
+            .as_mut()
+            .filter(|process| process.id() == pid)?;
+        let code = wait_for_exit(process).await;
+        self.process = None;
+        self.standby = false;
+        self.stats.write().await.record_crash();
+
+        if self
+            .started_at
+            .is_some_and(|started_at| started_at.elapsed() >= STABLE_RUN)
+        {
+            self.crashes = 0;
+        }
+        self.crashes += 1;
+        if self.crashes > MAX_RESTARTS {
+            self.failed = true;
+            error!(
+                "gpu-screen-recorder crashed {} times in a row (exit code {:?}), replays are not recorded anymore. Try again from the tray menu.",
+                self.crashes, code
+            );
+            return None;
+        }
+
+        let delay = (RESTART_BACKOFF * 2u32.pow(self.crashes - 1)).min(MAX_RESTART_BACKOFF);
+        // Not a warning, a dialog for every crash would pile up
+        info!(
+            "gpu-screen-recorder exited with code {:?}, restarting it in {} s",
+            code,
+            delay.as_secs()
+        );
+        Some(delay)
+    }
+
+    async fn recover(&mut self) -> Result<(), Error> {
+        // Started again in the meantime, e.g. by a config change
+        if self.process.is_some() {
+            return Ok(());
+        }
+        if self.failed {
+            info!("Trying to record replays again");
+        }
+
+        self.start().await
+    }
+
+    async fn save_replay(&mut self) -> Result<(), Error> {
+        if self.standby {
+            return Err(Error::RecorderNotRunning);
+        }
+        if let Some(secs) = self.secs_since_standby().await {
+            return self.save_replay_last(secs).await;
+        }
+
+        self.request_save(None).await
+    }
+
+    /// gpu-screen-recorder only supports a few fixed lengths, so the shortest one that covers
+    /// `secs` is used. Right after standby it's the longest one without frames from before.
+    async fn save_replay_last(&mut self, secs: i64) -> Result<(), Error> {
+        if self.standby {
+            return Err(Error::RecorderNotRunning);
+        }
+        let offset = match self.secs_since_standby().await {
+            Some(limit) => Some(
+                SAVE_LAST_SIGNAL_OFFSETS
+                    .iter()
+                    .rev()
+                    .find(|(duration, _)| *duration <= secs.min(limit))
+                    .map(|(_, offset)| *offset)
+                    .ok_or(Error::BufferEmpty)?,
+            ),
+            None => SAVE_LAST_SIGNAL_OFFSETS
+                .iter()
+                .find(|(duration, _)| *duration >= secs)
+                .map(|(_, offset)| *offset),
+        };
+
+        self.request_save(offset).await
     }
 }
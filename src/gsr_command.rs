@@ -0,0 +1,460 @@
+use std::{ffi::OsString, path::Path, process::Command};
+
+use log::info;
+
+use crate::{
+    config::{
+        CaptureTarget, ColorRange, Config, Container, Encoder, RateControl, RateControlMode,
+        ReplayStorage, SystemdScope, WebcamCorner,
+    },
+    gpu::{self, Gpu},
+    gsr::{hdr_codec, ten_bit_codec},
+    gsr_info, kms, monitors,
+    recorder::Error,
+};
+
+/// Value of the -w option. gpu-screen-recorder composites sources separated by `|`, each
+/// placed with its own alignment and size options.
+fn capture_target(config: &Config) -> String {
+    let screen = if kms::use_portal(config) {
+        info!(
+            "Capturing through the portal, gsr-kms-server lacks the permission to capture monitors"
+        );
+        CaptureTarget::Portal.to_string()
+    } else {
+        monitors::capture_screen(config)
+    };
+    let webcam = &config.webcam;
+    if !webcam.enabled {
+        return screen;
+    }
+
+    let (halign, valign) = match webcam.corner {
+        WebcamCorner::TopLeft => ("start", "start"),
+        WebcamCorner::TopRight => ("end", "start"),
+        WebcamCorner::BottomLeft => ("start", "end"),
+        WebcamCorner::BottomRight => ("end", "end"),
+    };
+
+    format!(
+        "{}|{};halign={};valign={};width={}%;height={}%",
+        screen,
+        webcam.device.display(),
+        halign,
+        valign,
+        webcam.size_percent,
+        webcam.size_percent
+    )
+}
+
+/// Why `container` can't hold video encoded with `codec`, if it can't
+pub fn container_problem(container: Container, codec: &str) -> Option<String> {
+    let holds = match container {
+        Container::MKV => true,
+        Container::MP4 => !codec.ends_with("_hdr"),
+        Container::FLV => codec == "h264",
+        Container::WEBM => matches!(codec, "vp8" | "vp9") || codec.starts_with("av1"),
+    };
+    if holds {
+        return None;
+    }
+
+    Some(if container == Container::MP4 {
+        "MP4 files cannot hold HDR video, use the mkv container".to_string()
+    } else {
+        format!(
+            "{} files cannot hold {} video",
+            container.to_string().to_uppercase(),
+            codec
+        )
+    })
+}
+
+/// What replay mode adds to a recording
+#[derive(Clone)]
+struct ReplayOptions {
+    secs: i64,
+    storage: ReplayStorage,
+    /// Left out for recorders that don't set it
+    restart_on_save: Option<bool>,
+}
+
+/// A gpu-screen-recorder invocation, with the capture and encoding options shared by every
+/// recording mode taken from config. Combinations gpu-screen-recorder rejects or records wrong
+/// make [`GsrCommandBuilder::build`] fail, before anything is started.
+#[derive(Clone)]
+pub struct GsrCommandBuilder {
+    /// -w, see [`capture_target`]
+    window: String,
+    /// Area recorded when `window` is "region", as WxH+X+Y
+    region: Option<String>,
+    /// Whether the portal remembers the picked monitor, so restarts don't ask again
+    restore_portal_session: bool,
+    container: Container,
+    framerate: i64,
+    keyframe_interval_secs: f64,
+    cursor: bool,
+    /// One -a per track
+    audio_tracks: Vec<String>,
+    encoder: Encoder,
+    /// -s, as width and height
+    size: Option<(u32, u32)>,
+    /// HDR or 10-bit codec, which takes the place of the preset's
+    codec_override: Option<&'static str>,
+    preset_codec: Option<String>,
+    /// -cr, gpu-screen-recorder records limited range when it's not given
+    color_range: Option<String>,
+    bitrate_mode: String,
+    quality: String,
+    /// Rate control from config, gpu-screen-recorder only takes a number for CBR
+    unsupported_rate_control: Option<RateControl>,
+    replay: Option<ReplayOptions>,
+    /// -o, a file, a directory in replay mode or a stream URL
+    output: Option<OsString>,
+    extra_args: Vec<String>,
+    scope: SystemdScope,
+    gpu: Option<Gpu>,
+}
+
+impl GsrCommandBuilder {
+    /// Records to `container` with the capture and encoding settings from config
+    pub fn new(config: &Config, container: Container) -> Self {
+        let preset = config.active_quality_preset();
+        // Constant quality suits local files unless a preset says otherwise
+        let (bitrate_mode, quality) = match (config.rate_control, preset) {
+            (Some(rate_control), _) => ("cbr".to_string(), rate_control.value.to_string()),
+            (None, Some(preset)) => (preset.bitrate_mode.clone(), preset.quality.clone()),
+            (None, None) => ("qp".to_string(), config.quality.to_string()),
+        };
+
+        let window = capture_target(config);
+        Self {
+            region: config
+                .region
+                .clone()
+                .filter(|_| window.starts_with(&CaptureTarget::Region.to_string())),
+            // Only when it stands in for a monitor, see `portal_without_kms_permission`
+            restore_portal_session: config.capture_target() != CaptureTarget::Portal
+                && window.starts_with(&CaptureTarget::Portal.to_string()),
+            window,
+            container,
+            framerate: config.effective_framerate(),
+            keyframe_interval_secs: config.keyframe_interval_secs.max(0.1),
+            cursor: config.record_cursor,
+            audio_tracks: config.recorded_audio_tracks(),
+            encoder: config.encoder,
+            size: config.scale_size(),
+            codec_override: hdr_codec(config).or_else(|| ten_bit_codec(config, container)),
+            preset_codec: preset.and_then(|preset| preset.codec.clone()),
+            // A preset's color range wins
+            color_range: preset
+                .and_then(|preset| preset.color_range.clone())
+                .or_else(|| (config.color_range == ColorRange::Full).then(|| "full".to_string())),
+            bitrate_mode,
+            quality,
+            unsupported_rate_control: config
+                .rate_control
+                .filter(|rate_control| rate_control.mode != RateControlMode::Cbr),
+            replay: None,
+            output: None,
+            extra_args: config.extra_args.clone(),
+            scope: config.systemd_scope.clone(),
+            gpu: gpu::selected(config),
+        }
+    }
+
+    /// Constant bitrate, which streaming services expect, in place of the quality settings and
+    /// the preset's codec. Streams are FLV, so they leave HDR out whatever the replays use.
+    pub fn bitrate_kbps(mut self, bitrate_kbps: u32) -> Self {
+        self.bitrate_mode = "cbr".to_string();
+        self.quality = bitrate_kbps.to_string();
+        self.unsupported_rate_control = None;
+        self.preset_codec = None;
+        self.codec_override = self.codec_override.filter(|codec| !codec.ends_with("_hdr"));
+        self
+    }
+
+    /// Keeps the last `secs` seconds instead of recording everything
+    pub fn replay(mut self, secs: i64, storage: ReplayStorage) -> Self {
+        self.replay = Some(ReplayOptions {
+            secs,
+            storage,
+            restart_on_save: None,
+        });
+        self
+    }
+
+    /// Whether saving clears the replay buffer. Only for [`GsrCommandBuilder::replay`].
+    pub fn restart_replay_on_save(mut self, restart: bool) -> Self {
+        if let Some(replay) = &mut self.replay {
+            replay.restart_on_save = Some(restart);
+        }
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<OsString>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// The -k value, gpu-screen-recorder picks a codec when there's none
+    fn codec(&self) -> Option<&str> {
+        self.codec_override.or(self.preset_codec.as_deref())
+    }
+
+    /// Why gpu-screen-recorder can't record with these options, if it can't
+    pub fn validate(&self) -> Result<(), String> {
+        if self.output.is_none() {
+            return Err("no output is set".to_string());
+        }
+        if self.framerate < 1 {
+            return Err(format!("cannot record at {} fps", self.framerate));
+        }
+        if self.window.starts_with(&CaptureTarget::Region.to_string()) && self.region.is_none() {
+            return Err("the screen is \"region\", but no region is set".to_string());
+        }
+        if let Some(rate_control) = self.unsupported_rate_control {
+            return Err(format!(
+                "gpu-screen-recorder cannot record with {}, only with a constant bitrate (CBR)",
+                rate_control
+            ));
+        }
+        let target = self.window.split('|').next().unwrap_or_default();
+        if !gsr_info::can_capture(target) {
+            return Err(format!("\"{}\" cannot be captured on this system", target));
+        }
+        // Left to gpu-screen-recorder
+        let Some(codec) = self.codec().filter(|codec| *codec != "auto") else {
+            return Ok(());
+        };
+        if let Some(problem) = container_problem(self.container, codec) {
+            return Err(problem);
+        }
+        if self.encoder == Encoder::Software && codec != "h264" {
+            return Err(format!(
+                "software encoding only supports h264, not {}",
+                codec
+            ));
+        }
+        if !gsr_info::can_encode(codec, self.encoder) {
+            return Err(format!("the GPU cannot encode {}", codec));
+        }
+
+        Ok(())
+    }
+
+    /// The command, logged by [`crate::gsr::spawn_logged`] when started
+    pub fn build(&self) -> Result<Command, Error> {
+        self.validate().map_err(Error::InvalidOptions)?;
+
+        let mut command = self.program();
+        command
+            .arg("-w")
+            .arg(&self.window)
+            .arg("-c")
+            .arg(self.container.to_string())
+            .arg("-f")
+            .arg(self.framerate.to_string())
+            .arg("-keyint")
+            .arg(self.keyframe_interval_secs.to_string())
+            .arg("-cursor")
+            .arg(if self.cursor { "yes" } else { "no" })
+            .args(
+                self.audio_tracks
+                    .iter()
+                    .flat_map(|track| ["-a", track.as_str()]),
+            );
+        match self.encoder {
+            Encoder::Auto => {}
+            Encoder::Software => {
+                command.args(["-encoder", "cpu"]);
+            }
+            // gpu-screen-recorder picks the API that fits the GPU, the tray only offers GPU and
+            // software. The APIs can still be set for wf-recorder.
+            Encoder::Vaapi | Encoder::Nvenc | Encoder::Qsv => {
+                command.args(["-encoder", "gpu"]);
+            }
+        }
+        if let Some(region) = &self.region {
+            command.arg("-region").arg(region);
+        }
+        if self.restore_portal_session {
+            command.args(["-restore-portal-session", "yes"]);
+        }
+        if let Some((width, height)) = self.size {
+            command.arg("-s").arg(format!("{}x{}", width, height));
+        }
+        if let Some(codec) = self.codec() {
+            command.args(["-k", codec]);
+        }
+        if let Some(color_range) = &self.color_range {
+            command.args(["-cr", color_range]);
+        }
+        command.args(["-bm", &self.bitrate_mode, "-q", &self.quality]);
+        command.args(&self.extra_args);
+
+        if let Some(replay) = &self.replay {
+            command.arg("-r").arg(replay.secs.to_string());
+            // Left out for RAM, the default, which older gpu-screen-recorder versions that don't
+            // know the option also use
+            if replay.storage != ReplayStorage::Ram {
+                command.args(["-replay-storage", &replay.storage.to_string()]);
+            }
+            if let Some(restart) = replay.restart_on_save {
+                command
+                    .arg("-restart-replay-on-save")
+                    .arg(if restart { "yes" } else { "no" });
+            }
+        }
+        if let Some(output) = &self.output {
+            command.arg("-o").arg(output);
+        }
+
+        Ok(command)
+    }
+
+    /// Starts gpu-screen-recorder directly or, if enabled, in a transient systemd scope. The
+    /// scope limits its resources and gets cleaned up on logout. `systemd-run --scope` execs the
+    /// command, so the process keeps the PID we send signals to.
+    fn program(&self) -> Command {
+        let mut command = self.scoped_program();
+        if let Some(gpu) = &self.gpu {
+            gpu.apply_env(&mut command);
+        }
+
+        command
+    }
+
+    fn scoped_program(&self) -> Command {
+        let scope = &self.scope;
+        // systemd-run can't reach the host's systemd from inside Flatpak
+        if !scope.enabled || Path::new("/.flatpak-info").exists() {
+            return Command::new("gpu-screen-recorder");
+        }
+
+        let mut command = Command::new("systemd-run");
+        command.args(["--user", "--scope", "--quiet", "--collect"]);
+        if let Some(cpu_weight) = scope.cpu_weight {
+            command.arg("-p").arg(format!("CPUWeight={}", cpu_weight));
+        }
+        if let Some(memory_max) = &scope.memory_max {
+            command.arg("-p").arg(format!("MemoryMax={}", memory_max));
+        }
+        command.args(["--", "gpu-screen-recorder"]);
+
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateControlMode;
+
+    /// Default config without the settings that ask the system, e.g. for the KMS permission
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.portal_without_kms_permission = false;
+        config
+    }
+
+    fn args(builder: &GsrCommandBuilder) -> Vec<String> {
+        let command = builder.build().unwrap();
+        assert_eq!(command.get_program(), "gpu-screen-recorder");
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn replay_buffer() {
+        let builder = GsrCommandBuilder::new(&config(), Container::MKV)
+            .replay(30, ReplayStorage::Disk)
+            .restart_replay_on_save(true)
+            .output("/tmp/replays");
+
+        assert_eq!(
+            args(&builder),
+            [
+                "-w",
+                "screen",
+                "-c",
+                "mkv",
+                "-f",
+                "60",
+                "-keyint",
+                "2",
+                "-cursor",
+                "yes",
+                "-a",
+                "default_output",
+                "-a",
+                "default_input",
+                "-bm",
+                "qp",
+                "-q",
+                "ultra",
+                "-r",
+                "30",
+                "-replay-storage",
+                "disk",
+                "-restart-replay-on-save",
+                "yes",
+                "-o",
+                "/tmp/replays",
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_at_constant_bitrate() {
+        let mut builder =
+            GsrCommandBuilder::new(&config(), Container::FLV).output("rtmp://localhost/live");
+        builder.codec_override = Some("hevc_hdr");
+        let builder = builder.bitrate_kbps(6000);
+
+        let args = args(&builder);
+        assert!(
+            args.windows(4)
+                .any(|args| args == ["-bm", "cbr", "-q", "6000"])
+        );
+        assert!(!args.contains(&"-k".to_string()));
+    }
+
+    #[test]
+    fn software_encoder() {
+        let mut config = config();
+        config.encoder = Encoder::Software;
+        let builder = GsrCommandBuilder::new(&config, Container::MP4).output("/tmp/clip.mp4");
+
+        assert!(
+            args(&builder)
+                .windows(2)
+                .any(|args| args == ["-encoder", "cpu"])
+        );
+    }
+
+    #[test]
+    fn rejects_hdr_in_mp4() {
+        let mut builder = GsrCommandBuilder::new(&config(), Container::MP4).output("/tmp/clip.mp4");
+        builder.codec_override = Some("hevc_hdr");
+
+        assert!(builder.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_rate_control_gsr_cannot_record() {
+        for (mode, accepted) in [
+            (RateControlMode::Cbr, true),
+            (RateControlMode::Vbr, false),
+            (RateControlMode::Cqp, false),
+        ] {
+            let mut config = config();
+            config.rate_control = Some(RateControl { mode, value: 20 });
+            let builder = GsrCommandBuilder::new(&config, Container::MKV).output("/tmp/clip.mkv");
+
+            assert_eq!(builder.validate().is_ok(), accepted, "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::{
+    config::{Config, Container, Quality},
+    utils::portable_path,
+};
+
+/// Config locations of gpu-screen-recorder-gtk, native and Flatpak
+fn config_paths() -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap();
+    vec![
+        dirs::config_dir()
+            .unwrap()
+            .join("gpu-screen-recorder/config"),
+        home.join(".var/app/com.dec05eba.gpu_screen_recorder/config/gpu-screen-recorder/config"),
+    ]
+}
+
+/// Copies quality, audio devices, replay folder and other replay settings from
+/// gpu-screen-recorder-gtk's config. Returns whether a config was found.
+pub fn import_settings(config: &mut Config) -> bool {
+    let Some((path, gsr_config)) = config_paths()
+        .into_iter()
+        .find_map(|path| Some((path.clone(), std::fs::read_to_string(path).ok()?)))
+    else {
+        return false;
+    };
+
+    let mut audio_tracks = Vec::new();
+
+    // Each line is "<section>.<key> <value>", keys that hold lists are repeated
+    for line in gsr_config.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+
+        match key {
+            "main.record_area_option" if value != "window" => config.screen = value.to_string(),
+            "main.fps" => {
+                if let Ok(framerate) = value.parse() {
+                    config.framerate = framerate;
+                }
+            }
+            "main.quality" => {
+                if let Some(quality) = match value {
+                    "medium" => Some(Quality::Medium),
+                    "high" => Some(Quality::High),
+                    "very_high" => Some(Quality::VeryHigh),
+                    "ultra" => Some(Quality::Ultra),
+                    _ => None,
+                } {
+                    config.quality = quality;
+                }
+            }
+            // Newer versions prefix devices with "device:", application audio is not supported here
+            "main.audio_input" => match value.split_once(':') {
+                Some(("device", device)) => audio_tracks.push(device.to_string()),
+                Some(_) => {}
+                None => audio_tracks.push(value.to_string()),
+            },
+            "replay.save_directory" => config.replay_directory = portable_path(Path::new(value)),
+            "replay.container" => {
+                if let Some(container) = Container::from_extension(value) {
+                    config.container = container;
+                }
+            }
+            "replay.time" => {
+                if let Ok(secs) = value.parse() {
+                    config.replay_duration_secs = secs;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !audio_tracks.is_empty() {
+        config.audio_tracks = audio_tracks;
+    }
+
+    info!(
+        "Imported gpu-screen-recorder-gtk settings from {}",
+        path.display()
+    );
+
+    true
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, io::ErrorKind, process::Command};
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::config::{BitDepth, CaptureTarget, Config, Encoder, RecorderBackend};
+
+/// Capture targets gpu-screen-recorder only has in some sessions or builds: focused and window
+/// need X11, portal needs to be built with it
+const OPTIONAL_CAPTURE_TARGETS: [&str; 3] = ["focused", "window", "portal"];
+
+/// What gpu-screen-recorder reports it can do on this system
+struct GsrInfo {
+    /// Sections of --info by name, e.g. "video_codecs", with their lines
+    sections: HashMap<String, Vec<String>>,
+    /// --list-capture-options without the resolutions, e.g. "portal" or "DP-1"
+    capture_options: Vec<String>,
+}
+
+lazy_static! {
+    /// Asked once, see [`probe_on_startup`]. `None` if gpu-screen-recorder couldn't be run.
+    static ref INFO: Option<GsrInfo> = probe();
+}
+
+fn probe() -> Option<GsrInfo> {
+    let output = match Command::new("gpu-screen-recorder").arg("--info").output() {
+        Ok(output) => output,
+        // Starting the recorder says it's not installed
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            info!("gpu-screen-recorder is not installed");
+            return None;
+        }
+        Err(err) => {
+            warn!("Cannot ask gpu-screen-recorder what it supports: {}", err);
+            return None;
+        }
+    };
+
+    // One "section=<name>" line followed by its entries
+    let mut sections: HashMap<String, Vec<String>> = HashMap::new();
+    let mut section = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(name) = line.strip_prefix("section=") {
+            section = Some(name.to_string());
+            sections.entry(name.to_string()).or_default();
+        } else if let Some(section) = &section {
+            sections
+                .entry(section.clone())
+                .or_default()
+                .push(line.to_string());
+        }
+    }
+
+    // Older versions only list them in --info
+    let capture_options = Command::new("gpu-screen-recorder")
+        .arg("--list-capture-options")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .or_else(|| sections.get("capture_options").cloned())
+        .unwrap_or_default()
+        .iter()
+        // DP-1|1920x1080
+        .filter_map(|option| option.split('|').next())
+        .filter(|option| !option.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Some(GsrInfo {
+        sections,
+        capture_options,
+    })
+}
+
+/// Video codecs gpu-screen-recorder reported, empty if it couldn't be run
+pub fn video_codecs() -> &'static [String] {
+    INFO.as_ref()
+        .and_then(|info| info.sections.get("video_codecs"))
+        .map_or(&[], |codecs| codecs.as_slice())
+}
+
+/// Whether `codec` can be encoded, with the CPU when `encoder` is software. Assumed when
+/// gpu-screen-recorder couldn't be asked.
+pub fn can_encode(codec: &str, encoder: Encoder) -> bool {
+    let codecs = video_codecs();
+    let listed = |codec: &str| codecs.iter().any(|listed| listed == codec);
+    codecs.is_empty()
+        || listed(codec)
+        || (encoder == Encoder::Software && listed(&format!("{}_software", codec)))
+}
+
+/// Whether gpu-screen-recorder can capture `target`, a -w value. Monitors and the always
+/// available targets are assumed to be capturable, as is anything when it couldn't be asked.
+pub fn can_capture(target: &str) -> bool {
+    let Some(info) = INFO
+        .as_ref()
+        .filter(|info| !info.capture_options.is_empty())
+    else {
+        return true;
+    };
+    // X11 window ids
+    let kind = if target.parse::<u64>().is_ok() || target.starts_with("0x") {
+        "window"
+    } else {
+        target
+    };
+
+    !OPTIONAL_CAPTURE_TARGETS.contains(&kind) || info.capture_options.iter().any(|o| o == kind)
+}
+
+/// Settings gpu-screen-recorder can't record with on this system
+pub fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let target = config.capture_target();
+    if !can_capture(&target.to_string()) {
+        problems.push(match target {
+            CaptureTarget::Portal => {
+                "gpu-screen-recorder was built without the screen sharing portal".to_string()
+            }
+            _ => format!("\"{}\" can only be captured on X11", target),
+        });
+    }
+    if let Some(codec) = config
+        .active_quality_preset()
+        .and_then(|preset| preset.codec.as_deref())
+        .filter(|codec| *codec != "auto" && !can_encode(codec, config.encoder))
+    {
+        problems.push(format!(
+            "the quality preset's codec {} cannot be encoded",
+            codec
+        ));
+    }
+    if config.bit_depth == BitDepth::Ten && !can_encode_ten_bit() {
+        problems.push("10-bit video cannot be encoded".to_string());
+    }
+
+    problems
+}
+
+/// Whether the GPU can encode any 10-bit codec, assumed when gpu-screen-recorder couldn't be
+/// asked
+pub fn can_encode_ten_bit() -> bool {
+    let codecs = video_codecs();
+    codecs.is_empty() || codecs.iter().any(|codec| codec.ends_with("_10bit"))
+}
+
+/// Asks gpu-screen-recorder what it supports without holding up the start, then points out
+/// settings it can't record with
+pub fn probe_on_startup(config: Config) {
+    if config.recorder_backend != RecorderBackend::GpuScreenRecorder {
+        return;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        lazy_static::initialize(&INFO);
+        let problems = config_problems(&config);
+        if !problems.is_empty() {
+            warn!(
+                "Some settings don't work on this system:\n- {}",
+                problems.join("\n- ")
+            );
+        }
+    });
+}
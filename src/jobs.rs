@@ -0,0 +1,263 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use tokio::{
+    process::Command,
+    sync::{RwLock, mpsc},
+};
+
+use crate::{
+    config::{Config, CropAnchor, ShareCopy, VerticalExport, VerticalFit},
+    storage, trim,
+};
+
+#[derive(Debug)]
+pub enum Job {
+    /// Cuts black and silent stretches off the start and end
+    TrimDeadAir(PathBuf),
+    NormalizeLoudness(PathBuf),
+    /// Rewrites the container with extra ffmpeg muxer options
+    Remux(PathBuf, Vec<String>),
+    ExportVertical(PathBuf, VerticalExport),
+    ShareCopy(PathBuf, ShareCopy),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(std::io::Error),
+    FfmpegFailed(Option<i32>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IoError(err) => write!(f, "{}", err),
+            Error::FfmpegFailed(code) => write!(f, "ffmpeg exited with code {:?}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+/// Runs post-processing jobs on saved replays one at a time in the background
+#[derive(Clone)]
+pub struct JobQueue {
+    tx: mpsc::UnboundedSender<Job>,
+}
+
+impl JobQueue {
+    pub fn new(config: Arc<RwLock<Config>>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+
+        tokio::spawn(async move {
+            while let Some(job) = rx.recv().await {
+                info!("Running job {:?}", job);
+                let work_dir = config.read().await.work_dir();
+                if let Err(err) = job.run(work_dir.as_deref()).await {
+                    warn!("Post-processing job {:?} failed: {}", job, err);
+                }
+            }
+        });
+
+        Self { tx }
+    }
+
+    pub fn push(&self, job: Job) {
+        self.tx.send(job).unwrap();
+    }
+}
+
+impl Job {
+    /// Writes into `work_dir` and moves the result into place, next to the clip if it's not set
+    async fn run(&self, work_dir: Option<&Path>) -> Result<(), Error> {
+        match self {
+            Job::TrimDeadAir(path) => {
+                let Some(range) = trim::content_range(path).await else {
+                    return Ok(());
+                };
+
+                // Stream copy can only start at a keyframe, seeking the input goes back to the
+                // one before, so nothing past the dead stretch is lost
+                ffmpeg_into(
+                    path,
+                    Some(range.start),
+                    path,
+                    work_dir,
+                    |command, output| {
+                        command
+                            .args(["-map", "0", "-c", "copy"])
+                            .args(["-t", &format!("{:.3}", range.end - range.start)])
+                            .args(["-avoid_negative_ts", "make_zero"])
+                            .arg(output);
+                    },
+                )
+                .await?;
+
+                info!(
+                    "Trimmed {} to {:.1}s-{:.1}s",
+                    path.display(),
+                    range.start,
+                    range.end
+                );
+                Ok(())
+            }
+            Job::NormalizeLoudness(path) => {
+                let audio_codec = match extension(path).as_str() {
+                    "webm" => "libopus",
+                    _ => "aac",
+                };
+
+                replace_with_ffmpeg(path, work_dir, |command, output| {
+                    command
+                        .args(["-map", "0", "-c", "copy", "-c:a", audio_codec])
+                        .args(["-af", "loudnorm=I=-16:TP=-1.5:LRA=11"])
+                        .arg(output);
+                })
+                .await
+            }
+            Job::Remux(path, options) => {
+                replace_with_ffmpeg(path, work_dir, |command, output| {
+                    command
+                        .args(["-map", "0", "-c", "copy"])
+                        .args(options)
+                        .arg(output);
+                })
+                .await
+            }
+            Job::ExportVertical(path, export) => {
+                let filter = match export.fit {
+                    // Scaled to cover the whole frame, so sources narrower than 9:16 are cropped
+                    // at the top and bottom instead
+                    VerticalFit::Crop => format!(
+                        "scale=1080:1920:force_original_aspect_ratio=increase,crop=1080:1920:{}:(ih-1920)/2,setsar=1",
+                        match export.anchor {
+                            CropAnchor::Left => "0",
+                            CropAnchor::Center => "(iw-1080)/2",
+                            CropAnchor::Right => "iw-1080",
+                        }
+                    ),
+                    VerticalFit::Letterbox => "scale=1080:1920:force_original_aspect_ratio=decrease,pad=1080:1920:(ow-iw)/2:(oh-ih)/2,setsar=1".to_string(),
+                };
+                // libvpx-vp9 only uses -crf as constant quality with a bitrate of 0
+                let video_codec: &[&str] = match extension(path).as_str() {
+                    "webm" => &["-c:v", "libvpx-vp9", "-crf", "20", "-b:v", "0"],
+                    _ => &["-c:v", "libx264", "-crf", "20"],
+                };
+                let output = sibling_path(path, "vertical");
+
+                ffmpeg_into(path, None, &output, work_dir, |command, temp_path| {
+                    command
+                        .args(["-map", "0", "-c", "copy", "-vf", &filter])
+                        .args(video_codec)
+                        .arg(temp_path);
+                })
+                .await?;
+
+                info!("Vertical export saved to {}", output.display());
+                Ok(())
+            }
+            Job::ShareCopy(path, share_copy) => {
+                let output = sibling_path(path, "share").with_extension("mp4");
+                let bitrate = format!("{}k", share_copy.video_bitrate_kbps);
+
+                ffmpeg_into(path, None, &output, work_dir, |command, temp_path| {
+                    command
+                        .args(["-map", "0:v:0", "-map", "0:a:0?"])
+                        .args(["-vf", &format!("scale=-2:'min({},ih)'", share_copy.height)])
+                        .args(["-c:v", "libx264", "-preset", "veryfast"])
+                        .args(["-b:v", &bitrate, "-maxrate", &bitrate])
+                        .args([
+                            "-bufsize",
+                            &format!("{}k", share_copy.video_bitrate_kbps * 2),
+                        ])
+                        .args(["-c:a", "aac", "-b:a", "128k", "-movflags", "+faststart"])
+                        .arg(temp_path);
+                })
+                .await?;
+
+                info!("Share copy saved to {}", output.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `clip.mkv` -> `clip_<suffix>.mkv`
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}_{}.{}", stem, suffix, extension(path)))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Runs ffmpeg on `input`, read from `start` seconds on
+async fn run_ffmpeg(
+    input: &Path,
+    start: Option<f64>,
+    args: impl FnOnce(&mut Command),
+) -> Result<(), Error> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-hide_banner", "-loglevel", "error"]);
+    if let Some(start) = start {
+        command.args(["-ss", &format!("{:.3}", start)]);
+    }
+    command.arg("-i").arg(input);
+    args(&mut command);
+
+    let status = command.status().await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::FfmpegFailed(status.code()))
+    }
+}
+
+/// Processes `path` with ffmpeg and swaps the result in on success
+async fn replace_with_ffmpeg(
+    path: &Path,
+    work_dir: Option<&Path>,
+    args: impl FnOnce(&mut Command, &Path),
+) -> Result<(), Error> {
+    ffmpeg_into(path, None, path, work_dir, args).await
+}
+
+/// Processes `input` with ffmpeg into a work file, which is moved to `output` once it's
+/// complete. Nothing half-written ever shows up in the replay directory.
+async fn ffmpeg_into(
+    input: &Path,
+    start: Option<f64>,
+    output: &Path,
+    work_dir: Option<&Path>,
+    args: impl FnOnce(&mut Command, &Path),
+) -> Result<(), Error> {
+    let temp_path = storage::work_path(work_dir, output);
+    if let Some(work_dir) = work_dir {
+        tokio::fs::create_dir_all(work_dir).await?;
+    }
+
+    let result = run_ffmpeg(input, start, |command| args(command, &temp_path)).await;
+    match result {
+        Ok(_) => Ok(storage::move_into_place(&temp_path, output).await?),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            Err(err)
+        }
+    }
+}
@@ -172,15 +172,22 @@ pub struct InputBox {
     label: String,
     title: Option<String>,
     initial: String,
+    ok_label: Option<String>,
+    cancel_label: Option<String>,
+    size: Option<(u32, u32)>,
     input_box_type: InputBoxType,
 }
 
+#[allow(dead_code)]
 impl InputBox {
     pub fn new(label: impl Into<String>, input_box_type: InputBoxType) -> Self {
         Self {
             label: label.into(),
             title: None,
             initial: "".into(),
+            ok_label: None,
+            cancel_label: None,
+            size: None,
             input_box_type,
         }
     }
@@ -195,6 +202,21 @@ impl InputBox {
         self
     }
 
+    pub fn ok_label(mut self, label: impl Into<String>) -> Self {
+        self.ok_label = Some(label.into());
+        self
+    }
+
+    pub fn cancel_label(mut self, label: impl Into<String>) -> Self {
+        self.cancel_label = Some(label.into());
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
     pub fn show(&self) -> Result<Option<String>, std::io::Error> {
         let mut command = Command::new("kdialog");
 
@@ -202,6 +224,18 @@ impl InputBox {
             command.args(["--title", title]);
         }
 
+        if let Some(ok_label) = &self.ok_label {
+            command.args(["--ok-label", ok_label]);
+        }
+
+        if let Some(cancel_label) = &self.cancel_label {
+            command.args(["--cancel-label", cancel_label]);
+        }
+
+        if let Some((width, height)) = self.size {
+            command.args(["--geometry", &format!("{}x{}", width, height)]);
+        }
+
         command.arg(match self.input_box_type {
             InputBoxType::Text => "--inputbox",
             InputBoxType::Password => "--password",
@@ -211,6 +245,14 @@ impl InputBox {
 
         command.arg(&self.label);
 
+        // Password dialogs don't take an initial value
+        if matches!(
+            self.input_box_type,
+            InputBoxType::Text | InputBoxType::TextArea
+        ) {
+            command.arg(&self.initial);
+        }
+
         let child = command.stdout(Stdio::piped()).spawn()?;
 
         let output = child.wait_with_output()?;
@@ -222,3 +264,174 @@ impl InputBox {
         })
     }
 }
+
+pub struct ComboBox {
+    label: String,
+    title: Option<String>,
+    items: Vec<String>,
+    default: Option<String>,
+}
+
+impl ComboBox {
+    pub fn new(
+        label: impl Into<String>,
+        items: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            label: label.into(),
+            title: None,
+            items: items.into_iter().map(|item| item.into()).collect(),
+            default: None,
+        }
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn show(&self) -> Result<Option<String>, std::io::Error> {
+        let mut command = Command::new("kdialog");
+
+        if let Some(title) = &self.title {
+            command.args(["--title", title]);
+        }
+
+        command.arg("--combobox").arg(&self.label).args(&self.items);
+
+        if let Some(default) = &self.default {
+            command.args(["--default", default]);
+        }
+
+        let child = command.stdout(Stdio::piped()).spawn()?;
+
+        let output = child.wait_with_output()?;
+
+        Ok(if output.status.success() {
+            Some(
+                String::from_utf8(output.stdout)
+                    .unwrap()
+                    .trim_end_matches('\n')
+                    .to_string(),
+            )
+        } else {
+            None
+        })
+    }
+}
+
+/// D-Bus interface of a shown kdialog progress bar
+const PROGRESS_INTERFACE: &str = "org.kde.kdialog.ProgressDialog";
+
+pub struct ProgressDialog {
+    label: String,
+    title: Option<String>,
+    maximum: u32,
+}
+
+/// A progress dialog kdialog keeps showing, driven over D-Bus until it's closed. Calls on a
+/// dialog that's already gone are ignored.
+pub struct Progress {
+    connection: zbus::blocking::Connection,
+    service: String,
+    path: String,
+}
+
+#[allow(dead_code)]
+impl ProgressDialog {
+    pub fn new(label: impl Into<String>, maximum: u32) -> Self {
+        Self {
+            label: label.into(),
+            title: None,
+            maximum,
+        }
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn show(&self) -> Result<Progress, std::io::Error> {
+        let mut command = Command::new("kdialog");
+
+        if let Some(title) = &self.title {
+            command.args(["--title", title]);
+        }
+
+        command
+            .arg("--progressbar")
+            .arg(&self.label)
+            .arg(self.maximum.to_string());
+
+        // kdialog detaches and prints where to reach the dialog, "org.kde.kdialog-123 /ProgressDialog"
+        let output = command.stdout(Stdio::piped()).output()?;
+        let reference = String::from_utf8_lossy(&output.stdout);
+        let Some((service, path)) = reference.trim().split_once(' ') else {
+            return Err(std::io::Error::other(format!(
+                "kdialog printed no progress dialog: {:?}",
+                reference
+            )));
+        };
+
+        let progress = Progress {
+            connection: zbus::blocking::Connection::session().map_err(std::io::Error::other)?,
+            service: service.to_string(),
+            path: path.to_string(),
+        };
+        progress.call("showCancelButton", &(true,));
+        Ok(progress)
+    }
+}
+
+#[allow(dead_code)]
+impl Progress {
+    fn call<B>(&self, method: &str, body: &B) -> Option<zbus::Message>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.connection
+            .call_method(
+                Some(self.service.as_str()),
+                self.path.as_str(),
+                Some(PROGRESS_INTERFACE),
+                method,
+                body,
+            )
+            .ok()
+    }
+
+    pub fn set_value(&self, value: u32) {
+        let _ = self.connection.call_method(
+            Some(self.service.as_str()),
+            self.path.as_str(),
+            Some("org.freedesktop.DBus.Properties"),
+            "Set",
+            &(
+                PROGRESS_INTERFACE,
+                "value",
+                zbus::zvariant::Value::from(value as i32),
+            ),
+        );
+    }
+
+    pub fn set_label(&self, label: &str) {
+        self.call("setLabelText", &(label,));
+    }
+
+    /// Whether Cancel was pressed, or the dialog was closed
+    pub fn was_cancelled(&self) -> bool {
+        self.call("wasCancelled", &())
+            .and_then(|reply| reply.body().deserialize::<bool>().ok())
+            .unwrap_or(true)
+    }
+
+    pub fn close(&self) {
+        self.call("close", &());
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use log::{error, info, warn};
+use nix::libc;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ActionEvent,
+    config::{CaptureTarget, Config, RecorderBackend},
+    gsr_info,
+    kdialog::{ClickedButton, MessageBox, MessageBoxButtons},
+};
+
+/// Helper gpu-screen-recorder captures monitors on Wayland with. Without the capability it's
+/// started through pkexec, asking for the password on every start or failing without a polkit
+/// agent.
+const KMS_SERVER: &str = "gsr-kms-server";
+const CAPABILITY: &str = "cap_sys_admin+ep";
+
+/// What [`missing_permission`] found, it's looked up once and again after granting
+static MISSING_PERMISSION: Mutex<Option<Option<PathBuf>>> = Mutex::new(None);
+
+/// Path of gsr-kms-server when it lacks the capability to capture monitors, `None` when it has
+/// it or isn't used: on X11, inside Flatpak (which ships its own) or when it's not installed
+pub fn missing_permission() -> Option<PathBuf> {
+    MISSING_PERMISSION
+        .lock()
+        .unwrap()
+        .get_or_insert_with(find_missing_permission)
+        .clone()
+}
+
+/// Looks for gsr-kms-server and its capability again, after it was granted
+pub fn refresh() {
+    *MISSING_PERMISSION.lock().unwrap() = Some(find_missing_permission());
+}
+
+fn find_missing_permission() -> Option<PathBuf> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_none()
+        || Path::new("/.flatpak-info").exists()
+        || nix::unistd::geteuid().is_root()
+    {
+        return None;
+    }
+
+    let server = std::env::var_os("PATH")?
+        .to_string_lossy()
+        .split(':')
+        .map(|dir| Path::new(dir).join(KMS_SERVER))
+        .find(|path| path.is_file())?;
+    (!has_capabilities(&server)).then_some(server)
+}
+
+/// Whether the file has any capabilities set, as setcap stores them
+fn has_capabilities(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            c"security.capability".as_ptr(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    size > 0
+}
+
+/// Whether `config` captures a monitor through gsr-kms-server
+pub fn captures_with_kms(config: &Config) -> bool {
+    config.recorder_backend == RecorderBackend::GpuScreenRecorder
+        && matches!(
+            config.capture_target(),
+            CaptureTarget::Screen
+                | CaptureTarget::ScreenDirect
+                | CaptureTarget::Monitor(_)
+                | CaptureTarget::Region
+        )
+}
+
+/// Whether monitors are captured through the ScreenCast portal instead, because
+/// gsr-kms-server can't capture them
+pub fn use_portal(config: &Config) -> bool {
+    config.portal_without_kms_permission
+        && captures_with_kms(config)
+        && gsr_info::can_capture(&CaptureTarget::Portal.to_string())
+        && missing_permission().is_some()
+}
+
+/// Gives gsr-kms-server the capability, pkexec asks for the password
+fn grant(server: &Path) -> std::io::Result<()> {
+    let status = Command::new("pkexec")
+        .args(["setcap", CAPABILITY])
+        .arg(server)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "setcap exited with {}",
+            status
+        )))
+    }
+}
+
+/// Explains why monitors are captured through the portal and offers to grant gsr-kms-server
+/// the capability. On startup it's only asked while `ask_for_kms_permission` is on, and
+/// "Cancel" turns it off.
+pub fn ask_to_grant(config: &Config, action_tx: Sender<ActionEvent>, on_startup: bool) {
+    // Asked for from the menu, the capability may have been granted by hand since
+    if !on_startup {
+        refresh();
+    }
+    let Some(server) = missing_permission() else {
+        if !on_startup {
+            info!("gpu-screen-recorder can already capture monitors directly");
+        }
+        return;
+    };
+
+    let meanwhile = if config.portal_without_kms_permission {
+        "TrayPlay records through the screen sharing portal, which asks which monitor to share"
+    } else {
+        "gpu-screen-recorder asks for your password whenever it starts"
+    };
+    let mut label = format!(
+        "gpu-screen-recorder cannot capture monitors directly, because {} lacks the {} capability. Until it has it, {}.\n\nGrant the capability now? You will be asked for your password. It can also be granted with:\nsudo setcap {} {}",
+        server.display(),
+        CAPABILITY,
+        meanwhile,
+        CAPABILITY,
+        server.display()
+    );
+    if on_startup {
+        label.push_str("\n\nPress Cancel to not be asked on start again.");
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let answer = MessageBox::new(label)
+            .title("TrayPlay screen capture")
+            .buttons(if on_startup {
+                MessageBoxButtons::YesNoCancel
+            } else {
+                MessageBoxButtons::YesNo
+            })
+            .show();
+        match answer {
+            Ok(ClickedButton::Yes) => match grant(&server) {
+                Ok(()) => {
+                    info!("Granted {} the {} capability", server.display(), CAPABILITY);
+                    let _ = action_tx.blocking_send(ActionEvent::KmsPermissionGranted);
+                }
+                Err(err) => error!("Cannot grant the capture permission: {}", err),
+            },
+            Ok(ClickedButton::Cancel) => {
+                let _ = action_tx.blocking_send(ActionEvent::StopAskingForKmsPermission);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Cannot ask to grant the capture permission: {}", err),
+        }
+    });
+}
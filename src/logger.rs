@@ -1,6 +1,22 @@
+use std::{collections::VecDeque, sync::Mutex};
+
 use log::{Level, Log};
 
-use crate::kdialog::{InfoBox, MessageBox};
+use crate::{
+    events::{self, Event},
+    kdialog::{InfoBox, MessageBox},
+};
+
+/// Number of log lines kept in memory for the log viewer
+const LOG_HISTORY_LINES: usize = 500;
+
+static LOG_HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Returns the recent log lines, oldest first
+pub fn log_history() -> String {
+    let history = LOG_HISTORY.lock().unwrap();
+    history.iter().cloned().collect::<Vec<_>>().join("\n")
+}
 
 pub struct CombinedLogger(pub Vec<Box<dyn Log>>);
 
@@ -67,3 +83,51 @@ impl Log for KDialogLogger {
 
     fn flush(&self) {}
 }
+
+/// Keeps recent log lines in memory so they can be viewed from the tray menu
+pub struct HistoryLogger;
+
+impl Log for HistoryLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Info messages from dependencies are too noisy to be useful here
+        metadata.level() <= Level::Warn
+            || (metadata.level() <= Level::Info
+                && metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut history = LOG_HISTORY.lock().unwrap();
+        if history.len() == LOG_HISTORY_LINES {
+            history.pop_front();
+        }
+        history.push_back(format!(
+            "[{} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Passes errors on to event clients, see [`events`]
+pub struct EventLogger;
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() == Level::Error
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            events::publish(Event::Error(record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -1,34 +1,168 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use ashpd::desktop::registry::Registry;
-use config::Config;
-use gsr::GpuScreenRecorder;
+use active_window::{ActiveWindow, ActiveWindowWatcher};
+use ashpd::desktop::{registry::Registry, screenshot::Screenshot};
+use audio::{AudioChoices, CapturedAudio};
+use cli::CliCommand;
+use config::{Config, ReplayAction, SaveSource};
+use denoise::NoiseSuppression;
+use events::Event;
+use format::format_duration;
+use gsr::{LiveStream, Recording};
+use jobs::{Job, JobQueue};
+use kdialog::MessageBox;
 use ksni::TrayMethods;
-use kwin::KWinScriptManager;
 use log::{error, info, warn};
-use logger::{CombinedLogger, KDialogLogger};
-use tokio::sync::{RwLock, mpsc};
-use tray::TrayIcon;
-use utils::ask_path;
+use logger::{CombinedLogger, EventLogger, HistoryLogger, KDialogLogger};
+use probe::ClipInfo;
+use recorder::{AnyRecorder, Recorder};
+use recorders::RecorderSupervisor;
+use stats::Statistics;
+use tokio::sync::{
+    RwLock,
+    mpsc::{self, Sender},
+};
+use tray::{TrayIcon, TrayStatus, TrayUpdater};
+use triggers::TriggerManager;
+use utils::{ask_custom_string, ask_path, copy_image_to_clipboard, portable_path};
 use zbus::{Connection, names::BusName, proxy};
 
 mod active_window;
+mod audio;
+mod autostart;
+mod capture_control;
+mod cli;
 mod config;
+mod config_preview;
+mod custom_actions;
+mod denoise;
+mod events;
+mod format;
+mod games;
+mod gamescope;
+mod gpu;
 mod gsr;
+mod gsr_command;
+mod gsr_gtk;
+mod gsr_info;
+mod jobs;
 mod kdialog;
+mod kms;
 mod kwin;
 mod logger;
+mod migration;
+mod monitors;
+mod notes;
+mod picker;
+mod power;
+mod privacy;
+mod probe;
+mod recorder;
+mod recorders;
+mod replay_actions;
+mod screencast;
+mod segments;
+mod selftest;
 mod shortcuts;
+mod signals;
+mod stats;
+mod storage;
+mod thermal;
+mod timeline;
 mod tray;
+mod triggers;
+mod trim;
 mod utils;
+mod watch_later;
+mod wf_recorder;
 
-#[derive(Debug)]
+/// How much shorter than asked for a clip can be before it's reported as such, saves are cut at
+/// keyframes
+const SHORT_CLIP_TOLERANCE: Duration = Duration::from_secs(3);
+/// How close together the two saves of one hotkey press can arrive, see `gamescope_hotkey`
+const HOTKEY_SEEN_TWICE_WITHIN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
 pub enum ActionEvent {
-    SaveReplay,
+    SaveReplay(SaveSource),
     Quit,
     Unknown,
     ChangeReplayPath,
     ConfigSaved,
+    SaveReplayLast(SaveSource, i64),
+    SwitchProfile(Option<String>),
+    PowerSourceChanged(bool),
+    ExportVertical,
+    AddNote,
+    DeleteLastReplay,
+    VideoCallActive(bool),
+    SetAutostart(bool),
+    SetAudioMuted(CapturedAudio, bool),
+    /// The noise suppression filter process exited on its own, with its exit code
+    NoiseSuppressionExited(u32, Option<i32>),
+    /// Sets the volume `audio` is recorded at, in percent
+    SetAudioVolume(CapturedAudio, i64),
+    CopyToClipboard(String),
+    ToggleStreaming,
+    /// The streaming gpu-screen-recorder process exited, with its exit code
+    StreamExited(u32, Option<i32>),
+    /// Starts or stops recording everything into a file
+    ToggleRecording,
+    ToggleRecorder(String),
+    SaveRecorderReplay(String),
+    RecorderExited(String, u32, Option<i32>),
+    /// The replay buffer's recorder process exited, stopped or crashed
+    ReplayRecorderExited(u32),
+    /// Starts the replay buffer's recorder again after it crashed
+    RecoverRecorder,
+    SplitRecording(String, u32),
+    EncoderOverloaded,
+    /// The machine started (`true`) or stopped overheating
+    ThermalPressure(bool),
+    RestoreQuality,
+    RunCustomAction(usize),
+    RestoreConfigBackup(PathBuf),
+    ToggleBuffer,
+    ActiveWindowChanged(ActiveWindow),
+    ReplaySaved(PathBuf, Option<ClipInfo>),
+    /// Clips were moved to the new replay folder, new paths by old ones
+    ReplaysMoved(HashMap<PathBuf, PathBuf>),
+    /// Opens a clip from the watch later queue and takes it off
+    WatchQueuedClip(PathBuf),
+    ClearWatchLater,
+    ToggleBoost,
+    EndBoost,
+    CopyFrame,
+    /// Another app asked to pause capture over D-Bus: client's bus name and reason
+    PauseCapture(String, String),
+    ResumeCapture(String),
+    /// The running game, detected when game sessions are enabled, or their settings changed
+    GameSessionChanged(Option<String>),
+    /// Devices or applications the audio menus of the tray offer changed
+    AudioChoicesChanged(AudioChoices),
+    RunSelfTest,
+    /// The user stopped the portal's screen sharing the recorder captures
+    CaptureRevoked,
+    /// Asks the portal for the screen again after sharing was stopped
+    RequestCapture,
+    /// The recorded monitor's refresh rate, which a `framerate` of 0 follows, changed
+    DisplayRefreshRateChanged(i64),
+    /// SIGHUP, sent by `trayplay config set`
+    ReloadConfig,
+    /// Offers to let gpu-screen-recorder capture monitors directly
+    AskForKmsPermission,
+    /// gsr-kms-server can capture monitors now, so the portal isn't needed anymore
+    KmsPermissionGranted,
+    /// "Cancel" in the dialog asking for the capture permission on start
+    StopAskingForKmsPermission,
+    /// A replay action picked in the tray or a save notification
+    ReplayAction(ReplayAction, PathBuf),
 }
 
 #[proxy(
@@ -41,22 +175,97 @@ trait OsdService {
     fn show_text(&self, icon: &str, text: &str) -> zbus::Result<()>;
 }
 
+#[proxy(
+    interface = "org.kde.klipper.klipper",
+    default_service = "org.kde.klipper",
+    default_path = "/klipper"
+)]
+trait Klipper {
+    #[zbus(name = "setClipboardContents")]
+    fn set_clipboard_contents(&self, contents: &str) -> zbus::Result<()>;
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let env_logger = env_logger::builder()
         .parse_env(env_logger::Env::default().default_filter_or("warn"))
         .build();
+
+    match command {
+        CliCommand::Tray => {}
+        CliCommand::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        CliCommand::Record { duration, output } => {
+            // No dialogs when running from a terminal
+            log::set_max_level(env_logger.filter());
+            log::set_boxed_logger(Box::new(env_logger))?;
+
+            return cli::record(duration, output).await;
+        }
+        CliCommand::Events { follow } => {
+            let socket = events::socket_path().ok_or("There is no runtime directory")?;
+            return Ok(events::print_events(&socket, follow)
+                .await
+                .map_err(|err| format!("Cannot connect to TrayPlay, is it running? ({})", err))?);
+        }
+        CliCommand::List { search } => {
+            log::set_max_level(env_logger.filter());
+            log::set_boxed_logger(Box::new(env_logger))?;
+
+            return cli::list(search).await;
+        }
+        CliCommand::Delete { clip, yes } => return cli::delete(clip, yes),
+        CliCommand::WatchLater { clip, remove } => return cli::watch_later(clip, remove),
+        CliCommand::ConfigSet {
+            key,
+            value,
+            dry_run,
+        } => {
+            log::set_max_level(env_logger.filter());
+            log::set_boxed_logger(Box::new(env_logger))?;
+
+            return cli::config_set(key, value, dry_run).await;
+        }
+        CliCommand::ExportNotes(file) => {
+            log::set_max_level(env_logger.filter());
+            log::set_boxed_logger(Box::new(env_logger))?;
+
+            return cli::export_notes(file).await;
+        }
+        CliCommand::ImportNotes(file) => {
+            log::set_max_level(env_logger.filter());
+            log::set_boxed_logger(Box::new(env_logger))?;
+
+            return cli::import_notes(file).await;
+        }
+    }
+
     let kdialog_logger = KDialogLogger {};
 
-    log::set_max_level(env_logger.filter());
+    // The log viewer keeps info messages even if they are not printed
+    log::set_max_level(env_logger.filter().max(log::LevelFilter::Info));
     log::set_boxed_logger(Box::new(CombinedLogger(vec![
         Box::new(env_logger),
         Box::new(kdialog_logger),
+        Box::new(HistoryLogger),
+        Box::new(EventLogger),
     ])))?;
 
     let (action_tx, mut action_rx) = mpsc::channel(8);
 
     let config = Arc::new(RwLock::new(Config::load(action_tx.clone()).await));
+    monitors::update_display_framerate(&mut *config.write().await);
+    let stats = Arc::new(RwLock::new(Statistics::load()));
 
     let connection = Connection::session().await?;
     let service_name = "ovh.kabus.trayplay";
@@ -70,61 +279,262 @@ async fn main() -> Result<(), Box<dyn Error>> {
         std::process::exit(1);
     }
 
-    let kwin_script_manager = KWinScriptManager::new().await?;
-    kwin_script_manager.load().await;
-
     // Let xdg portal know what desktop file are we
     Registry::default().register("ovh.kabus.trayplay").await?;
 
-    let tray = TrayIcon::new(action_tx.clone(), &config).await;
-    let _tray_handle = tray.spawn().await.unwrap();
-    shortcuts::setup_global_shortcuts(action_tx);
+    if config.read().await.autostart {
+        match autostart::verify_autostart().await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Start on login was disabled outside of TrayPlay");
+                let mut config = config.write().await;
+                config.autostart = false;
+                config.write_file();
+            }
+            Err(err) => warn!("Cannot verify start on login: {}", err),
+        }
+    }
+
+    let tray = TrayIcon::new(action_tx.clone(), &config, &stats).await;
+    let mut tray_updater = TrayUpdater::new(
+        tray.spawn().await.unwrap(),
+        &*config.read().await,
+        action_tx.clone(),
+    );
+    let shortcut_reload_tx = shortcuts::setup_global_shortcuts(action_tx.clone(), config.clone());
+    privacy::setup_video_call_watcher(action_tx.clone(), config.clone());
+    if let Err(err) = signals::setup_signal_handlers(action_tx.clone()) {
+        warn!("Cannot listen for SIGUSR1 and SIGUSR2: {}", err);
+    }
+    if let Err(err) = power::setup_power_source_listener(action_tx.clone()).await {
+        warn!("Cannot watch power source changes: {}", err);
+    }
 
     let app_name = Arc::new(RwLock::new("unknown".to_string()));
-    active_window::setup_active_window_manager(app_name.clone()).await?;
+    let jobs = JobQueue::new(config.clone());
+    let active_window_watcher = ActiveWindowWatcher::start().await?;
+    let event_socket = events::setup_event_socket();
+    let game = games::setup_game_watcher(action_tx.clone(), config.clone());
+    gamescope::setup_gamescope_hotkey(action_tx.clone(), config.clone());
+    monitors::setup_refresh_rate_watcher(action_tx.clone(), config.clone());
+    audio::setup_audio_levels(config.clone());
+    audio::setup_choices_watcher(action_tx.clone());
+    thermal::setup_thermal_watcher(action_tx.clone(), config.clone());
+    active_window::track_app_name(active_window_watcher.subscribe(), game, app_name.clone());
+    if let Err(err) =
+        capture_control::serve(active_window_watcher.connection(), action_tx.clone()).await
+    {
+        warn!("Cannot let other apps pause capture: {}", err);
+    }
+    {
+        let mut active_window = active_window_watcher.subscribe();
+        let action_tx = action_tx.clone();
+        tokio::spawn(async move {
+            while active_window.changed().await.is_ok() {
+                let window = active_window.borrow_and_update().clone();
+                action_tx
+                    .send(ActionEvent::ActiveWindowChanged(window))
+                    .await
+                    .unwrap();
+            }
+        });
+    }
+
+    {
+        let config = config.read().await;
+        storage::check_replay_dir_on_startup(&config.replay_dir(), action_tx.clone());
+        gsr_info::probe_on_startup(config.clone());
+        if config.ask_for_kms_permission && kms::captures_with_kms(&config) {
+            kms::ask_to_grant(&config, action_tx.clone(), true);
+        }
+        storage::remove_orphaned_work_files(config.work_dir().as_deref(), &config.replay_dir());
+    }
 
-    let mut gpu_screen_recorder = GpuScreenRecorder::new(config.clone(), app_name.clone()).await?;
-    handle_gsr_start_result(gpu_screen_recorder.start().await);
+    let mut replay_recorder = recorder::create(
+        config.clone(),
+        app_name.clone(),
+        stats.clone(),
+        jobs.clone(),
+        action_tx.clone(),
+    )
+    .await?;
+    let mut noise_suppression = NoiseSuppression::new(action_tx.clone());
+    noise_suppression.apply(&*config.read().await).await;
+    handle_recorder_start_result(replay_recorder.start().await);
+    let mut live_stream = LiveStream::default();
+    let mut recording = Recording::default();
+    let mut watch_later_queue = watch_later::QueueCache::default();
+    let mut recorders = RecorderSupervisor::new(action_tx.clone());
+    recorders.apply(&*config.read().await);
+    let mut triggers = TriggerManager::new(action_tx.clone(), active_window_watcher.subscribe());
+    triggers.apply(&config.read().await.triggers);
 
     let conn = Connection::session().await?;
 
+    let mut on_battery = false;
+    let mut settings_before_battery = None;
+    let mut settings_before_overload = None;
+    // Settings to restore once the machine cools down
+    let mut settings_before_heat = None;
+    let mut settings_before_app_profile = None;
+    let mut settings_before_boost = None;
+    let mut boost_timer: Option<tokio::task::JoinHandle<()>> = None;
+    // Bus name of each app that paused capture, with the reason the recorder is paused for
+    let mut capture_pauses: HashMap<String, String> = HashMap::new();
+    let mut last_saves: HashMap<SaveSource, Instant> = HashMap::new();
+    // Status and pause reasons of the replay buffer last sent to event clients
+    let mut last_buffer = None;
+    // Length of the last save that was asked for, to tell when the buffer had less
+    let mut requested_length = None;
+    // Listed by the audio choices watcher, shown in the tray menus
+    let mut audio_choices = AudioChoices::default();
+
     loop {
         if let Some(action) = action_rx.recv().await {
             match action {
-                ActionEvent::SaveReplay => {
+                action @ (ActionEvent::SaveReplay(source)
+                | ActionEvent::SaveReplayLast(source, _)) => {
+                    let mut cooldown = config.read().await.save_cooldowns.of(source);
+                    // A press seen by both the portal and the gamescope listener saves once
+                    if source == SaveSource::Hotkey && config.read().await.gamescope_hotkey.enabled
+                    {
+                        cooldown = cooldown.max(HOTKEY_SEEN_TWICE_WITHIN);
+                    }
+                    if let Some(last_save) = last_saves.get(&source)
+                        && last_save.elapsed() < cooldown
+                    {
+                        info!(
+                            "Ignoring save from {:?}, it saved less than {} ago",
+                            source,
+                            format_duration(cooldown)
+                        );
+                        continue;
+                    }
+                    events::publish(Event::SaveStarted(source));
+
                     info!("Saving replay from {}", app_name.read().await);
-                    match gpu_screen_recorder.save_replay().await {
-                        Ok(_) => {
-                            OsdServiceProxy::new(&conn)
-                                .await?
-                                .show_text(
-                                    "media-record",
-                                    &format!("Replay from \"{}\" saved!", app_name.read().await),
-                                )
-                                .await?;
-                        }
-                        Err(err) => match err {
-                            gsr::Error::RecorderNotRunning => {
+                    let secs = match action {
+                        ActionEvent::SaveReplayLast(_, secs) => secs,
+                        _ => config.read().await.replay_duration_secs,
+                    };
+                    requested_length = Some(Duration::from_secs(secs.max(0) as u64));
+                    let result = match action {
+                        ActionEvent::SaveReplayLast(_, secs) => {
+                            replay_recorder.save_replay_last(secs).await
+                        }
+                        _ => replay_recorder.save_replay().await,
+                    };
+                    // The OSD is shown once gpu-screen-recorder has written the file, see ReplaySaved
+                    if result.is_ok() {
+                        last_saves.insert(source, Instant::now());
+                    }
+                    if let Err(err) = result {
+                        stats.write().await.record_failure(err.to_string());
+                        events::publish(Event::SaveFailed(err.to_string()));
+                        match err {
+                            recorder::Error::RecorderNotRunning => {
                                 error!("Replay recording is either turned off or has crashed.")
                             }
+                            recorder::Error::BufferEmpty => {
+                                error!("Nothing has been recorded yet, try again in a few seconds.")
+                            }
                             err => {
                                 error!("Failed to save replay: {}", err);
                             }
-                        },
+                        }
+                    }
+                }
+                ActionEvent::ReplaySaved(path, info) => {
+                    events::publish(Event::SaveFinished {
+                        path: path.clone(),
+                        duration: info.as_ref().and_then(|info| info.duration),
+                    });
+                    let app = path
+                        .parent()
+                        .and_then(|parent| parent.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    // A buffer that's still filling up is saved as it is
+                    let requested = requested_length.take().filter(|requested| {
+                        info.as_ref()
+                            .and_then(|info| info.duration)
+                            .is_some_and(|duration| duration + SHORT_CLIP_TOLERANCE < *requested)
+                    });
+                    let text = match (&info, requested) {
+                        (Some(info), Some(requested)) => {
+                            info!(
+                                "Saved {} ({}), the buffer didn't hold {} yet",
+                                path.display(),
+                                info,
+                                format_duration(requested)
+                            );
+                            format!(
+                                "Replay from \"{}\" saved ({}), shorter than {} as the buffer is still filling",
+                                app,
+                                info,
+                                format_duration(requested)
+                            )
+                        }
+                        (Some(info), None) => {
+                            info!("Saved {} ({})", path.display(), info);
+                            format!("Replay from \"{}\" saved ({})", app, info)
+                        }
+                        (None, _) => {
+                            info!("Saved {}", path.display());
+                            format!("Replay from \"{}\" saved!", app)
+                        }
+                    };
+                    let config = config.read().await;
+                    let notified = !config.notification_actions.is_empty()
+                        && match replay_actions::notify_saved(
+                            &conn,
+                            &config,
+                            &text,
+                            path,
+                            action_tx.clone(),
+                        )
+                        .await
+                        {
+                            Ok(()) => true,
+                            Err(err) => {
+                                info!("Cannot show the notification, showing an OSD: {}", err);
+                                false
+                            }
+                        };
+                    if !notified {
+                        OsdServiceProxy::new(&conn)
+                            .await?
+                            .show_text("media-record", &text)
+                            .await?;
                     }
                 }
                 ActionEvent::Quit => {
-                    kwin_script_manager.unload().await;
-                    gpu_screen_recorder.stop().await?;
+                    active_window_watcher.stop().await;
+                    noise_suppression.stop().await;
+                    if let Some(path) = &event_socket {
+                        let _ = std::fs::remove_file(path);
+                    }
+                    if live_stream.is_live() {
+                        live_stream.stop()?;
+                    }
+                    if recording.is_recording()
+                        && let Err(err) = recording.stop().await
+                    {
+                        error!("Cannot stop recording: {}", err);
+                    }
+                    recorders.stop_all();
+                    replay_recorder.stop().await?;
                     std::process::exit(0);
                 }
                 ActionEvent::ChangeReplayPath => {
                     let mut config = config.write().await;
-                    match ask_path(true, &config.replay_directory).await {
+                    let old_dir = config.replay_dir();
+                    match ask_path(true, &old_dir).await {
                         Ok(directory) => {
                             if let Some(directory) = directory {
-                                config.replay_directory = directory;
+                                config.replay_directory = portable_path(&directory);
                                 config.save().await;
+                                migration::offer(old_dir, config.replay_dir(), action_tx.clone());
                             }
                         }
                         Err(err) => {
@@ -132,26 +542,658 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         }
                     };
                 }
+                ActionEvent::SwitchProfile(name) => {
+                    let mut config = config.write().await;
+                    if config.apply_profile(name.as_deref()) {
+                        info!("Active profile is now {:?}", config.active_profile);
+                        config.save().await;
+                        reload_shortcuts(&shortcut_reload_tx).await;
+                    } else {
+                        warn!("Profile {:?} does not exist", name);
+                    }
+                }
+                ActionEvent::PowerSourceChanged(now_on_battery) => {
+                    if now_on_battery == on_battery {
+                        continue;
+                    }
+                    on_battery = now_on_battery;
+
+                    let mut config = config.write().await;
+                    let changed = if on_battery {
+                        match config.power_profiles.battery.clone() {
+                            Some(name) => {
+                                settings_before_battery = Some(config.snapshot());
+                                config.apply_profile(Some(&name))
+                            }
+                            None => false,
+                        }
+                    } else if let Some(name) = config.power_profiles.ac.clone() {
+                        settings_before_battery = None;
+                        config.apply_profile(Some(&name))
+                    } else if let Some(snapshot) = settings_before_battery.take() {
+                        config.restore(snapshot);
+                        true
+                    } else {
+                        false
+                    };
+
+                    if changed {
+                        info!("Active profile is now {:?}", config.active_profile);
+                        config.save().await;
+                        reload_shortcuts(&shortcut_reload_tx).await;
+                    }
+                }
+                ActionEvent::ExportVertical => match replay_recorder.last_replay().await {
+                    Some(path) => {
+                        let export = config.read().await.vertical_export;
+                        jobs.push(Job::ExportVertical(path, export));
+                    }
+                    None => warn!("No replay has been saved yet"),
+                },
+                ActionEvent::AddNote => match replay_recorder.last_replay().await {
+                    // The dialog would hold up the main loop
+                    Some(path) => {
+                        tokio::task::spawn_blocking(move || {
+                            let note = notes::read_note(&path).unwrap_or_default();
+                            match ask_custom_string("TrayPlay", "Note for the last replay", note) {
+                                Ok(Some(note)) => {
+                                    if let Err(err) = notes::write_note(&path, &note) {
+                                        error!("Cannot save the note: {}", err);
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(err) => error!("Error when asking for a note: {}", err),
+                            }
+                        });
+                    }
+                    None => warn!("No replay has been saved yet"),
+                },
+                ActionEvent::DeleteLastReplay => match replay_recorder.last_replay().await {
+                    Some(path) => {
+                        let config = config.read().await;
+                        replay_actions::run(ReplayAction::Delete, path, &config, &conn).await;
+                    }
+                    None => warn!("No replay has been saved yet"),
+                },
+                ActionEvent::ReplayAction(action, path) => {
+                    let config = config.read().await;
+                    replay_actions::run(action, path, &config, &conn).await;
+                }
+                ActionEvent::WatchQueuedClip(path) => {
+                    let config = config.read().await;
+                    replay_actions::run(ReplayAction::Open, path.clone(), &config, &conn).await;
+                    watch_later::remove(&path);
+                }
+                ActionEvent::ClearWatchLater => {
+                    watch_later::clear();
+                    info!("Cleared the watch later queue");
+                }
+                ActionEvent::RunSelfTest => {
+                    let config = config.read().await.clone();
+                    // The test capture takes a few seconds
+                    tokio::spawn(async move {
+                        info!("Running self-test");
+                        let report = selftest::run(&config).await;
+                        info!("Self-test finished: {}", report);
+                        tokio::task::spawn_blocking(move || {
+                            if let Err(err) =
+                                MessageBox::new(report).title("TrayPlay self-test").show()
+                            {
+                                error!("Cannot show the self-test results: {}", err);
+                            }
+                        });
+                    });
+                }
+                ActionEvent::VideoCallActive(active) => {
+                    let result = if active {
+                        replay_recorder.pause("video call in progress").await
+                    } else {
+                        replay_recorder.resume("video call in progress").await
+                    };
+                    handle_recorder_start_result(result);
+                }
+                ActionEvent::PauseCapture(client, reason) => {
+                    let reason = format!("{} (requested by another app)", reason);
+                    handle_recorder_start_result(replay_recorder.pause(&reason).await);
+
+                    if let Some(previous) = capture_pauses.insert(client, reason.clone())
+                        && previous != reason
+                        && !capture_pauses.values().any(|reason| *reason == previous)
+                    {
+                        handle_recorder_start_result(replay_recorder.resume(&previous).await);
+                    }
+                }
+                ActionEvent::ResumeCapture(client) => {
+                    if let Some(reason) = capture_pauses.remove(&client)
+                        && !capture_pauses.values().any(|other| *other == reason)
+                    {
+                        handle_recorder_start_result(replay_recorder.resume(&reason).await);
+                    }
+                }
+                ActionEvent::GameSessionChanged(game) => {
+                    stats.write().await.record_game_session(game.as_deref());
+                    let settings = config.read().await.game_sessions;
+                    // Without game sessions no game is ever seen, which mustn't keep the buffer off
+                    let result = if settings.enabled && settings.control_buffer && game.is_none() {
+                        replay_recorder.pause("no game running").await
+                    } else {
+                        replay_recorder.resume("no game running").await
+                    };
+                    handle_recorder_start_result(result);
+                }
+                ActionEvent::AudioChoicesChanged(choices) => audio_choices = choices,
+                ActionEvent::ActiveWindowChanged(window) => {
+                    let private = config
+                        .read()
+                        .await
+                        .private_apps
+                        .iter()
+                        .any(|app| window.is_app(app));
+                    let result = if private {
+                        replay_recorder.pause("private app focused").await
+                    } else {
+                        replay_recorder.resume("private app focused").await
+                    };
+                    handle_recorder_start_result(result);
+
+                    let mut config = config.write().await;
+                    let app_profile = config
+                        .profiles
+                        .iter()
+                        .find(|profile| profile.apps.iter().any(|app| window.is_app(app)))
+                        .map(|profile| profile.name.clone());
+                    let changed = match app_profile {
+                        Some(name) if config.active_profile.as_ref() != Some(&name) => {
+                            if settings_before_app_profile.is_none() {
+                                settings_before_app_profile = Some(config.snapshot());
+                            }
+                            config.apply_profile(Some(&name))
+                        }
+                        Some(_) => false,
+                        None => match settings_before_app_profile.take() {
+                            Some(snapshot) => {
+                                config.restore(snapshot);
+                                true
+                            }
+                            None => false,
+                        },
+                    };
+                    if changed {
+                        info!("Active profile is now {:?}", config.active_profile);
+                        config.save().await;
+                        reload_shortcuts(&shortcut_reload_tx).await;
+                    }
+                }
+                ActionEvent::SetAutostart(enabled) => {
+                    match autostart::set_autostart(enabled).await {
+                        Ok(enabled) => {
+                            let mut config = config.write().await;
+                            config.autostart = enabled;
+                            config.write_file();
+                        }
+                        Err(err) => error!("Cannot change start on login: {}", err),
+                    }
+                }
+                ActionEvent::SetAudioMuted(audio, muted) => {
+                    // Saved without restarting the recorder, its streams are muted instead
+                    let mut config = config.write().await;
+                    match audio {
+                        CapturedAudio::Desktop => config.mute_desktop_audio = muted,
+                        CapturedAudio::Microphone => config.mute_microphone = muted,
+                    }
+                    config.write_file();
+                    drop(config);
+                    match audio::set_recorder_audio_muted(audio, muted) {
+                        Ok(_) if muted => info!("Muted {}", audio.name()),
+                        Ok(_) => info!("Unmuted {}", audio.name()),
+                        Err(err) => error!("Cannot mute {}: {}", audio.name(), err),
+                    }
+                }
+                ActionEvent::NoiseSuppressionExited(pid, code) => {
+                    if noise_suppression.exited(pid) {
+                        error!(
+                            "Noise suppression stopped (exit code {:?}), recording the microphone without it",
+                            code
+                        );
+                        handle_recorder_start_result(replay_recorder.restart().await);
+                    }
+                }
+                ActionEvent::SetAudioVolume(audio, volume) => {
+                    // Like muting, the recorder's streams are changed instead of restarting it
+                    let mut config = config.write().await;
+                    match audio {
+                        CapturedAudio::Desktop => config.desktop_audio_volume = volume,
+                        CapturedAudio::Microphone => config.microphone_volume = volume,
+                    }
+                    config.write_file();
+                    let muted = audio.is_muted(&config);
+                    drop(config);
+                    // Muted streams get it from the audio level watcher once unmuted
+                    if !muted {
+                        match audio::set_recorder_audio_volume(audio, volume) {
+                            Ok(_) => info!("Recording {} at {}%", audio.name(), volume),
+                            Err(err) => {
+                                error!("Cannot change the volume of {}: {}", audio.name(), err)
+                            }
+                        }
+                    }
+                }
+                ActionEvent::CopyToClipboard(text) => {
+                    let result = match KlipperProxy::new(&conn).await {
+                        Ok(klipper) => klipper.set_clipboard_contents(&text).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = result {
+                        error!("Cannot copy to clipboard: {}", err);
+                    }
+                }
+                ActionEvent::CopyFrame => {
+                    let screenshot = Screenshot::request()
+                        .interactive(false)
+                        .modal(false)
+                        .send()
+                        .await
+                        .and_then(|request| request.response());
+                    let path = match screenshot.map(|screenshot| screenshot.uri().to_file_path()) {
+                        Ok(Ok(path)) => path,
+                        Ok(Err(_)) => {
+                            error!("Screenshot portal returned a non-local file");
+                            continue;
+                        }
+                        Err(err) => {
+                            error!("Cannot take screenshot: {}", err);
+                            continue;
+                        }
+                    };
+
+                    let result = copy_image_to_clipboard(&path).await;
+                    // Only the clipboard copy is wanted, not another file in Pictures
+                    let _ = std::fs::remove_file(&path);
+                    match result {
+                        Ok(_) => show_osd(&conn, "edit-copy", "Frame copied to clipboard").await,
+                        Err(err) => error!("Cannot copy frame to clipboard: {}", err),
+                    }
+                }
+                ActionEvent::ToggleStreaming => {
+                    let result = if live_stream.is_live() {
+                        live_stream.stop()
+                    } else {
+                        live_stream.start(&*config.read().await, action_tx.clone())
+                    };
+                    if let Err(err) = result {
+                        error!("Cannot toggle streaming: {}", err);
+                    }
+                }
+                ActionEvent::StreamExited(pid, code) => {
+                    if live_stream.exited(pid) {
+                        error!(
+                            "Streaming stopped, gpu-screen-recorder exited with {:?}",
+                            code
+                        );
+                    }
+                }
+                ActionEvent::ToggleRecording => {
+                    let (event, text) = if recording.is_recording() {
+                        match recording.stop().await {
+                            Ok(path) => {
+                                replay_recorder.set_last_replay(path.clone()).await;
+                                (Event::RecordingFinished(path), "Recording saved")
+                            }
+                            Err(err) => {
+                                error!("Cannot stop recording: {}", err);
+                                continue;
+                            }
+                        }
+                    } else {
+                        let app = app_name.read().await.clone();
+                        match recording.start(&*config.read().await, &app) {
+                            Ok(path) => (Event::RecordingStarted(path), "Recording started"),
+                            Err(err) => {
+                                error!("Cannot start recording: {}", err);
+                                continue;
+                            }
+                        }
+                    };
+                    events::publish(event);
+                    show_osd(&conn, "media-record", text).await;
+                }
+                ActionEvent::ToggleRecorder(name) => {
+                    if let Err(err) = recorders.toggle(&name, &*config.read().await) {
+                        error!("Cannot toggle recorder {}: {}", name, err);
+                    }
+                }
+                ActionEvent::SaveRecorderReplay(name) => {
+                    if let Err(err) = recorders.save_replay(&name) {
+                        error!("Cannot save replay of recorder {}: {}", name, err);
+                    }
+                }
+                ActionEvent::RecorderExited(name, pid, code) => recorders.exited(&name, pid, code),
+                ActionEvent::ReplayRecorderExited(pid) => {
+                    if let Some(delay) = replay_recorder.exited(pid).await {
+                        let action_tx = action_tx.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = action_tx.send(ActionEvent::RecoverRecorder).await;
+                        });
+                    }
+                }
+                ActionEvent::RecoverRecorder => {
+                    handle_recorder_start_result(replay_recorder.recover().await)
+                }
+                ActionEvent::SplitRecording(name, pid) => {
+                    recorders.split(&name, pid, &*config.read().await)
+                }
+                ActionEvent::EncoderOverloaded => {
+                    let mut config = config.write().await;
+                    if !config.lower_quality_on_overload {
+                        continue;
+                    }
+
+                    let snapshot = config.snapshot();
+                    match config.lower_quality() {
+                        Some(change) => {
+                            settings_before_overload.get_or_insert(snapshot);
+                            let message = format!("Encoder is overloaded, lowered {}", change);
+                            warn!("{}", message);
+                            show_osd(&conn, "dialog-warning", &message).await;
+                            config.save().await;
+                        }
+                        None => warn!("Encoder is overloaded and quality can't be lowered further"),
+                    }
+                }
+                ActionEvent::ThermalPressure(true) if settings_before_heat.is_none() => {
+                    let mut config = config.write().await;
+                    let snapshot = config.snapshot();
+                    config.begin_temporary_settings();
+                    if let Some(change) = config.apply_thermal_throttling() {
+                        settings_before_heat = Some(snapshot);
+                        let message = format!("System is overheating, lowered {}", change);
+                        info!("{}", message);
+                        show_osd(&conn, "temperature-warm", &message).await;
+                        config.apply().await;
+                    } else if settings_before_boost.is_none() {
+                        config.end_temporary_settings();
+                    }
+                }
+                ActionEvent::ThermalPressure(true) => {}
+                ActionEvent::ThermalPressure(false) => {
+                    if let Some(snapshot) = settings_before_heat.take() {
+                        let mut config = config.write().await;
+                        config.restore(snapshot);
+                        if settings_before_boost.is_none() {
+                            config.end_temporary_settings();
+                        }
+                        info!("System cooled down, restored settings");
+                        show_osd(
+                            &conn,
+                            "temperature-normal",
+                            "System cooled down, restored settings",
+                        )
+                        .await;
+                        config.apply().await;
+                    }
+                }
+                ActionEvent::RestoreQuality => {
+                    if let Some(snapshot) = settings_before_overload.take() {
+                        let mut config = config.write().await;
+                        config.restore(snapshot);
+                        config.save().await;
+                    }
+                }
+                ActionEvent::ToggleBoost if settings_before_boost.is_none() => {
+                    let mut config = config.write().await;
+                    settings_before_boost = Some(config.snapshot());
+                    config.begin_temporary_settings();
+                    config.apply_boost();
+                    info!(
+                        "Boosted to {} fps for {}",
+                        config.framerate,
+                        format_duration(Duration::from_secs(config.boost.duration_secs))
+                    );
+                    config.apply().await;
+
+                    let duration = Duration::from_secs(config.boost.duration_secs);
+                    let action_tx = action_tx.clone();
+                    boost_timer = Some(tokio::spawn(async move {
+                        tokio::time::sleep(duration).await;
+                        let _ = action_tx.send(ActionEvent::EndBoost).await;
+                    }));
+                }
+                ActionEvent::ToggleBoost | ActionEvent::EndBoost => {
+                    if let Some(timer) = boost_timer.take() {
+                        timer.abort();
+                    }
+                    if let Some(snapshot) = settings_before_boost.take() {
+                        info!("Boost ended");
+                        let mut config = config.write().await;
+                        config.restore(snapshot);
+                        if settings_before_heat.is_none() {
+                            config.end_temporary_settings();
+                        }
+                        config.apply().await;
+                    }
+                }
+                ActionEvent::RunCustomAction(index) => {
+                    match config.read().await.custom_actions.get(index) {
+                        Some(action) => custom_actions::run(
+                            action,
+                            replay_recorder.last_replay().await.as_deref(),
+                        ),
+                        None => warn!("Custom action {} does not exist", index),
+                    }
+                }
+                ActionEvent::CaptureRevoked => {
+                    handle_recorder_start_result(
+                        replay_recorder.pause(recorder::SHARING_STOPPED).await,
+                    );
+                }
+                ActionEvent::RequestCapture => {
+                    handle_recorder_start_result(
+                        replay_recorder.resume(recorder::SHARING_STOPPED).await,
+                    );
+                }
+                ActionEvent::AskForKmsPermission => {
+                    kms::ask_to_grant(&*config.read().await, action_tx.clone(), false)
+                }
+                ActionEvent::ReplaysMoved(moved) => {
+                    watch_later::moved(&moved);
+                    let moved_to = replay_recorder
+                        .last_replay()
+                        .await
+                        .and_then(|last| moved.get(&last).cloned());
+                    if let Some(path) = moved_to {
+                        replay_recorder.set_last_replay(path).await;
+                    }
+                }
+                ActionEvent::KmsPermissionGranted => {
+                    kms::refresh();
+                    handle_recorder_start_result(replay_recorder.restart().await)
+                }
+                ActionEvent::StopAskingForKmsPermission => {
+                    let mut config = config.write().await;
+                    config.ask_for_kms_permission = false;
+                    config.save().await;
+                }
+                ActionEvent::ToggleBuffer => {
+                    let result = if replay_recorder.is_paused_for(recorder::TURNED_OFF) {
+                        replay_recorder.resume(recorder::TURNED_OFF).await
+                    } else {
+                        replay_recorder.pause(recorder::TURNED_OFF).await
+                    };
+                    handle_recorder_start_result(result);
+                }
+                ActionEvent::RestoreConfigBackup(backup) => {
+                    match config::restore_config_backup(&backup) {
+                        Ok(_) => {
+                            let restored = Config::load(action_tx.clone()).await;
+                            let mut config = config.write().await;
+                            *config = restored;
+                            info!("Restored settings from {}", backup.display());
+                            config.save().await;
+                            reload_shortcuts(&shortcut_reload_tx).await;
+                        }
+                        Err(err) => error!("Cannot restore previous settings: {}", err),
+                    }
+                }
+                ActionEvent::ReloadConfig => match Config::read(action_tx.clone()) {
+                    Ok(mut reloaded) => {
+                        info!("Reloaded the config file");
+                        timeline::record("Config file reloaded");
+                        reloaded.display_framerate = config.read().await.display_framerate;
+                        reloaded.forget_stale_screen_serial(&*config.read().await);
+                        monitors::update_display_framerate(&mut reloaded);
+                        *config.write().await = reloaded;
+                        noise_suppression.apply(&*config.read().await).await;
+                        handle_recorder_start_result(
+                            apply_recorder_settings(
+                                &mut replay_recorder,
+                                &config,
+                                &app_name,
+                                &stats,
+                                &jobs,
+                                &action_tx,
+                            )
+                            .await,
+                        );
+                        recorders.apply(&*config.read().await);
+                        triggers.apply(&config.read().await.triggers);
+                    }
+                    Err(err) => error!("Cannot reload the config file: {}", err),
+                },
+                ActionEvent::DisplayRefreshRateChanged(rate) => {
+                    info!("Refresh rate changed to {} Hz, recording at it", rate);
+                    config.write().await.display_framerate = Some(rate);
+                    handle_recorder_start_result(replay_recorder.restart().await);
+                    recorders.apply(&*config.read().await);
+                }
                 ActionEvent::ConfigSaved => {
-                    gpu_screen_recorder.stop().await?;
-                    handle_gsr_start_result(gpu_screen_recorder.start().await);
+                    timeline::record("Settings changed");
+                    // The recorded monitor may have changed
+                    monitors::update_display_framerate(&mut *config.write().await);
+                    noise_suppression.apply(&*config.read().await).await;
+                    handle_recorder_start_result(
+                        apply_recorder_settings(
+                            &mut replay_recorder,
+                            &config,
+                            &app_name,
+                            &stats,
+                            &jobs,
+                            &action_tx,
+                        )
+                        .await,
+                    );
+                    recorders.apply(&*config.read().await);
+                    triggers.apply(&config.read().await.triggers);
                 }
                 other => {
                     warn!("Unhandled action event: {:?}", other)
                 }
             }
+
+            let buffer = (replay_recorder.status(), {
+                let mut reasons = replay_recorder.pause_reasons();
+                reasons.sort();
+                reasons
+            });
+            if last_buffer.as_ref() != Some(&buffer) {
+                events::publish(Event::BufferChanged {
+                    status: buffer.0,
+                    pause_reasons: buffer.1.clone(),
+                });
+                last_buffer = Some(buffer.clone());
+            }
+
+            tray_updater.sync(&*config.read().await).await;
+            tray_updater
+                .set_status(TrayStatus {
+                    live: live_stream.is_live(),
+                    recording: recording.is_recording(),
+                    watch_later: watch_later_queue.clips().to_vec(),
+                    recorders: recorders.states(),
+                    quality_lowered: settings_before_overload.is_some(),
+                    boosted: settings_before_boost.is_some(),
+                    buffer_bytes_per_sec: stats
+                        .read()
+                        .await
+                        .estimated_bytes_per_sec(&*config.read().await),
+                    buffer: buffer.0,
+                    pause_reasons: buffer.1,
+                    audio: audio_choices.clone(),
+                })
+                .await;
         }
     }
 }
 
-fn handle_gsr_start_result(result: Result<(), gsr::Error>) {
+/// Restarts the replay buffer with changed settings, switching to another backend if one was
+/// picked
+async fn apply_recorder_settings(
+    replay_recorder: &mut AnyRecorder,
+    config: &Arc<RwLock<Config>>,
+    app_name: &Arc<RwLock<String>>,
+    stats: &Arc<RwLock<Statistics>>,
+    jobs: &JobQueue,
+    action_tx: &Sender<ActionEvent>,
+) -> Result<(), recorder::Error> {
+    let switched = recorder::switch_backend(
+        replay_recorder,
+        config.clone(),
+        app_name.clone(),
+        stats.clone(),
+        jobs.clone(),
+        action_tx.clone(),
+    )
+    .await?;
+    if switched {
+        return Ok(());
+    }
+
+    replay_recorder.restart().await
+}
+
+/// The shortcuts can't be reloaded if their task stopped, which isn't a reason to quit
+async fn reload_shortcuts(shortcut_reload_tx: &Sender<()>) {
+    if shortcut_reload_tx.send(()).await.is_err() {
+        error!(
+            "Cannot reload the global shortcuts, they stopped working. Restart TrayPlay to get them back."
+        );
+    }
+}
+
+/// Shows a KDE OSD. Sessions without the OSD service only get a log line, it's not a reason
+/// to quit.
+async fn show_osd(conn: &Connection, icon: &str, text: &str) {
+    let result = match OsdServiceProxy::new(conn).await {
+        Ok(osd) => osd.show_text(icon, text).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        info!("Cannot show the OSD \"{}\": {}", text, err);
+    }
+}
+
+fn handle_recorder_start_result(result: Result<(), recorder::Error>) {
     match result {
         Ok(gsr) => gsr,
         Err(err) => match err {
-            gsr::Error::IoError(err) => match err.kind() {
+            recorder::Error::IoError(err) => match err.kind() {
                 std::io::ErrorKind::NotFound => error!("gpu-screen-recorder is not installed!"),
                 err => error!("Error while starting gpu-screen-recorder: {}", err),
             },
+            recorder::Error::MonitorNotConnected(screen) => error!(
+                "Monitor {} selected for recording is not connected. Reconnect it or change the screen option in the config.",
+                screen
+            ),
+            recorder::Error::InvalidOptions(problem) => {
+                error!(
+                    "gpu-screen-recorder cannot record with these settings: {}",
+                    problem
+                )
+            }
+            recorder::Error::MonitorExcluded(screen) => error!(
+                "{} is excluded from recording. Pick another screen or change excluded_monitors in the config.",
+                screen
+            ),
             err => error!("Error while starting gpu-screen-recorder: {}", err),
         },
     }
@@ -0,0 +1,331 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ActionEvent,
+    format::format_size,
+    kdialog::{ClickedButton, ComboBox, MessageBox, MessageBoxButtons, Progress, ProgressDialog},
+    notes, storage, timeline,
+};
+
+const TITLE: &str = "TrayPlay replay folder";
+
+/// What happens to a clip when the new folder already has one with its name
+#[derive(Clone, Copy)]
+enum Conflict {
+    KeepBoth,
+    Skip,
+    Replace,
+}
+
+const CONFLICT_CHOICES: [(&str, Conflict); 3] = [
+    (
+        "Keep both, the moved clip gets a number",
+        Conflict::KeepBoth,
+    ),
+    ("Leave the clip in the old folder", Conflict::Skip),
+    ("Replace the clip in the new folder", Conflict::Replace),
+];
+
+/// What moving the clips did
+#[derive(Default)]
+struct Migration {
+    /// New path of every moved clip by its old one
+    moved: HashMap<PathBuf, PathBuf>,
+    /// Left in the old folder because the new one has a clip with its name
+    skipped: Vec<PathBuf>,
+    failed: Vec<(PathBuf, std::io::Error)>,
+    cancelled: bool,
+}
+
+/// Clips in `from` that aren't in `to` already, which they are when `to` is a folder in `from`
+fn clips_to_move(from: &Path, to: &Path) -> Vec<PathBuf> {
+    let from = std::fs::canonicalize(from).unwrap_or_else(|_| from.to_path_buf());
+    let to = std::fs::canonicalize(to).unwrap_or_else(|_| to.to_path_buf());
+    if from == to {
+        return Vec::new();
+    }
+
+    notes::list_clips(&from)
+        .into_iter()
+        .filter(|clip| !clip.starts_with(&to))
+        .collect()
+}
+
+/// Offers to move the clips left in `from` after the replay folder was changed to `to`, so they
+/// don't end up split across both. The per-app folders, notes and exported copies move along.
+pub fn offer(from: PathBuf, to: PathBuf, action_tx: Sender<ActionEvent>) {
+    tokio::task::spawn_blocking(move || {
+        let clips = clips_to_move(&from, &to);
+        if clips.is_empty() {
+            return;
+        }
+
+        let size = clips
+            .iter()
+            .filter_map(|clip| clip.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let label = format!(
+            "{} clips ({}) are still in {}.\n\nMove them to {}? Their notes and exported copies are moved along with them.",
+            clips.len(),
+            format_size(size),
+            from.display(),
+            to.display()
+        );
+        let answer = MessageBox::new(label)
+            .title(TITLE)
+            .buttons(MessageBoxButtons::YesNo)
+            .show();
+        match answer {
+            Ok(ClickedButton::Yes) => {}
+            Ok(_) => return,
+            Err(err) => {
+                warn!("Cannot offer to move the clips: {}", err);
+                return;
+            }
+        }
+
+        let migration = migrate(&from, &to, &clips);
+        report(&migration, &to);
+        if !migration.moved.is_empty() {
+            let _ = action_tx.blocking_send(ActionEvent::ReplaysMoved(migration.moved));
+        }
+    });
+}
+
+fn migrate(from: &Path, to: &Path, clips: &[PathBuf]) -> Migration {
+    let progress = ProgressDialog::new(
+        format!("Moving clips to {}", to.display()),
+        clips.len() as u32,
+    )
+    .title(TITLE)
+    .show()
+    .inspect_err(|err| info!("Cannot show the progress of moving clips: {}", err))
+    .ok();
+
+    let from = std::fs::canonicalize(from).unwrap_or_else(|_| from.to_path_buf());
+    let mut migration = Migration::default();
+    // Asked on the first conflict, for all of them
+    let mut conflict = None;
+    for (moved, clip) in clips.iter().enumerate() {
+        if let Some(progress) = &progress {
+            if progress.was_cancelled() {
+                migration.cancelled = true;
+                break;
+            }
+            progress.set_value(moved as u32);
+            progress.set_label(&format!(
+                "Moving {}",
+                clip.file_name().unwrap_or_default().to_string_lossy()
+            ));
+        }
+        // An exported copy that was moved along with its clip
+        if !clip.exists() {
+            continue;
+        }
+
+        let mut target = to.join(clip.strip_prefix(&from).unwrap_or(clip));
+        if target.exists() {
+            match *conflict.get_or_insert_with(|| ask_conflict(&target, progress.as_ref())) {
+                Conflict::KeepBoth => target = free_path(&target),
+                Conflict::Skip => {
+                    migration.skipped.push(clip.clone());
+                    continue;
+                }
+                Conflict::Replace => {
+                    if let Err(err) = notes::delete_clip(&target) {
+                        migration.failed.push((clip.clone(), err));
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match move_clip(clip, &target) {
+            Ok(()) => {
+                migration.moved.insert(clip.clone(), target);
+            }
+            Err(err) => migration.failed.push((clip.clone(), err)),
+        }
+    }
+
+    let dirs: BTreeSet<&Path> = migration
+        .moved
+        .keys()
+        .filter_map(|clip| clip.parent())
+        .collect();
+    for dir in dirs {
+        if let Err(err) = move_index_lines(dir, &migration.moved) {
+            warn!(
+                "Cannot update the recording indexes in {}: {}",
+                dir.display(),
+                err
+            );
+        }
+        // Per-app folders that are empty now, fails for the others
+        if dir != from {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+
+    if let Some(progress) = &progress {
+        progress.close();
+    }
+    migration
+}
+
+/// Asks what to do with clips whose name is taken in the new folder, `target` is the first one
+fn ask_conflict(target: &Path, progress: Option<&Progress>) -> Conflict {
+    if let Some(progress) = progress {
+        progress.set_label("Waiting for what to do with clips that are already there");
+    }
+    let label = format!(
+        "{} already has a clip named {}. What should happen to it, and to other clips with a name that's taken?",
+        target.parent().unwrap_or(target).display(),
+        target.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let answer = ComboBox::new(label, CONFLICT_CHOICES.map(|(choice, _)| choice))
+        .title(TITLE)
+        .default(CONFLICT_CHOICES[0].0)
+        .show();
+    match answer {
+        Ok(Some(answer)) => CONFLICT_CHOICES
+            .into_iter()
+            .find(|(choice, _)| *choice == answer)
+            .map_or(Conflict::Skip, |(_, conflict)| conflict),
+        Ok(None) => Conflict::Skip,
+        Err(err) => {
+            info!("Cannot ask what to do with clips that exist: {}", err);
+            Conflict::Skip
+        }
+    }
+}
+
+/// `clip.mkv` -> `clip (2).mkv`, or the next number that isn't taken
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    (2..)
+        .map(|number| path.with_file_name(format!("{} ({}).{}", stem, number, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Moves `clip` to `target` with the files that belong to it
+fn move_clip(clip: &Path, target: &Path) -> std::io::Result<()> {
+    move_file(clip, target)?;
+
+    let companions = notes::companion_files(clip)
+        .into_iter()
+        .zip(notes::companion_files(target));
+    for (companion, companion_target) in companions.filter(|(companion, _)| companion.exists()) {
+        if let Err(err) = move_file(&companion, &companion_target) {
+            info!("Cannot move {}: {}", companion.display(), err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Renames `from` to `to`, copying it when they're on different filesystems
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    match std::fs::rename(from, to) {
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            // Copied to a work file first, so it's never seen half-written
+            let copy = storage::work_path(None, to);
+            let result = std::fs::copy(from, &copy).and_then(|_| std::fs::rename(&copy, to));
+            if result.is_err() {
+                let _ = std::fs::remove_file(&copy);
+                return result;
+            }
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Moves the lines of moved parts from the indexes of split recordings in `dir` to indexes next
+/// to where the parts went, under their new name
+fn move_index_lines(dir: &Path, moved: &HashMap<PathBuf, PathBuf>) -> std::io::Result<()> {
+    let indexes = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(".index.txt"));
+    for index in indexes {
+        let contents = std::fs::read_to_string(&index)?;
+        let mut kept = String::new();
+        // Lines for each index they're moved to
+        let mut moved_lines: HashMap<PathBuf, String> = HashMap::new();
+        for line in contents.lines() {
+            let name = line.split('\t').next().unwrap_or_default();
+            match moved.get(&dir.join(name)) {
+                Some(target) => {
+                    let target_name = target.file_name().unwrap_or_default().to_string_lossy();
+                    let target_index = target.with_file_name(index.file_name().unwrap_or_default());
+                    let lines = moved_lines.entry(target_index).or_default();
+                    lines.push_str(&format!("{}\n", line.replacen(name, &target_name, 1)));
+                }
+                None => kept.push_str(&format!("{}\n", line)),
+            }
+        }
+        if moved_lines.is_empty() {
+            continue;
+        }
+
+        for (target_index, lines) in moved_lines {
+            let mut contents = std::fs::read_to_string(&target_index).unwrap_or_default();
+            contents.push_str(&lines);
+            std::fs::write(&target_index, contents)?;
+        }
+        if kept.is_empty() {
+            std::fs::remove_file(&index)?;
+        } else {
+            std::fs::write(&index, kept)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn report(migration: &Migration, to: &Path) {
+    let moved = migration.moved.len();
+    info!("Moved {} clips to {}", moved, to.display());
+    if moved > 0 {
+        timeline::record(format!("Moved {} clips to {}", moved, to.display()));
+    }
+    if migration.cancelled {
+        info!("Stopped moving clips, the others are left in the old folder");
+    }
+    for clip in &migration.skipped {
+        info!(
+            "Left {}, its name is taken in {}",
+            clip.display(),
+            to.display()
+        );
+    }
+
+    if !migration.failed.is_empty() {
+        let failed: Vec<String> = migration
+            .failed
+            .iter()
+            .map(|(clip, err)| format!("{}: {}", clip.display(), err))
+            .collect();
+        warn!(
+            "{} clips could not be moved to {}:\n{}",
+            failed.len(),
+            to.display(),
+            failed.join("\n")
+        );
+    }
+}
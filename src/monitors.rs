@@ -0,0 +1,399 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use log::{info, warn};
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{
+    ActionEvent,
+    config::{CaptureTarget, Config},
+};
+
+/// How often the refresh rate is checked while the framerate follows it
+const REFRESH_RATE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Capture targets where gpu-screen-recorder picks the monitor itself
+const AUTOMATIC_CAPTURE_TARGETS: [&str; 3] = ["screen", "screen-direct", "focused"];
+
+#[derive(Debug)]
+pub enum ScreenError {
+    /// Connector, or connector and serial, of the monitor that is missing
+    NotConnected(String),
+    /// Monitor that is listed in `excluded_monitors`
+    Excluded(String),
+}
+
+impl Display for ScreenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenError::NotConnected(screen) => write!(f, "Monitor {} is not connected", screen),
+            ScreenError::Excluded(screen) => {
+                write!(f, "Monitor {} is excluded from recording", screen)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    pub connector: String,
+    pub serial: Option<String>,
+}
+
+/// Lists connected outputs using DRM connectors exposed in sysfs
+pub fn connected_monitors() -> Vec<Monitor> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = entry.file_name().to_str()?.to_string();
+
+            // Connector directories are named like card1-DP-2, card1-HDMI-A-1
+            let (card, connector) = name.split_once('-')?;
+            if !card.starts_with("card") {
+                return None;
+            }
+
+            let status = std::fs::read_to_string(path.join("status")).ok()?;
+            if status.trim() != "connected" {
+                return None;
+            }
+
+            Some(Monitor {
+                connector: connector.to_string(),
+                serial: std::fs::read(path.join("edid"))
+                    .ok()
+                    .and_then(|edid| edid_serial(&edid)),
+            })
+        })
+        .collect()
+}
+
+/// Reads the serial from EDID, preferring the display descriptor string over the numeric serial
+fn edid_serial(edid: &[u8]) -> Option<String> {
+    if edid.len() < 128 {
+        return None;
+    }
+
+    let descriptor_serial = [54, 72, 90, 108].iter().find_map(|&offset| {
+        let descriptor = &edid[offset..offset + 18];
+        if descriptor[0..3] == [0, 0, 0] && descriptor[3] == 0xFF {
+            let text = String::from_utf8_lossy(&descriptor[5..18]);
+            Some(
+                text.split('\n')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            )
+        } else {
+            None
+        }
+    });
+
+    descriptor_serial
+        .filter(|serial| !serial.is_empty())
+        .or_else(|| {
+            let serial = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+            (serial != 0).then(|| serial.to_string())
+        })
+}
+
+/// Whether the monitor on `connector` is listed in `excluded_monitors`, by connector or serial
+pub fn is_excluded(config: &Config, connector: &str) -> bool {
+    if config.excluded_monitors.is_empty() {
+        return false;
+    }
+
+    let serial = connected_monitors()
+        .into_iter()
+        .find(|monitor| monitor.connector == connector)
+        .and_then(|monitor| monitor.serial);
+    config
+        .excluded_monitors
+        .iter()
+        .any(|excluded| excluded == connector || Some(excluded) == serial.as_ref())
+}
+
+/// First connected monitor that isn't excluded
+fn first_allowed_monitor(config: &Config) -> Option<String> {
+    connected_monitors()
+        .into_iter()
+        .find(|monitor| !is_excluded(config, &monitor.connector))
+        .map(|monitor| monitor.connector)
+}
+
+/// Capture target to pass to gpu-screen-recorder. While some monitors are excluded, targets
+/// where gpu-screen-recorder picks the monitor (including following the focused window) are
+/// pinned to the first monitor that isn't excluded.
+pub fn capture_screen(config: &Config) -> String {
+    let screen = config.capture_target().to_string();
+    if config.excluded_monitors.is_empty() || !AUTOMATIC_CAPTURE_TARGETS.contains(&screen.as_str())
+    {
+        return screen;
+    }
+
+    match first_allowed_monitor(config) {
+        Some(connector) => {
+            if screen == "focused" {
+                warn!(
+                    "Not following the focused window because some monitors are excluded, recording {} instead",
+                    connector
+                );
+            }
+            connector
+        }
+        None => screen,
+    }
+}
+
+/// Refresh rate of the recorded monitor in Hz, or of the fastest one when
+/// gpu-screen-recorder picks the monitor. Asks kscreen-doctor, wlr-randr or xrandr.
+pub fn refresh_rate(config: &Config) -> Option<i64> {
+    let rates = output_refresh_rates();
+    let screen = capture_screen(config);
+    let rate = if is_monitor_target(&screen) {
+        rates
+            .iter()
+            .find(|(connector, _)| *connector == screen)
+            .map(|(_, rate)| *rate)
+    } else {
+        None
+    };
+
+    rate.or_else(|| rates.iter().map(|(_, rate)| *rate).reduce(f64::max))
+        .map(|rate| rate.round() as i64)
+        .filter(|rate| *rate > 0)
+}
+
+/// Looks up the refresh rate a `framerate` of 0 records at. The last known one is kept if it
+/// can't be told.
+pub fn update_display_framerate(config: &mut Config) {
+    if config.framerate == 0
+        && let Some(rate) = refresh_rate(config)
+    {
+        config.display_framerate = Some(rate);
+    }
+}
+
+/// Reports changes of the recorded monitor's refresh rate with
+/// [`ActionEvent::DisplayRefreshRateChanged`], while `framerate` is 0
+pub fn setup_refresh_rate_watcher(action_tx: Sender<ActionEvent>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REFRESH_RATE_CHECK_INTERVAL).await;
+            let config = config.read().await.clone();
+            if config.framerate != 0 {
+                continue;
+            }
+
+            let known = config.display_framerate;
+            let rate = tokio::task::spawn_blocking(move || refresh_rate(&config))
+                .await
+                .ok()
+                .flatten();
+            // Can't be told while the monitor is off, the last known rate is kept then
+            if let Some(rate) = rate
+                && Some(rate) != known
+                && action_tx
+                    .send(ActionEvent::DisplayRefreshRateChanged(rate))
+                    .await
+                    .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
+
+/// Reads the refresh rates out of a tool's output
+type OutputParser = fn(&str) -> Vec<(String, f64)>;
+
+/// Current refresh rate of every enabled output, by connector
+fn output_refresh_rates() -> Vec<(String, f64)> {
+    let tools: [(&str, &[&str], OutputParser); 3] = [
+        ("kscreen-doctor", &["-o"], parse_kscreen_doctor),
+        ("wlr-randr", &[], parse_wlr_randr),
+        ("xrandr", &["--query"], parse_xrandr),
+    ];
+
+    tools
+        .iter()
+        .find_map(|(program, args, parse)| {
+            let output = std::process::Command::new(program)
+                .args(*args)
+                .output()
+                .ok()?;
+            let rates = parse(&String::from_utf8_lossy(&output.stdout));
+            (!rates.is_empty()).then_some(rates)
+        })
+        .unwrap_or_default()
+}
+
+/// `Output: 1 DP-1` followed by `Modes:  0:2560x1440@143.97*!  1:2560x1440@60`, where * marks
+/// the current mode. Colored with escape codes.
+fn parse_kscreen_doctor(output: &str) -> Vec<(String, f64)> {
+    let mut rates = Vec::new();
+    let mut connector = None;
+    let mut words = output.split_whitespace().map(strip_escape_codes);
+    while let Some(word) = words.next() {
+        if word == "Output:" {
+            // Its number, then its name
+            words.next();
+            connector = words.next();
+        } else if word.contains('*')
+            && let Some(connector) = &connector
+            && let Some((_, rate)) = word.split_once('@')
+            && let Ok(rate) = rate.trim_end_matches(['*', '!']).parse()
+        {
+            rates.push((connector.clone(), rate));
+        }
+    }
+
+    rates
+}
+
+fn strip_escape_codes(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_escape = false;
+    for char in text.chars() {
+        match char {
+            '\u{1b}' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if in_escape => {}
+            char => stripped.push(char),
+        }
+    }
+
+    stripped
+}
+
+/// `DP-1 "Dell Inc. ..."` followed by `    2560x1440 px, 143.973007 Hz (preferred, current)`
+fn parse_wlr_randr(output: &str) -> Vec<(String, f64)> {
+    let mut rates = Vec::new();
+    let mut connector = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            connector = line.split_whitespace().next();
+        } else if line.contains("current")
+            && let Some(connector) = connector
+            && let Some((mode, _)) = line.split_once(" Hz")
+            && let Some(Ok(rate)) = mode.split_whitespace().last().map(str::parse)
+        {
+            rates.push((connector.to_string(), rate));
+        }
+    }
+
+    rates
+}
+
+/// `DP-1 connected primary 2560x1440+0+0 ...` followed by `   2560x1440    143.97*+  60.00`
+fn parse_xrandr(output: &str) -> Vec<(String, f64)> {
+    let mut rates = Vec::new();
+    let mut connector = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            connector = line
+                .contains(" connected")
+                .then(|| line.split_whitespace().next())
+                .flatten();
+        } else if let Some(connector) = connector
+            && let Some(Ok(rate)) = line
+                .split_whitespace()
+                .find(|rate| rate.contains('*'))
+                .map(|rate| rate.trim_end_matches(['*', '+']).parse())
+        {
+            rates.push((connector.to_string(), rate));
+        }
+    }
+
+    rates
+}
+
+pub fn is_monitor_target(screen: &str) -> bool {
+    matches!(CaptureTarget::from(screen), CaptureTarget::Monitor(_))
+}
+
+/// Makes sure `config.screen` points at the monitor the user picked, even if it was plugged
+/// into another connector since. Remembers the monitor's serial the first time it's seen.
+/// Fails if the monitor is not connected or is excluded from recording.
+pub fn resolve_screen(config: &mut Config) -> Result<(), ScreenError> {
+    resolve_connector(config)?;
+
+    let screen = config.capture_target().to_string();
+    if AUTOMATIC_CAPTURE_TARGETS.contains(&screen.as_str()) {
+        if !config.excluded_monitors.is_empty()
+            && !connected_monitors().is_empty()
+            && first_allowed_monitor(config).is_none()
+        {
+            return Err(ScreenError::Excluded("every connected monitor".to_string()));
+        }
+    } else if is_monitor_target(&screen) && is_excluded(config, &screen) {
+        return Err(ScreenError::Excluded(screen));
+    }
+
+    Ok(())
+}
+
+fn resolve_connector(config: &mut Config) -> Result<(), ScreenError> {
+    if config.follow_focus || !is_monitor_target(&config.screen) {
+        return Ok(());
+    }
+
+    let monitors = connected_monitors();
+    if monitors.is_empty() {
+        // Nothing to compare against, let gpu-screen-recorder decide
+        return Ok(());
+    }
+
+    if let Some(serial) = &config.screen_serial
+        && let Some(monitor) = monitors
+            .iter()
+            .find(|monitor| monitor.serial.as_ref() == Some(serial))
+    {
+        // Both are connected, `screen` was changed by hand while TrayPlay wasn't running
+        if monitor.connector != config.screen
+            && let Some(chosen) = monitors
+                .iter()
+                .find(|monitor| monitor.connector == config.screen)
+        {
+            info!(
+                "Recording {} as set in the config file instead of monitor {} on {}",
+                config.screen, serial, monitor.connector
+            );
+            config.screen_serial = chosen.serial.clone();
+            config.write_file();
+        } else if monitor.connector != config.screen {
+            info!(
+                "Monitor {} moved from {} to {}",
+                serial, config.screen, monitor.connector
+            );
+            config.screen = monitor.connector.clone();
+            config.write_file();
+        }
+        return Ok(());
+    }
+
+    let monitor = monitors
+        .iter()
+        .find(|monitor| monitor.connector == config.screen);
+
+    match (monitor, &config.screen_serial) {
+        (Some(monitor), None) => {
+            if monitor.serial.is_some() {
+                config.screen_serial = monitor.serial.clone();
+                config.write_file();
+            }
+            Ok(())
+        }
+        // Same connector, but a different monitor is plugged into it now
+        (Some(_), Some(serial)) => Err(ScreenError::NotConnected(format!(
+            "{} (serial {})",
+            config.screen, serial
+        ))),
+        (None, _) => Err(ScreenError::NotConnected(config.screen.clone())),
+    }
+}
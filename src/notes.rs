@@ -0,0 +1,239 @@
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Container, jobs::sibling_path};
+
+/// Rating Baloo stores for five stars, in half stars
+const FAVORITE_RATING: &[u8] = b"10";
+
+/// Notes and favorites of a clip collection, written by `trayplay export-notes` as JSON
+#[derive(Serialize, Deserialize, Default)]
+pub struct NotesExport {
+    #[serde(default)]
+    pub clips: Vec<ExportedNote>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedNote {
+    /// Relative to the replay directory, so the collection can be moved
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub favorite: bool,
+}
+
+/// What importing notes did
+#[derive(Default)]
+pub struct NotesImport {
+    pub imported: usize,
+    /// Clips of the export that aren't in the replay directory
+    pub missing: Vec<PathBuf>,
+}
+
+/// Notes live in a text file next to the clip, `clip.mkv` -> `clip.mkv.note.txt`, so they move
+/// and get deleted along with it in a file manager
+fn note_path(clip: &Path) -> PathBuf {
+    let mut path = clip.as_os_str().to_owned();
+    path.push(".note.txt");
+    PathBuf::from(path)
+}
+
+pub fn read_note(clip: &Path) -> Option<String> {
+    std::fs::read_to_string(note_path(clip))
+        .ok()
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty())
+}
+
+/// Replaces the note of `clip`, an empty one removes it
+pub fn write_note(clip: &Path, note: &str) -> std::io::Result<()> {
+    let path = note_path(clip);
+    if note.trim().is_empty() {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    } else {
+        std::fs::write(path, format!("{}\n", note.trim()))
+    }
+}
+
+/// Rates the clip with five stars the way Baloo stores ratings, which Dolphin shows and sorts by
+pub fn set_favorite(clip: &Path) -> std::io::Result<()> {
+    let path = CString::new(clip.as_os_str().as_bytes())?;
+    let result = unsafe {
+        nix::libc::setxattr(
+            path.as_ptr(),
+            c"user.baloo.rating".as_ptr(),
+            FAVORITE_RATING.as_ptr().cast(),
+            FAVORITE_RATING.len(),
+            0,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Whether the clip is rated with five stars, like [`set_favorite`] does
+pub fn is_favorite(clip: &Path) -> bool {
+    let Ok(path) = CString::new(clip.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut rating = [0u8; 8];
+    let length = unsafe {
+        nix::libc::getxattr(
+            path.as_ptr(),
+            c"user.baloo.rating".as_ptr(),
+            rating.as_mut_ptr().cast(),
+            rating.len(),
+        )
+    };
+
+    length >= 0 && &rating[..length as usize] == FAVORITE_RATING
+}
+
+/// Files that belong to `clip`, whether they exist or not: its note and the vertical and share
+/// copies exported from it
+pub fn companion_files(clip: &Path) -> [PathBuf; 3] {
+    [
+        note_path(clip),
+        sibling_path(clip, "vertical"),
+        sibling_path(clip, "share").with_extension("mp4"),
+    ]
+}
+
+/// Clips in `replay_dir` and the per-app directories in it, oldest first
+pub fn list_clips(replay_dir: &Path) -> Vec<PathBuf> {
+    let mut clips = Vec::new();
+    let mut dirs = vec![replay_dir.to_path_buf()];
+    if let Ok(entries) = std::fs::read_dir(replay_dir) {
+        dirs.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir()),
+        );
+    }
+
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        clips.extend(entries.filter_map(|entry| entry.ok()).filter_map(|entry| {
+            let path = entry.path();
+            let extension = path.extension()?.to_str()?;
+            Container::from_extension(extension)?;
+            Some((entry.metadata().ok()?.modified().ok()?, path))
+        }));
+    }
+    clips.sort();
+
+    clips.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Deletes `clip` with its note, the copies exported from it and its line in the index of a
+/// split recording. Returns the files that were removed.
+pub fn delete_clip(clip: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::remove_file(clip)?;
+    let mut removed = vec![clip.to_path_buf()];
+
+    for extra in companion_files(clip) {
+        if std::fs::remove_file(&extra).is_ok() {
+            removed.push(extra);
+        }
+    }
+
+    // name_session.index.txt: "name_session_part001.mkv\t<date>" per part
+    let name = clip.file_name().unwrap_or_default().to_string_lossy();
+    let indexes = clip
+        .parent()
+        .and_then(|dir| std::fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(".index.txt"));
+    for index in indexes {
+        let Ok(contents) = std::fs::read_to_string(&index) else {
+            continue;
+        };
+        let kept = contents
+            .lines()
+            .filter(|line| line.split('\t').next() != Some(name.as_ref()))
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        if kept.len() == contents.len() {
+            continue;
+        }
+        if kept.is_empty() {
+            std::fs::remove_file(&index)?;
+            removed.push(index);
+        } else {
+            std::fs::write(&index, kept)?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Notes and favorites of every clip in `replay_dir`
+pub fn export_notes(replay_dir: &Path) -> NotesExport {
+    NotesExport {
+        clips: list_clips(replay_dir)
+            .into_iter()
+            .filter_map(|clip| {
+                let note = read_note(&clip);
+                let favorite = is_favorite(&clip);
+                (note.is_some() || favorite).then(|| ExportedNote {
+                    path: clip.strip_prefix(replay_dir).unwrap_or(&clip).to_path_buf(),
+                    note,
+                    favorite,
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Writes the notes of `export` to the clips in `replay_dir` and marks its favorites. Clips that
+/// were moved to another folder in it are found by their file name.
+pub fn import_notes(replay_dir: &Path, export: &NotesExport) -> std::io::Result<NotesImport> {
+    let clips = list_clips(replay_dir);
+    let mut result = NotesImport::default();
+
+    for exported in &export.clips {
+        let path = replay_dir.join(&exported.path);
+        let clip = if path.is_file() {
+            Some(path)
+        } else {
+            clips
+                .iter()
+                .find(|clip| clip.file_name() == exported.path.file_name())
+                .cloned()
+        };
+
+        match clip {
+            Some(clip) => {
+                if let Some(note) = &exported.note {
+                    write_note(&clip, note)?;
+                }
+                if exported.favorite {
+                    set_favorite(&clip)?;
+                }
+                result.imported += 1;
+            }
+            None => result.missing.push(exported.path.clone()),
+        }
+    }
+
+    Ok(result)
+}
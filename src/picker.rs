@@ -0,0 +1,174 @@
+use std::{
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::{CaptureTarget, Config, RecorderBackend};
+
+type PickResult<T> = Result<Option<T>, Box<dyn std::error::Error>>;
+
+/// How long the listed X11 windows are reused for. The menu is built on every change of config
+/// or status, wmctrl would run each time otherwise.
+const WINDOW_LIST_TTL: Duration = Duration::from_secs(10);
+
+static X11_WINDOWS: Mutex<Option<(Instant, Vec<PickableWindow>)>> = Mutex::new(None);
+
+/// A window that can be recorded, as listed in the capture menu
+#[derive(Clone)]
+pub struct PickableWindow {
+    pub title: String,
+    /// Freedesktop icon name of the window's app, its class is the best guess there is
+    pub icon_name: String,
+    pub target: CaptureTarget,
+}
+
+/// How the user picks a window or region to record. Each session type has its own way of
+/// telling windows apart, so the capture menu goes through whichever fits.
+pub trait CapturePicker {
+    /// Windows to offer in the menu. Empty where windows can't be recorded by id.
+    fn windows(&self) -> Vec<PickableWindow>;
+
+    /// Lets the user choose the window interactively
+    fn pick_window(&self) -> PickResult<CaptureTarget>;
+
+    /// Lets the user drag out the area to record. Returns its geometry as WxH+X+Y.
+    fn select_region(&self) -> PickResult<String>;
+}
+
+/// Picker for the running session and recorder backend
+pub fn detect(config: &Config) -> Box<dyn CapturePicker + Send> {
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    let wlroots = !desktop
+        .split(':')
+        .any(|desktop| desktop == "KDE" || desktop == "GNOME");
+
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        Box::new(X11Picker)
+    } else if wlroots || config.recorder_backend == RecorderBackend::WfRecorder {
+        Box::new(WlrPicker)
+    } else {
+        Box::new(PortalPicker {
+            // GNOME Shell has no layer-shell, which slurp needs
+            slurp: desktop.split(':').any(|desktop| desktop == "KDE"),
+        })
+    }
+}
+
+/// Runs a selection tool. They exit with an error when the selection is cancelled.
+fn selection_output(program: &str, args: &[&str]) -> PickResult<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let selection = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!selection.is_empty()).then_some(selection))
+}
+
+/// X11 windows have ids gpu-screen-recorder records directly
+pub struct X11Picker;
+
+impl X11Picker {
+    fn list_windows() -> Vec<PickableWindow> {
+        let Ok(output) = Command::new("wmctrl").arg("-lx").output() else {
+            return Vec::new();
+        };
+
+        // 0x03a00007  0 firefox.firefox       hostname Title of the window
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let id = fields.next()?;
+                let desktop = fields.next()?;
+                let class = fields.next()?;
+                fields.next()?;
+                let title = fields.collect::<Vec<_>>().join(" ");
+                // Sticky windows like panels and docks
+                (desktop != "-1").then(|| PickableWindow {
+                    title,
+                    icon_name: class.rsplit('.').next().unwrap_or(class).to_lowercase(),
+                    target: CaptureTarget::Window(id.to_string()),
+                })
+            })
+            .collect()
+    }
+}
+
+impl CapturePicker for X11Picker {
+    fn windows(&self) -> Vec<PickableWindow> {
+        let mut cached = X11_WINDOWS.lock().unwrap();
+        match &*cached {
+            Some((listed, windows)) if listed.elapsed() < WINDOW_LIST_TTL => windows.clone(),
+            _ => {
+                let windows = Self::list_windows();
+                *cached = Some((Instant::now(), windows.clone()));
+                windows
+            }
+        }
+    }
+
+    fn pick_window(&self) -> PickResult<CaptureTarget> {
+        // Turns the cursor into a crosshair until a window is clicked
+        let Some(info) = selection_output("xwininfo", &[])? else {
+            return Ok(None);
+        };
+
+        // "xwininfo: Window id: 0x3a00007 "title""
+        let id = info
+            .lines()
+            .find_map(|line| line.split_once("Window id: "))
+            .and_then(|(_, rest)| rest.split_whitespace().next().map(str::to_string));
+
+        Ok(id.map(CaptureTarget::Window))
+    }
+
+    fn select_region(&self) -> PickResult<String> {
+        selection_output("slop", &["-f", "%wx%h+%x+%y"])
+    }
+}
+
+/// wlroots compositors only share whole outputs, with wf-recorder and through their portal
+pub struct WlrPicker;
+
+impl CapturePicker for WlrPicker {
+    fn windows(&self) -> Vec<PickableWindow> {
+        Vec::new()
+    }
+
+    fn pick_window(&self) -> PickResult<CaptureTarget> {
+        Err(
+            "single windows cannot be recorded on wlroots compositors, select a region instead"
+                .into(),
+        )
+    }
+
+    fn select_region(&self) -> PickResult<String> {
+        selection_output("slurp", &["-f", "%wx%h+%x+%y"])
+    }
+}
+
+/// Wayland desktops like KDE and GNOME have no window ids gpu-screen-recorder could record, so
+/// windows aren't listed. Their portal's dialog (KWin's on KDE) picks the window each time the
+/// recorder starts.
+pub struct PortalPicker {
+    slurp: bool,
+}
+
+impl CapturePicker for PortalPicker {
+    fn windows(&self) -> Vec<PickableWindow> {
+        Vec::new()
+    }
+
+    fn pick_window(&self) -> PickResult<CaptureTarget> {
+        Ok(Some(CaptureTarget::Portal))
+    }
+
+    fn select_region(&self) -> PickResult<String> {
+        if !self.slurp {
+            return Err("regions cannot be selected on this desktop".into());
+        }
+        selection_output("slurp", &["-f", "%wx%h+%x+%y"])
+    }
+}
@@ -0,0 +1,53 @@
+use futures_util::StreamExt;
+use log::info;
+use tokio::sync::mpsc::Sender;
+use zbus::{Connection, proxy};
+
+use crate::ActionEvent;
+
+#[proxy(
+    interface = "org.freedesktop.UPower",
+    default_service = "org.freedesktop.UPower",
+    default_path = "/org/freedesktop/UPower"
+)]
+trait UPower {
+    #[zbus(property)]
+    fn on_battery(&self) -> zbus::Result<bool>;
+}
+
+pub async fn setup_power_source_listener(
+    action_tx: Sender<ActionEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connection = Connection::system().await?;
+    let upower_proxy = UPowerProxy::new(&connection).await?;
+    let mut changes = upower_proxy.receive_on_battery_changed().await;
+
+    if upower_proxy.on_battery().await? {
+        action_tx
+            .send(ActionEvent::PowerSourceChanged(true))
+            .await?;
+    }
+
+    tokio::spawn(async move {
+        // Keep the proxy alive for as long as we listen for changes
+        let _upower_proxy = upower_proxy;
+
+        while let Some(change) = changes.next().await {
+            if let Ok(on_battery) = change.get().await {
+                info!(
+                    "Power source changed to {}",
+                    if on_battery { "battery" } else { "AC" }
+                );
+                if action_tx
+                    .send(ActionEvent::PowerSourceChanged(on_battery))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    process::Command,
+    sync::{RwLock, mpsc::Sender},
+};
+
+use crate::{ActionEvent, config::Config};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Checks whether any PipeWire video source (webcam, screen-share portal stream) is running
+async fn video_source_active() -> bool {
+    let Ok(output) = Command::new("pw-dump").output().await else {
+        return false;
+    };
+    let dump = String::from_utf8_lossy(&output.stdout);
+
+    // pw-dump prints a JSON array of objects, each starting with its "type". Splitting on it gives
+    // one chunk per object, which is enough to match a node's class with its state.
+    dump.split("\"type\": \"PipeWire:Interface:")
+        .filter(|object| object.starts_with("Node"))
+        .any(|node| {
+            node.contains("\"media.class\": \"Video/Source\"")
+                && node.contains("\"state\": \"running\"")
+        })
+}
+
+/// Pauses the replay buffer while a video call or screen-share is running, if enabled in config
+pub fn setup_video_call_watcher(action_tx: Sender<ActionEvent>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut call_active = false;
+
+        loop {
+            let enabled = {
+                let config = config.read().await;
+                // With portal capture our own screencast stream would count as a call
+                config.pause_during_calls && config.screen != "portal"
+            };
+            let active = enabled && video_source_active().await;
+
+            if active != call_active {
+                call_active = active;
+                if action_tx
+                    .send(ActionEvent::VideoCallActive(active))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
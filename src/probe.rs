@@ -0,0 +1,79 @@
+use std::{fmt::Display, path::Path, time::Duration};
+
+use log::warn;
+use tokio::process::Command;
+
+use crate::format::{format_duration, format_size};
+
+/// What ffprobe found out about a saved clip
+#[derive(Debug, Clone)]
+pub struct ClipInfo {
+    pub duration: Option<Duration>,
+    pub resolution: Option<(u32, u32)>,
+    pub size_bytes: u64,
+}
+
+impl Display for ClipInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(duration) = self.duration {
+            write!(f, "{}, ", format_duration(duration))?;
+        }
+        if let Some((width, height)) = self.resolution {
+            write!(f, "{}x{}, ", width, height)?;
+        }
+        write!(f, "{}", format_size(self.size_bytes))
+    }
+}
+
+/// Reads duration and resolution of `path` with ffprobe. Only the size is filled in when
+/// ffprobe is missing or fails.
+pub async fn probe_clip(path: &Path) -> Option<ClipInfo> {
+    let size_bytes = std::fs::metadata(path).ok()?.len();
+    let mut info = ClipInfo {
+        duration: None,
+        resolution: None,
+        size_bytes,
+    };
+
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=width,height:format=duration",
+            "-of",
+            "default=noprint_wrappers=1",
+        ])
+        .arg(path)
+        .output()
+        .await;
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warn!("ffprobe exited with {}", output.status);
+            return Some(info);
+        }
+        Err(err) => {
+            warn!("Cannot run ffprobe: {}", err);
+            return Some(info);
+        }
+    };
+
+    // One "key=value" per line
+    let (mut width, mut height) = (None, None);
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match line.split_once('=') {
+            Some(("width", value)) => width = value.parse().ok(),
+            Some(("height", value)) => height = value.parse().ok(),
+            Some(("duration", value)) => {
+                info.duration = value.parse().ok().map(Duration::from_secs_f64)
+            }
+            _ => {}
+        }
+    }
+    info.resolution = width.zip(height);
+
+    Some(info)
+}
@@ -0,0 +1,375 @@
+use std::{fmt::Display, path::PathBuf, sync::Arc, time::Duration};
+
+use lazy_static::lazy_static;
+use log::info;
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{
+    ActionEvent,
+    config::{Config, Encoder, RecorderBackend},
+    gsr::GpuScreenRecorder,
+    jobs::{Job, JobQueue},
+    monitors::ScreenError,
+    probe::probe_clip,
+    screencast::ScreencastRecorder,
+    stats::Statistics,
+    wf_recorder::WfRecorder,
+};
+
+/// Pause reason of the tray's "Replay buffer" toggle
+pub const TURNED_OFF: &str = "turned off";
+/// Pause reason after the user stopped screen sharing from the system UI
+pub const SHARING_STOPPED: &str = "screen sharing stopped";
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum Error {
+    IoError(std::io::Error),
+    NixErrno(nix::errno::Errno),
+    RecorderNotRunning,
+    MonitorNotConnected(String),
+    MonitorExcluded(String),
+    Portal(ashpd::Error),
+    /// Nothing has been recorded yet
+    BufferEmpty,
+    /// Settings gpu-screen-recorder can't record with, see [`crate::gsr_command`]
+    InvalidOptions(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "recorder error: {:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<ScreenError> for Error {
+    fn from(value: ScreenError) -> Self {
+        match value {
+            ScreenError::NotConnected(screen) => Self::MonitorNotConnected(screen),
+            ScreenError::Excluded(screen) => Self::MonitorExcluded(screen),
+        }
+    }
+}
+
+impl From<ashpd::Error> for Error {
+    fn from(value: ashpd::Error) -> Self {
+        Self::Portal(value)
+    }
+}
+
+impl From<nix::errno::Errno> for Error {
+    fn from(value: nix::errno::Errno) -> Self {
+        Self::NixErrno(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RecorderStatus {
+    Running,
+    Paused,
+    #[default]
+    Stopped,
+    /// Exited on its own too often in a row to be restarted again
+    Failed,
+}
+
+impl Display for RecorderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecorderStatus::Running => write!(f, "recording"),
+            RecorderStatus::Paused => write!(f, "paused"),
+            RecorderStatus::Stopped => write!(f, "stopped"),
+            RecorderStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Backend keeping the replay buffer. Everything outside of the backend's module goes through
+/// this trait, so other backends can be added next to gpu-screen-recorder.
+#[allow(async_fn_in_trait)]
+pub trait Recorder {
+    /// Starts recording with current config, unless paused
+    async fn start(&mut self) -> Result<(), Error>;
+
+    async fn stop(&mut self) -> Result<(), Error>;
+
+    /// Stops the recorder if it's running and starts it again with current config
+    async fn restart(&mut self) -> Result<(), Error> {
+        match self.stop().await {
+            Ok(_) | Err(Error::RecorderNotRunning) => {}
+            Err(err) => return Err(err),
+        }
+
+        self.start().await
+    }
+
+    async fn save_replay(&mut self) -> Result<(), Error>;
+
+    /// Saves only the last `secs` seconds of the buffer, backends may round it up
+    async fn save_replay_last(&mut self, secs: i64) -> Result<(), Error>;
+
+    /// Stops recording until every reason it was paused for is resumed
+    async fn pause(&mut self, reason: &str) -> Result<(), Error>;
+
+    async fn resume(&mut self, reason: &str) -> Result<(), Error>;
+
+    fn is_paused_for(&self, reason: &str) -> bool;
+
+    /// Everything the recorder is paused for, in no particular order
+    fn pause_reasons(&self) -> Vec<String>;
+
+    fn status(&self) -> RecorderStatus;
+
+    /// Path of the most recently saved replay
+    async fn last_replay(&self) -> Option<PathBuf>;
+
+    /// Points the last replay at where it was moved to
+    async fn set_last_replay(&self, path: PathBuf);
+
+    /// Called when the recorder process `pid` exits. Returns how long to wait before
+    /// [`Recorder::recover`] when it crashed and should be started again.
+    async fn exited(&mut self, _pid: u32) -> Option<Duration> {
+        None
+    }
+
+    /// Starts the recorder again after it crashed, also after it gave up on restarting
+    async fn recover(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Bookkeeping shared by all backends once a replay has been written to `path`: statistics,
+/// post-processing jobs and the "saved" OSD
+pub async fn replay_saved(
+    path: PathBuf,
+    config: &RwLock<Config>,
+    stats: &RwLock<Statistics>,
+    jobs: &JobQueue,
+    last_replay: &RwLock<Option<PathBuf>>,
+    action_tx: &Sender<ActionEvent>,
+) {
+    stats.write().await.record_save();
+    *last_replay.write().await = Some(path.clone());
+
+    // Probed before any job rewrites the file
+    let info = probe_clip(&path).await;
+
+    let config = config.read().await;
+    if let Some(info) = &info {
+        stats.write().await.record_clip(info, &config);
+    }
+    // First, so loudness is measured and options are applied on what's kept
+    if config.trim_dead_air {
+        jobs.push(Job::TrimDeadAir(path.clone()));
+    }
+    if config.normalize_loudness {
+        jobs.push(Job::NormalizeLoudness(path.clone()));
+    }
+    // After anything else that rewrites the file, which would drop the options
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if let Some(options) = config.muxer_options.get(&extension)
+        && !options.is_empty()
+    {
+        jobs.push(Job::Remux(path.clone(), options.clone()));
+    }
+    if config.share_copy.enabled {
+        jobs.push(Job::ShareCopy(path.clone(), config.share_copy));
+    }
+    drop(config);
+
+    let _ = action_tx.send(ActionEvent::ReplaySaved(path, info)).await;
+}
+
+/// One of the backends, picked by `recorder_backend` in config
+pub enum AnyRecorder {
+    GpuScreenRecorder(GpuScreenRecorder),
+    Screencast(ScreencastRecorder),
+    WfRecorder(WfRecorder),
+}
+
+impl AnyRecorder {
+    pub fn backend(&self) -> RecorderBackend {
+        match self {
+            AnyRecorder::GpuScreenRecorder(_) => RecorderBackend::GpuScreenRecorder,
+            AnyRecorder::Screencast(_) => RecorderBackend::Screencast,
+            AnyRecorder::WfRecorder(_) => RecorderBackend::WfRecorder,
+        }
+    }
+}
+
+/// Calls the same method on whichever backend is in use
+macro_rules! delegate {
+    ($self:expr, $recorder:ident => $call:expr) => {
+        match $self {
+            AnyRecorder::GpuScreenRecorder($recorder) => $call,
+            AnyRecorder::Screencast($recorder) => $call,
+            AnyRecorder::WfRecorder($recorder) => $call,
+        }
+    };
+}
+
+impl Recorder for AnyRecorder {
+    async fn start(&mut self) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.start().await)
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.stop().await)
+    }
+
+    async fn restart(&mut self) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.restart().await)
+    }
+
+    async fn save_replay(&mut self) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.save_replay().await)
+    }
+
+    async fn save_replay_last(&mut self, secs: i64) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.save_replay_last(secs).await)
+    }
+
+    async fn pause(&mut self, reason: &str) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.pause(reason).await)
+    }
+
+    async fn resume(&mut self, reason: &str) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.resume(reason).await)
+    }
+
+    fn is_paused_for(&self, reason: &str) -> bool {
+        delegate!(self, recorder => recorder.is_paused_for(reason))
+    }
+
+    fn pause_reasons(&self) -> Vec<String> {
+        delegate!(self, recorder => recorder.pause_reasons())
+    }
+
+    fn status(&self) -> RecorderStatus {
+        delegate!(self, recorder => recorder.status())
+    }
+
+    async fn last_replay(&self) -> Option<PathBuf> {
+        delegate!(self, recorder => recorder.last_replay().await)
+    }
+
+    async fn set_last_replay(&self, path: PathBuf) {
+        delegate!(self, recorder => recorder.set_last_replay(path).await)
+    }
+
+    async fn exited(&mut self, pid: u32) -> Option<Duration> {
+        delegate!(self, recorder => recorder.exited(pid).await)
+    }
+
+    async fn recover(&mut self) -> Result<(), Error> {
+        delegate!(self, recorder => recorder.recover().await)
+    }
+}
+
+/// PCI vendor ids of the GPUs with a render node
+fn gpu_vendors() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("renderD"))
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("device/vendor")).ok())
+        .map(|vendor| vendor.trim().to_string())
+        .collect()
+}
+
+lazy_static! {
+    static ref GPU_VENDORS: Vec<String> = gpu_vendors();
+}
+
+const NVIDIA: &str = "0x10de";
+const AMD: &str = "0x1002";
+const INTEL: &str = "0x8086";
+
+/// Encoders `backend` can use on this system's GPUs
+pub fn supported_encoders(backend: RecorderBackend) -> Vec<Encoder> {
+    let has_gpu = |vendor: &str| GPU_VENDORS.iter().any(|gpu| gpu == vendor);
+    let mut encoders = vec![Encoder::Auto, Encoder::Software];
+    match backend {
+        // GStreamer pipeline encodes with x264, gpu-screen-recorder picks the GPU's API itself
+        RecorderBackend::Screencast | RecorderBackend::GpuScreenRecorder => {}
+        RecorderBackend::WfRecorder => {
+            if has_gpu(AMD) || has_gpu(INTEL) {
+                encoders.push(Encoder::Vaapi);
+            }
+            if has_gpu(NVIDIA) {
+                encoders.push(Encoder::Nvenc);
+            }
+            if has_gpu(INTEL) {
+                encoders.push(Encoder::Qsv);
+            }
+        }
+    }
+
+    encoders
+}
+
+/// Creates the replay buffer backend set in config
+pub async fn create(
+    config: Arc<RwLock<Config>>,
+    app_name: Arc<RwLock<String>>,
+    stats: Arc<RwLock<Statistics>>,
+    jobs: JobQueue,
+    action_tx: Sender<ActionEvent>,
+) -> Result<AnyRecorder, Error> {
+    let backend = config.read().await.recorder_backend;
+    Ok(match backend {
+        RecorderBackend::GpuScreenRecorder => AnyRecorder::GpuScreenRecorder(
+            GpuScreenRecorder::new(config, app_name, stats, jobs, action_tx).await?,
+        ),
+        RecorderBackend::Screencast => AnyRecorder::Screencast(
+            ScreencastRecorder::new(config, app_name, stats, jobs, action_tx).await?,
+        ),
+        RecorderBackend::WfRecorder => AnyRecorder::WfRecorder(
+            WfRecorder::new(config, app_name, stats, jobs, action_tx).await?,
+        ),
+    })
+}
+
+/// Replaces `recorder` with the backend config picks now, if that changed, and starts it paused
+/// for the same reasons. Returns whether it was replaced.
+pub async fn switch_backend(
+    recorder: &mut AnyRecorder,
+    config: Arc<RwLock<Config>>,
+    app_name: Arc<RwLock<String>>,
+    stats: Arc<RwLock<Statistics>>,
+    jobs: JobQueue,
+    action_tx: Sender<ActionEvent>,
+) -> Result<bool, Error> {
+    let backend = config.read().await.recorder_backend;
+    if recorder.backend() == backend {
+        return Ok(false);
+    }
+
+    info!("Switching the replay buffer to {:?}", backend);
+    match recorder.stop().await {
+        Ok(_) | Err(Error::RecorderNotRunning) => {}
+        Err(err) => return Err(err),
+    }
+    let pause_reasons = recorder.pause_reasons();
+    *recorder = create(config, app_name, stats, jobs, action_tx).await?;
+    for reason in pause_reasons {
+        recorder.pause(&reason).await?;
+    }
+    recorder.start().await?;
+
+    Ok(true)
+}
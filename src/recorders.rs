@@ -0,0 +1,428 @@
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ActionEvent,
+    config::{Config, RecorderConfig, RecorderMode},
+    format::format_full_date_time,
+    gsr::spawn_logged,
+    gsr_command::GsrCommandBuilder,
+    monitors, recorder,
+    utils::resolve_path,
+};
+
+/// How often the size and length of split recordings are checked
+const SPLIT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecorderState {
+    Stopped,
+    Running,
+    Failed(Option<i32>),
+}
+
+impl Display for RecorderState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecorderState::Stopped => write!(f, "stopped"),
+            RecorderState::Running => write!(f, "running"),
+            RecorderState::Failed(Some(code)) => write!(f, "failed with code {}", code),
+            RecorderState::Failed(None) => write!(f, "failed"),
+        }
+    }
+}
+
+struct Instance {
+    recorder: RecorderConfig,
+    pid: Option<u32>,
+    state: RecorderState,
+    /// Start time of the current recording and the number of its current part, when recordings
+    /// are split
+    part: (u64, u32),
+    /// What the running process was started with, see [`RecorderSupervisor::settings`]
+    settings: Option<String>,
+}
+
+/// Runs the extra recorders from config next to the main replay buffer and keeps track of
+/// their state. Exits are reported back through [`ActionEvent::RecorderExited`].
+pub struct RecorderSupervisor {
+    instances: Vec<Instance>,
+    action_tx: Sender<ActionEvent>,
+}
+
+impl RecorderSupervisor {
+    pub fn new(action_tx: Sender<ActionEvent>) -> Self {
+        Self {
+            instances: Vec::new(),
+            action_tx,
+        }
+    }
+
+    /// Picks up recorders added to or removed from the config and restarts the running ones whose
+    /// settings changed. New recorders start right away, stopped ones stay stopped.
+    pub fn apply(&mut self, config: &Config) {
+        self.instances.retain_mut(|instance| {
+            let kept = config
+                .recorders
+                .iter()
+                .any(|r| r.name == instance.recorder.name);
+            if !kept && let Err(err) = Self::kill(instance) {
+                warn!("Cannot stop recorder {}: {}", instance.recorder.name, err);
+            }
+            kept
+        });
+
+        for recorder in &config.recorders {
+            match self
+                .instances
+                .iter_mut()
+                .find(|instance| instance.recorder.name == recorder.name)
+            {
+                Some(instance) => instance.recorder = recorder.clone(),
+                None => self.instances.push(Instance {
+                    recorder: recorder.clone(),
+                    pid: None,
+                    state: RecorderState::Running,
+                    part: (0, 0),
+                    settings: None,
+                }),
+            }
+        }
+
+        for index in 0..self.instances.len() {
+            let instance = &mut self.instances[index];
+            if instance.pid.is_some() {
+                let settings = Self::settings(&instance.recorder, config);
+                if settings.is_some() && settings == instance.settings {
+                    continue;
+                }
+                if let Err(err) = Self::kill(instance) {
+                    warn!("Cannot stop recorder {}: {}", instance.recorder.name, err);
+                }
+                // Restarted below
+                instance.state = RecorderState::Running;
+            }
+
+            if self.instances[index].state == RecorderState::Running {
+                let name = self.instances[index].recorder.name.clone();
+                if let Err(err) = self.start(&name, config) {
+                    warn!("Cannot start recorder {}: {}", name, err);
+                    self.instances[index].state = RecorderState::Failed(None);
+                }
+            }
+        }
+    }
+
+    pub fn start(&mut self, name: &str, config: &Config) -> Result<(), recorder::Error> {
+        self.launch(name, config, false)
+    }
+
+    /// Starts a recorder process. `next_part` continues the current recording in a new file
+    /// instead of starting a new recording.
+    fn launch(
+        &mut self,
+        name: &str,
+        config: &Config,
+        next_part: bool,
+    ) -> Result<(), recorder::Error> {
+        let Some(instance) = self.instances.iter_mut().find(|i| i.recorder.name == name) else {
+            return Err(recorder::Error::RecorderNotRunning);
+        };
+
+        let (builder, directory) = Self::builder(&instance.recorder, config)?;
+        std::fs::create_dir_all(&directory)?;
+        let settings = Self::settings(&instance.recorder, config);
+
+        let mut output = None;
+        let mut command = match instance.recorder.mode {
+            RecorderMode::Replay => builder.output(&directory).build()?,
+            RecorderMode::Recording => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                let path = if instance.recorder.splits() {
+                    instance.part = match next_part {
+                        true => (instance.part.0, instance.part.1 + 1),
+                        false => (timestamp, 1),
+                    };
+                    let path = directory.join(format!(
+                        "{}_{}_part{:03}.{}",
+                        instance.recorder.name,
+                        instance.part.0,
+                        instance.part.1,
+                        config.container.to_string()
+                    ));
+                    let index = directory.join(format!(
+                        "{}_{}.index.txt",
+                        instance.recorder.name, instance.part.0
+                    ));
+                    if let Err(err) = add_to_index(&index, &path) {
+                        warn!("Cannot update recording index {}: {}", index.display(), err);
+                    }
+                    path
+                } else {
+                    directory.join(format!(
+                        "{}_{}.{}",
+                        instance.recorder.name,
+                        timestamp,
+                        config.container.to_string()
+                    ))
+                };
+                output = Some(path.clone());
+                builder.output(path).build()?
+            }
+        };
+
+        let mut process = spawn_logged(command.stdout(Stdio::null()), None)?;
+        let pid = process.id();
+        instance.pid = Some(pid);
+        instance.state = RecorderState::Running;
+        instance.settings = settings;
+        info!("Started recorder {}", name);
+
+        let action_tx = self.action_tx.clone();
+        let name = name.to_string();
+        let split = output.filter(|_| instance.recorder.splits()).map(|path| {
+            (
+                path,
+                instance.recorder.split_minutes,
+                instance.recorder.split_size_gb,
+            )
+        });
+        tokio::spawn(async move {
+            let mut wait = tokio::task::spawn_blocking(move || process.wait());
+            let started = Instant::now();
+            let mut split_requested = false;
+            let status = loop {
+                tokio::select! {
+                    status = &mut wait => break status,
+                    _ = tokio::time::sleep(SPLIT_CHECK_INTERVAL), if split.is_some() && !split_requested => {
+                        let (path, minutes, size_gb) = split.as_ref().unwrap();
+                        if split_due(path, started, *minutes, *size_gb) {
+                            split_requested = true;
+                            if action_tx
+                                .send(ActionEvent::SplitRecording(name.clone(), pid))
+                                .await
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                    }
+                }
+            };
+            let code = status
+                .ok()
+                .and_then(|status| status.ok())
+                .and_then(|status| status.code());
+            let _ = action_tx
+                .send(ActionEvent::RecorderExited(name, pid, code))
+                .await;
+        });
+
+        Ok(())
+    }
+
+    /// The gpu-screen-recorder command of `recorder` without its output file, and the directory
+    /// it saves to
+    fn builder(
+        recorder: &RecorderConfig,
+        config: &Config,
+    ) -> Result<(GsrCommandBuilder, PathBuf), recorder::Error> {
+        // Everything but the capture target is shared with the main recorder
+        let mut instance_config = config.clone();
+        instance_config.screen = recorder.screen.clone();
+        instance_config.follow_focus = false;
+        instance_config.webcam.enabled = false;
+        if monitors::is_excluded(config, &instance_config.screen) {
+            return Err(recorder::Error::MonitorExcluded(instance_config.screen));
+        }
+
+        let directory = recorder
+            .directory
+            .as_deref()
+            .map(resolve_path)
+            .unwrap_or_else(|| config.replay_dir());
+        let builder = GsrCommandBuilder::new(&instance_config, config.container);
+        let builder = match recorder.mode {
+            RecorderMode::Replay => {
+                builder.replay(config.replay_duration_secs, config.replay_storage)
+            }
+            RecorderMode::Recording => builder,
+        };
+
+        Ok((builder, directory))
+    }
+
+    /// Everything a recorder process is started with but the name of the file it records to, a
+    /// recorder whose settings didn't change keeps running when the config is applied
+    fn settings(recorder: &RecorderConfig, config: &Config) -> Option<String> {
+        let (builder, directory) = Self::builder(recorder, config).ok()?;
+        let command = builder.output(&directory).build().ok()?;
+        Some(format!(
+            "{:?} {:?}",
+            recorder,
+            command.get_args().collect::<Vec<_>>()
+        ))
+    }
+
+    /// Continues the recording of process `pid` in a new file. The new part starts before the
+    /// old one is finalized, so nothing is lost in between.
+    pub fn split(&mut self, name: &str, pid: u32, config: &Config) {
+        if !self
+            .instances
+            .iter()
+            .any(|instance| instance.recorder.name == name && instance.pid == Some(pid))
+        {
+            return;
+        }
+
+        if let Err(err) = self.launch(name, config, true) {
+            warn!("Cannot start the next part of recording {}: {}", name, err);
+            return;
+        }
+        if let Err(err) = Self::interrupt(pid) {
+            warn!(
+                "Cannot finish the previous part of recording {}: {}",
+                name, err
+            );
+        }
+        info!("Recorder {} continues in a new part", name);
+    }
+
+    pub fn stop(&mut self, name: &str) -> Result<(), recorder::Error> {
+        let Some(instance) = self.instances.iter_mut().find(|i| i.recorder.name == name) else {
+            return Err(recorder::Error::RecorderNotRunning);
+        };
+
+        Self::kill(instance)?;
+        instance.state = RecorderState::Stopped;
+        info!("Stopped recorder {}", name);
+
+        Ok(())
+    }
+
+    pub fn stop_all(&mut self) {
+        for instance in &mut self.instances {
+            if let Err(err) = Self::kill(instance) {
+                warn!("Cannot stop recorder {}: {}", instance.recorder.name, err);
+            }
+            instance.state = RecorderState::Stopped;
+        }
+    }
+
+    /// SIGINT finalizes recordings, replay buffers are discarded
+    fn kill(instance: &mut Instance) -> Result<(), recorder::Error> {
+        let Some(pid) = instance.pid.take() else {
+            return Ok(());
+        };
+
+        Self::interrupt(pid)
+    }
+
+    fn interrupt(pid: u32) -> Result<(), recorder::Error> {
+        match signal::kill(Pid::from_raw(pid as i32), Signal::SIGINT) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str, config: &Config) -> Result<(), recorder::Error> {
+        if self.is_running(name) {
+            self.stop(name)
+        } else {
+            self.start(name, config)
+        }
+    }
+
+    pub fn is_running(&self, name: &str) -> bool {
+        self.instances
+            .iter()
+            .any(|instance| instance.recorder.name == name && instance.pid.is_some())
+    }
+
+    /// Called when a recorder process exits. Exits of processes that were stopped or replaced
+    /// on purpose are ignored.
+    pub fn exited(&mut self, name: &str, pid: u32, code: Option<i32>) {
+        let Some(instance) = self
+            .instances
+            .iter_mut()
+            .find(|instance| instance.recorder.name == name && instance.pid == Some(pid))
+        else {
+            return;
+        };
+
+        warn!("Recorder {} exited unexpectedly with code {:?}", name, code);
+        instance.pid = None;
+        instance.state = RecorderState::Failed(code);
+    }
+
+    pub fn save_replay(&self, name: &str) -> Result<(), recorder::Error> {
+        let Some(pid) = self
+            .instances
+            .iter()
+            .find(|instance| instance.recorder.name == name)
+            .and_then(|instance| instance.pid)
+        else {
+            return Err(recorder::Error::RecorderNotRunning);
+        };
+
+        signal::kill(Pid::from_raw(pid as i32), Signal::SIGUSR1)?;
+
+        Ok(())
+    }
+
+    /// Name, mode and state of every recorder, in config order
+    pub fn states(&self) -> Vec<(String, RecorderMode, RecorderState)> {
+        self.instances
+            .iter()
+            .map(|instance| {
+                (
+                    instance.recorder.name.clone(),
+                    instance.recorder.mode,
+                    instance.state.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Time or size limit of a split recording reached
+fn split_due(path: &Path, started: Instant, minutes: Option<u64>, size_gb: Option<f64>) -> bool {
+    let too_long = minutes.is_some_and(|minutes| started.elapsed().as_secs() >= minutes * 60);
+    let too_big = size_gb.is_some_and(|size_gb| {
+        std::fs::metadata(path)
+            .is_ok_and(|metadata| metadata.len() as f64 >= size_gb * 1_000_000_000.0)
+    });
+    too_long || too_big
+}
+
+/// Appends a part to the index of a split recording, which lists the parts in order with the
+/// time they started
+fn add_to_index(index: &Path, part: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index)
+        .map(std::io::BufWriter::new)?;
+    writeln!(
+        file,
+        "{}\t{}",
+        part.file_name().unwrap_or_default().to_string_lossy(),
+        format_full_date_time(SystemTime::now())
+    )?;
+    file.flush()
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use futures::StreamExt;
+use log::{error, info, warn};
+use tokio::sync::mpsc::Sender;
+use zbus::{Connection, proxy, zvariant::Value};
+
+use crate::{
+    ActionEvent,
+    config::{Config, ReplayAction},
+    jobs::sibling_path,
+    kdialog::{ClickedButton, MessageBox, MessageBoxButtons},
+    notes, utils, watch_later,
+};
+
+/// Most actions a notification has room for
+pub const MAX_NOTIFICATION_ACTIONS: usize = 3;
+/// How long the actions of a save notification can be clicked, servers keep old notifications
+/// in their history
+const NOTIFICATION_ACTIONS_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.FileManager1",
+    default_service = "org.freedesktop.FileManager1",
+    default_path = "/org/freedesktop/FileManager1"
+)]
+trait FileManager {
+    fn show_items(&self, uris: &[&str], startup_id: &str) -> zbus::Result<()>;
+}
+
+impl ReplayAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReplayAction::Open => "Open",
+            ReplayAction::Folder => "Show in folder",
+            ReplayAction::Editor => "Edit",
+            ReplayAction::Share => "Copy for sharing",
+            ReplayAction::Delete => "Delete…",
+            ReplayAction::Favorite => "Favorite",
+            ReplayAction::WatchLater => "Watch later",
+        }
+    }
+
+    pub fn icon(&self) -> &'static str {
+        match self {
+            ReplayAction::Open => "media-playback-start",
+            ReplayAction::Folder => "folder-open",
+            ReplayAction::Editor => "document-edit",
+            ReplayAction::Share => "edit-copy",
+            ReplayAction::Delete => "edit-delete",
+            ReplayAction::Favorite => "starred",
+            ReplayAction::WatchLater => "appointment-new",
+        }
+    }
+}
+
+/// Shows a notification for the saved `clip` with the actions from `notification_actions`.
+/// Clicked actions come back as [`ActionEvent::ReplayAction`], like the tray's.
+pub async fn notify_saved(
+    conn: &Connection,
+    config: &Config,
+    text: &str,
+    clip: PathBuf,
+    action_tx: Sender<ActionEvent>,
+) -> zbus::Result<()> {
+    let actions: Vec<ReplayAction> = config
+        .notification_actions
+        .iter()
+        .copied()
+        .take(MAX_NOTIFICATION_ACTIONS)
+        .collect();
+    let keys: Vec<String> = actions.iter().map(ReplayAction::to_string).collect();
+    // Pairs of key and label
+    let action_list: Vec<&str> = actions
+        .iter()
+        .zip(&keys)
+        .flat_map(|(action, key)| [key.as_str(), action.label()])
+        .collect();
+
+    let notifications = NotificationsProxy::new(conn).await?;
+    // Subscribed first, so a quick click isn't missed
+    let mut invoked = notifications.receive_action_invoked().await?;
+    let mut closed = notifications.receive_notification_closed().await?;
+    let id = notifications
+        .notify(
+            "TrayPlay",
+            0,
+            "media-record",
+            "Replay saved",
+            text,
+            &action_list,
+            HashMap::from([("desktop-entry", Value::from("trayplay"))]),
+            -1,
+        )
+        .await?;
+
+    tokio::spawn(async move {
+        let wait_for_click = async {
+            loop {
+                tokio::select! {
+                    Some(signal) = invoked.next() => {
+                        let Ok(args) = signal.args() else { continue };
+                        if args.id != id {
+                            continue;
+                        }
+                        return actions
+                            .iter()
+                            .find(|action| action.to_string() == args.action_key)
+                            .copied();
+                    }
+                    Some(signal) = closed.next() => {
+                        if signal.args().is_ok_and(|args| args.id == id) {
+                            return None;
+                        }
+                    }
+                    else => return None,
+                }
+            }
+        };
+
+        if let Ok(Some(action)) =
+            tokio::time::timeout(NOTIFICATION_ACTIONS_TIMEOUT, wait_for_click).await
+        {
+            let _ = action_tx
+                .send(ActionEvent::ReplayAction(action, clip))
+                .await;
+        }
+    });
+
+    Ok(())
+}
+
+/// Runs `action` on `clip`, from the tray or a notification
+pub async fn run(action: ReplayAction, clip: PathBuf, config: &Config, conn: &Connection) {
+    if !clip.exists() {
+        warn!("{} has already been deleted", clip.display());
+        return;
+    }
+
+    match action {
+        ReplayAction::Open => spawn("xdg-open", &[clip.as_os_str()]),
+        ReplayAction::Folder => {
+            let uri = utils::file_uri(&clip);
+            let shown = match FileManagerProxy::new(conn).await {
+                Ok(file_manager) => file_manager.show_items(&[&uri], "").await,
+                Err(err) => Err(err),
+            };
+            // Without a file manager that selects files, the folder is opened
+            if shown.is_err()
+                && let Some(folder) = clip.parent()
+            {
+                spawn("xdg-open", &[folder.as_os_str()]);
+            }
+        }
+        ReplayAction::Editor => match &config.video_editor {
+            Some(editor) => {
+                let mut args = editor.split_whitespace();
+                if let Some(program) = args.next() {
+                    let mut args: Vec<&std::ffi::OsStr> = args.map(|arg| arg.as_ref()).collect();
+                    args.push(clip.as_os_str());
+                    spawn(program, &args);
+                }
+            }
+            None => error!("Set video_editor in config to edit replays, e.g. \"kdenlive\""),
+        },
+        ReplayAction::Share => {
+            // The small copy, if share_copy has made it by now
+            let share_copy = sibling_path(&clip, "share").with_extension("mp4");
+            let file = if share_copy.exists() {
+                share_copy
+            } else {
+                clip
+            };
+            match copy_file_to_clipboard(&file).await {
+                Ok(()) => info!("Copied {} to the clipboard", file.display()),
+                Err(err) => error!("Cannot copy the replay to the clipboard: {}", err),
+            }
+        }
+        ReplayAction::Delete => {
+            // The dialog would hold up the main loop
+            tokio::task::spawn_blocking(move || {
+                let answer = MessageBox::new(format!(
+                    "Delete {} along with its note and exported copies?",
+                    clip.display()
+                ))
+                .title("Delete replay")
+                .buttons(MessageBoxButtons::YesNo)
+                .show();
+                match answer {
+                    Ok(ClickedButton::Yes) => match notes::delete_clip(&clip) {
+                        Ok(removed) => info!(
+                            "Deleted {}",
+                            removed
+                                .iter()
+                                .map(|path| path.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        Err(err) => error!("Cannot delete the replay: {}", err),
+                    },
+                    Ok(_) => {}
+                    Err(err) => error!("Cannot ask to delete the replay: {}", err),
+                }
+            });
+        }
+        ReplayAction::Favorite => match notes::set_favorite(&clip) {
+            Ok(()) => info!("Marked {} as favorite", clip.display()),
+            Err(err) => error!("Cannot mark the replay as favorite: {}", err),
+        },
+        ReplayAction::WatchLater => {
+            if watch_later::add(&clip) {
+                info!("Queued {} to watch later", clip.display());
+            }
+        }
+    }
+}
+
+fn spawn(program: &str, args: &[&std::ffi::OsStr]) {
+    if let Err(err) = Command::new(program).args(args).spawn() {
+        error!("Cannot run {}: {}", program, err);
+    }
+}
+
+/// Puts the file on the clipboard the way file managers do, so it can be pasted into chats
+async fn copy_file_to_clipboard(path: &Path) -> std::io::Result<()> {
+    let uri = format!("{}\r\n", utils::file_uri(path));
+    let mut command = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut command = tokio::process::Command::new("wl-copy");
+        command.args(["--type", "text/uri-list"]);
+        command
+    } else {
+        let mut command = tokio::process::Command::new("xclip");
+        command.args(["-selection", "clipboard", "-target", "text/uri-list", "-in"]);
+        command
+    };
+
+    let mut child = command.stdin(std::process::Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        use tokio::io::AsyncWriteExt;
+        stdin.write_all(uri.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "clipboard tool exited with {}",
+            status
+        )))
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::BTreeSet,
+    os::fd::{AsRawFd, OwnedFd},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use ashpd::desktop::{
+    PersistMode, Session,
+    screencast::{CursorMode, Screencast, SourceType},
+};
+use futures::StreamExt;
+use log::{info, warn};
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::{
+    sync::{RwLock, mpsc::Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    ActionEvent,
+    config::{Config, Quality, RateControl, RateControlMode},
+    jobs::JobQueue,
+    recorder::{Error, Recorder, RecorderStatus},
+    segments::{SEGMENT_LENGTH, SegmentBuffer},
+    stats::Statistics,
+};
+
+fn bitrate_kbps(quality: Quality) -> u32 {
+    match quality {
+        Quality::Medium => 8000,
+        Quality::High => 15000,
+        Quality::VeryHigh => 25000,
+        Quality::Ultra => 40000,
+    }
+}
+
+/// Scales the stream down to fit in `scale_resolution`, keeping its aspect ratio. A smaller
+/// `source` is recorded as it is. When the portal doesn't tell the size of the source, the caps
+/// only limit the size and videoscale picks one.
+fn scale_elements(config: &Config, source: Option<(u32, u32)>) -> Vec<String> {
+    let Some((width, height)) = config.scale_size() else {
+        return Vec::new();
+    };
+
+    let caps = match source {
+        Some((source_width, source_height)) => {
+            let factor =
+                (width as f64 / source_width as f64).min(height as f64 / source_height as f64);
+            if factor >= 1.0 {
+                return Vec::new();
+            }
+            // Encoders want even sizes
+            let even = |size: u32| ((size as f64 * factor) as u32 / 2 * 2).max(2);
+            format!(
+                "video/x-raw,width={},height={}",
+                even(source_width),
+                even(source_height)
+            )
+        }
+        None => format!(
+            "video/x-raw,width=[2,{}],height=[2,{}],pixel-aspect-ratio=1/1",
+            width, height
+        ),
+    };
+    vec![
+        "videoscale".to_string(),
+        "!".to_string(),
+        caps,
+        "!".to_string(),
+    ]
+}
+
+/// x264enc properties for the rate control, or the bitrate matching the quality
+fn rate_control_options(config: &Config) -> Vec<String> {
+    match config.rate_control {
+        None => vec![format!("bitrate={}", bitrate_kbps(config.quality))],
+        Some(RateControl {
+            mode: RateControlMode::Cqp,
+            value,
+        }) => vec!["pass=quant".to_string(), format!("quantizer={}", value)],
+        Some(RateControl {
+            mode: RateControlMode::Vbr,
+            value,
+        }) => vec![format!("bitrate={}", value)],
+        // A one second VBV buffer keeps x264's average bitrate mode close to constant
+        Some(RateControl {
+            mode: RateControlMode::Cbr,
+            value,
+        }) => vec![
+            format!("bitrate={}", value),
+            "vbv-buf-capacity=1000".to_string(),
+        ],
+    }
+}
+
+/// Records the output device into a second branch muxed into the same segments, if any
+/// `audio_tracks` are set. Like wf-recorder, a single track is recorded: `audio_output`, or the
+/// monitor of the default output device.
+fn audio_elements(config: &Config) -> Vec<String> {
+    if config.audio_tracks.is_empty() {
+        return Vec::new();
+    }
+
+    vec![
+        "pulsesrc".to_string(),
+        format!(
+            "device={}",
+            config
+                .audio_output
+                .as_deref()
+                .unwrap_or("@DEFAULT_MONITOR@")
+        ),
+        "do-timestamp=true".to_string(),
+        "!".to_string(),
+        "audioconvert".to_string(),
+        "!".to_string(),
+        "audioresample".to_string(),
+        "!".to_string(),
+        "opusenc".to_string(),
+        "!".to_string(),
+        "mux.audio_0".to_string(),
+    ]
+}
+
+/// Restore token of the portal session, so the screen is only picked once. Without a state
+/// directory the monitor is asked for every session.
+fn restore_token_path() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("trayplay/screencast_restore_token"))
+}
+
+/// Portal session sharing the recorded monitor
+struct PortalCapture {
+    screencast: Screencast<'static>,
+    session: Arc<Session<'static, Screencast<'static>>>,
+    node: u32,
+    /// Width and height of the shared monitor, if the portal tells it
+    size: Option<(u32, u32)>,
+    /// Whether the stream includes the cursor
+    cursor: bool,
+    /// Set once the user stopped sharing from the system UI, the session is useless then
+    revoked: Arc<AtomicBool>,
+    /// Waits for the portal to close the session
+    watcher: JoinHandle<()>,
+}
+
+impl PortalCapture {
+    async fn close(self) {
+        self.watcher.abort();
+        let _ = self.session.close().await;
+    }
+}
+
+/// Replay buffer for systems without gpu-screen-recorder. The screen comes from the ScreenCast
+/// portal over PipeWire and is encoded by GStreamer into a [`SegmentBuffer`], along with the
+/// output device's audio.
+pub struct ScreencastRecorder {
+    process: Option<Child>,
+    config: Arc<RwLock<Config>>,
+    stats: Arc<RwLock<Statistics>>,
+    buffer: SegmentBuffer,
+    pause_reasons: BTreeSet<String>,
+    screencast: Option<PortalCapture>,
+    pipewire_fd: Option<OwnedFd>,
+    action_tx: Sender<ActionEvent>,
+}
+
+impl ScreencastRecorder {
+    pub async fn new(
+        config: Arc<RwLock<Config>>,
+        app_name: Arc<RwLock<String>>,
+        stats: Arc<RwLock<Statistics>>,
+        jobs: JobQueue,
+        action_tx: Sender<ActionEvent>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            process: None,
+            buffer: SegmentBuffer::new(
+                "trayplay-screencast",
+                config.clone(),
+                app_name,
+                stats.clone(),
+                jobs,
+                action_tx.clone(),
+            ),
+            config,
+            stats,
+            pause_reasons: BTreeSet::new(),
+            screencast: None,
+            pipewire_fd: None,
+            action_tx,
+        })
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.pause_reasons.is_empty()
+    }
+
+    /// Asks the portal for a monitor the first time, later calls reuse the session until
+    /// `record_cursor` changes or the user stops sharing
+    async fn pipewire_node(&mut self) -> Result<u32, Error> {
+        let record_cursor = self.config.read().await.record_cursor;
+        match self.screencast.take() {
+            Some(capture)
+                if capture.cursor == record_cursor && !capture.revoked.load(Ordering::Relaxed) =>
+            {
+                let node = capture.node;
+                self.screencast = Some(capture);
+                return Ok(node);
+            }
+            Some(capture) => capture.close().await,
+            None => {}
+        }
+
+        let screencast = Screencast::new().await?;
+        let session = screencast.create_session().await?;
+        let restore_token =
+            restore_token_path().and_then(|path| std::fs::read_to_string(path).ok());
+        screencast
+            .select_sources(
+                &session,
+                if record_cursor {
+                    CursorMode::Embedded
+                } else {
+                    CursorMode::Hidden
+                },
+                SourceType::Monitor.into(),
+                false,
+                restore_token.as_deref(),
+                PersistMode::ExplicitlyRevoked,
+            )
+            .await?;
+        let streams = screencast.start(&session, None).await?.response()?;
+
+        if let Some(token) = streams.restore_token()
+            && let Some(path) = restore_token_path()
+        {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, token)?;
+        }
+        let stream = streams.streams().first().ok_or(Error::RecorderNotRunning)?;
+        let node = stream.pipe_wire_node_id();
+        let size = stream
+            .size()
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .map(|(width, height)| (width as u32, height as u32));
+
+        let session = Arc::new(session);
+        let revoked = Arc::new(AtomicBool::new(false));
+        let watcher = tokio::spawn(watch_session(
+            session.clone(),
+            revoked.clone(),
+            self.action_tx.clone(),
+        ));
+        self.screencast = Some(PortalCapture {
+            screencast,
+            session,
+            node,
+            size,
+            cursor: record_cursor,
+            revoked,
+            watcher,
+        });
+        Ok(node)
+    }
+
+    async fn save(&mut self, secs: Option<i64>) -> Result<(), Error> {
+        if self.process.is_none() {
+            return Err(Error::RecorderNotRunning);
+        }
+
+        self.buffer.save(secs).await
+    }
+}
+
+/// Reports the portal closing the session, which happens when the user stops sharing the screen
+/// from the system UI. PipeWire keeps the stream open without frames then, so the recorder would
+/// seem to record nothing but the last frame.
+async fn watch_session(
+    session: Arc<Session<'static, Screencast<'static>>>,
+    revoked: Arc<AtomicBool>,
+    action_tx: Sender<ActionEvent>,
+) {
+    let mut closed = match session.receive_closed().await {
+        Ok(closed) => closed,
+        Err(err) => {
+            warn!("Cannot watch the screen sharing session: {}", err);
+            return;
+        }
+    };
+    if closed.next().await.is_none() {
+        return;
+    }
+
+    info!("Screen sharing was stopped from outside TrayPlay");
+    revoked.store(true, Ordering::Relaxed);
+    // The user took the permission back, the next request should ask again
+    if let Some(path) = restore_token_path() {
+        let _ = std::fs::remove_file(path);
+    }
+    let _ = action_tx.send(ActionEvent::CaptureRevoked).await;
+}
+
+impl Recorder for ScreencastRecorder {
+    async fn start(&mut self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        if self.process.is_some() {
+            self.stats.write().await.record_restart();
+            self.stop().await?;
+        }
+
+        let node = self.pipewire_node().await?;
+        let capture = self.screencast.as_ref().unwrap();
+        let fd = capture
+            .screencast
+            .open_pipe_wire_remote(&capture.session)
+            .await?;
+        // Let gst-launch inherit the PipeWire connection
+        nix::errno::Errno::result(unsafe {
+            nix::libc::fcntl(fd.as_raw_fd(), nix::libc::F_SETFD, 0)
+        })?;
+
+        let config = self.config.read().await;
+        self.buffer.clear(&config)?;
+
+        let process = Command::new("gst-launch-1.0")
+            .arg("-e")
+            .args([
+                "pipewiresrc".to_string(),
+                format!("fd={}", fd.as_raw_fd()),
+                format!("path={}", node),
+                "do-timestamp=true".to_string(),
+                "keepalive-time=1000".to_string(),
+                "!".to_string(),
+                "videorate".to_string(),
+                "!".to_string(),
+                format!("video/x-raw,framerate={}/1", config.effective_framerate()),
+                "!".to_string(),
+                "videoconvert".to_string(),
+                "!".to_string(),
+            ])
+            .args(scale_elements(&config, capture.size))
+            .args([
+                "x264enc".to_string(),
+                "tune=zerolatency".to_string(),
+                "speed-preset=veryfast".to_string(),
+            ])
+            .args(rate_control_options(&config))
+            .args([
+                format!(
+                    "key-int-max={}",
+                    SegmentBuffer::keyframe_interval_frames(&config)
+                ),
+                "!".to_string(),
+                "h264parse".to_string(),
+                "!".to_string(),
+                "splitmuxsink".to_string(),
+                "name=mux".to_string(),
+                format!("location={}", self.buffer.segment_pattern().display()),
+                "muxer-factory=matroskamux".to_string(),
+                format!("max-size-time={}", SEGMENT_LENGTH.as_nanos()),
+                format!("max-files={}", SegmentBuffer::segment_count(&config)),
+                "send-keyframe-requests=true".to_string(),
+            ])
+            .args(audio_elements(&config))
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        info!(
+            "Started screencast replay buffer from PipeWire node {}",
+            node
+        );
+        self.process = Some(process);
+        self.pipewire_fd = Some(fd);
+
+        Ok(())
+    }
+
+    /// Returns once gst-launch exited, so a restart doesn't clear the segments while the last
+    /// one is still written
+    async fn stop(&mut self) -> Result<(), Error> {
+        let Some(mut process) = self.process.take() else {
+            return Err(Error::RecorderNotRunning);
+        };
+
+        match signal::kill(Pid::from_raw(process.id() as i32), Signal::SIGINT) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => return Err(err.into()),
+        }
+        let pipewire_fd = self.pipewire_fd.take();
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = process.wait() {
+                warn!("Cannot wait for gst-launch to exit: {}", err);
+            }
+            drop(pipewire_fd);
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+
+        Ok(())
+    }
+
+    async fn save_replay(&mut self) -> Result<(), Error> {
+        self.save(None).await
+    }
+
+    async fn save_replay_last(&mut self, secs: i64) -> Result<(), Error> {
+        self.save(Some(secs)).await
+    }
+
+    async fn pause(&mut self, reason: &str) -> Result<(), Error> {
+        let was_paused = self.is_paused();
+        self.pause_reasons.insert(reason.to_string());
+
+        if !was_paused && self.process.is_some() {
+            info!("Pausing replay recording: {}", reason);
+            self.stop().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn resume(&mut self, reason: &str) -> Result<(), Error> {
+        if self.pause_reasons.remove(reason) && !self.is_paused() {
+            info!("Resuming replay recording");
+            self.start().await?;
+        }
+
+        Ok(())
+    }
+
+    fn is_paused_for(&self, reason: &str) -> bool {
+        self.pause_reasons.contains(reason)
+    }
+
+    fn pause_reasons(&self) -> Vec<String> {
+        self.pause_reasons.iter().cloned().collect()
+    }
+
+    fn status(&self) -> RecorderStatus {
+        if self.is_paused() {
+            RecorderStatus::Paused
+        } else if self.process.is_some() {
+            RecorderStatus::Running
+        } else {
+            RecorderStatus::Stopped
+        }
+    }
+
+    async fn last_replay(&self) -> Option<PathBuf> {
+        self.buffer.last_replay().await
+    }
+
+    async fn set_last_replay(&self, path: PathBuf) {
+        self.buffer.set_last_replay(path).await
+    }
+}
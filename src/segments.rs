@@ -0,0 +1,303 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{
+    ActionEvent,
+    config::{Config, Container},
+    jobs::JobQueue,
+    recorder::{Error, replay_saved},
+    stats::Statistics,
+    storage,
+};
+
+/// Length of the segments the buffer is made of. Saved replays are cut to whole segments.
+pub const SEGMENT_LENGTH: Duration = Duration::from_secs(2);
+
+/// Numbers the segment lists handed to ffmpeg, so a save doesn't overwrite the list of one
+/// that's still joining
+static NEXT_LIST: AtomicU64 = AtomicU64::new(0);
+
+/// Replay buffer emulated with short segment files written by an external recorder, the oldest
+/// of which are deleted or overwritten as new ones come in. Saving joins the segments with
+/// ffmpeg. Used by backends whose recorder has no replay mode of its own.
+pub struct SegmentBuffer {
+    dir: PathBuf,
+    config: Arc<RwLock<Config>>,
+    app_name: Arc<RwLock<String>>,
+    stats: Arc<RwLock<Statistics>>,
+    jobs: JobQueue,
+    action_tx: Sender<ActionEvent>,
+    last_replay: Arc<RwLock<Option<PathBuf>>>,
+}
+
+impl SegmentBuffer {
+    /// Segments are kept in `name` inside the runtime directory, which is in RAM on most systems
+    pub fn new(
+        name: &str,
+        config: Arc<RwLock<Config>>,
+        app_name: Arc<RwLock<String>>,
+        stats: Arc<RwLock<Statistics>>,
+        jobs: JobQueue,
+        action_tx: Sender<ActionEvent>,
+    ) -> Self {
+        Self {
+            dir: dirs::runtime_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join(name),
+            config,
+            app_name,
+            stats,
+            jobs,
+            action_tx,
+            last_replay: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Where the recorder should write segments, with `%08d` in place of the segment number
+    pub fn segment_pattern(&self) -> PathBuf {
+        self.dir.join("segment%08d.mkv")
+    }
+
+    /// Frames between keyframes. Segments can only be cut at a keyframe, so there's at least one
+    /// per segment whatever `keyframe_interval_secs` says.
+    pub fn keyframe_interval_frames(config: &Config) -> i64 {
+        let secs = config
+            .keyframe_interval_secs
+            .clamp(0.1, SEGMENT_LENGTH.as_secs_f64());
+        ((secs * config.effective_framerate() as f64).round() as i64).max(1)
+    }
+
+    /// Number of segments covering `replay_duration_secs`, plus the one being written
+    pub fn segment_count(config: &Config) -> u64 {
+        config.replay_duration_secs.max(1) as u64 / SEGMENT_LENGTH.as_secs() + 2
+    }
+
+    /// Segments left over from earlier runs of the recorder, kept with `persist_buffer`
+    fn previous_dir(&self) -> PathBuf {
+        self.dir.join("previous")
+    }
+
+    /// Settings the segments are recorded with, kept next to them. Segments recorded with
+    /// different ones can't be joined without re-encoding.
+    fn settings_path(&self) -> PathBuf {
+        self.dir.with_extension("settings")
+    }
+
+    /// Starts over with an empty buffer, like gpu-screen-recorder does on restart. With
+    /// `persist_buffer`, segments from the last `replay_duration_secs` are moved aside and still
+    /// saved with the new ones, so restarting the recorder or TrayPlay doesn't lose them, unless
+    /// the settings they were recorded with changed.
+    pub fn clear(&self, config: &Config) -> Result<(), Error> {
+        let settings = recorded_settings(config);
+        let settings_path = self.settings_path();
+        let unchanged = std::fs::read_to_string(&settings_path).is_ok_and(|old| old == settings);
+        std::fs::write(&settings_path, settings)?;
+        if !config.persist_buffer || !unchanged {
+            let _ = std::fs::remove_dir_all(&self.dir);
+            std::fs::create_dir_all(&self.dir)?;
+            return Ok(());
+        }
+        let keep_secs = config.replay_duration_secs.max(0) as u64;
+
+        let previous_dir = self.previous_dir();
+        std::fs::create_dir_all(&previous_dir)?;
+        // Renaming keeps the modification time segments are ordered by
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        for (_, segment) in segment_files(&self.dir)? {
+            let name = format!(
+                "{}-{}",
+                session,
+                segment.file_name().unwrap_or_default().to_string_lossy()
+            );
+            std::fs::rename(&segment, previous_dir.join(name))?;
+        }
+
+        let keep = Duration::from_secs(keep_secs);
+        for (modified, segment) in segment_files(&previous_dir)? {
+            if modified.elapsed().unwrap_or_default() > keep {
+                let _ = std::fs::remove_file(segment);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finished segments, oldest first, including the ones from earlier runs. The newest one is
+    /// still being written.
+    fn segments(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut segments = segment_files(&self.dir)?;
+        segments.sort();
+        segments.pop();
+        if let Ok(previous) = segment_files(&self.previous_dir()) {
+            segments.extend(previous);
+            segments.sort();
+        }
+
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Joins the segments covering the last `secs` seconds, or all of them, into a replay
+    pub async fn save(&self, secs: Option<i64>) -> Result<(), Error> {
+        let mut segments = self.segments()?;
+        let secs = match secs {
+            Some(secs) => secs,
+            None => self.config.read().await.replay_duration_secs,
+        };
+        let count = (secs.max(1) as u64).div_ceil(SEGMENT_LENGTH.as_secs()) as usize;
+        segments = segments.split_off(segments.len().saturating_sub(count));
+        if segments.is_empty() {
+            return Err(Error::BufferEmpty);
+        }
+        self.stats.write().await.save_requested();
+
+        let config = self.config.read().await;
+        let app_name = self.app_name.read().await.clone();
+        let mut target_path = config.replay_dir();
+        target_path.push(&app_name);
+        std::fs::create_dir_all(&target_path)?;
+        // Segments hold H.264, which WebM can't
+        let container = match config.container {
+            Container::WEBM => Container::MKV,
+            container => container,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        target_path.push(format!(
+            "{}_replay_{}.{}",
+            app_name,
+            timestamp,
+            container.to_string()
+        ));
+
+        let list_path = self
+            .dir
+            .with_extension(format!("{}.txt", NEXT_LIST.fetch_add(1, Ordering::Relaxed)));
+        std::fs::write(
+            &list_path,
+            segments
+                .iter()
+                .map(|segment| format!("file '{}'\n", segment.display()))
+                .collect::<String>(),
+        )?;
+        let clear_buffer = config.clear_buffer_on_save;
+        let work_dir = config.work_dir();
+        if let Some(work_dir) = &work_dir {
+            std::fs::create_dir_all(work_dir)?;
+        }
+        let work_path = storage::work_path(work_dir.as_deref(), &target_path);
+        drop(config);
+
+        let config = self.config.clone();
+        let stats = self.stats.clone();
+        let jobs = self.jobs.clone();
+        let last_replay = self.last_replay.clone();
+        let action_tx = self.action_tx.clone();
+        tokio::spawn(async move {
+            let result = match join_segments(&list_path, &work_path).await {
+                Ok(()) => storage::move_into_place(&work_path, &target_path)
+                    .await
+                    .map_err(|err| format!("cannot move the replay into place: {}", err)),
+                Err(err) => Err(err),
+            };
+            let _ = std::fs::remove_file(&list_path);
+            if result.is_err() {
+                let _ = std::fs::remove_file(&work_path);
+            }
+
+            if clear_buffer {
+                for segment in &segments {
+                    let _ = std::fs::remove_file(segment);
+                }
+            }
+
+            match result {
+                Ok(_) => {
+                    replay_saved(
+                        target_path,
+                        &config,
+                        &stats,
+                        &jobs,
+                        &last_replay,
+                        &action_tx,
+                    )
+                    .await
+                }
+                Err(err) => {
+                    error!("Failed to join replay segments: {}", err);
+                    stats.write().await.record_failure(err);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn last_replay(&self) -> Option<PathBuf> {
+        self.last_replay.read().await.clone()
+    }
+
+    pub async fn set_last_replay(&self, path: PathBuf) {
+        *self.last_replay.write().await = Some(path);
+    }
+}
+
+/// Settings that change the format of the segments: the encoder, frame rate, size and audio
+fn recorded_settings(config: &Config) -> String {
+    format!(
+        "{:?} {} {:?} {} {:?} {:?} {:?} {:?}",
+        config.encoder,
+        config.effective_framerate(),
+        config.scale_size(),
+        config.screen,
+        config.capture_target(),
+        config.region,
+        config.audio_tracks,
+        config.audio_output
+    )
+}
+
+/// Segment files in `dir` with their modification time
+fn segment_files(dir: &Path) -> Result<Vec<(SystemTime, PathBuf)>, Error> {
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            metadata
+                .is_file()
+                .then_some((metadata.modified().ok()?, entry.path()))
+        })
+        .collect())
+}
+
+async fn join_segments(list_path: &Path, output: &Path) -> Result<(), String> {
+    let status = tokio::process::Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-loglevel", "error"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(list_path)
+        // ffmpeg would keep only the first audio track
+        .args(["-map", "0", "-c", "copy"])
+        .arg(output)
+        .status()
+        .await
+        .map_err(|err| format!("cannot run ffmpeg: {}", err))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("ffmpeg exited with {}", status))
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use ashpd::desktop::screencast::Screencast;
+
+use crate::{
+    audio,
+    config::{CaptureTarget, Config, Encoder, RecorderBackend},
+    denoise, gsr, gsr_info,
+    probe::probe_clip,
+    recorder, storage,
+};
+
+const TEST_CAPTURE_LENGTH: Duration = Duration::from_secs(3);
+
+/// Outcome of one check, with what was found or what went wrong
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+}
+
+/// Runs every check and the test capture, returns the report shown to the user
+pub async fn run(config: &Config) -> String {
+    let portal = check_portal().await;
+    let checks = vec![
+        Check {
+            name: "Recorder",
+            outcome: check_recorder(config),
+        },
+        Check {
+            name: "Capture permission",
+            outcome: check_capture_permission(config, &portal),
+        },
+        Check {
+            name: "Encoder",
+            outcome: check_encoder(config),
+        },
+        Check {
+            name: "Audio",
+            outcome: check_audio(config),
+        },
+        Check {
+            name: "Replay folder",
+            outcome: match storage::check_replay_dir(&config.replay_dir()) {
+                Ok(()) => Outcome::Pass(config.replay_dir().display().to_string()),
+                Err(problem) => Outcome::Fail(problem.to_string()),
+            },
+        },
+        Check {
+            name: "Desktop portal",
+            outcome: portal,
+        },
+        Check {
+            name: "Test capture",
+            outcome: test_capture(config).await,
+        },
+    ];
+
+    let failed = checks
+        .iter()
+        .filter(|check| matches!(check.outcome, Outcome::Fail(_)))
+        .count();
+    let mut report = match failed {
+        0 => "All checks passed.\n".to_string(),
+        failed => format!("{} of {} checks failed.\n", failed, checks.len()),
+    };
+    for check in checks {
+        let (status, detail) = match check.outcome {
+            Outcome::Pass(detail) => ("PASS", detail),
+            Outcome::Fail(detail) => ("FAIL", detail),
+            Outcome::Skip(detail) => ("SKIP", detail),
+        };
+        report.push_str(&format!("\n{}  {}: {}", status, check.name, detail));
+    }
+
+    report
+}
+
+/// First line of `program`'s output, or why it couldn't be run
+fn command_output(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| format!("cannot run {}: {}", program, err))?;
+    if !output.status.success() {
+        return Err(format!("{} exited with {}", program, output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH")?
+        .to_str()?
+        .split(':')
+        .map(|dir| Path::new(dir).join(program))
+        .find(|path| path.is_file())
+}
+
+fn check_recorder(config: &Config) -> Outcome {
+    let version = match config.recorder_backend {
+        RecorderBackend::GpuScreenRecorder => command_output("gpu-screen-recorder", &["--version"])
+            .map(|version| format!("gpu-screen-recorder {}", version)),
+        RecorderBackend::Screencast => command_output("gst-launch-1.0", &["--version"]),
+        RecorderBackend::WfRecorder => find_in_path("wf-recorder")
+            .map(|path| format!("wf-recorder at {}", path.display()))
+            .ok_or("wf-recorder is not installed".to_string()),
+    };
+
+    match version {
+        Ok(version) => Outcome::Pass(version),
+        Err(err) => Outcome::Fail(err),
+    }
+}
+
+/// gpu-screen-recorder captures Wayland sessions through its KMS server, which needs
+/// CAP_SYS_ADMIN, unless it goes through the portal
+fn check_capture_permission(config: &Config, portal: &Outcome) -> Outcome {
+    let through_portal = config.recorder_backend == RecorderBackend::Screencast
+        || config.capture_target() == CaptureTarget::Portal;
+    if through_portal {
+        return match portal {
+            Outcome::Pass(_) => {
+                Outcome::Pass("asked by the portal when the recording starts".to_string())
+            }
+            _ => Outcome::Fail("capture goes through the desktop portal".to_string()),
+        };
+    }
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        return Outcome::Pass("X11 needs no permission".to_string());
+    }
+    if config.recorder_backend == RecorderBackend::WfRecorder {
+        return Outcome::Pass("wlroots screencopy needs no permission".to_string());
+    }
+    if Path::new("/.flatpak-info").exists() {
+        return Outcome::Skip("cannot be checked from inside Flatpak".to_string());
+    }
+
+    let Some(kms_server) = find_in_path("gsr-kms-server") else {
+        return Outcome::Fail("gsr-kms-server is not installed".to_string());
+    };
+    match command_output("getcap", &[&kms_server.to_string_lossy()]) {
+        Ok(caps) if caps.contains("cap_sys_admin") => {
+            Outcome::Pass("gsr-kms-server has cap_sys_admin".to_string())
+        }
+        // Without the capability it asks for the password through pkexec on every start
+        Ok(_) => Outcome::Fail(format!(
+            "gsr-kms-server lacks cap_sys_admin, run: sudo setcap cap_sys_admin+ep {}",
+            kms_server.display()
+        )),
+        Err(err) => Outcome::Skip(err),
+    }
+}
+
+fn check_encoder(config: &Config) -> Outcome {
+    let supported = recorder::supported_encoders(config.recorder_backend);
+    // gpu-screen-recorder encodes with any of the hardware APIs on the GPU
+    let gsr_gpu = config.recorder_backend == RecorderBackend::GpuScreenRecorder
+        && config.encoder != Encoder::Software;
+    if !gsr_gpu && !supported.contains(&config.encoder) {
+        return Outcome::Fail(format!(
+            "{:?} is not available on this system",
+            config.encoder
+        ));
+    }
+    if config.recorder_backend == RecorderBackend::GpuScreenRecorder {
+        let codecs = gsr_info::video_codecs();
+        if codecs.is_empty() {
+            return Outcome::Fail("gpu-screen-recorder reported no video codecs".to_string());
+        }
+        return Outcome::Pass(format!(
+            "{:?}, codecs: {}",
+            config.encoder,
+            codecs.join(", ")
+        ));
+    }
+
+    Outcome::Pass(format!("{:?}", config.encoder))
+}
+
+fn check_audio(config: &Config) -> Outcome {
+    if config.audio_tracks.is_empty() {
+        return Outcome::Skip("no audio is recorded".to_string());
+    }
+    if let Err(err) = command_output("pactl", &["info"]) {
+        return Outcome::Fail(format!("cannot reach the sound server ({})", err));
+    }
+    if let Some(output) = &config.audio_output
+        && !audio::output_devices()
+            .iter()
+            .any(|device| device.monitor == *output)
+    {
+        return Outcome::Fail(format!("audio source {} is not connected", output));
+    }
+    if let Some(microphone) = &config.microphone
+        && config.include_microphone
+        && !audio::input_devices()
+            .iter()
+            .any(|device| device.name == *microphone)
+    {
+        return Outcome::Fail(format!("microphone {} is not connected", microphone));
+    }
+    if config.noise_suppression && config.include_microphone && !denoise::is_running() {
+        return Outcome::Fail("noise suppression is on but its filter isn't running".to_string());
+    }
+
+    Outcome::Pass(config.recorded_audio_tracks().join(", "))
+}
+
+async fn check_portal() -> Outcome {
+    let screencast = match Screencast::new().await {
+        Ok(screencast) => screencast,
+        Err(err) => return Outcome::Fail(err.to_string()),
+    };
+    match screencast.available_source_types().await {
+        Ok(types) => Outcome::Pass(format!("screen cast sources: {:?}", types)),
+        Err(err) => Outcome::Fail(err.to_string()),
+    }
+}
+
+/// Records a few seconds with the current settings and checks the result can be read back
+async fn test_capture(config: &Config) -> Outcome {
+    if config.recorder_backend != RecorderBackend::GpuScreenRecorder {
+        return Outcome::Skip("only done with gpu-screen-recorder".to_string());
+    }
+    // The portal would ask which screen to share
+    if config.capture_target() == CaptureTarget::Portal {
+        return Outcome::Skip("capture goes through the desktop portal".to_string());
+    }
+
+    let path = std::env::temp_dir().join(format!(
+        "trayplay-self-test.{}",
+        config.container.to_string()
+    ));
+    let outcome = match gsr::record(config, &path, Some(TEST_CAPTURE_LENGTH)).await {
+        Err(err) => Outcome::Fail(err.to_string()),
+        Ok(()) => match probe_clip(&path).await {
+            Some(info) if info.size_bytes > 0 => Outcome::Pass(info.to_string()),
+            _ => Outcome::Fail("gpu-screen-recorder wrote no video".to_string()),
+        },
+    };
+    let _ = std::fs::remove_file(&path);
+
+    outcome
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use futures_util::StreamExt;
 
 use ashpd::desktop::{
@@ -5,14 +7,24 @@ use ashpd::desktop::{
     global_shortcuts::{GlobalShortcuts, NewShortcut},
 };
 use lazy_static::lazy_static;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{
+    RwLock,
+    mpsc::{self, Receiver, Sender},
+};
 
-use crate::ActionEvent;
+use crate::{
+    ActionEvent,
+    config::{Config, HotkeyAction, SaveSource},
+};
+
+/// Prefix of shortcut ids coming from profile hotkeys
+const PROFILE_SHORTCUT_PREFIX: &str = "profile-";
 
 lazy_static! {
     static ref SHORTCUTS: Vec<(&'static str, &'static str, &'static str)> = vec![
         // id, description, trigger
         ("save-replay", "Save replay", "ALT+F10"),
+        ("boost", "Boost framerate", "ALT+SHIFT+F9"),
         // ("toggle-replay", "Toggle replay", "ALT+SHIFT+F10"), // TODO: implement toggling replays on and off
         ("quit", "Quit program", "ALT+SHIFT+F11")
     ];
@@ -22,18 +34,69 @@ pub struct GlobalShortcutManager<'a> {
     global_shortcuts_wrapper: GlobalShortcuts<'a>,
     global_shortcuts_session: Session<'a, GlobalShortcuts<'a>>,
     shortcut_tx: Sender<ActionEvent>,
+    config: Arc<RwLock<Config>>,
 }
 
 impl<'a> GlobalShortcutManager<'a> {
-    pub async fn new(shortcut_tx: Sender<ActionEvent>) -> Result<Self, GlobalShortcutManagerError> {
+    pub async fn new(
+        shortcut_tx: Sender<ActionEvent>,
+        config: Arc<RwLock<Config>>,
+    ) -> Result<Self, GlobalShortcutManagerError> {
         let wrapper = GlobalShortcuts::new().await?;
         Ok(Self {
             global_shortcuts_session: wrapper.create_session().await?,
             global_shortcuts_wrapper: wrapper,
             shortcut_tx,
+            config,
         })
     }
 
+    /// Built-in shortcuts followed by hotkeys of the active profile
+    async fn shortcuts(&self) -> Vec<(String, String, String)> {
+        let config = self.config.read().await;
+
+        SHORTCUTS
+            .iter()
+            .map(|s| (s.0.to_string(), s.1.to_string(), s.2.to_string()))
+            .chain(config.active_profile().into_iter().flat_map(|profile| {
+                profile.hotkeys.iter().map(|hotkey| {
+                    (
+                        format!("{}{}", PROFILE_SHORTCUT_PREFIX, hotkey.id),
+                        format!("{} ({})", hotkey.description, profile.name),
+                        hotkey.trigger.clone(),
+                    )
+                })
+            }))
+            .collect()
+    }
+
+    async fn action_for(&self, shortcut_id: &str) -> ActionEvent {
+        match shortcut_id {
+            "save-replay" => ActionEvent::SaveReplay(SaveSource::Hotkey),
+            "boost" => ActionEvent::ToggleBoost,
+            "quit" => ActionEvent::Quit,
+            id => {
+                let config = self.config.read().await;
+                let hotkey = id.strip_prefix(PROFILE_SHORTCUT_PREFIX).and_then(|id| {
+                    config
+                        .active_profile()
+                        .and_then(|profile| profile.hotkeys.iter().find(|h| h.id == id))
+                });
+
+                // Hotkeys of inactive profiles stay bound in the portal but do nothing
+                match hotkey.map(|hotkey| hotkey.action) {
+                    Some(HotkeyAction::SaveReplay) => ActionEvent::SaveReplay(SaveSource::Hotkey),
+                    Some(HotkeyAction::SaveLast(secs)) => {
+                        ActionEvent::SaveReplayLast(SaveSource::Hotkey, secs)
+                    }
+                    Some(HotkeyAction::Boost) => ActionEvent::ToggleBoost,
+                    Some(HotkeyAction::CopyFrame) => ActionEvent::CopyFrame,
+                    None => ActionEvent::Unknown,
+                }
+            }
+        }
+    }
+
     pub async fn register_all(&self) -> Result<(), GlobalShortcutManagerError> {
         let request = self
             .global_shortcuts_wrapper
@@ -47,10 +110,12 @@ impl<'a> GlobalShortcutManager<'a> {
             .map(|shortcut| shortcut.id().to_string())
             .collect::<Vec<String>>();
 
-        let shortcuts: Vec<NewShortcut> = SHORTCUTS
+        let shortcuts: Vec<NewShortcut> = self
+            .shortcuts()
+            .await
             .iter()
-            .filter(|s| !shortcut_ids.contains(&s.0.to_string()))
-            .map(|s| NewShortcut::new(s.0, s.1).preferred_trigger(s.2))
+            .filter(|s| !shortcut_ids.contains(&s.0))
+            .map(|s| NewShortcut::new(&s.0, &s.1).preferred_trigger(s.2.as_str()))
             .collect();
 
         if !shortcuts.is_empty() {
@@ -75,17 +140,26 @@ impl<'a> GlobalShortcutManager<'a> {
         Ok(())
     }
 
-    pub async fn run_handler(&self) -> Result<(), GlobalShortcutManagerError> {
+    pub async fn run_handler(
+        &self,
+        mut reload_rx: Receiver<()>,
+    ) -> Result<(), GlobalShortcutManagerError> {
         loop {
             if let Ok(mut activated) = self.global_shortcuts_wrapper.receive_activated().await {
-                while let Some(activation) = activated.next().await {
-                    self.shortcut_tx
-                        .send(match activation.shortcut_id() {
-                            "save-replay" => ActionEvent::SaveReplay,
-                            "quit" => ActionEvent::Quit,
-                            _ => ActionEvent::Unknown,
-                        })
-                        .await?;
+                loop {
+                    tokio::select! {
+                        activation = activated.next() => match activation {
+                            Some(activation) => {
+                                self.shortcut_tx
+                                    .send(self.action_for(activation.shortcut_id()).await)
+                                    .await?;
+                            }
+                            None => break,
+                        },
+                        Some(()) = reload_rx.recv() => {
+                            self.register_all().await?;
+                        }
+                    }
                 }
             }
         }
@@ -111,9 +185,15 @@ impl From<tokio::sync::mpsc::error::SendError<ActionEvent>> for GlobalShortcutMa
     }
 }
 
-pub fn setup_global_shortcuts(shortcut_tx: Sender<ActionEvent>) {
+/// Returns a sender used to re-register shortcuts after the active profile changes
+pub fn setup_global_shortcuts(
+    shortcut_tx: Sender<ActionEvent>,
+    config: Arc<RwLock<Config>>,
+) -> Sender<()> {
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+
     tokio::spawn(async move {
-        let global_shortcuts_manager = GlobalShortcutManager::new(shortcut_tx)
+        let global_shortcuts_manager = GlobalShortcutManager::new(shortcut_tx, config)
             .await
             .expect("Cannot setup global shortcuts");
 
@@ -123,8 +203,10 @@ pub fn setup_global_shortcuts(shortcut_tx: Sender<ActionEvent>) {
             .expect("Cannot register global shortcuts");
 
         global_shortcuts_manager
-            .run_handler()
+            .run_handler(reload_rx)
             .await
             .expect("Cannot run shortcut handler");
     });
+
+    reload_tx
 }
@@ -0,0 +1,32 @@
+use log::info;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::mpsc::Sender,
+};
+
+use crate::{ActionEvent, config::SaveSource};
+
+/// Lets minimal setups control TrayPlay without D-Bus, e.g. `pkill -USR1 trayplay`.
+/// SIGUSR1 saves a replay, SIGUSR2 turns the replay buffer off and on and SIGHUP reloads the
+/// config file.
+pub fn setup_signal_handlers(action_tx: Sender<ActionEvent>) -> std::io::Result<()> {
+    let mut save_signal = signal(SignalKind::user_defined1())?;
+    let mut toggle_signal = signal(SignalKind::user_defined2())?;
+    let mut reload_signal = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        loop {
+            let action = tokio::select! {
+                _ = save_signal.recv() => ActionEvent::SaveReplay(SaveSource::Signal),
+                _ = toggle_signal.recv() => ActionEvent::ToggleBuffer,
+                _ = reload_signal.recv() => ActionEvent::ReloadConfig,
+            };
+            info!("Received signal for {:?}", action);
+            if action_tx.send(action).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{Config, Quality},
+    format::{format_date_time, format_duration},
+    probe::ClipInfo,
+    timeline,
+};
+
+const MAX_FAILURES: usize = 10;
+const MAX_GAME_SESSIONS: usize = 10;
+
+/// Statistics are saved after every change, a failure to is only shown in a dialog once
+static SAVE_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Rough gpu-screen-recorder data rates at 60 fps, used until a replay has been probed
+const QUALITY_BYTES_PER_SEC: [(Quality, u64); 4] = [
+    (Quality::Medium, 1_250_000),
+    (Quality::High, 2_500_000),
+    (Quality::VeryHigh, 4_500_000),
+    (Quality::Ultra, 6_500_000),
+];
+
+/// Data rate of the last saved replay and the settings it was recorded with
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ClipRate {
+    pub bytes_per_sec: u64,
+    pub quality: Quality,
+    pub framerate: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SaveFailure {
+    pub timestamp: u64,
+    pub reason: String,
+}
+
+/// A game session and the replays saved during it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameSession {
+    pub game: String,
+    pub started: u64,
+    /// None while the game is running
+    pub ended: Option<u64>,
+    pub clips: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Statistics {
+    pub saves: u64,
+    pub failed_saves: u64,
+    pub recorder_restarts: u64,
+    pub total_save_latency_ms: u64,
+    pub failures: VecDeque<SaveFailure>,
+    #[serde(default)]
+    pub last_clip_rate: Option<ClipRate>,
+    #[serde(default)]
+    pub game_sessions: VecDeque<GameSession>,
+    /// Times the replay buffer's recorder exited on its own
+    #[serde(default)]
+    pub recorder_crashes: u64,
+
+    #[serde(skip, default = "Instant::now")]
+    started_at: Instant,
+    #[serde(skip, default = "Option::default")]
+    pending_save: Option<Instant>,
+}
+
+impl Statistics {
+    fn path() -> Option<PathBuf> {
+        dirs::state_dir().map(|dir| dir.join("trayplay_stats.toml"))
+    }
+
+    pub fn load() -> Self {
+        match Self::path().map(std::fs::read_to_string) {
+            Some(Ok(stats)) => toml::from_str(&stats).unwrap_or_default(),
+            _ => Statistics::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        let result = match Self::path() {
+            Some(path) => toml::to_string(&self)
+                .map_err(|err| err.to_string())
+                .and_then(|stats| std::fs::write(path, stats).map_err(|err| err.to_string())),
+            None => Err("there is no state directory".to_string()),
+        };
+
+        if let Err(err) = result {
+            if SAVE_FAILED.swap(true, Ordering::Relaxed) {
+                info!("Cannot save statistics: {}", err);
+            } else {
+                warn!("Cannot save statistics: {}", err);
+            }
+        }
+    }
+
+    pub fn save_requested(&mut self) {
+        self.pending_save = Some(Instant::now());
+    }
+
+    pub fn record_save(&mut self) {
+        self.saves += 1;
+        if let Some(session) = self
+            .game_sessions
+            .back_mut()
+            .filter(|session| session.ended.is_none())
+        {
+            session.clips += 1;
+        }
+        if let Some(requested_at) = self.pending_save.take() {
+            self.total_save_latency_ms += requested_at.elapsed().as_millis() as u64;
+        }
+        self.save();
+    }
+
+    pub fn record_failure(&mut self, reason: impl Into<String>) {
+        self.failed_saves += 1;
+        self.pending_save = None;
+
+        if self.failures.len() >= MAX_FAILURES {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(SaveFailure {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            reason: reason.into(),
+        });
+        self.save();
+    }
+
+    /// Ends the running game session, if any, and starts one for `game`
+    pub fn record_game_session(&mut self, game: Option<&str>) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if let Some(session) = self
+            .game_sessions
+            .back_mut()
+            .filter(|session| session.ended.is_none())
+        {
+            if Some(session.game.as_str()) == game {
+                return;
+            }
+            session.ended = Some(now);
+        } else if game.is_none() {
+            return;
+        }
+
+        match game {
+            Some(game) => timeline::record(format!("Playing {}", game)),
+            None => timeline::record("Game closed"),
+        }
+        if let Some(game) = game {
+            if self.game_sessions.len() >= MAX_GAME_SESSIONS {
+                self.game_sessions.pop_front();
+            }
+            self.game_sessions.push_back(GameSession {
+                game: game.to_string(),
+                started: now,
+                ended: None,
+                clips: 0,
+            });
+        }
+        self.save();
+    }
+
+    pub fn record_restart(&mut self) {
+        timeline::record("Recorder restarted");
+        self.recorder_restarts += 1;
+        self.save();
+    }
+
+    pub fn record_crash(&mut self) {
+        timeline::record("Recorder crashed");
+        self.recorder_crashes += 1;
+        self.save();
+    }
+
+    pub fn record_clip(&mut self, info: &ClipInfo, config: &Config) {
+        let Some(duration) = info.duration.filter(|duration| duration.as_secs() > 0) else {
+            return;
+        };
+
+        self.last_clip_rate = Some(ClipRate {
+            bytes_per_sec: (info.size_bytes as f64 / duration.as_secs_f64()) as u64,
+            quality: config.quality,
+            framerate: config.effective_framerate(),
+        });
+        self.save();
+    }
+
+    /// Estimated size of one second of the replay buffer with current settings. Based on the
+    /// last saved replay if it was recorded at the same quality, scaled by framerate.
+    pub fn estimated_bytes_per_sec(&self, config: &Config) -> u64 {
+        let (bytes_per_sec, framerate) = match self.last_clip_rate {
+            Some(rate) if rate.quality == config.quality && rate.framerate > 0 => {
+                (rate.bytes_per_sec, rate.framerate)
+            }
+            _ => (
+                QUALITY_BYTES_PER_SEC
+                    .iter()
+                    .find(|(quality, _)| *quality == config.quality)
+                    .map(|(_, bytes_per_sec)| *bytes_per_sec)
+                    .unwrap(),
+                60,
+            ),
+        };
+
+        bytes_per_sec * config.effective_framerate().max(1) as u64 / framerate as u64
+    }
+
+    pub fn average_save_latency(&self) -> Option<Duration> {
+        self.total_save_latency_ms
+            .checked_div(self.saves)
+            .map(Duration::from_millis)
+    }
+
+    pub fn summary(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let failures = if self.failures.is_empty() {
+            "none".to_string()
+        } else {
+            self.failures
+                .iter()
+                .rev()
+                .map(|failure| {
+                    format!(
+                        "\n  {} ({} ago): {}",
+                        format_date_time(UNIX_EPOCH + Duration::from_secs(failure.timestamp)),
+                        format_duration(Duration::from_secs(now.saturating_sub(failure.timestamp))),
+                        failure.reason
+                    )
+                })
+                .collect()
+        };
+
+        let game_sessions = if self.game_sessions.is_empty() {
+            "none".to_string()
+        } else {
+            self.game_sessions
+                .iter()
+                .rev()
+                .map(|session| {
+                    let length = match session.ended {
+                        Some(ended) => format_duration(Duration::from_secs(
+                            ended.saturating_sub(session.started),
+                        )),
+                        None => "running".to_string(),
+                    };
+                    format!(
+                        "\n  {}, {} ({}): {} clips",
+                        session.game,
+                        format_date_time(UNIX_EPOCH + Duration::from_secs(session.started)),
+                        length,
+                        session.clips
+                    )
+                })
+                .collect()
+        };
+
+        format!(
+            "Uptime: {}\nSaved replays: {}\nFailed saves: {}\nRecorder restarts: {}\nRecorder crashes: {}\nAverage save latency: {}\nRecent failures: {}\nRecent game sessions: {}",
+            format_duration(self.started_at.elapsed()),
+            self.saves,
+            self.failed_saves,
+            self.recorder_restarts,
+            self.recorder_crashes,
+            self.average_save_latency()
+                .map(|latency| format!("{} ms", latency.as_millis()))
+                .unwrap_or("n/a".to_string()),
+            failures,
+            game_sessions
+        )
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self {
+            saves: 0,
+            failed_saves: 0,
+            recorder_restarts: 0,
+            total_save_latency_ms: 0,
+            failures: VecDeque::new(),
+            last_clip_rate: None,
+            game_sessions: VecDeque::new(),
+            recorder_crashes: 0,
+            started_at: Instant::now(),
+            pending_save: None,
+        }
+    }
+}
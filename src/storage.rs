@@ -0,0 +1,183 @@
+use std::{
+    ffi::CString,
+    fmt::Display,
+    io::ErrorKind,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ActionEvent,
+    format::format_size,
+    kdialog::{ClickedButton, MessageBox, MessageBoxButtons},
+};
+
+/// Marks work files, which are only complete once they're moved to their final name
+const WORK_FILE_MARKER: &str = ".trayplay-tmp.";
+
+/// Less free space than this is reported, a few minutes of high quality replays can take it up
+const LOW_SPACE_BYTES: u64 = 2_000_000_000;
+
+/// Why replays probably can't be saved into the replay directory
+#[derive(Debug)]
+pub enum StorageProblem {
+    CannotCreate(std::io::Error),
+    ReadOnly,
+    NotWritable(std::io::Error),
+    LowSpace(u64),
+}
+
+impl Display for StorageProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageProblem::CannotCreate(err) => write!(f, "it cannot be created ({})", err),
+            StorageProblem::ReadOnly => write!(f, "it is on a read-only filesystem"),
+            StorageProblem::NotWritable(err) => write!(f, "it is not writable ({})", err),
+            StorageProblem::LowSpace(free) => {
+                write!(f, "only {} of space is left", format_size(*free))
+            }
+        }
+    }
+}
+
+/// Makes sure `dir` exists and a replay could be written into it
+pub fn check_replay_dir(dir: &Path) -> Result<(), StorageProblem> {
+    std::fs::create_dir_all(dir).map_err(StorageProblem::CannotCreate)?;
+
+    if let Some(stat) = statvfs(dir) {
+        if stat.f_flag & nix::libc::ST_RDONLY != 0 {
+            return Err(StorageProblem::ReadOnly);
+        }
+        let free = stat.f_bavail * stat.f_frsize;
+        if free < LOW_SPACE_BYTES {
+            return Err(StorageProblem::LowSpace(free));
+        }
+    }
+
+    // Permissions alone don't tell, e.g. with ACLs or a root-owned mount point
+    let probe = dir.join(".trayplay-write-test");
+    std::fs::write(&probe, b"").map_err(StorageProblem::NotWritable)?;
+    let _ = std::fs::remove_file(probe);
+
+    Ok(())
+}
+
+fn statvfs(path: &Path) -> Option<nix::libc::statvfs> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = unsafe { std::mem::zeroed::<nix::libc::statvfs>() };
+    let result = unsafe { nix::libc::statvfs(path.as_ptr(), &mut stat) };
+
+    (result == 0).then_some(stat)
+}
+
+/// Checks the replay directory on startup and offers to pick another one if replays can't be
+/// saved there. The dialog doesn't hold up starting the replay buffer.
+pub fn check_replay_dir_on_startup(dir: &Path, action_tx: Sender<ActionEvent>) {
+    let problem = match check_replay_dir(dir) {
+        Ok(()) => {
+            info!("Replay directory {} is ready", dir.display());
+            return;
+        }
+        Err(problem) => problem,
+    };
+    // Not a warning, which would show a second dialog
+    info!("Replays may fail to save to {}: {}", dir.display(), problem);
+
+    let label = format!(
+        "Replays may fail to save to {} because {}.\n\nDo you want to choose another folder?",
+        dir.display(),
+        problem
+    );
+    tokio::task::spawn_blocking(move || {
+        let answer = MessageBox::new(label)
+            .title("TrayPlay replay folder")
+            .buttons(MessageBoxButtons::YesNo)
+            .show();
+        match answer {
+            Ok(ClickedButton::Yes) => {
+                let _ = action_tx.blocking_send(ActionEvent::ChangeReplayPath);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Cannot ask for another replay folder: {}", err),
+        }
+    });
+}
+
+/// Where a file that ends up at `output` is written first: in `work_dir`, or next to `output`
+/// when there's none
+pub fn work_path(work_dir: Option<&Path>, output: &Path) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let extension = output
+        .extension()
+        .map(|extension| extension.to_string_lossy())
+        .unwrap_or_default();
+    let name = format!("{}{}{}", stem, WORK_FILE_MARKER, extension);
+
+    match work_dir {
+        Some(dir) => dir.join(name),
+        None => output.with_file_name(name),
+    }
+}
+
+/// Moves a finished work file to `output`, copying it when the work directory is on another
+/// filesystem
+pub async fn move_into_place(work_file: &Path, output: &Path) -> std::io::Result<()> {
+    match tokio::fs::rename(work_file, output).await {
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            // Copied next to the output first, so it's never seen half-written either
+            let copy = work_path(None, output);
+            let result = async {
+                tokio::fs::copy(work_file, &copy).await?;
+                tokio::fs::rename(&copy, output).await
+            }
+            .await;
+            if result.is_err() {
+                let _ = tokio::fs::remove_file(&copy).await;
+            }
+            let _ = tokio::fs::remove_file(work_file).await;
+            result
+        }
+        result => result,
+    }
+}
+
+/// Removes work files left behind when TrayPlay was killed or crashed while saving or
+/// post-processing, in `work_dir` and next to the replays in `replay_dir`
+pub fn remove_orphaned_work_files(work_dir: Option<&Path>, replay_dir: &Path) {
+    let mut dirs = vec![replay_dir.to_path_buf()];
+    // Replays are saved into a folder per app
+    if let Ok(entries) = std::fs::read_dir(replay_dir) {
+        dirs.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+                .map(|entry| entry.path()),
+        );
+    }
+    dirs.extend(work_dir.map(Path::to_path_buf));
+
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .contains(WORK_FILE_MARKER)
+            {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => info!("Removed unfinished {}", entry.path().display()),
+                Err(err) => warn!("Cannot remove {}: {}", entry.path().display(), err),
+            }
+        }
+    }
+}
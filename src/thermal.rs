@@ -0,0 +1,134 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use log::info;
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{ActionEvent, config::Config};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Checks in a row the machine has to be hot for, so a short spike doesn't lower settings
+const HOT_CHECKS: u32 = 2;
+/// Checks in a row it has to be cool for before settings are restored
+const COOL_CHECKS: u32 = 6;
+/// hwmon drivers of CPUs and GPUs, whose heat slows down encoding. Others (drives, wifi) don't.
+const SENSOR_DRIVERS: [&str; 6] = [
+    "coretemp", "k10temp", "zenpower", "amdgpu", "nouveau", "i915",
+];
+
+/// Labels of the CPU package and GPU edge sensors. Core and GPU junction (hotspot) sensors run
+/// well above them under normal load, and would lower settings all the time.
+const SENSOR_LABELS: [&str; 4] = ["edge", "Package id", "Tctl", "Tdie"];
+
+/// Whether the `temp*_input` file at `input` is a package or edge sensor. Unlabeled ones are the
+/// only sensor of their chip.
+fn is_package_sensor(input: &Path) -> bool {
+    let label = input.with_file_name(
+        input
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .replace("_input", "_label"),
+    );
+    match std::fs::read_to_string(label) {
+        Ok(label) => SENSOR_LABELS
+            .iter()
+            .any(|prefix| label.trim().starts_with(prefix)),
+        Err(_) => true,
+    }
+}
+
+/// Highest temperature of the CPU package and GPU edge sensors in °C, `None` if there are none
+fn hottest_sensor() -> Option<f64> {
+    let hwmons = std::fs::read_dir("/sys/class/hwmon").ok()?;
+
+    hwmons
+        .filter_map(|hwmon| hwmon.ok())
+        .filter(|hwmon| {
+            std::fs::read_to_string(hwmon.path().join("name"))
+                .is_ok_and(|name| SENSOR_DRIVERS.contains(&name.trim()))
+        })
+        .flat_map(|hwmon| std::fs::read_dir(hwmon.path()).into_iter().flatten())
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("temp") && name.ends_with("_input")
+        })
+        .map(|entry| entry.path())
+        .filter(|input| is_package_sensor(input))
+        .filter_map(|input| read_number(&input))
+        // Millidegrees
+        .map(|temp| temp as f64 / 1000.0)
+        .reduce(f64::max)
+}
+
+/// Times the CPUs were throttled for heat so far, counted by Intel CPUs only
+fn throttle_count() -> u64 {
+    let Ok(cpus) = std::fs::read_dir("/sys/devices/system/cpu") else {
+        return 0;
+    };
+
+    cpus.filter_map(|cpu| cpu.ok())
+        .filter_map(|cpu| read_number(&cpu.path().join("thermal_throttle/package_throttle_count")))
+        .sum()
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Reports the machine starting and stopping to overheat with
+/// [`ActionEvent::ThermalPressure`], while `thermal_throttling` is enabled
+pub fn setup_thermal_watcher(action_tx: Sender<ActionEvent>, config: Arc<RwLock<Config>>) {
+    tokio::spawn(async move {
+        let mut hot = false;
+        let mut checks_in_state = 0;
+        let mut last_throttle_count = throttle_count();
+
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let throttling = config.read().await.thermal_throttling.clone();
+            let throttle_count = throttle_count();
+            let throttled = throttle_count > last_throttle_count;
+            last_throttle_count = throttle_count;
+            if !throttling.enabled {
+                if hot {
+                    hot = false;
+                    let _ = action_tx.send(ActionEvent::ThermalPressure(false)).await;
+                }
+                continue;
+            }
+
+            let temperature = hottest_sensor();
+            let now_hot = if hot {
+                // Between the two thresholds it stays hot, so settings don't flip back and forth
+                throttled || temperature.is_some_and(|temp| temp > throttling.cool_celsius as f64)
+            } else {
+                throttled || temperature.is_some_and(|temp| temp >= throttling.hot_celsius as f64)
+            };
+            if now_hot == hot {
+                checks_in_state = 0;
+                continue;
+            }
+            checks_in_state += 1;
+            if checks_in_state < if hot { COOL_CHECKS } else { HOT_CHECKS } {
+                continue;
+            }
+
+            hot = now_hot;
+            checks_in_state = 0;
+            match temperature {
+                Some(temp) if hot => info!("Running hot ({:.0} °C)", temp),
+                Some(temp) => info!("Cooled down ({:.0} °C)", temp),
+                None => info!("CPU {} throttled", if hot { "is" } else { "is no longer" }),
+            }
+            if action_tx
+                .send(ActionEvent::ThermalPressure(hot))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
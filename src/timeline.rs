@@ -0,0 +1,47 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::format::format_date_time;
+
+/// How far back the timeline in the Statistics window goes
+const SPAN: Duration = Duration::from_secs(24 * 60 * 60);
+/// Entries kept at most, so a flood of errors can't grow it without bound
+const MAX_ENTRIES: usize = 1000;
+
+/// Notable events with when they happened, oldest first. Only kept in memory.
+static TIMELINE: Mutex<VecDeque<(SystemTime, String)>> = Mutex::new(VecDeque::new());
+
+fn age(time: SystemTime) -> Duration {
+    SystemTime::now().duration_since(time).unwrap_or_default()
+}
+
+/// Adds `event` to the timeline, e.g. "Replay saved". Can be called from any thread.
+pub fn record(event: impl Into<String>) {
+    let mut timeline = TIMELINE.lock().unwrap();
+    while timeline.len() >= MAX_ENTRIES
+        || timeline.front().is_some_and(|(time, _)| age(*time) > SPAN)
+    {
+        timeline.pop_front();
+    }
+    timeline.push_back((SystemTime::now(), event.into()));
+}
+
+/// Events of the last 24 hours, newest first and one per line
+pub fn summary() -> String {
+    let timeline = TIMELINE.lock().unwrap();
+    let events: Vec<String> = timeline
+        .iter()
+        .rev()
+        .take_while(|(time, _)| age(*time) <= SPAN)
+        .map(|(time, event)| format!("{}  {}", format_date_time(*time), event))
+        .collect();
+
+    if events.is_empty() {
+        "Nothing has happened yet".to_string()
+    } else {
+        events.join("\n")
+    }
+}
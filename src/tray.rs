@@ -1,37 +1,283 @@
-use log::error;
-use std::{iter::once, process::Command, sync::Arc};
+use log::{error, warn};
+use std::{
+    iter::once,
+    path::PathBuf,
+    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
 use ksni::{
     MenuItem,
-    menu::{RadioGroup, RadioItem, StandardItem, SubMenu},
+    menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu},
 };
 use tokio::sync::{RwLock, mpsc::Sender};
 
 use crate::{
     ActionEvent,
-    config::{Config, Container, Quality},
-    kdialog::MessageBox,
-    utils::ask_custom_number,
+    audio::{AudioChoices, CapturedAudio},
+    config::{
+        AppAudioMode, BitDepth, CaptureTarget, ColorRange, Config, Container, Encoder, MenuEntry,
+        Quality, RateControl, RateControlMode, RecorderBackend, RecorderMode, ReplayAction,
+        ReplayStorage, SaveSource, WebcamCorner, config_backups, default_menu_layout,
+        parse_resolution,
+    },
+    config_preview,
+    format::{format_date_time, format_decimal, format_duration, format_size},
+    gpu, gsr, gsr_info,
+    kdialog::{ClickedButton, ComboBox, InputBox, InputBoxType, MessageBox, MessageBoxButtons},
+    kms,
+    logger::log_history,
+    monitors, picker,
+    recorder::{self, RecorderStatus},
+    recorders::RecorderState,
+    replay_actions::MAX_NOTIFICATION_ACTIONS,
+    stats::Statistics,
+    timeline,
+    utils::{self, ask_custom_number, ask_custom_string, ask_custom_value, ask_duration},
 };
 
 pub struct TrayIcon {
     _enabled: bool,
-    tray_event_tx: Sender<ActionEvent>,
+    tray_event_tx: ActionSender,
     config: Arc<RwLock<Config>>,
+    /// Copy of the config the menu is built from, kept up to date by [`TrayUpdater`]
+    menu_config: Config,
+    status: TrayStatus,
+    stats: Arc<RwLock<Statistics>>,
+}
+
+/// App state shown in the menu that is not part of the config
+#[derive(Clone, PartialEq, Default)]
+pub struct TrayStatus {
+    pub live: bool,
+    /// "Start recording" is recording into a file
+    pub recording: bool,
+    /// Clips queued to watch later, oldest first
+    pub watch_later: Vec<PathBuf>,
+    pub recorders: Vec<(String, RecorderMode, RecorderState)>,
+    /// Quality was lowered because the encoder couldn't keep up
+    pub quality_lowered: bool,
+    /// Framerate and quality are temporarily boosted
+    pub boosted: bool,
+    /// Estimated size of one second of the replay buffer
+    pub buffer_bytes_per_sec: u64,
+    /// State of the main replay buffer
+    pub buffer: RecorderStatus,
+    /// Why the main replay buffer is paused
+    pub pause_reasons: Vec<String>,
+    /// Listed in the background, `pactl` is too slow to run while the menu is built
+    pub audio: AudioChoices,
 }
 
 impl TrayIcon {
-    pub async fn new(tray_event_tx: Sender<ActionEvent>, config: &Arc<RwLock<Config>>) -> Self {
+    pub async fn new(
+        tray_event_tx: Sender<ActionEvent>,
+        config: &Arc<RwLock<Config>>,
+        stats: &Arc<RwLock<Statistics>>,
+    ) -> Self {
         Self {
-            tray_event_tx,
+            tray_event_tx: ActionSender::new(tray_event_tx),
             _enabled: true,
             config: config.clone(),
+            menu_config: config.read().await.clone(),
+            status: TrayStatus::default(),
+            stats: stats.clone(),
         }
     }
 }
 
+/// Sends menu actions to the main loop. Should the main loop stop, the tray stays up and tells
+/// the user so instead of panicking along with it.
+#[derive(Clone)]
+struct ActionSender {
+    tx: Sender<ActionEvent>,
+    reported: Arc<AtomicBool>,
+}
+
+impl ActionSender {
+    fn new(tx: Sender<ActionEvent>) -> Self {
+        Self {
+            tx,
+            reported: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    async fn send(&self, event: ActionEvent) {
+        let Err(err) = self.tx.send(event).await else {
+            return;
+        };
+        // Quitting still has to work, there's just nothing left to stop cleanly
+        if matches!(err.0, ActionEvent::Quit) {
+            std::process::exit(1);
+        }
+        if !self.reported.swap(true, Ordering::Relaxed) {
+            error!(
+                "TrayPlay stopped working and cannot handle menu actions anymore. Quit and start it again."
+            );
+        }
+    }
+
+    /// Whether the main loop is gone
+    fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+/// Pushes config and status changes to the tray. The menu is only rebuilt when something it shows
+/// changed, ksni then diffs it against the previous one and updates just the changed items.
+pub struct TrayUpdater {
+    handle: ksni::Handle<TrayIcon>,
+    state: Config,
+    status: TrayStatus,
+}
+
+impl TrayUpdater {
+    pub fn new(
+        handle: ksni::Handle<TrayIcon>,
+        config: &Config,
+        action_tx: Sender<ActionEvent>,
+    ) -> Self {
+        // The icon and menu tell that the main loop stopped as soon as it does, not only once
+        // something else changes
+        let closed_handle = handle.clone();
+        tokio::spawn(async move {
+            action_tx.closed().await;
+            closed_handle.update(|_| {}).await;
+        });
+
+        Self {
+            handle,
+            state: config.clone(),
+            status: TrayStatus::default(),
+        }
+    }
+
+    pub async fn sync(&mut self, config: &Config) {
+        if *config == self.state {
+            return;
+        }
+        self.state = config.clone();
+
+        let config = config.clone();
+        self.handle
+            .update(move |tray| tray.menu_config = config)
+            .await;
+    }
+
+    pub async fn set_status(&mut self, status: TrayStatus) {
+        if status == self.status {
+            return;
+        }
+        self.status = status.clone();
+
+        self.handle.update(move |tray| tray.status = status).await;
+    }
+}
+
 struct TrayMultipleOption<T>(String, T);
 
+const BUILT_IN_QUALITIES: [(&str, Quality); 4] = [
+    ("Medium", Quality::Medium),
+    ("High", Quality::High),
+    ("Very high", Quality::VeryHigh),
+    ("Ultra", Quality::Ultra),
+];
+
+/// Label, mode and the prompt for its value
+const RATE_CONTROL_MODES: [(&str, RateControlMode, &str); 3] = [
+    ("CBR…", RateControlMode::Cbr, "Constant bitrate in kbps"),
+    ("VBR…", RateControlMode::Vbr, "Average bitrate in kbps"),
+    (
+        "CQP…",
+        RateControlMode::Cqp,
+        "Quantizer from 0 to 51, lower is better quality",
+    ),
+];
+
+const ENCODERS: [(&str, Encoder); 5] = [
+    ("Automatic", Encoder::Auto),
+    ("VAAPI", Encoder::Vaapi),
+    ("NVENC", Encoder::Nvenc),
+    ("Quick Sync", Encoder::Qsv),
+    ("Software", Encoder::Software),
+];
+/// gpu-screen-recorder only tells GPU from CPU encoding, and encodes on the GPU by default
+const GSR_ENCODERS: [(&str, Encoder); 2] =
+    [("GPU", Encoder::Auto), ("Software", Encoder::Software)];
+
+/// Common output resolutions to scale down to
+const SCALE_RESOLUTIONS: [(&str, &str); 3] = [
+    ("1440p", "2560x1440"),
+    ("1080p", "1920x1080"),
+    ("720p", "1280x720"),
+];
+
+/// Recording volumes in percent, anything else can be entered as a custom value
+const VOLUMES: [i64; 6] = [50, 75, 100, 125, 150, 200];
+/// Seconds between keyframes. Shorter ones make saved replays start closer to the requested
+/// length and seek better, longer ones make them smaller.
+const KEYFRAME_INTERVALS: [f64; 4] = [0.5, 1.0, 2.0, 5.0];
+/// Webcam overlay sizes in percent of the recorded screen
+const WEBCAM_SIZES: [u32; 4] = [15, 20, 25, 33];
+
+/// Label of a submenu of choices that also tells the current choice, so it can be read without
+/// opening the submenu (e.g. by screen readers, which don't announce the checked radio item)
+fn label_with_value(label: &str, value: &str) -> String {
+    format!("{}: {}", label, value)
+}
+
+/// Radio items that change config through `select` and save it
+fn settings_radio(
+    locked: bool,
+    selected: usize,
+    labels: Vec<String>,
+    select: impl Fn(&mut Config, usize) + Send + 'static,
+) -> MenuItem<TrayIcon> {
+    RadioGroup {
+        selected,
+        options: labels
+            .into_iter()
+            .map(|label| RadioItem {
+                label,
+                enabled: !locked,
+                ..Default::default()
+            })
+            .collect(),
+        select: Box::new(move |this: &mut TrayIcon, index| {
+            futures::executor::block_on(async {
+                let config = this.get_config();
+                let mut config = config.write().await;
+                select(&mut config, index);
+                config.save().await;
+            });
+        }),
+    }
+    .into()
+}
+
+/// Named group of settings shown as a submenu of Settings
+struct SettingsGroup<T> {
+    label: &'static str,
+    icon: &'static str,
+    items: Vec<MenuItem<T>>,
+}
+
+impl<T> From<SettingsGroup<T>> for MenuItem<T> {
+    fn from(group: SettingsGroup<T>) -> Self {
+        SubMenu {
+            label: group.label.into(),
+            icon_name: group.icon.into(),
+            submenu: group.items,
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl<T> Into<RadioItem> for &TrayMultipleOption<T> {
     fn into(self) -> RadioItem {
         RadioItem {
@@ -52,16 +298,22 @@ where
         options: Vec<TrayMultipleOption<O>>,
         initial_state: usize,
         show_custom: bool,
+        /// The current value as told in the label when it's none of the options
+        custom_value: String,
+        enabled: bool,
         action: Box<dyn Fn(&mut T, usize) + Send + 'static>,
     },
     Toggle {
         label: String,
         icon: String,
+        checked: bool,
+        enabled: bool,
         action: Box<dyn Fn(&mut T) + Send + 'static>,
     },
     Custom {
         label: String,
         icon: String,
+        enabled: bool,
         action: Box<dyn Fn(&mut T) + Send + 'static>,
     },
 }
@@ -79,9 +331,17 @@ where
                 action,
                 initial_state,
                 show_custom,
+                custom_value,
+                enabled,
             } => SubMenu {
-                label,
+                label: label_with_value(
+                    &label,
+                    options
+                        .get(initial_state)
+                        .map_or(custom_value.as_str(), |option| option.0.as_str()),
+                ),
                 icon_name: icon,
+                enabled,
                 submenu: vec![
                     RadioGroup {
                         selected: initial_state,
@@ -107,17 +367,29 @@ where
             }
             .into(),
             TrayConfigItem::Toggle {
-                label: _,
-                icon: _,
-                action: _,
-            } => todo!("Implement toggle config menu item type"),
+                label,
+                icon,
+                checked,
+                enabled,
+                action,
+            } => CheckmarkItem {
+                label,
+                icon_name: icon,
+                checked,
+                enabled,
+                activate: action,
+                ..Default::default()
+            }
+            .into(),
             TrayConfigItem::Custom {
                 label,
                 icon,
+                enabled,
                 action,
             } => StandardItem {
                 label,
                 icon_name: icon,
+                enabled,
                 activate: action,
                 ..Default::default()
             }
@@ -128,10 +400,31 @@ where
 
 macro_rules! tray_config_item_radio {
     (@custombool nocustom) => { false };
+    (@custombool duration) => { true };
     (@custombool) => { true };
 
+    (@customvalue $config:expr, $config_key:ident, nocustom) => { String::new() };
+    (@customvalue $config:expr, $config_key:ident, duration) => {
+        format_duration(Duration::from_secs($config.$config_key.max(0) as u64))
+    };
+    (@customvalue $config:expr, $config_key:ident,) => { $config.$config_key.to_string() };
+
     (@customhandler $config:expr, $config_key:ident, $label:expr, nocustom) => {};
 
+    (@customhandler $config:expr, $config_key:ident, $label:expr, duration) => {
+        match ask_duration("TrayPlay Settings", $label, $config.$config_key) {
+            Ok(secs) => {
+                if let Some(secs) = secs {
+                    $config.$config_key = secs;
+                    $config.save().await;
+                }
+            }
+            Err(err) => {
+                error!("Error when asking for custom config value: {}", err);
+            }
+        }
+    };
+
     (@customhandler $config:expr, $config_key:ident, $label:expr,) => {
         match ask_custom_number("TrayPlay Settings", $label, 0) {
             Ok(number) => {
@@ -154,6 +447,8 @@ macro_rules! tray_config_item_radio {
             icon: $icon.into(),
             options: $values,
             show_custom: tray_config_item_radio!(@custombool $($nocustom)?),
+            custom_value: tray_config_item_radio!(@customvalue config, $config_key, $($nocustom)?),
+            enabled: !config.is_locked(stringify!($config_key)),
             initial_state: $values
                 .iter()
                 .position(|element: &TrayMultipleOption<_>| {
@@ -178,11 +473,34 @@ macro_rules! tray_config_item_radio {
     }};
 }
 
+macro_rules! tray_config_item_toggle {
+    ($($config_key:ident).+, $config:expr, $label:expr, $icon:expr) => {
+        TrayConfigItem::Toggle::<TrayIcon, u8> {
+            label: $label.into(),
+            icon: $icon.into(),
+            checked: $config.$($config_key).+,
+            enabled: !$config.is_locked(stringify!($($config_key).+)),
+            action: Box::new(|item| {
+                futures::executor::block_on(async {
+                    let config = item.get_config();
+                    let mut config = config.write().await;
+                    config.$($config_key).+ = !config.$($config_key).+;
+                    config.save().await;
+                });
+            }),
+        }
+    };
+}
+
 macro_rules! tray_config_item_custom {
     ($label:expr, $icon:expr, $action:expr) => {
+        tray_config_item_custom!($label, $icon, $action, true)
+    };
+    ($label:expr, $icon:expr, $action:expr, $enabled:expr) => {
         TrayConfigItem::Custom::<TrayIcon, u8> {
             label: $label.into(),
             icon: $icon.into(),
+            enabled: $enabled,
             action: Box::new(|item| {
                 futures::executor::block_on(async {
                     $action(item.get_config(), item.get_action_event_tx()).await;
@@ -200,126 +518,333 @@ impl ksni::Tray for TrayIcon {
     }
 
     fn icon_name(&self) -> String {
+        if self.tray_event_tx.is_closed() || self.status.buffer == RecorderStatus::Failed {
+            return "dialog-error".into();
+        }
+        if self.status.live {
+            return "network-wireless".into();
+        }
+        if self.status.recording {
+            return "media-record".into();
+        }
         "media-skip-backward".into()
     }
 
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let mut description = format!(
+            "{} buffer, ~{} {}",
+            format_duration(Duration::from_secs(
+                self.menu_config.replay_duration_secs.max(0) as u64
+            )),
+            self.buffer_size(self.menu_config.replay_duration_secs),
+            match self.menu_config.replay_storage {
+                ReplayStorage::Ram => "of RAM",
+                ReplayStorage::Disk => "on disk",
+            }
+        );
+        if !self.status.pause_reasons.is_empty() {
+            description.push_str(&format!(
+                "\nPaused: {}",
+                self.status.pause_reasons.join(", ")
+            ));
+        }
+        if self.status.live {
+            description.push_str("\nStreaming live");
+        }
+        if self.status.recording {
+            description.push_str("\nRecording to a file");
+        }
+
+        ksni::ToolTip {
+            title: self.title(),
+            description,
+            ..Default::default()
+        }
+    }
+
     fn title(&self) -> String {
-        "TrayPlay".into()
+        if self.tray_event_tx.is_closed() {
+            return "TrayPlay (not responding)".into();
+        }
+        match self.status.buffer {
+            RecorderStatus::Running => "TrayPlay".into(),
+            status => format!("TrayPlay ({})", status),
+        }
     }
 
     fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        let config = &self.menu_config;
+        // Nothing else in the menu would do anything
+        if self.tray_event_tx.is_closed() {
+            return vec![
+                StandardItem {
+                    label: "TrayPlay stopped working, quit and start it again".into(),
+                    icon_name: "dialog-error".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                self.menu_entry(MenuEntry::Quit, config)
+                    .unwrap_or(MenuItem::Separator),
+            ];
+        }
+
+        let mut menu = Vec::new();
+        // Otherwise it's only visible in the tooltip, while nothing gets recorded
+        if self
+            .status
+            .pause_reasons
+            .iter()
+            .any(|reason| reason == recorder::SHARING_STOPPED)
+        {
+            menu.push(self.action_item(
+                "Screen sharing stopped, share again…",
+                "dialog-warning",
+                ActionEvent::RequestCapture,
+            ));
+            menu.push(MenuItem::Separator);
+        }
+        if kms::use_portal(config) {
+            menu.push(self.action_item(
+                "Recording through the portal, capture directly…",
+                "dialog-information",
+                ActionEvent::AskForKmsPermission,
+            ));
+            menu.push(MenuItem::Separator);
+        }
+        if self.status.buffer == RecorderStatus::Failed {
+            menu.push(self.action_item(
+                "Replay recording keeps failing, try again",
+                "dialog-error",
+                ActionEvent::RecoverRecorder,
+            ));
+            menu.push(MenuItem::Separator);
+        }
+        menu.extend(
+            config
+                .menu_layout
+                .clone()
+                .unwrap_or_else(default_menu_layout)
+                .into_iter()
+                .filter_map(|entry| self.menu_entry(entry, config)),
+        );
+        menu
+    }
+}
+
+impl TrayIcon {
+    fn action_item(&self, label: &str, icon: &str, event: ActionEvent) -> MenuItem<Self> {
         let tx_clone = self.tray_event_tx.clone();
-        use ksni::menu::*;
-
-        let config = futures::executor::block_on(async { self.config.read().await });
-
-        let settings_menu = vec![
-            tray_config_item_radio!(
-                framerate,
-                &config,
-                "Framerate",
-                "speedometer",
-                vec![
-                    TrayMultipleOption("30".into(), 30),
-                    TrayMultipleOption("60".into(), 60),
-                ]
-            )
-            .into(),
-            tray_config_item_radio!(
-                replay_duration_secs,
-                &config,
-                "Duration",
-                "clock",
-                vec![
-                    TrayMultipleOption("30s".into(), 30),
-                    TrayMultipleOption("1min".into(), 60),
-                    TrayMultipleOption("2min".into(), 120),
-                    TrayMultipleOption("3min".into(), 180),
-                    TrayMultipleOption("5min".into(), 300),
-                ]
-            )
-            .into(),
-            tray_config_item_radio!(
-                quality,
-                &config,
-                "Quality",
-                "star-new-symbolic",
-                vec![
-                    TrayMultipleOption("Medium".into(), Quality::Medium),
-                    TrayMultipleOption("High".into(), Quality::High),
-                    TrayMultipleOption("Very high".into(), Quality::VeryHigh),
-                    TrayMultipleOption("Ultra".into(), Quality::Ultra),
-                ],
-                nocustom
+
+        StandardItem {
+            label: label.into(),
+            icon_name: icon.into(),
+            activate: Box::new(move |_| {
+                futures::executor::block_on(async {
+                    tx_clone.send(event.clone()).await;
+                });
+            }),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn menu_entry(&self, entry: MenuEntry, config: &Config) -> Option<MenuItem<Self>> {
+        Some(match entry {
+            MenuEntry::SaveReplay => {
+                self.action_item(
+                    "Save replay",
+                    "document-save",
+                    ActionEvent::SaveReplay(SaveSource::Tray),
+                )
+            }
+            MenuEntry::CopyFrame => self.action_item(
+                "Copy frame to clipboard",
+                "edit-copy",
+                ActionEvent::CopyFrame,
+            ),
+            MenuEntry::Microphone => tray_config_item_toggle!(
+                include_microphone,
+                config,
+                "Include microphone",
+                "audio-input-microphone"
             )
             .into(),
-            tray_config_item_radio!(
-                container,
-                &config,
-                "Container",
-                "archive-extract",
-                vec![
-                    TrayMultipleOption("MKV".into(), Container::MKV),
-                    TrayMultipleOption("MP4".into(), Container::MP4),
-                    TrayMultipleOption("WEBM".into(), Container::WEBM),
-                    TrayMultipleOption("FLV".into(), Container::FLV),
-                ],
-                nocustom
+            MenuEntry::MuteMicrophone => self.mute_item(
+                CapturedAudio::Microphone,
+                config,
+                "Mute microphone",
+                "microphone-sensitivity-muted",
+            ),
+            MenuEntry::MuteDesktopAudio => self.mute_item(
+                CapturedAudio::Desktop,
+                config,
+                "Mute desktop audio",
+                "audio-volume-muted",
+            ),
+            MenuEntry::FollowFocus => tray_config_item_toggle!(
+                follow_focus,
+                config,
+                "Follow focused window",
+                "window"
             )
             .into(),
-            tray_config_item_custom!(
-                "Path",
-                "inode-directory",
-                async move |_, action_event_tx: Sender<ActionEvent>| {
-                    // Need to send message to main thread because for some reason portal file picker request
-                    // is not being sent when directly called here...
-                    action_event_tx
-                        .send(ActionEvent::ChangeReplayPath)
-                        .await
-                        .unwrap();
+            MenuEntry::Profile => self.profile_menu(config)?,
+            MenuEntry::RestoreQuality => {
+                if !self.status.quality_lowered {
+                    return None;
                 }
-            )
-            .into(),
-        ];
 
-        vec![
-            // TODO: implement toggling replays on and off
-            // CheckmarkItem {
-            //     label: "Record replays".into(),
-            //     checked: self.enabled,
-            //     icon_name: "media-skip-backward".into(),
-            //     activate: Box::new(move |this: &mut Self| {
-            //         this.enabled = !this.enabled;
-            //         futures::executor::block_on(async {
-            //             sender_clone1.send("toggle-replay".into()).await.unwrap();
-            //         });
-            //     }),
-            //     ..Default::default()
-            // }
-            // .into(),
-            StandardItem {
-                label: "Save replay".into(),
-                icon_name: "document-save".into(),
-                activate: Box::new({
-                    let tx_clone = tx_clone.clone();
-                    move |_| {
-                        futures::executor::block_on(async {
-                            tx_clone.send(ActionEvent::SaveReplay).await.unwrap();
-                        });
-                    }
+                self.action_item(
+                    "Restore quality settings",
+                    "edit-undo",
+                    ActionEvent::RestoreQuality,
+                )
+            }
+            MenuEntry::Boost => {
+                if self.status.boosted {
+                    self.action_item("End boost", "edit-undo", ActionEvent::EndBoost)
+                } else {
+                    self.action_item(
+                        &format!(
+                            "Boost to {} fps for {}",
+                            config.boost.framerate,
+                            format_duration(Duration::from_secs(config.boost.duration_secs))
+                        ),
+                        "go-up",
+                        ActionEvent::ToggleBoost,
+                    )
+                }
+            }
+            MenuEntry::ExportVertical => self.action_item(
+                "Export vertical",
+                "object-rotate-right",
+                ActionEvent::ExportVertical,
+            ),
+            MenuEntry::DeleteReplay => self.action_item(
+                "Delete last replay…",
+                ReplayAction::Delete.icon(),
+                ActionEvent::DeleteLastReplay,
+            ),
+            MenuEntry::AddNote => {
+                self.action_item("Add note…", "document-edit", ActionEvent::AddNote)
+            }
+            MenuEntry::WatchLater => self.watch_later_menu()?,
+            MenuEntry::Record => {
+                if self.status.recording {
+                    self.action_item(
+                        "Stop recording",
+                        "media-playback-stop",
+                        ActionEvent::ToggleRecording,
+                    )
+                } else {
+                    self.action_item(
+                        "Start recording",
+                        "media-record",
+                        ActionEvent::ToggleRecording,
+                    )
+                }
+            }
+            MenuEntry::Stream => {
+                if config.streaming.url.is_empty() {
+                    return None;
+                }
+
+                if self.status.live {
+                    self.action_item(
+                        "Stop streaming",
+                        "media-playback-stop",
+                        ActionEvent::ToggleStreaming,
+                    )
+                } else {
+                    self.action_item(
+                        "Start streaming",
+                        "network-wireless",
+                        ActionEvent::ToggleStreaming,
+                    )
+                }
+            }
+            MenuEntry::Recorders => self.recorders_menu()?,
+            MenuEntry::CustomActions => {
+                if config.custom_actions.is_empty() {
+                    return None;
+                }
+
+                SubMenu {
+                    label: "Custom".into(),
+                    icon_name: "system-run".into(),
+                    submenu: config
+                        .custom_actions
+                        .iter()
+                        .enumerate()
+                        .map(|(index, action)| {
+                            self.action_item(
+                                &action.label,
+                                action.icon.as_deref().unwrap_or_default(),
+                                ActionEvent::RunCustomAction(index),
+                            )
+                        })
+                        .collect(),
+                    ..Default::default()
+                }
+                .into()
+            }
+            MenuEntry::Settings => SubMenu {
+                label: "Settings".into(),
+                icon_name: "configure".into(),
+                submenu: self.settings_menu(config),
+                ..Default::default()
+            }
+            .into(),
+            MenuEntry::Statistics => StandardItem {
+                label: "Statistics…".into(),
+                icon_name: "view-statistics".into(),
+                activate: Box::new(|this: &mut Self| {
+                    futures::executor::block_on(async {
+                        let summary = format!(
+                            "{}\n\nLast 24 hours:\n{}",
+                            this.stats.read().await.summary(),
+                            timeline::summary()
+                        );
+                        // A text area, the timeline can get long
+                        let result = InputBox::new(
+                            "Statistics and what happened in the last 24 hours",
+                            InputBoxType::TextArea,
+                        )
+                        .title("TrayPlay Statistics")
+                        .initial(summary)
+                        .ok_label("Copy to clipboard")
+                        .cancel_label("Close")
+                        .size(700, 600)
+                        .show();
+                        match result {
+                            Ok(Some(summary)) => {
+                                this.tray_event_tx
+                                    .send(ActionEvent::CopyToClipboard(summary))
+                                    .await
+                            }
+                            Ok(None) => {}
+                            Err(err) => error!("Cannot show statistics: {}", err),
+                        }
+                    });
                 }),
                 ..Default::default()
             }
             .into(),
-            MenuItem::Separator,
-            SubMenu {
-                label: "Settings".into(),
-                icon_name: "configure".into(),
-                submenu: settings_menu,
+            MenuEntry::Logs => self.logs_item(),
+            MenuEntry::Troubleshooting => SubMenu {
+                label: "Troubleshooting".into(),
+                icon_name: "tools-report-bug".into(),
+                submenu: vec![
+                    self.action_item("Run self-test", "system-run", ActionEvent::RunSelfTest),
+                    self.logs_item(),
+                    self.restore_settings_item(),
+                ],
                 ..Default::default()
             }
             .into(),
-            tray_config_item_custom!("About", "help-about", async move |_, _| {
+            MenuEntry::About => tray_config_item_custom!("About", "help-about", async move |_, _| {
                 let gsr_version = Command::new("gpu-screen-recorder")
                     .arg("--version")
                     .output()
@@ -334,22 +859,1581 @@ impl ksni::Tray for TrayIcon {
                 .unwrap();
             })
             .into(),
+            MenuEntry::Separator => MenuItem::Separator,
+            MenuEntry::Quit => self.action_item("Quit", "gtk-quit", ActionEvent::Quit),
+        })
+    }
+
+    /// Declarative layout of the Settings submenu
+    fn settings_schema(&self, config: &Config) -> Vec<SettingsGroup<Self>> {
+        vec![
+            SettingsGroup {
+                label: "Video",
+                icon: "video-x-generic",
+                items: vec![
+                    self.framerate_menu(config),
+                    self.quality_menu(config),
+                    self.rate_control_menu(config),
+                    self.encoder_menu(config),
+                    self.gpu_menu(config),
+                    self.keyframe_interval_menu(config),
+                    self.capture_menu(config),
+                    self.scale_resolution_menu(config),
+                    tray_config_item_toggle!(record_cursor, config, "Record cursor", "input-mouse")
+                        .into(),
+                    self.webcam_menu(config),
+                    self.hdr_item(config),
+                    tray_config_item_radio!(
+                        color_range,
+                        config,
+                        "Color range",
+                        "color-management",
+                        vec![
+                            TrayMultipleOption("Limited".into(), ColorRange::Limited),
+                            TrayMultipleOption("Full".into(), ColorRange::Full),
+                        ],
+                        nocustom
+                    )
+                    .into(),
+                    self.bit_depth_menu(config),
+                ],
+            },
+            SettingsGroup {
+                label: "Audio",
+                icon: "audio-volume-high",
+                items: vec![
+                    self.audio_source_menu(config),
+                    self.microphone_menu(config),
+                    tray_config_item_toggle!(
+                        noise_suppression,
+                        config,
+                        "Noise suppression",
+                        "audio-input-microphone"
+                    )
+                    .into(),
+                    self.app_audio_menu(config),
+                    self.volume_menu(
+                        CapturedAudio::Desktop,
+                        config,
+                        "Desktop volume",
+                        "audio-volume-high",
+                    ),
+                    self.volume_menu(
+                        CapturedAudio::Microphone,
+                        config,
+                        "Microphone volume",
+                        "audio-input-microphone",
+                    ),
+                    tray_config_item_toggle!(
+                        mix_audio_tracks,
+                        config,
+                        "Mix into one track",
+                        "audio-volume-medium"
+                    )
+                    .into(),
+                    tray_config_item_toggle!(
+                        normalize_loudness,
+                        config,
+                        "Normalize loudness",
+                        "audio-volume-high"
+                    )
+                    .into(),
+                ],
+            },
+            SettingsGroup {
+                label: "Output",
+                icon: "document-save",
+                items: vec![
+                    tray_config_item_radio!(
+                        container,
+                        config,
+                        "Container",
+                        "archive-extract",
+                        vec![
+                            TrayMultipleOption("MKV".into(), Container::MKV),
+                            TrayMultipleOption("MP4".into(), Container::MP4),
+                            TrayMultipleOption("WEBM".into(), Container::WEBM),
+                            TrayMultipleOption("FLV".into(), Container::FLV),
+                        ],
+                        nocustom
+                    )
+                    .into(),
+                    tray_config_item_toggle!(
+                        trim_dead_air,
+                        config,
+                        "Trim black and silent edges",
+                        "edit-cut"
+                    )
+                    .into(),
+                    self.notification_actions_menu(config),
+                    self.streaming_menu(config),
+                    tray_config_item_custom!(
+                        "Path",
+                        "inode-directory",
+                        async move |_, action_event_tx: ActionSender| {
+                            // Need to send message to main thread because for some reason portal file picker request
+                            // is not being sent when directly called here...
+                            action_event_tx.send(ActionEvent::ChangeReplayPath).await;
+                        },
+                        !config.is_locked("replay_directory")
+                    )
+                    .into(),
+                ],
+            },
+            SettingsGroup {
+                label: "Behavior",
+                icon: "preferences-system",
+                items: vec![
+                    tray_config_item_radio!(
+                        recorder_backend,
+                        config,
+                        "Recorder",
+                        "media-record",
+                        vec![
+                            TrayMultipleOption(
+                                "gpu-screen-recorder".into(),
+                                RecorderBackend::GpuScreenRecorder
+                            ),
+                            TrayMultipleOption(
+                                "Screen cast portal".into(),
+                                RecorderBackend::Screencast
+                            ),
+                            TrayMultipleOption("wf-recorder".into(), RecorderBackend::WfRecorder),
+                        ],
+                        nocustom
+                    )
+                    .into(),
+                    self.duration_menu(config),
+                    self.replay_storage_menu(config),
+                    tray_config_item_toggle!(
+                        clear_buffer_on_save,
+                        config,
+                        "Clear buffer on save",
+                        "edit-clear-history"
+                    )
+                    .into(),
+                    tray_config_item_toggle!(
+                        save_before_restart,
+                        config,
+                        "Save buffer before applying settings",
+                        "document-save"
+                    )
+                    .into(),
+                    tray_config_item_toggle!(
+                        pause_during_calls,
+                        config,
+                        "Pause during video calls",
+                        "camera-web"
+                    )
+                    .into(),
+                    tray_config_item_toggle!(
+                        gamescope_hotkey.enabled,
+                        config,
+                        "Save hotkey inside gamescope",
+                        "input-keyboard"
+                    )
+                    .into(),
+                    CheckmarkItem {
+                        label: "Start on login".into(),
+                        icon_name: "system-run".into(),
+                        checked: config.autostart,
+                        enabled: !config.is_locked("autostart"),
+                        activate: Box::new(|this: &mut Self| {
+                            let enabled = !this.menu_config.autostart;
+                            futures::executor::block_on(async {
+                                // Portal requests have to be sent from the main thread, see Path above
+                                this.tray_event_tx
+                                    .send(ActionEvent::SetAutostart(enabled))
+                                    .await;
+                            });
+                        }),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+            },
+        ]
+    }
+
+    /// 10-bit is marked when gpu-screen-recorder can't encode it, 8-bit is recorded then
+    fn bit_depth_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut item = tray_config_item_radio!(
+            bit_depth,
+            config,
+            "Pixel format",
+            "color-management",
+            vec![
+                TrayMultipleOption("8-bit".into(), BitDepth::Eight),
+                TrayMultipleOption("10-bit (HEVC/AV1)".into(), BitDepth::Ten),
+            ],
+            nocustom
+        );
+
+        if let TrayConfigItem::Multiple { options, .. } = &mut item
+            && config.recorder_backend == RecorderBackend::GpuScreenRecorder
+            && !gsr_info::can_encode_ten_bit()
+        {
+            options[1].0 = "10-bit (not supported by the GPU)".into();
+        }
+
+        item.into()
+    }
+
+    /// Fixed framerates, or the refresh rate of the recorded monitor
+    fn framerate_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut item = tray_config_item_radio!(
+            framerate,
+            config,
+            "Framerate",
+            "speedometer",
+            vec![
+                TrayMultipleOption("Auto (match display)".into(), 0),
+                TrayMultipleOption("30".into(), 30),
+                TrayMultipleOption("60".into(), 60),
+            ]
+        );
+
+        if let TrayConfigItem::Multiple { options, .. } = &mut item
+            && let Some(rate) = config.display_framerate
+        {
+            options[0].0 = format!("Auto (match display, {} fps)", rate);
+        }
+
+        item.into()
+    }
+
+    /// Duration options, each with the estimated size of the buffer
+    fn duration_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut item = tray_config_item_radio!(
+            replay_duration_secs,
+            config,
+            "Duration",
+            "clock",
+            vec![
+                TrayMultipleOption("30s".into(), 30),
+                TrayMultipleOption("1min".into(), 60),
+                TrayMultipleOption("2min".into(), 120),
+                TrayMultipleOption("3min".into(), 180),
+                TrayMultipleOption("5min".into(), 300),
+            ],
+            duration
+        );
+
+        if let TrayConfigItem::Multiple { options, .. } = &mut item {
+            for option in options {
+                option.0 = format!("{} (~{})", option.0, self.buffer_size(option.1));
+            }
+        }
+
+        item.into()
+    }
+
+    /// RAM or disk for gpu-screen-recorder's buffer, telling how much the buffer takes up
+    fn replay_storage_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut item = tray_config_item_radio!(
+            replay_storage,
+            config,
+            "Buffer storage",
+            "media-flash",
+            vec![
+                TrayMultipleOption("RAM".into(), ReplayStorage::Ram),
+                TrayMultipleOption("Disk".into(), ReplayStorage::Disk),
+            ],
+            nocustom
+        );
+
+        let size = self.buffer_size(config.replay_duration_secs);
+        if let TrayConfigItem::Multiple { options, .. } = &mut item {
+            for option in options {
+                option.0 = match option.1 {
+                    ReplayStorage::Ram => format!("{} (~{} of memory)", option.0, size),
+                    ReplayStorage::Disk => format!("{} (~{} in the replay folder)", option.0, size),
+                };
+            }
+        }
+
+        item.into()
+    }
+
+    /// Estimated size of a buffer of `secs` in RAM or on disk, which is also the size of a saved replay
+    fn buffer_size(&self, secs: i64) -> String {
+        format_size(self.status.buffer_bytes_per_sec * secs.max(0) as u64)
+    }
+
+    /// Built-in qualities followed by the quality presets from config
+    fn quality_menu(&self, config: &Config) -> MenuItem<Self> {
+        let presets: Vec<String> = config
+            .quality_presets
+            .iter()
+            .map(|preset| preset.name.clone())
+            .collect();
+        let selected = match config.active_quality_preset() {
+            Some(preset) => {
+                BUILT_IN_QUALITIES.len()
+                    + presets
+                        .iter()
+                        .position(|name| *name == preset.name)
+                        .unwrap()
+            }
+            None => BUILT_IN_QUALITIES
+                .iter()
+                .position(|(_, quality)| *quality == config.quality)
+                .unwrap(),
+        };
+
+        SubMenu {
+            label: label_with_value(
+                "Quality",
+                BUILT_IN_QUALITIES
+                    .iter()
+                    .map(|(label, _)| *label)
+                    .chain(presets.iter().map(String::as_str))
+                    .nth(selected)
+                    .unwrap_or_default(),
+            ),
+            icon_name: "star-new-symbolic".into(),
+            enabled: !config.is_locked("quality") && !config.is_locked("quality_preset"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: BUILT_IN_QUALITIES
+                        .iter()
+                        .map(|(label, _)| label.to_string())
+                        .chain(presets.iter().cloned())
+                        .map(|label| RadioItem {
+                            label,
+                            ..Default::default()
+                        })
+                        .collect(),
+                    select: Box::new(move |this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            config.rate_control = None;
+                            match BUILT_IN_QUALITIES.get(index) {
+                                Some((_, quality)) => {
+                                    config.quality = *quality;
+                                    config.quality_preset = None;
+                                }
+                                None => {
+                                    config.quality_preset =
+                                        presets.get(index - BUILT_IN_QUALITIES.len()).cloned();
+                                }
+                            }
+                            config.save().await;
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// "Quality setting" leaves it to quality and presets, the modes ask for their value
+    fn rate_control_menu(&self, config: &Config) -> MenuItem<Self> {
+        let selected = match config.rate_control {
+            None => 0,
+            Some(rate_control) => {
+                RATE_CONTROL_MODES
+                    .iter()
+                    .position(|(_, mode, _)| *mode == rate_control.mode)
+                    .unwrap()
+                    + 1
+            }
+        };
+        let value = match config.rate_control {
+            None => "Quality setting".to_string(),
+            Some(rate_control) => rate_control.to_string(),
+        };
+
+        SubMenu {
+            label: label_with_value("Rate control", &value),
+            icon_name: "network-transmit".into(),
+            enabled: !config.is_locked("rate_control"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: once(RadioItem {
+                        label: "Quality setting".to_string(),
+                        ..Default::default()
+                    })
+                    .chain(RATE_CONTROL_MODES.iter().map(|(label, mode, _)| RadioItem {
+                        label: label.to_string(),
+                        // gpu-screen-recorder only takes a number for CBR
+                        enabled: config.recorder_backend != RecorderBackend::GpuScreenRecorder
+                            || *mode == RateControlMode::Cbr,
+                        ..Default::default()
+                    }))
+                    .collect(),
+                    select: Box::new(|this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            let Some((_, mode, prompt)) = index
+                                .checked_sub(1)
+                                .and_then(|index| RATE_CONTROL_MODES.get(index))
+                            else {
+                                config.rate_control = None;
+                                config.save().await;
+                                return;
+                            };
+
+                            let initial = match config.rate_control {
+                                Some(rate_control) if rate_control.mode == *mode => {
+                                    rate_control.value
+                                }
+                                _ if *mode == RateControlMode::Cqp => 23,
+                                _ => 20000,
+                            };
+                            match ask_custom_number("TrayPlay Settings", prompt, initial) {
+                                Ok(Some(value)) if value >= 0 => {
+                                    let rate_control = RateControl {
+                                        mode: *mode,
+                                        value: value as u32,
+                                    };
+                                    if let Some(problem) = rate_control.problem() {
+                                        warn!("Cannot set the rate control: {}", problem);
+                                        return;
+                                    }
+                                    config.rate_control = Some(rate_control);
+                                    config.save().await;
+                                }
+                                Ok(_) => {}
+                                Err(err) => {
+                                    error!("Error when asking for custom config value: {}", err);
+                                }
+                            }
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Output devices are listed again every few seconds, so newly plugged in ones show up
+    fn audio_source_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut outputs: Vec<(String, Option<String>)> = once(("Default output".into(), None))
+            .chain(
+                self.status
+                    .audio
+                    .outputs
+                    .iter()
+                    .map(|output| (output.description.clone(), Some(output.monitor.clone()))),
+            )
+            .collect();
+        // A device that is unplugged right now stays listed while it's selected
+        if let Some(current) = &config.audio_output
+            && !outputs
+                .iter()
+                .any(|(_, monitor)| monitor.as_ref() == Some(current))
+        {
+            outputs.push((
+                format!("{} (not connected)", current),
+                Some(current.clone()),
+            ));
+        }
+        let selected = outputs
+            .iter()
+            .position(|(_, monitor)| *monitor == config.audio_output)
+            .unwrap();
+
+        SubMenu {
+            label: label_with_value("Audio source", &outputs[selected].0),
+            icon_name: "audio-card".into(),
+            enabled: !config.is_locked("audio_output"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: outputs
+                        .iter()
+                        .map(|(label, _)| RadioItem {
+                            label: label.clone(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    select: Box::new(move |this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            config.audio_output = outputs[index].1.clone();
+                            config.save().await;
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn keyframe_interval_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut item = tray_config_item_radio!(
+            keyframe_interval_secs,
+            config,
+            "Keyframe interval",
+            "media-seek-forward",
+            KEYFRAME_INTERVALS
+                .iter()
+                .map(|secs| TrayMultipleOption(String::new(), *secs))
+                .collect::<Vec<_>>(),
+            nocustom
+        );
+
+        if let TrayConfigItem::Multiple { options, .. } = &mut item {
+            for option in options {
+                option.0 = if option.1.fract() == 0.0 {
+                    format!("{}s", option.1)
+                } else {
+                    format!("{}s", format_decimal(option.1, 1))
+                };
+            }
+        }
+
+        item.into()
+    }
+
+    /// GPU doing the capture and encoding, for laptops with hybrid graphics
+    fn gpu_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut gpus: Vec<(String, Option<String>)> = once(("Automatic".into(), None))
+            .chain(gpu::gpus().into_iter().map(|gpu| {
+                (
+                    gpu.to_string(),
+                    Some(gpu.card.to_string_lossy().into_owned()),
+                )
+            }))
+            .collect();
+        // A vendor set in config picks its first card
+        let mut selected = match &config.gpu {
+            None => Some(0),
+            Some(wanted) => gpu::gpus()
+                .iter()
+                .position(|gpu| gpu.matches(wanted))
+                .map(|index| index + 1),
+        };
+        if let Some(current) = &config.gpu
+            && selected.is_none()
+        {
+            gpus.push((format!("{} (not found)", current), Some(current.clone())));
+            selected = Some(gpus.len() - 1);
+        }
+        let selected = selected.unwrap();
+
+        SubMenu {
+            label: label_with_value("GPU", &gpus[selected].0),
+            icon_name: "video-display".into(),
+            enabled: !config.is_locked("gpu"),
+            submenu: vec![settings_radio(
+                false,
+                selected,
+                gpus.iter().map(|(label, _)| label.clone()).collect(),
+                move |config, index| config.gpu = gpus[index].1.clone(),
+            )],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Webcam overlay with its device, corner and size
+    fn webcam_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut devices = utils::webcam_devices();
+        if !devices
+            .iter()
+            .any(|(_, device)| *device == config.webcam.device)
+        {
+            devices.push((
+                format!("{} (not connected)", config.webcam.device.display()),
+                config.webcam.device.clone(),
+            ));
+        }
+        let corners = [
+            ("Top left", WebcamCorner::TopLeft),
+            ("Top right", WebcamCorner::TopRight),
+            ("Bottom left", WebcamCorner::BottomLeft),
+            ("Bottom right", WebcamCorner::BottomRight),
+        ];
+        let sizes = WEBCAM_SIZES
+            .into_iter()
+            .chain(Some(config.webcam.size_percent).filter(|size| !WEBCAM_SIZES.contains(size)))
+            .collect::<Vec<_>>();
+        let locked = config.is_locked("webcam");
+
+        SubMenu {
+            label: "Webcam overlay".into(),
+            icon_name: "camera-web".into(),
+            submenu: vec![
+                tray_config_item_toggle!(webcam.enabled, config, "Show webcam", "camera-web")
+                    .into(),
+                MenuItem::Separator,
+                settings_radio(
+                    locked,
+                    devices
+                        .iter()
+                        .position(|(_, device)| *device == config.webcam.device)
+                        .unwrap(),
+                    devices.iter().map(|(name, _)| name.clone()).collect(),
+                    move |config, index| config.webcam.device = devices[index].1.clone(),
+                ),
+                MenuItem::Separator,
+                settings_radio(
+                    locked,
+                    corners
+                        .iter()
+                        .position(|(_, corner)| *corner == config.webcam.corner)
+                        .unwrap(),
+                    corners.iter().map(|(label, _)| label.to_string()).collect(),
+                    move |config, index| config.webcam.corner = corners[index].1,
+                ),
+                MenuItem::Separator,
+                settings_radio(
+                    locked,
+                    sizes
+                        .iter()
+                        .position(|size| *size == config.webcam.size_percent)
+                        .unwrap(),
+                    sizes.iter().map(|size| format!("{}% size", size)).collect(),
+                    move |config, index| config.webcam.size_percent = sizes[index],
+                ),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Like the audio source menu, for the device recorded as "default_input"
+    fn microphone_menu(&self, config: &Config) -> MenuItem<Self> {
+        let mut inputs: Vec<(String, Option<String>)> = once(("Default microphone".into(), None))
+            .chain(
+                self.status
+                    .audio
+                    .inputs
+                    .iter()
+                    .map(|input| (input.description.clone(), Some(input.name.clone()))),
+            )
+            .collect();
+        if let Some(current) = &config.microphone
+            && !inputs
+                .iter()
+                .any(|(_, name)| name.as_ref() == Some(current))
+        {
+            inputs.push((
+                format!("{} (not connected)", current),
+                Some(current.clone()),
+            ));
+        }
+        let selected = inputs
+            .iter()
+            .position(|(_, name)| *name == config.microphone)
+            .unwrap();
+
+        SubMenu {
+            label: label_with_value("Microphone", &inputs[selected].0),
+            icon_name: "audio-input-microphone".into(),
+            enabled: !config.is_locked("microphone"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: inputs
+                        .iter()
+                        .map(|(label, _)| RadioItem {
+                            label: label.clone(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    select: Box::new(move |this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            config.microphone = inputs[index].1.clone();
+                            config.save().await;
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Mutes the recorder's own streams, so the buffer keeps going
+    fn mute_item(
+        &self,
+        audio: CapturedAudio,
+        config: &Config,
+        label: &str,
+        icon: &str,
+    ) -> MenuItem<Self> {
+        let (key, recorded) = match audio {
+            CapturedAudio::Desktop => (
+                "mute_desktop_audio",
+                config
+                    .audio_tracks
+                    .iter()
+                    .any(|track| !track.contains("input")),
+            ),
+            CapturedAudio::Microphone => ("mute_microphone", config.include_microphone),
+        };
+
+        CheckmarkItem {
+            label: label.into(),
+            icon_name: icon.into(),
+            checked: audio.is_muted(config),
+            enabled: recorded && !config.is_locked(key),
+            activate: Box::new(move |this: &mut Self| {
+                let muted = !audio.is_muted(&this.menu_config);
+                futures::executor::block_on(async {
+                    this.tray_event_tx
+                        .send(ActionEvent::SetAudioMuted(audio, muted))
+                        .await;
+                });
+            }),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Like the mute items, changing the volume doesn't restart the recorder, the volume of its
+    /// streams is changed instead
+    fn volume_menu(
+        &self,
+        audio: CapturedAudio,
+        config: &Config,
+        label: &str,
+        icon: &str,
+    ) -> MenuItem<Self> {
+        let key = match audio {
+            CapturedAudio::Desktop => "desktop_audio_volume",
+            CapturedAudio::Microphone => "microphone_volume",
+        };
+        let current = audio.volume(config);
+        let prompt = format!("{} in percent", label);
+
+        TrayConfigItem::Multiple::<TrayIcon, _> {
+            label: label.into(),
+            icon: icon.into(),
+            options: VOLUMES
+                .iter()
+                .map(|volume| TrayMultipleOption(format!("{}%", volume), *volume))
+                .collect(),
+            show_custom: true,
+            custom_value: format!("{}%", current),
+            enabled: !config.is_locked(key),
+            initial_state: VOLUMES
+                .iter()
+                .position(|volume| *volume == current)
+                .unwrap_or(VOLUMES.len()),
+            action: Box::new(move |this: &mut Self, selection| {
+                let volume = match VOLUMES.get(selection) {
+                    Some(volume) => *volume,
+                    None => match ask_custom_number("TrayPlay Settings", &prompt, current) {
+                        Ok(Some(volume)) if volume >= 0 => volume,
+                        Ok(_) => return,
+                        Err(err) => {
+                            error!("Error when asking for custom config value: {}", err);
+                            return;
+                        }
+                    },
+                };
+                futures::executor::block_on(async {
+                    this.tray_event_tx
+                        .send(ActionEvent::SetAudioVolume(audio, volume))
+                        .await;
+                });
+            }),
+        }
+        .into()
+    }
+
+    /// Server and stream key of "Start streaming"
+    fn streaming_menu(&self, config: &Config) -> MenuItem<Self> {
+        let enabled = !config.is_locked("streaming");
+        SubMenu {
+            label: "Streaming".into(),
+            icon_name: "network-wireless".into(),
+            submenu: vec![
+                tray_config_item_custom!(
+                    "Server URL…",
+                    "network-server",
+                    async move |config: Arc<RwLock<Config>>, _| {
+                        let url = config.read().await.streaming.url.clone();
+                        let result = InputBox::new(
+                            "RTMP server to stream to, e.g. rtmp://live.twitch.tv/app",
+                            InputBoxType::Text,
+                        )
+                        .title("TrayPlay Streaming")
+                        .initial(url)
+                        .show();
+                        match result {
+                            Ok(Some(url)) => {
+                                let mut config = config.write().await;
+                                config.streaming.url = url.trim().to_string();
+                                config.save().await;
+                            }
+                            Ok(None) => {}
+                            Err(err) => error!("Cannot ask for the streaming server: {}", err),
+                        }
+                    },
+                    enabled
+                )
+                .into(),
+                tray_config_item_custom!(
+                    if config.streaming.key.is_empty() {
+                        "Stream key…"
+                    } else {
+                        "Stream key (set)…"
+                    },
+                    "dialog-password",
+                    async move |config: Arc<RwLock<Config>>, _| {
+                        let result = InputBox::new(
+                            "Stream key from your streaming service, leave empty if the URL has it",
+                            InputBoxType::Password,
+                        )
+                        .title("TrayPlay Streaming")
+                        .show();
+                        match result {
+                            Ok(Some(key)) => {
+                                let mut config = config.write().await;
+                                config.streaming.key = key.trim().to_string();
+                                config.save().await;
+                            }
+                            Ok(None) => {}
+                            Err(err) => error!("Cannot ask for the stream key: {}", err),
+                        }
+                    },
+                    enabled
+                )
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Actions offered on the notification of a saved replay, in the order they were checked
+    fn notification_actions_menu(&self, config: &Config) -> MenuItem<Self> {
+        let full = config.notification_actions.len() >= MAX_NOTIFICATION_ACTIONS;
+        let submenu = ReplayAction::ALL
+            .into_iter()
+            .map(|action| {
+                let checked = config.notification_actions.contains(&action);
+                CheckmarkItem {
+                    label: action.label().into(),
+                    icon_name: action.icon().into(),
+                    checked,
+                    // Notifications have room for a few buttons only
+                    enabled: !config.is_locked("notification_actions") && (checked || !full),
+                    activate: Box::new(move |this: &mut Self| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            let actions = &mut config.notification_actions;
+                            match actions.iter().position(|picked| *picked == action) {
+                                Some(index) => {
+                                    actions.remove(index);
+                                }
+                                None => actions.push(action),
+                            }
+                            config.save().await;
+                        });
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect();
+
+        SubMenu {
+            label: format!(
+                "Notification actions ({}/{})",
+                config.notification_actions.len(),
+                MAX_NOTIFICATION_ACTIONS
+            ),
+            icon_name: "preferences-desktop-notification".into(),
+            submenu,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Applications playing right now are listed next to the ones already picked, so they can be
+    /// picked without knowing their name
+    fn app_audio_menu(&self, config: &Config) -> MenuItem<Self> {
+        let modes = [
+            ("All applications", AppAudioMode::All),
+            ("Only checked", AppAudioMode::Only),
+            ("All but checked", AppAudioMode::Except),
+        ];
+        let selected = modes
+            .iter()
+            .position(|(_, mode)| *mode == config.app_audio_mode)
+            .unwrap();
+        let mut apps = config.audio_apps.clone();
+        for app in &self.status.audio.playing_apps {
+            if !apps.iter().any(|picked| picked.eq_ignore_ascii_case(app)) {
+                apps.push(app.clone());
+            }
+        }
+
+        let mut submenu = vec![
+            RadioGroup {
+                selected,
+                options: modes
+                    .iter()
+                    .map(|(label, _)| RadioItem {
+                        label: label.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                select: Box::new(move |this: &mut Self, index| {
+                    futures::executor::block_on(async {
+                        let config = this.get_config();
+                        let mut config = config.write().await;
+                        config.app_audio_mode = modes[index].1;
+                        config.save().await;
+                    });
+                }),
+            }
+            .into(),
             MenuItem::Separator,
-            StandardItem {
-                label: "Quit".into(),
-                icon_name: "gtk-quit".into(),
-                activate: Box::new({
-                    let tx_clone = tx_clone.clone();
-                    move |_| {
+        ];
+        for app in apps {
+            submenu.push(
+                CheckmarkItem {
+                    label: app.clone(),
+                    checked: config.audio_apps.contains(&app),
+                    enabled: config.app_audio_mode != AppAudioMode::All,
+                    activate: Box::new(move |this: &mut Self| {
                         futures::executor::block_on(async {
-                            tx_clone.send(ActionEvent::Quit).await.unwrap();
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            match config.audio_apps.iter().position(|picked| *picked == app) {
+                                Some(index) => {
+                                    config.audio_apps.remove(index);
+                                }
+                                None => config.audio_apps.push(app.clone()),
+                            }
+                            config.save().await;
                         });
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        submenu.push(
+            tray_config_item_custom!(
+                "Edit names…",
+                "document-edit",
+                async move |config: Arc<RwLock<Config>>, _| {
+                    let initial = config.read().await.audio_apps.join(", ");
+                    let names = ask_custom_value(
+                        "TrayPlay Settings",
+                        "Application names, separated by commas (* matches any text)",
+                        initial,
+                        |input| {
+                            Ok::<_, String>(
+                                input
+                                    .split(',')
+                                    .map(str::trim)
+                                    .filter(|name| !name.is_empty())
+                                    .map(str::to_string)
+                                    .collect::<Vec<_>>(),
+                            )
+                        },
+                    );
+                    match names {
+                        Ok(Some(names)) => {
+                            let mut config = config.write().await;
+                            config.audio_apps = names;
+                            config.save().await;
+                        }
+                        Ok(None) => {}
+                        Err(err) => error!("Error when asking for application names: {}", err),
                     }
-                }),
+                }
+            )
+            .into(),
+        );
+
+        SubMenu {
+            label: label_with_value("Applications", modes[selected].0),
+            icon_name: "applications-multimedia".into(),
+            enabled: !config.is_locked("app_audio_mode")
+                && config.recorder_backend == RecorderBackend::GpuScreenRecorder,
+            submenu,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Encoders the recorder can't use on this system are greyed out
+    fn encoder_menu(&self, config: &Config) -> MenuItem<Self> {
+        let supported = recorder::supported_encoders(config.recorder_backend);
+        let encoders: &'static [(&str, Encoder)] =
+            if config.recorder_backend == RecorderBackend::GpuScreenRecorder {
+                &GSR_ENCODERS
+            } else {
+                &ENCODERS
+            };
+        // A hardware API picked for wf-recorder means the GPU to gpu-screen-recorder
+        let selected = encoders
+            .iter()
+            .position(|(_, encoder)| *encoder == config.encoder)
+            .unwrap_or(0);
+
+        SubMenu {
+            label: label_with_value("Encoder", encoders[selected].0),
+            icon_name: "cpu".into(),
+            enabled: !config.is_locked("encoder"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: encoders
+                        .iter()
+                        .map(|(label, encoder)| RadioItem {
+                            label: label.to_string(),
+                            enabled: supported.contains(encoder),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    select: Box::new(move |this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            config.encoder = encoders[index].1;
+                            config.save().await;
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// "Native" keeps the captured resolution, "Custom…" asks for any WxH
+    fn scale_resolution_menu(&self, config: &Config) -> MenuItem<Self> {
+        let (selected, value) = match &config.scale_resolution {
+            None => (0, "Native".to_string()),
+            Some(resolution) => match SCALE_RESOLUTIONS
+                .iter()
+                .position(|(_, preset)| preset == resolution)
+            {
+                Some(index) => (index + 1, SCALE_RESOLUTIONS[index].0.to_string()),
+                None => (SCALE_RESOLUTIONS.len() + 1, resolution.clone()),
+            },
+        };
+
+        SubMenu {
+            label: label_with_value("Resolution", &value),
+            icon_name: "zoom-fit-best".into(),
+            enabled: !config.is_locked("scale_resolution"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options: once("Native")
+                        .chain(SCALE_RESOLUTIONS.iter().map(|(label, _)| *label))
+                        .chain(once("Custom…"))
+                        .map(|label| RadioItem {
+                            label: label.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    select: Box::new(|this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            let resolution =
+                                match index {
+                                    0 => Ok(Some(None)),
+                                    index if index <= SCALE_RESOLUTIONS.len() => {
+                                        Ok(Some(Some(SCALE_RESOLUTIONS[index - 1].1.to_string())))
+                                    }
+                                    _ => ask_custom_value(
+                                        "TrayPlay Settings",
+                                        "Largest resolution of replays, like 1920x1080",
+                                        config.scale_resolution.clone().unwrap_or_default(),
+                                        |input| match parse_resolution(input) {
+                                            Some((width, height)) => {
+                                                Ok(Some(format!("{}x{}", width, height)))
+                                            }
+                                            None => Err("The resolution must look like 1920x1080."
+                                                .to_string()),
+                                        },
+                                    ),
+                                };
+                            match resolution {
+                                Ok(Some(resolution)) => {
+                                    config.scale_resolution = resolution;
+                                    config.save().await;
+                                }
+                                Ok(None) => {}
+                                Err(err) => {
+                                    error!("Error when asking for custom config value: {}", err);
+                                }
+                            }
+                        });
+                    }),
+                }
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Screen, focused or a picked window, followed by the connected monitors. Other targets are
+    /// set in the config file.
+    fn capture_menu(&self, config: &Config) -> MenuItem<Self> {
+        let current = config.capture_target();
+        // None is the window picker
+        let mut targets: Vec<(String, Option<CaptureTarget>)> = vec![
+            ("Screen".into(), Some(CaptureTarget::Screen)),
+            ("Focused window".into(), Some(CaptureTarget::Focused)),
+            ("Specific window…".into(), None),
+        ];
+        // Listed windows keep their app icon
+        let mut icons = vec![String::new(); targets.len()];
+        for window in picker::detect(config).windows() {
+            targets.push((format!("Window: {}", window.title), Some(window.target)));
+            icons.push(window.icon_name);
+        }
+        for monitor in monitors::connected_monitors() {
+            let label = match &monitor.serial {
+                Some(serial) => format!("Monitor {} ({})", monitor.connector, serial),
+                None => format!("Monitor {}", monitor.connector),
+            };
+            targets.push((label, Some(CaptureTarget::Monitor(monitor.connector))));
+        }
+        // A monitor that is unplugged right now stays listed while it's selected
+        if let CaptureTarget::Monitor(connector) = &current
+            && !targets
+                .iter()
+                .any(|(_, target)| target.as_ref() == Some(&current))
+        {
+            targets.push((
+                format!("Monitor {} (not connected)", connector),
+                Some(current.clone()),
+            ));
+        }
+
+        if current == CaptureTarget::Region {
+            targets.push((
+                format!("Region {}", config.region.as_deref().unwrap_or_default()),
+                Some(CaptureTarget::Region),
+            ));
+        }
+
+        let selected = targets
+            .iter()
+            .position(|(_, target)| target.as_ref() == Some(&current))
+            .unwrap_or(match current {
+                CaptureTarget::Window(_) | CaptureTarget::Portal => 2,
+                _ => targets.len(),
+            });
+        let options = targets
+            .iter()
+            .enumerate()
+            .map(|(index, (label, target))| RadioItem {
+                label: label.clone(),
+                icon_name: icons.get(index).cloned().unwrap_or_default(),
+                enabled: match target {
+                    Some(CaptureTarget::Monitor(connector)) => {
+                        !monitors::is_excluded(config, connector)
+                    }
+                    // Targets only some sessions have, e.g. focused on X11
+                    Some(target) => {
+                        config.recorder_backend != RecorderBackend::GpuScreenRecorder
+                            || gsr_info::can_capture(&target.to_string())
+                    }
+                    None => true,
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        SubMenu {
+            label: label_with_value(
+                "Capture",
+                &match targets.get(selected) {
+                    Some((label, Some(_))) => label.clone(),
+                    _ => current.to_string(),
+                },
+            ),
+            icon_name: "video-display".into(),
+            enabled: !config.is_locked("screen"),
+            submenu: vec![
+                RadioGroup {
+                    selected,
+                    options,
+                    select: Box::new(move |this: &mut Self, index| {
+                        futures::executor::block_on(async {
+                            let config = this.get_config();
+                            let mut config = config.write().await;
+                            let target = match &targets[index] {
+                                (_, Some(target)) => Ok(Some(target.clone())),
+                                (_, None) => picker::detect(&config).pick_window(),
+                            };
+                            match target {
+                                Ok(Some(target)) => {
+                                    if target != config.capture_target() {
+                                        config.set_capture_target(target);
+                                        config.save().await;
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(err) => {
+                                    error!("Cannot change the capture target: {}", err);
+                                }
+                            }
+                        });
+                    }),
+                }
+                .into(),
+                MenuItem::Separator,
+                tray_config_item_custom!(
+                    "Select region…",
+                    "select-rectangular",
+                    async move |config: Arc<RwLock<Config>>, _| {
+                        let picker = picker::detect(&*config.read().await);
+                        match picker.select_region() {
+                            Ok(Some(region)) => {
+                                let mut config = config.write().await;
+                                config.set_capture_target(CaptureTarget::Region);
+                                config.region = Some(region);
+                                config.save().await;
+                            }
+                            Ok(None) => {}
+                            Err(err) => error!("Cannot select a region: {}", err),
+                        }
+                    }
+                )
+                .into(),
+            ],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Turning HDR on first checks it can be recorded, and explains why not otherwise
+    fn hdr_item(&self, config: &Config) -> MenuItem<Self> {
+        TrayConfigItem::Toggle::<TrayIcon, u8> {
+            label: "HDR".into(),
+            icon: "color-management".into(),
+            checked: config.hdr,
+            enabled: !config.is_locked("hdr"),
+            action: Box::new(|item| {
+                futures::executor::block_on(async {
+                    let config = item.get_config();
+                    let mut config = config.write().await;
+                    if !config.hdr
+                        && let Err(reason) = gsr::hdr_support(&config)
+                    {
+                        drop(config);
+                        warn!("HDR cannot be recorded: {}", reason);
+                        return;
+                    }
+                    config.hdr = !config.hdr;
+                    config.save().await;
+                });
+            }),
+        }
+        .into()
+    }
+
+    fn logs_item(&self) -> MenuItem<Self> {
+        tray_config_item_custom!(
+            "View logs…",
+            "text-x-log",
+            async move |_, action_event_tx: ActionSender| {
+                let logs = log_history();
+                let result = InputBox::new("Recent log messages", InputBoxType::TextArea)
+                    .title("TrayPlay Logs")
+                    .initial(if logs.is_empty() {
+                        "Nothing has been logged yet".into()
+                    } else {
+                        logs
+                    })
+                    .ok_label("Copy to clipboard")
+                    .cancel_label("Close")
+                    .size(800, 600)
+                    .show();
+
+                match result {
+                    Ok(Some(logs)) => {
+                        action_event_tx
+                            .send(ActionEvent::CopyToClipboard(logs))
+                            .await
+                    }
+                    Ok(None) => {}
+                    Err(err) => error!("Cannot show logs: {}", err),
+                }
+            }
+        )
+        .into()
+    }
+
+    /// Any setting by its config key, with a preview of what it does to the recorder
+    fn change_setting_item(&self) -> MenuItem<Self> {
+        tray_config_item_custom!(
+            "Change setting…",
+            "document-properties",
+            async move |config: Arc<RwLock<Config>>, _| {
+                let current = config.read().await.clone();
+                let key = ask_custom_string(
+                    "Change setting",
+                    "Config key, dotted for nested ones (e.g. webcam.enabled)",
+                    "",
+                );
+                let key = match key {
+                    Ok(Some(key)) if !key.trim().is_empty() => key.trim().to_string(),
+                    Ok(_) => return,
+                    Err(err) => {
+                        error!("Error when asking for a setting: {}", err);
+                        return;
+                    }
+                };
+                let changed = ask_custom_value(
+                    "Change setting",
+                    &format!("New value of {}, as in the config file", key),
+                    "",
+                    |value| current.with_value(&key, value),
+                );
+                let changed = match changed {
+                    Ok(Some(changed)) => changed,
+                    Ok(None) => return,
+                    Err(err) => {
+                        error!("Error when asking for a value: {}", err);
+                        return;
+                    }
+                };
+
+                let preview = config_preview::preview(&current, &changed);
+                if let Some(problem) = &preview.problem {
+                    warn!("{} cannot be changed like that: {}", key, problem);
+                    return;
+                }
+                let answer = MessageBox::new(format!("{}\n\nApply the change?", preview))
+                    .title("Change setting")
+                    .buttons(MessageBoxButtons::YesNo)
+                    .show();
+                match answer {
+                    Ok(ClickedButton::Yes) => {
+                        let mut config = config.write().await;
+                        *config = changed;
+                        config.save().await;
+                    }
+                    Ok(_) => {}
+                    Err(err) => error!("Cannot ask to apply the change: {}", err),
+                }
+            }
+        )
+        .into()
+    }
+
+    fn restore_settings_item(&self) -> MenuItem<Self> {
+        tray_config_item_custom!(
+            "Restore previous settings…",
+            "document-revert",
+            async move |_, action_event_tx: ActionSender| {
+                let backups = config_backups();
+                if backups.is_empty() {
+                    if let Err(err) =
+                        MessageBox::new("There are no previous settings to restore yet")
+                            .title("Restore previous settings")
+                            .show()
+                    {
+                        error!("Cannot show previous settings: {}", err);
+                    }
+                    return;
+                }
+
+                let labels: Vec<String> = backups
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (_, replaced))| {
+                        format!(
+                            "{}. replaced {} ({} ago)",
+                            index + 1,
+                            format_date_time(*replaced),
+                            format_duration(replaced.elapsed().unwrap_or_default())
+                        )
+                    })
+                    .collect();
+
+                let result = ComboBox::new("Settings to restore", labels.clone())
+                    .title("Restore previous settings")
+                    .default(labels[0].clone())
+                    .show();
+
+                match result {
+                    Ok(Some(label)) => {
+                        let Some(index) = labels.iter().position(|l| *l == label) else {
+                            return;
+                        };
+                        action_event_tx
+                            .send(ActionEvent::RestoreConfigBackup(backups[index].0.clone()))
+                            .await;
+                    }
+                    Ok(None) => {}
+                    Err(err) => error!("Cannot show previous settings: {}", err),
+                }
+            }
+        )
+        .into()
+    }
+
+    /// Queued clips, opening one takes it off the queue
+    fn watch_later_menu(&self) -> Option<MenuItem<Self>> {
+        if self.status.watch_later.is_empty() {
+            return None;
+        }
+
+        let mut submenu: Vec<MenuItem<Self>> = self
+            .status
+            .watch_later
+            .iter()
+            .map(|clip| {
+                self.action_item(
+                    &clip.file_name().unwrap_or_default().to_string_lossy(),
+                    ReplayAction::Open.icon(),
+                    ActionEvent::WatchQueuedClip(clip.clone()),
+                )
+            })
+            .collect();
+        submenu.push(MenuItem::Separator);
+        submenu.push(self.action_item("Clear", "edit-clear-all", ActionEvent::ClearWatchLater));
+
+        Some(
+            SubMenu {
+                label: format!("Watch later ({})", self.status.watch_later.len()),
+                icon_name: ReplayAction::WatchLater.icon().into(),
+                submenu,
                 ..Default::default()
             }
             .into(),
-        ]
+        )
+    }
+
+    /// Section with the state and actions of every extra recorder
+    fn recorders_menu(&self) -> Option<MenuItem<Self>> {
+        if self.status.recorders.is_empty() {
+            return None;
+        }
+
+        let sections = self
+            .status
+            .recorders
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (name, mode, state))| {
+                let mut section = Vec::new();
+                if index > 0 {
+                    section.push(MenuItem::Separator);
+                }
+                section.push(
+                    StandardItem {
+                        label: format!("{}: {}", name, state),
+                        enabled: false,
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+                if *state == RecorderState::Running {
+                    section.push(self.action_item(
+                        "Stop",
+                        "media-playback-stop",
+                        ActionEvent::ToggleRecorder(name.clone()),
+                    ));
+                    if *mode == RecorderMode::Replay {
+                        section.push(self.action_item(
+                            "Save replay",
+                            "document-save",
+                            ActionEvent::SaveRecorderReplay(name.clone()),
+                        ));
+                    }
+                } else {
+                    section.push(self.action_item(
+                        "Start",
+                        "media-record",
+                        ActionEvent::ToggleRecorder(name.clone()),
+                    ));
+                }
+                section
+            })
+            .collect();
+
+        Some(
+            SubMenu {
+                label: "Recorders".into(),
+                icon_name: "media-record".into(),
+                submenu: sections,
+                ..Default::default()
+            }
+            .into(),
+        )
+    }
+
+    fn settings_menu(&self, config: &Config) -> Vec<MenuItem<Self>> {
+        self.settings_schema(config)
+            .into_iter()
+            .map(|group| group.into())
+            .chain([MenuItem::Separator, self.change_setting_item()])
+            .collect()
+    }
+
+    fn profile_menu(&self, config: &Config) -> Option<MenuItem<Self>> {
+        if config.profiles.is_empty() {
+            return None;
+        }
+
+        let names: Vec<String> = config.profiles.iter().map(|p| p.name.clone()).collect();
+        let tx_clone = self.tray_event_tx.clone();
+
+        Some(
+            SubMenu {
+                label: label_with_value(
+                    "Profile",
+                    config.active_profile.as_deref().unwrap_or("None"),
+                ),
+                icon_name: "user-identity".into(),
+                submenu: vec![
+                    RadioGroup {
+                        selected: config
+                            .active_profile
+                            .as_ref()
+                            .and_then(|active| names.iter().position(|name| name == active))
+                            .map(|index| index + 1)
+                            .unwrap_or(0),
+                        options: once(RadioItem {
+                            label: "None".into(),
+                            ..Default::default()
+                        })
+                        .chain(names.iter().map(|name| RadioItem {
+                            label: name.clone(),
+                            ..Default::default()
+                        }))
+                        .collect(),
+                        select: Box::new(move |_, selection| {
+                            let name = selection.checked_sub(1).map(|index| names[index].clone());
+                            futures::executor::block_on(async {
+                                tx_clone.send(ActionEvent::SwitchProfile(name)).await;
+                            });
+                        }),
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into(),
+        )
     }
 }
 
@@ -358,12 +2442,12 @@ impl CommunicationProvider for TrayIcon {
         self.config.clone()
     }
 
-    fn get_action_event_tx(&self) -> Sender<ActionEvent> {
+    fn get_action_event_tx(&self) -> ActionSender {
         self.tray_event_tx.clone()
     }
 }
 
 trait CommunicationProvider {
     fn get_config(&self) -> Arc<RwLock<Config>>;
-    fn get_action_event_tx(&self) -> Sender<ActionEvent>;
+    fn get_action_event_tx(&self) -> ActionSender;
 }
@@ -0,0 +1,166 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use futures_util::StreamExt;
+use log::{info, warn};
+use tokio::{
+    sync::{mpsc::Sender, watch},
+    task::JoinHandle,
+};
+use zbus::{Connection, MatchRule, MessageStream, message::Type};
+
+use crate::{
+    ActionEvent,
+    active_window::ActiveWindow,
+    config::{Bus, SaveSource, Trigger},
+};
+
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Runs the save triggers from config, each in its own task
+pub struct TriggerManager {
+    triggers: Vec<Trigger>,
+    tasks: Vec<JoinHandle<()>>,
+    action_tx: Sender<ActionEvent>,
+    active_window: watch::Receiver<ActiveWindow>,
+}
+
+impl TriggerManager {
+    pub fn new(
+        action_tx: Sender<ActionEvent>,
+        active_window: watch::Receiver<ActiveWindow>,
+    ) -> Self {
+        Self {
+            triggers: Vec::new(),
+            tasks: Vec::new(),
+            action_tx,
+            active_window,
+        }
+    }
+
+    /// Restarts the triggers if they changed in config
+    pub fn apply(&mut self, triggers: &[Trigger]) {
+        if self.triggers == triggers {
+            return;
+        }
+
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+
+        self.triggers = triggers.to_vec();
+        for (index, trigger) in triggers.iter().enumerate() {
+            let fire = Fire {
+                index,
+                trigger: trigger.clone(),
+                action_tx: self.action_tx.clone(),
+            };
+            self.tasks.push(match trigger.clone() {
+                Trigger::FileModified { path } => tokio::spawn(watch_file(path, fire)),
+                Trigger::DbusSignal {
+                    bus,
+                    interface,
+                    member,
+                    path,
+                } => tokio::spawn(async move {
+                    if let Err(err) = watch_signal(bus, interface, member, path, fire).await {
+                        warn!("Cannot watch D-Bus signal for save trigger: {}", err);
+                    }
+                }),
+                Trigger::WindowTitle { contains } => tokio::spawn(watch_window_title(
+                    contains,
+                    self.active_window.clone(),
+                    fire,
+                )),
+            });
+        }
+    }
+}
+
+/// Sends SaveReplay for a trigger. How often each trigger can save is limited by
+/// `save_cooldowns.trigger_secs`.
+struct Fire {
+    index: usize,
+    trigger: Trigger,
+    action_tx: Sender<ActionEvent>,
+}
+
+impl Fire {
+    /// Returns false once the main loop is gone, the trigger should stop watching then
+    async fn fire(&mut self) -> bool {
+        info!("Save trigger fired: {:?}", self.trigger);
+        self.action_tx
+            .send(ActionEvent::SaveReplay(SaveSource::Trigger(self.index)))
+            .await
+            .is_ok()
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+async fn watch_file(path: PathBuf, mut fire: Fire) {
+    let mut last_modified = modified_time(&path);
+
+    loop {
+        tokio::time::sleep(FILE_POLL_INTERVAL).await;
+
+        let modified = modified_time(&path);
+        if modified.is_some() && modified != last_modified && !fire.fire().await {
+            return;
+        }
+        last_modified = modified;
+    }
+}
+
+async fn watch_signal(
+    bus: Bus,
+    interface: String,
+    member: String,
+    path: Option<String>,
+    mut fire: Fire,
+) -> Result<(), zbus::Error> {
+    let connection = match bus {
+        Bus::Session => Connection::session().await?,
+        Bus::System => Connection::system().await?,
+    };
+
+    let mut rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface(interface.as_str())?
+        .member(member.as_str())?;
+    if let Some(path) = &path {
+        rule = rule.path(path.as_str())?;
+    }
+
+    let mut stream = MessageStream::for_match_rule(rule.build(), &connection, None).await?;
+    while stream.next().await.is_some() {
+        if !fire.fire().await {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+async fn watch_window_title(
+    contains: String,
+    mut active_window: watch::Receiver<ActiveWindow>,
+    mut fire: Fire,
+) {
+    let contains = contains.to_lowercase();
+
+    while active_window.changed().await.is_ok() {
+        let matches = active_window
+            .borrow_and_update()
+            .title
+            .to_lowercase()
+            .contains(&contains);
+        if matches && !fire.fire().await {
+            return;
+        }
+    }
+}
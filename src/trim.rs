@@ -0,0 +1,243 @@
+use std::{ops::Range, path::Path};
+
+use log::{info, warn};
+use tokio::process::Command;
+
+/// Shortest stretch of black or silence that counts, shorter ones are cuts and pauses
+const MIN_DEAD_SECS: f64 = 1.0;
+/// Kept around what's left so it doesn't start or end abruptly
+const MARGIN_SECS: f64 = 0.5;
+/// Less than this is left alone, it's not worth rewriting the clip for
+const MIN_TRIM_SECS: f64 = 1.5;
+/// A clip that would get shorter than this is most likely all menus or detection went wrong
+const MIN_KEPT_SECS: f64 = 3.0;
+/// How close to the start or end a stretch has to reach to be at the edge
+const EDGE_TOLERANCE_SECS: f64 = 0.2;
+
+/// What ffmpeg's blackdetect and silencedetect filters reported for a clip
+#[derive(Debug, Default)]
+struct Detection {
+    duration: Option<f64>,
+    has_audio: bool,
+    black: Vec<Range<f64>>,
+    silence: Vec<Range<f64>>,
+}
+
+/// Part of `path` worth keeping, without the black and silent stretches (menus, loading
+/// screens) at its start and end. `None` when there's nothing to trim or detection isn't sure.
+pub async fn content_range(path: &Path) -> Option<Range<f64>> {
+    let detection = match detect(path).await {
+        Ok(detection) => detection,
+        Err(err) => {
+            warn!("Cannot look for black frames and silence: {}", err);
+            return None;
+        }
+    };
+    kept_range(&detection, path)
+}
+
+/// [`content_range`] of a clip ffmpeg looked at
+fn kept_range(detection: &Detection, path: &Path) -> Option<Range<f64>> {
+    let duration = detection.duration?;
+
+    // Dead when the picture is black and, in clips with sound, nothing can be heard either
+    let leading = |ranges: &[Range<f64>]| {
+        ranges
+            .iter()
+            .find(|range| range.start <= EDGE_TOLERANCE_SECS)
+            .map_or(0.0, |range| range.end)
+    };
+    let trailing = |ranges: &[Range<f64>]| {
+        ranges
+            .iter()
+            .find(|range| range.end >= duration - EDGE_TOLERANCE_SECS)
+            .map_or(duration, |range| range.start)
+    };
+    let (mut start, mut end) = (leading(&detection.black), trailing(&detection.black));
+    if detection.has_audio {
+        start = start.min(leading(&detection.silence));
+        end = end.max(trailing(&detection.silence));
+    }
+
+    let start = if start > 0.0 {
+        (start - MARGIN_SECS).max(0.0)
+    } else {
+        0.0
+    };
+    let end = if end < duration {
+        (end + MARGIN_SECS).min(duration)
+    } else {
+        duration
+    };
+
+    if end - start < MIN_KEPT_SECS {
+        info!(
+            "{} looks black or silent almost all the way through, keeping it as it is",
+            path.display()
+        );
+        return None;
+    }
+    (start + (duration - end) >= MIN_TRIM_SECS).then_some(start..end)
+}
+
+async fn detect(path: &Path) -> Result<Detection, String> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-map", "0:v:0", "-map", "0:a:0?"])
+        .args([
+            "-vf",
+            &format!("blackdetect=d={}:pix_th=0.05", MIN_DEAD_SECS),
+        ])
+        .args(["-af", &format!("silencedetect=n=-60dB:d={}", MIN_DEAD_SECS)])
+        .args(["-f", "null", "-"])
+        .output()
+        .await
+        .map_err(|err| format!("cannot run ffmpeg: {}", err))?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg exited with {}", output.status));
+    }
+
+    Ok(parse_detection(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Reads ffmpeg's log, e.g.
+/// `  Duration: 00:00:30.03, start: 0.000000, bitrate: 9000 kb/s`
+/// `[blackdetect @ 0x5583] black_start:0 black_end:2.5 black_duration:2.5`
+/// `[silencedetect @ 0x5583] silence_start: 27.1`
+/// `[silencedetect @ 0x5583] silence_end: 30.03 | silence_duration: 2.93`
+fn parse_detection(log: &str) -> Detection {
+    let mut detection = Detection::default();
+    let mut silence_start = None;
+
+    for line in log.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Duration: ") {
+            detection.duration = rest.split(',').next().and_then(parse_timestamp);
+        } else if line.starts_with("Stream #0") && line.contains(": Audio: ") {
+            detection.has_audio = true;
+        } else if line.contains("black_start:") {
+            let value = |key: &str| {
+                line.split_whitespace()
+                    .find_map(|field| field.strip_prefix(key))
+                    .and_then(|value| value.parse::<f64>().ok())
+            };
+            if let (Some(start), Some(end)) = (value("black_start:"), value("black_end:")) {
+                detection.black.push(start..end);
+            }
+        } else if let Some((_, value)) = line.split_once("silence_start: ") {
+            silence_start = value.trim().parse::<f64>().ok();
+        } else if let Some((_, value)) = line.split_once("silence_end: ") {
+            let end = value
+                .split_whitespace()
+                .next()
+                .and_then(|value| value.parse::<f64>().ok());
+            if let (Some(start), Some(end)) = (silence_start.take(), end) {
+                detection.silence.push(start.max(0.0)..end);
+            }
+        }
+    }
+
+    // Older ffmpeg versions don't end silence that lasts until the end of the file
+    if let (Some(start), Some(duration)) = (silence_start, detection.duration) {
+        detection.silence.push(start.max(0.0)..duration);
+    }
+
+    detection
+}
+
+/// `00:00:30.03` -> 30.03
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    timestamp.trim().split(':').try_fold(0.0, |total, part| {
+        Some(total * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+Input #0, matroska,webm, from 'clip.mkv':
+  Duration: 00:00:30.00, start: 0.000000, bitrate: 9000 kb/s
+  Stream #0:0: Video: h264 (High), yuv420p(tv, bt709, progressive), 1920x1080, 60 fps
+  Stream #0:1(eng): Audio: opus, 48000 Hz, stereo, fltp
+Output #0, null, to 'pipe:':
+  Stream #0:0: Video: wrapped_avframe, yuv420p, 1920x1080, 60 fps
+  Stream #0:1(eng): Audio: pcm_s16le, 48000 Hz, stereo, s16, 1536 kb/s
+[blackdetect @ 0x55d0c8f0a2c0] black_start:0 black_end:4 black_duration:4
+[silencedetect @ 0x55d0c8f0b100] silence_start: 0
+[silencedetect @ 0x55d0c8f0b100] silence_end: 3.5 | silence_duration: 3.5
+[blackdetect @ 0x55d0c8f0a2c0] black_start:27 black_end:30 black_duration:3
+[silencedetect @ 0x55d0c8f0b100] silence_start: 26
+";
+
+    fn clip() -> &'static Path {
+        Path::new("clip.mkv")
+    }
+
+    #[test]
+    fn parses_ffmpeg_log() {
+        let detection = parse_detection(LOG);
+
+        assert_eq!(detection.duration, Some(30.0));
+        assert!(detection.has_audio);
+        assert_eq!(detection.black, [0.0..4.0, 27.0..30.0]);
+        // Silence that never ended, as older ffmpeg versions report it, lasts until the end
+        assert_eq!(detection.silence, [0.0..3.5, 26.0..30.0]);
+    }
+
+    #[test]
+    fn trims_what_is_black_and_silent() {
+        let detection = parse_detection(LOG);
+
+        // Sound starts before the picture and ends after it, a margin is kept around both
+        assert_eq!(kept_range(&detection, clip()), Some(3.0..27.5));
+    }
+
+    #[test]
+    fn silence_does_not_matter_without_audio() {
+        let detection = parse_detection(&LOG.replace(": Audio: ", ": Data: "));
+
+        assert!(!detection.has_audio);
+        assert_eq!(kept_range(&detection, clip()), Some(3.5..27.5));
+    }
+
+    #[test]
+    fn leaves_short_stretches_alone() {
+        let detection = Detection {
+            duration: Some(30.0),
+            black: vec![0.0..1.5],
+            ..Default::default()
+        };
+
+        assert_eq!(kept_range(&detection, clip()), None);
+    }
+
+    #[test]
+    fn keeps_clips_that_are_dead_almost_all_the_way_through() {
+        let detection = Detection {
+            duration: Some(10.0),
+            black: vec![0.0..8.0],
+            ..Default::default()
+        };
+
+        assert_eq!(kept_range(&detection, clip()), None);
+    }
+
+    #[test]
+    fn needs_the_duration() {
+        let detection = parse_detection(&LOG.replace("Duration: ", "Length: "));
+
+        assert_eq!(detection.duration, None);
+        assert!(detection.silence.iter().all(|range| range.end < 30.0));
+        assert_eq!(kept_range(&detection, clip()), None);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("00:00:30.03"), Some(30.03));
+        assert_eq!(parse_timestamp("01:02:03.5"), Some(3723.5));
+        assert_eq!(parse_timestamp("N/A"), None);
+    }
+}
@@ -1,8 +1,16 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use ashpd::desktop::file_chooser::OpenFileRequest;
 
-use crate::kdialog::{self, InfoBox, InputBox};
+use crate::{
+    format::{decimal_separator, format_duration},
+    kdialog::{self, InfoBox, InputBox},
+};
 
 pub fn get_app_name(desktop_file: &str) -> Result<Option<String>, std::io::Error> {
     let user_applications_path = format!("{}/applications/", dirs::data_dir().unwrap().display());
@@ -48,32 +56,214 @@ pub fn get_script_path() -> Option<PathBuf> {
     })
 }
 
+/// Connected webcams as name and V4L2 device. Each webcam also has metadata nodes, only the
+/// first node of a device captures video.
+pub fn webcam_devices() -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/video4linux") else {
+        return Vec::new();
+    };
+
+    let mut devices: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            std::fs::read_to_string(entry.path().join("index"))
+                .is_ok_and(|index| index.trim() == "0")
+        })
+        .map(|entry| {
+            let device = Path::new("/dev").join(entry.file_name());
+            let name = std::fs::read_to_string(entry.path().join("name"))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| device.display().to_string());
+            (name, device)
+        })
+        .collect();
+    devices.sort_by(|a, b| a.1.cmp(&b.1));
+    devices
+}
+
+/// Asks for a value until `parse` accepts it. `parse` returns the message shown on invalid input.
+pub fn ask_custom_value<T>(
+    title: &str,
+    label: &str,
+    initial: impl Into<String>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, Box<dyn std::error::Error>> {
+    let mut initial = initial.into();
+
+    loop {
+        let result = InputBox::new(label, kdialog::InputBoxType::Text)
+            .initial(&initial)
+            .title(title)
+            .show()?;
+
+        let Some(result) = result else {
+            return Ok(None);
+        };
+
+        let result = result.replace("\n", "");
+        match parse(result.trim()) {
+            Ok(value) => return Ok(Some(value)),
+            Err(message) => {
+                InfoBox::warning(message).title("Wrong input").show()?;
+                initial = result;
+            }
+        }
+    }
+}
+
 pub fn ask_custom_number(
     title: &str,
     label: &str,
     initial: impl Into<i64>,
 ) -> Result<Option<i64>, Box<dyn std::error::Error>> {
-    let initial = initial.into();
+    ask_custom_value(title, label, initial.into().to_string(), parse_integer)
+}
+
+/// Parses a number written with `decimal` as decimal separator, e.g. "1.5", "1,5" or "1 500".
+/// Numbers that could mean two things, like "1,500" where the decimal separator is a comma, are
+/// rejected with a message telling how to write them.
+fn parse_number(input: &str, decimal: char) -> Result<f64, String> {
+    normalize_number(input, decimal)?
+        .parse()
+        .map_err(|_| number_hint("You need to input a number"))
+}
 
-    let result = InputBox::new(label, kdialog::InputBoxType::Text)
-        .initial(initial.to_string())
-        .title(title)
-        .show()?;
+/// Like [`parse_number`] in the user's locale, for whole numbers. Separators between groups of
+/// thousands are fine.
+pub fn parse_integer(input: &str) -> Result<i64, String> {
+    normalize_number(input, decimal_separator())?
+        .parse()
+        .map_err(|_| "You need to input a whole number.".to_string())
+}
 
-    if let Some(result) = result {
-        let number = result.replace("\n", "").parse::<i64>();
-        if let Ok(number) = number {
-            Ok(Some(number))
-        } else {
-            InfoBox::warning("You need to input an integer.")
-                .title("Wrong input")
-                .show()?;
+/// `message` with an example of a decimal number in the user's locale
+fn number_hint(message: &str) -> String {
+    format!("{}, e.g. 1{}5.", message, decimal_separator())
+}
+
+/// Turns a number written with `decimal` as decimal separator into one Rust can parse. Dots,
+/// commas, spaces and apostrophes are all used to group thousands somewhere, so the one that
+/// isn't the decimal separator is only taken as grouping when it separates groups of three.
+fn normalize_number(input: &str, decimal: char) -> Result<String, String> {
+    let grouping = if decimal == ',' { '.' } else { ',' };
+    let input: String = input
+        .chars()
+        // Spaces, including the non-breaking ones French and Polish group with, and Swiss '
+        .filter(|char| !char.is_whitespace() && !matches!(char, '\u{a0}' | '\u{202f}' | '\''))
+        .collect();
+    let (sign, digits) = match input.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", input.as_str()),
+    };
+
+    let (whole, fraction) = match digits.split_once(decimal) {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (digits, None),
+    };
+    if fraction.is_some_and(|fraction| fraction.contains([decimal, grouping])) {
+        return Err(number_hint(&format!(
+            "\"{}\" is not a number, use \"{}\" as decimal separator",
+            input, decimal
+        )));
+    }
+
+    let groups: Vec<&str> = whole.split(grouping).collect();
+    let grouped_by_thousands = groups.len() == 1
+        || (!groups[0].is_empty()
+            && groups[0].len() <= 3
+            && groups[1..].iter().all(|group| group.len() == 3));
+    if !grouped_by_thousands {
+        return Err(number_hint(&format!(
+            "\"{}\" is ambiguous, use \"{}\" as decimal separator",
+            input, decimal
+        )));
+    }
+
+    let whole = groups.concat();
+    let all_digits = |text: &str| text.chars().all(|char| char.is_ascii_digit());
+    if whole.is_empty() || !all_digits(&whole) || !fraction.is_none_or(all_digits) {
+        return Err(number_hint("You need to input a number"));
+    }
+
+    Ok(match fraction {
+        Some(fraction) if !fraction.is_empty() => format!("{}{}.{}", sign, whole, fraction),
+        _ => format!("{}{}", sign, whole),
+    })
+}
 
-            ask_custom_number(title, label, initial)
+pub fn ask_custom_string(
+    title: &str,
+    label: &str,
+    initial: impl Into<String>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    ask_custom_value(title, label, initial, |input| {
+        if input.is_empty() {
+            Err("The value cannot be empty.".to_string())
+        } else {
+            Ok(input.to_string())
         }
-    } else {
-        Ok(None)
+    })
+}
+
+/// Asks for a duration like "90", "1m30s" or "1h". Returns seconds.
+pub fn ask_duration(
+    title: &str,
+    label: &str,
+    initial_secs: i64,
+) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    ask_custom_value(
+        title,
+        label,
+        format_duration(Duration::from_secs(initial_secs.max(0) as u64)).replace(' ', ""),
+        |input| {
+            parse_duration(input).ok_or(format!(
+                "You need to input a duration, e.g. 90, 90s, 1m30s, 1{}5h or 1h.",
+                decimal_separator()
+            ))
+        },
+    )
+}
+
+/// Parses durations like "90", "90s", "1m30s", "2min", "1h 5m" or, in the locale's way of
+/// writing decimals, "1.5h" into seconds
+pub fn parse_duration(input: &str) -> Option<i64> {
+    duration_secs(input, decimal_separator())
+}
+
+/// [`parse_duration`] with `decimal` as decimal separator
+fn duration_secs(input: &str, decimal: char) -> Option<i64> {
+    let input = input.replace(' ', "").to_lowercase();
+    if input.is_empty() {
+        return None;
+    }
+
+    let integer = normalize_number(&input, decimal).ok();
+    if let Some(secs) = integer.and_then(|number| number.parse::<i64>().ok()) {
+        return (secs > 0).then_some(secs);
+    }
+
+    let is_number = |c: char| c.is_ascii_digit() || c == decimal;
+    let mut total = 0.0;
+    let mut rest = input.as_str();
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !is_number(c))?;
+        let value = parse_number(&rest[..digits], decimal).ok()?;
+        rest = &rest[digits..];
+
+        let unit_length = rest.find(is_number).unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_length] {
+            "h" | "hr" | "hour" | "hours" => 3600.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            _ => return None,
+        };
+        rest = &rest[unit_length..];
+
+        total += value * multiplier;
     }
+
+    let total = total.round() as i64;
+    (total > 0).then_some(total)
 }
 
 pub async fn ask_path(
@@ -101,3 +291,147 @@ pub async fn ask_path(
         },
     }
 }
+
+/// Puts a PNG on the clipboard with wl-copy, or xclip outside of Wayland
+pub async fn copy_image_to_clipboard(path: &Path) -> std::io::Result<()> {
+    let mut command = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut command = tokio::process::Command::new("wl-copy");
+        command
+            .args(["--type", "image/png"])
+            .stdin(std::fs::File::open(path)?);
+        command
+    } else {
+        let mut command = tokio::process::Command::new("xclip");
+        command
+            .args(["-selection", "clipboard", "-target", "image/png", "-in"])
+            .arg(path);
+        command
+    };
+
+    let status = command.status().await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "clipboard tool exited with {}",
+            status
+        )))
+    }
+}
+
+/// Placeholder for the user's Videos directory in paths stored in config
+pub const VIDEOS_DIR_PLACEHOLDER: &str = "$XDG_VIDEOS_DIR";
+
+fn videos_dir() -> PathBuf {
+    dirs::video_dir().unwrap_or_else(|| dirs::home_dir().unwrap().join("Videos"))
+}
+
+/// Turns a path from config into an absolute one. `$XDG_VIDEOS_DIR` and `~` are expanded and
+/// relative paths are relative to the Videos directory. The Videos directory is looked up on
+/// every call, so paths follow it if the user moves it.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix(VIDEOS_DIR_PLACEHOLDER) {
+        videos_dir().join(rest)
+    } else if let Ok(rest) = path.strip_prefix("~") {
+        dirs::home_dir().unwrap().join(rest)
+    } else if path.is_relative() {
+        videos_dir().join(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Opposite of [`resolve_path`], stores paths inside the Videos or home directory relative to it
+pub fn portable_path(path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix(videos_dir()) {
+        Path::new(VIDEOS_DIR_PLACEHOLDER).join(rest)
+    } else if let Ok(rest) = path.strip_prefix(dirs::home_dir().unwrap()) {
+        Path::new("~").join(rest)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// `file://` URI of an absolute path, with everything but unreserved characters and `/`
+/// percent-encoded, so spaces and `#` in file names survive
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(*byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separator_that_is_not_decimal_groups_thousands() {
+        assert_eq!(normalize_number("1,500", '.').unwrap(), "1500");
+        assert_eq!(normalize_number("1.500", ',').unwrap(), "1500");
+        assert_eq!(normalize_number("1,234,567.5", '.').unwrap(), "1234567.5");
+        assert_eq!(normalize_number("-2.5", '.').unwrap(), "-2.5");
+    }
+
+    #[test]
+    fn comma_is_decimal_where_the_locale_says_so() {
+        assert_eq!(normalize_number("1,500", ',').unwrap(), "1.500");
+        assert_eq!(normalize_number("1,5", ',').unwrap(), "1.5");
+    }
+
+    #[test]
+    fn grouping_that_is_not_by_thousands_is_ambiguous() {
+        assert!(normalize_number("1,5", '.').is_err());
+        assert!(normalize_number("1,50", '.').is_err());
+        assert!(normalize_number(",500", '.').is_err());
+        assert!(normalize_number("1.2.3", '.').is_err());
+    }
+
+    #[test]
+    fn spaces_and_apostrophes_group_thousands() {
+        assert_eq!(normalize_number("1\u{a0}500", ',').unwrap(), "1500");
+        assert_eq!(normalize_number("1\u{202f}500,5", ',').unwrap(), "1500.5");
+        assert_eq!(normalize_number("1 500", '.').unwrap(), "1500");
+        assert_eq!(normalize_number("1'500.5", '.').unwrap(), "1500.5");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("1,5", ','), Ok(1.5));
+        assert_eq!(parse_number("1.5", '.'), Ok(1.5));
+        assert_eq!(parse_number("-3", '.'), Ok(-3.0));
+        assert!(parse_number("", '.').is_err());
+        assert!(parse_number("abc", '.').is_err());
+
+        assert_eq!(parse_integer("42"), Ok(42));
+        assert_eq!(parse_integer("1\u{a0}500"), Ok(1500));
+        assert!(parse_integer(&format!("1{}5", decimal_separator())).is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration_secs("90", '.'), Some(90));
+        assert_eq!(duration_secs("90s", '.'), Some(90));
+        assert_eq!(duration_secs("1m30s", '.'), Some(90));
+        assert_eq!(duration_secs("1h 5m", '.'), Some(3900));
+        assert_eq!(duration_secs("2MIN", '.'), Some(120));
+        assert_eq!(duration_secs("1.5h", '.'), Some(5400));
+        assert_eq!(duration_secs("1,5h", ','), Some(5400));
+        assert_eq!(parse_duration("1m30s"), Some(90));
+    }
+
+    #[test]
+    fn rejected_durations() {
+        assert_eq!(duration_secs("", '.'), None);
+        assert_eq!(duration_secs("h", '.'), None);
+        assert_eq!(duration_secs("0", '.'), None);
+        assert_eq!(duration_secs("5x", '.'), None);
+        assert_eq!(duration_secs("1,5h", '.'), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Clips queued with "Watch later", oldest first. Kept in a file next to the statistics, so the
+/// tray app and `trayplay watch-later` see the same queue.
+#[derive(Serialize, Deserialize, Default)]
+struct Queue {
+    #[serde(default)]
+    clips: Vec<PathBuf>,
+}
+
+fn path() -> PathBuf {
+    let mut path = dirs::state_dir().unwrap();
+    path.push("trayplay_watch_later.toml");
+    path
+}
+
+fn load() -> Queue {
+    match std::fs::read_to_string(path()) {
+        Ok(queue) => toml::from_str(&queue).unwrap_or_default(),
+        Err(_) => Queue::default(),
+    }
+}
+
+fn save(queue: &Queue) {
+    let result = toml::to_string(queue)
+        .map_err(std::io::Error::other)
+        .and_then(|queue| std::fs::write(path(), queue));
+    if let Err(err) = result {
+        warn!("Cannot save the watch later queue: {}", err);
+    }
+}
+
+/// Queued clips that still exist, oldest first. Deleted ones drop out of the queue.
+pub fn queued() -> Vec<PathBuf> {
+    let mut queue = load();
+    let before = queue.clips.len();
+    queue.clips.retain(|clip| clip.exists());
+    if queue.clips.len() != before {
+        save(&queue);
+    }
+
+    queue.clips
+}
+
+fn modified() -> Option<SystemTime> {
+    std::fs::metadata(path())
+        .and_then(|file| file.modified())
+        .ok()
+}
+
+/// The queue as the tray app shows it, read again only when the file changed (by the app itself
+/// or `trayplay watch-later`)
+#[derive(Default)]
+pub struct QueueCache {
+    modified: Option<SystemTime>,
+    clips: Vec<PathBuf>,
+}
+
+impl QueueCache {
+    pub fn clips(&mut self) -> &[PathBuf] {
+        if modified() != self.modified {
+            self.clips = queued();
+            // Dropping deleted clips may have written the file again
+            self.modified = modified();
+        }
+
+        &self.clips
+    }
+}
+
+/// Queues `clip`, returns whether it wasn't queued yet
+pub fn add(clip: &Path) -> bool {
+    let mut queue = load();
+    if queue.clips.iter().any(|queued| queued == clip) {
+        return false;
+    }
+
+    queue.clips.push(clip.to_path_buf());
+    save(&queue);
+    true
+}
+
+/// Takes `clip` off the queue, returns whether it was queued
+pub fn remove(clip: &Path) -> bool {
+    let mut queue = load();
+    let before = queue.clips.len();
+    queue.clips.retain(|queued| queued != clip);
+    if queue.clips.len() == before {
+        return false;
+    }
+
+    save(&queue);
+    true
+}
+
+pub fn clear() {
+    save(&Queue::default());
+}
+
+/// Follows clips that were moved, new paths by old ones
+pub fn moved(moved: &HashMap<PathBuf, PathBuf>) {
+    let mut queue = load();
+    let mut changed = false;
+    for clip in &mut queue.clips {
+        if let Some(target) = moved.get(clip) {
+            *clip = target.clone();
+            changed = true;
+        }
+    }
+    if changed {
+        save(&queue);
+    }
+}
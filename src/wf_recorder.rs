@@ -0,0 +1,312 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::sync::{RwLock, mpsc::Sender};
+
+use crate::{
+    ActionEvent,
+    config::{BitDepth, CaptureTarget, Config, Encoder, Quality, RateControl, RateControlMode},
+    gpu,
+    jobs::JobQueue,
+    monitors,
+    recorder::{Error, Recorder, RecorderStatus},
+    segments::{SEGMENT_LENGTH, SegmentBuffer},
+    stats::Statistics,
+};
+
+fn crf(quality: Quality) -> u32 {
+    match quality {
+        Quality::Medium => 28,
+        Quality::High => 23,
+        Quality::VeryHigh => 20,
+        Quality::Ultra => 17,
+    }
+}
+
+/// Encoder options for the rate control, or the CRF matching the quality
+fn codec_options(config: &Config) -> Vec<String> {
+    match config.rate_control {
+        None => vec![format!("crf={}", crf(config.quality))],
+        Some(RateControl {
+            mode: RateControlMode::Cqp,
+            value,
+        }) => vec![format!("qp={}", value)],
+        Some(RateControl {
+            mode: RateControlMode::Vbr,
+            value,
+        }) => vec![format!("b={}k", value)],
+        Some(RateControl {
+            mode: RateControlMode::Cbr,
+            value,
+        }) => vec![
+            format!("b={}k", value),
+            format!("maxrate={}k", value),
+            format!("bufsize={}k", value * 2),
+        ],
+    }
+}
+
+/// WxH+X+Y as "X,Y WxH", the format wf-recorder's -g takes
+fn slurp_geometry(region: &str) -> Option<String> {
+    let (size, position) = region.split_once('+')?;
+    let (x, y) = position.split_once('+')?;
+    Some(format!("{},{} {}", x, y, size))
+}
+
+/// Replay buffer for wlroots compositors without gpu-screen-recorder. wf-recorder streams
+/// Matroska into ffmpeg, which cuts it into a [`SegmentBuffer`].
+pub struct WfRecorder {
+    /// wf-recorder and the ffmpeg reading from it
+    processes: Option<(Child, Child)>,
+    config: Arc<RwLock<Config>>,
+    stats: Arc<RwLock<Statistics>>,
+    buffer: SegmentBuffer,
+    pause_reasons: BTreeSet<String>,
+}
+
+impl WfRecorder {
+    pub async fn new(
+        config: Arc<RwLock<Config>>,
+        app_name: Arc<RwLock<String>>,
+        stats: Arc<RwLock<Statistics>>,
+        jobs: JobQueue,
+        action_tx: Sender<ActionEvent>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            processes: None,
+            buffer: SegmentBuffer::new(
+                "trayplay-wf-recorder",
+                config.clone(),
+                app_name,
+                stats.clone(),
+                jobs,
+                action_tx,
+            ),
+            config,
+            stats,
+            pause_reasons: BTreeSet::new(),
+        })
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.pause_reasons.is_empty()
+    }
+
+    async fn save(&mut self, secs: Option<i64>) -> Result<(), Error> {
+        if self.processes.is_none() {
+            return Err(Error::RecorderNotRunning);
+        }
+
+        self.buffer.save(secs).await
+    }
+}
+
+impl Recorder for WfRecorder {
+    async fn start(&mut self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        if self.processes.is_some() {
+            self.stats.write().await.record_restart();
+            self.stop().await?;
+        }
+
+        monitors::resolve_screen(&mut *self.config.write().await)?;
+        let config = self.config.read().await;
+        self.buffer.clear(&config)?;
+
+        let gpu = gpu::selected(&config);
+        let mut recorder = Command::new("wf-recorder");
+        if let Some(gpu) = &gpu {
+            gpu.apply_env(&mut recorder);
+        }
+        recorder
+            .args(["-y", "-m", "matroska", "-f", "/dev/stdout"])
+            .arg("-r")
+            .arg(config.effective_framerate().to_string());
+        match config.encoder {
+            // libx264, the only encoder the -p options are written for
+            Encoder::Auto | Encoder::Software => {
+                recorder.args(["-p", "preset=veryfast"]);
+                for option in codec_options(&config) {
+                    recorder.arg("-p").arg(option);
+                }
+                recorder.arg("-p").arg(format!(
+                    "g={}",
+                    SegmentBuffer::keyframe_interval_frames(&config)
+                ));
+            }
+            Encoder::Vaapi => {
+                let device = gpu
+                    .and_then(|gpu| gpu.render_node)
+                    .unwrap_or_else(|| PathBuf::from("/dev/dri/renderD128"));
+                recorder.args(["-c", "h264_vaapi", "-d"]).arg(device);
+            }
+            Encoder::Nvenc => {
+                recorder.args(["-c", "h264_nvenc"]);
+            }
+            Encoder::Qsv => {
+                recorder.args(["-c", "h264_qsv"]);
+            }
+        }
+        if !matches!(config.encoder, Encoder::Auto | Encoder::Software) {
+            info!(
+                "Quality, rate control and keyframe interval are left to the {:?} encoder's defaults",
+                config.encoder
+            );
+        }
+        // The hardware encoders only take 8-bit H.264, color_range is left to wf-recorder
+        if config.bit_depth == BitDepth::Ten {
+            match config.encoder {
+                Encoder::Auto | Encoder::Software => {
+                    recorder.args(["-x", "yuv420p10le"]);
+                }
+                _ => info!("Recording 8-bit video: wf-recorder encodes 10-bit in software only"),
+            }
+        }
+        // Any other capture target means the whole (first) output to wf-recorder
+        let screen = monitors::capture_screen(&config);
+        if monitors::is_monitor_target(&screen) {
+            recorder.arg("-o").arg(&screen);
+        } else if config.capture_target() == CaptureTarget::Region
+            && let Some(geometry) = config.region.as_deref().and_then(slurp_geometry)
+        {
+            recorder.arg("-g").arg(geometry);
+        }
+        if let Some((width, height)) = config.scale_size() {
+            recorder.arg("-F").arg(format!(
+                "scale=w={}:h={}:force_original_aspect_ratio=decrease:force_divisible_by=2",
+                width, height
+            ));
+        }
+        if !config.audio_tracks.is_empty() {
+            match &config.audio_output {
+                Some(output) => recorder.arg(format!("--audio={}", output)),
+                None => recorder.arg("-a"),
+            };
+        }
+
+        let mut recorder = recorder.stdout(Stdio::piped()).spawn()?;
+        let segmenter = Command::new("ffmpeg")
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-i",
+                "-",
+                "-c",
+                "copy",
+            ])
+            .args(["-f", "segment", "-segment_format", "matroska"])
+            .arg("-segment_time")
+            .arg(SEGMENT_LENGTH.as_secs().to_string())
+            .arg("-segment_wrap")
+            .arg(SegmentBuffer::segment_count(&config).to_string())
+            .args(["-reset_timestamps", "1"])
+            .arg(self.buffer.segment_pattern())
+            .stdin(recorder.stdout.take().unwrap())
+            .stdout(Stdio::null())
+            .spawn();
+        let segmenter = match segmenter {
+            Ok(segmenter) => segmenter,
+            Err(err) => {
+                let _ = recorder.kill();
+                return Err(err.into());
+            }
+        };
+
+        info!("Started wf-recorder replay buffer");
+        self.processes = Some((recorder, segmenter));
+
+        Ok(())
+    }
+
+    /// wf-recorder finishes on SIGINT, ffmpeg follows once its input ends. Returns once both
+    /// exited, so a restart doesn't clear the segments while ffmpeg still writes the last one.
+    async fn stop(&mut self) -> Result<(), Error> {
+        let Some((mut recorder, mut segmenter)) = self.processes.take() else {
+            return Err(Error::RecorderNotRunning);
+        };
+
+        match signal::kill(Pid::from_raw(recorder.id() as i32), Signal::SIGINT) {
+            Ok(_) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => return Err(err.into()),
+        }
+        tokio::task::spawn_blocking(move || {
+            for process in [&mut recorder, &mut segmenter] {
+                if let Err(err) = process.wait() {
+                    warn!("Cannot wait for wf-recorder to exit: {}", err);
+                }
+            }
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+
+        Ok(())
+    }
+
+    async fn save_replay(&mut self) -> Result<(), Error> {
+        self.save(None).await
+    }
+
+    async fn save_replay_last(&mut self, secs: i64) -> Result<(), Error> {
+        self.save(Some(secs)).await
+    }
+
+    async fn pause(&mut self, reason: &str) -> Result<(), Error> {
+        let was_paused = self.is_paused();
+        self.pause_reasons.insert(reason.to_string());
+
+        if !was_paused && self.processes.is_some() {
+            info!("Pausing replay recording: {}", reason);
+            self.stop().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn resume(&mut self, reason: &str) -> Result<(), Error> {
+        if self.pause_reasons.remove(reason) && !self.is_paused() {
+            info!("Resuming replay recording");
+            self.start().await?;
+        }
+
+        Ok(())
+    }
+
+    fn is_paused_for(&self, reason: &str) -> bool {
+        self.pause_reasons.contains(reason)
+    }
+
+    fn pause_reasons(&self) -> Vec<String> {
+        self.pause_reasons.iter().cloned().collect()
+    }
+
+    fn status(&self) -> RecorderStatus {
+        if self.is_paused() {
+            RecorderStatus::Paused
+        } else if self.processes.is_some() {
+            RecorderStatus::Running
+        } else {
+            RecorderStatus::Stopped
+        }
+    }
+
+    async fn last_replay(&self) -> Option<PathBuf> {
+        self.buffer.last_replay().await
+    }
+
+    async fn set_last_replay(&self, path: PathBuf) {
+        self.buffer.set_last_replay(path).await
+    }
+}